@@ -325,6 +325,18 @@ impl Config {
         })
     }
 
+    /// Returns an `Err` if the first value found is not a valid floating
+    /// point number. Otherwise, returns an `Ok(value)` if found, or `None`.
+    pub fn get_f64(
+        &self,
+        section: &[u8],
+        item: &[u8],
+    ) -> Result<Option<f64>, ConfigValueParseError> {
+        self.get_parse(section, item, "valid number", |value| {
+            str::from_utf8(value).ok()?.trim().parse().ok()
+        })
+    }
+
     /// Returns an `Err` if the first value found is not a valid file size
     /// value such as `30` (default unit is bytes), `7 MB`, or `42.5 kb`.
     /// Otherwise, returns an `Ok(value_in_bytes)` if found, or `None`.
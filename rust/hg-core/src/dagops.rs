@@ -102,6 +102,31 @@ pub fn roots<G: Graph, S: std::hash::BuildHasher>(
     Ok(roots)
 }
 
+/// Descendants of `start` among the revisions lower than `end`, including
+/// `start` itself
+///
+/// Since parents always have lower revision numbers than their children,
+/// this takes a single pass over the revisions that follow `start`, like
+/// `revlog.heads(start)` does in Python.
+pub fn descendants(
+    graph: &impl Graph,
+    start: Revision,
+    end: Revision,
+) -> Result<HashSet<Revision>, GraphError> {
+    let mut descendants = HashSet::new();
+    descendants.insert(start);
+    for rev in start + 1..end {
+        if graph
+            .parents(rev)?
+            .iter()
+            .any(|parent| descendants.contains(parent))
+        {
+            descendants.insert(rev);
+        }
+    }
+    Ok(descendants)
+}
+
 /// Compute the topological range between two collections of revisions
 ///
 /// This is equivalent to the revset `<roots>::<heads>`.
@@ -249,6 +274,32 @@ mod tests {
         Ok(())
     }
 
+    /// Apply `descendants()` and sort the result for easier comparison
+    fn descendants_sorted(
+        graph: &impl Graph,
+        start: Revision,
+    ) -> Result<Vec<Revision>, GraphError> {
+        let mut as_vec: Vec<_> =
+            descendants(graph, start, 14)?.into_iter().collect();
+        as_vec.sort();
+        Ok(as_vec)
+    }
+
+    #[test]
+    fn test_descendants() -> Result<(), GraphError> {
+        assert_eq!(
+            descendants_sorted(&SampleGraph, 4)?,
+            vec![4, 5, 6, 7, 9, 10, 11, 12]
+        );
+        assert_eq!(descendants_sorted(&SampleGraph, 6)?, vec![6, 9, 12]);
+        assert_eq!(descendants_sorted(&SampleGraph, 13)?, vec![13]);
+        assert_eq!(
+            descendants_sorted(&SampleGraph, NULL_REVISION)?,
+            (NULL_REVISION..14).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
     /// Apply `range()` and convert the result into a Vec for easier comparison
     fn range_vec(
         graph: impl Graph + Clone,
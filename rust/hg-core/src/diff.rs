@@ -0,0 +1,231 @@
+// diff.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Line-based diffs between two texts
+//!
+//! Both algorithms return the lines that the texts have in common as a list
+//! of `Block`s, like `bdiff.blocks` in Python:
+//!
+//! * `bdiff_blocks` gives the same blocks as the `bdiff` C extension, which
+//!   is what revlog deltas and `annotate` are computed from,
+//! * `histogram_blocks` gives diffs that are usually easier to read, for
+//!   showing to users.
+//!
+//! `unified_hunks` formats the differences as hunks of a unified diff.
+
+mod bdiff;
+mod histogram;
+mod unified;
+
+pub use unified::{git_binary_patch, git_blob_id, unified_hunks};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+/// Lines `a1..a2` of the first text are equal to lines `b1..b2` of the
+/// second text
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub a1: usize,
+    pub a2: usize,
+    pub b1: usize,
+    pub b2: usize,
+}
+
+impl Block {
+    /// The number of lines in the block
+    pub fn len(&self) -> usize {
+        self.a2 - self.a1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.a1 == self.a2
+    }
+}
+
+/// Split a text into lines, each including its `\n` terminator if any, like
+/// `mdiff.splitnewlines`
+pub fn split_lines(text: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, &byte) in text.iter().enumerate() {
+        if byte == b'\n' {
+            lines.push(&text[start..i + 1]);
+            start = i + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(&text[start..])
+    }
+    lines
+}
+
+/// The common lines of two texts, computed like the `bdiff` C extension.
+///
+/// The last block is always an empty block at the end of both texts.
+pub fn bdiff_blocks(a: &[u8], b: &[u8]) -> Vec<Block> {
+    bdiff::blocks(&split_lines(a), &split_lines(b))
+}
+
+/// The common lines of two texts, computed with the histogram algorithm of
+/// `git diff --histogram`.
+///
+/// The last block is always an empty block at the end of both texts.
+pub fn histogram_blocks(a: &[u8], b: &[u8]) -> Vec<Block> {
+    let a = split_lines(a);
+    let b = split_lines(b);
+    let mut blocks = histogram::blocks(&a, &b);
+    normalize(&a, &b, &mut blocks);
+    blocks
+}
+
+/// A binary delta turning `a` into `b`, in the format read by
+/// `revlog::patch::PatchList`, like `bdiff.bdiff` in Python.
+pub fn text_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+    // Lines that start both texts are left out of the diff
+    let mut common = 0;
+    for (i, (&byte_a, &byte_b)) in a.iter().zip(b).enumerate() {
+        if byte_a != byte_b {
+            break;
+        }
+        if byte_a == b'\n' {
+            common = i + 1;
+        }
+    }
+    let (a, b) = (&a[common..], &b[common..]);
+    let a_lines = split_lines(a);
+    let b_lines = split_lines(b);
+    let a_offsets = line_offsets(&a_lines);
+    let b_offsets = line_offsets(&b_lines);
+
+    let mut delta = Vec::new();
+    let (mut a_done, mut b_done) = (0, 0);
+    for block in bdiff::blocks(&a_lines, &b_lines) {
+        if block.a1 != a_done || block.b1 != b_done {
+            let data = &b[b_offsets[b_done]..b_offsets[block.b1]];
+            for &number in &[
+                common + a_offsets[a_done],
+                common + a_offsets[block.a1],
+                data.len(),
+            ] {
+                delta
+                    .write_u32::<BigEndian>(number as u32)
+                    .expect("writing to a Vec never fails");
+            }
+            delta.extend_from_slice(data);
+        }
+        a_done = block.a2;
+        b_done = block.b2;
+    }
+    delta
+}
+
+/// The offset of the start of each line, and of the end of the text
+fn line_offsets(lines: &[&[u8]]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(lines.len() + 1);
+    let mut offset = 0;
+    offsets.push(offset);
+    for line in lines {
+        offset += line.len();
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// Push changes between blocks towards the end of the texts where possible,
+/// so that a line added after similar lines shows as added last
+fn normalize(a: &[&[u8]], b: &[&[u8]], blocks: &mut [Block]) {
+    for i in 1..blocks.len() {
+        let (mut current, mut next) = (blocks[i - 1], blocks[i]);
+        if current.a2 == next.a1 || current.b2 == next.b1 {
+            while current.a2 < a.len()
+                && current.b2 < b.len()
+                && next.a1 < next.a2
+                && next.b1 < next.b2
+                && a[current.a2] == b[current.b2]
+            {
+                current.a2 += 1;
+                current.b2 += 1;
+                next.a1 += 1;
+                next.b1 += 1;
+            }
+        }
+        blocks[i - 1] = current;
+        blocks[i] = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::revlog::patch::PatchList;
+    use pretty_assertions::assert_eq;
+
+    fn block(a1: usize, a2: usize, b1: usize, b2: usize) -> Block {
+        Block { a1, a2, b1, b2 }
+    }
+
+    #[test]
+    fn test_split_lines() {
+        assert_eq!(split_lines(b""), Vec::<&[u8]>::new());
+        assert_eq!(split_lines(b"a\n\nb"), vec![&b"a\n"[..], b"\n", b"b"]);
+        assert_eq!(split_lines(b"a\n"), vec![&b"a\n"[..]]);
+    }
+
+    #[test]
+    fn test_bdiff_blocks() {
+        assert_eq!(bdiff_blocks(b"", b""), vec![block(0, 0, 0, 0)]);
+        assert_eq!(
+            bdiff_blocks(b"a\nb\nc\n", b"a\nc\n"),
+            vec![block(0, 1, 0, 1), block(2, 3, 1, 2), block(3, 3, 2, 2)]
+        );
+        // The added line is the last of the identical ones
+        assert_eq!(
+            bdiff_blocks(b"x\nx\n", b"x\nx\nx\n"),
+            vec![block(0, 2, 0, 2), block(2, 2, 3, 3)]
+        );
+        assert_eq!(bdiff_blocks(b"a\nb\n", b"c\nd"), vec![block(2, 2, 2, 2)]);
+    }
+
+    #[test]
+    fn test_histogram_blocks() {
+        assert_eq!(histogram_blocks(b"", b""), vec![block(0, 0, 0, 0)]);
+        assert_eq!(
+            histogram_blocks(b"a\nb\nc\n", b"a\nc\n"),
+            vec![block(0, 1, 0, 1), block(2, 3, 1, 2), block(3, 3, 2, 2)]
+        );
+        // Unique lines are matched rather than the frequent blank lines
+        assert_eq!(
+            histogram_blocks(b"a\n\nb\n\nc\n", b"\nc\n\nb\n\na\n"),
+            vec![block(1, 4, 2, 5), block(5, 5, 6, 6)]
+        );
+        assert_eq!(
+            histogram_blocks(b"x\nx\n", b"x\nx\nx\n"),
+            vec![block(0, 2, 0, 2), block(2, 2, 3, 3)]
+        );
+    }
+
+    #[test]
+    fn test_text_delta() {
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"", b""),
+            (b"", b"a\n"),
+            (b"a\nb\nc\n", b"a\nc\n"),
+            (b"a\nb\nc\n", b"a\nB\nc\nd"),
+            (b"abc", b"abd"),
+            (b"x\ny\n", b""),
+        ];
+        for &(a, b) in cases {
+            let delta = text_delta(a, b);
+            assert_eq!(PatchList::new(&delta).unwrap().apply(a).unwrap(), b);
+        }
+        assert_eq!(text_delta(b"a\nb\n", b"a\nb\n"), b"");
+        assert_eq!(
+            text_delta(b"a\nb\nc\n", b"a\nc\n"),
+            b"\0\0\0\x02\0\0\0\x04\0\0\0\0"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+// bdiff.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! A port of `mercurial/bdiff.c`, which finds the longest matching run of
+//! lines, then recurses on both sides of it.
+//!
+//! Since revlog deltas and `annotate` results depend on the exact blocks,
+//! this follows the C code closely, including its heuristics.
+
+use super::{normalize, Block};
+
+/// Sentinel of `Line::next` and of the chains of equivalent lines
+const NONE: i32 = -1;
+
+struct Line<'a> {
+    text: &'a [u8],
+    hash: u32,
+    /// In the second text, the previous line in the same equivalence class.
+    /// In the first text, the last line of the second text in the same
+    /// class, or `NONE` if there is none or the class is too popular.
+    next: i32,
+    /// The equivalence class, for quick comparisons
+    class: usize,
+}
+
+impl<'a> Line<'a> {
+    fn new(text: &'a [u8]) -> Self {
+        // Hash from diffutils, on C `char`s which are signed
+        let hash = text.iter().fold(0u32, |hash, &byte| {
+            (byte as i8 as u32).wrapping_add(hash.rotate_left(7))
+        });
+        Self {
+            text,
+            hash,
+            next: NONE,
+            class: 0,
+        }
+    }
+}
+
+/// The position and length of the match of a line of the second text
+#[derive(Copy, Clone, Default)]
+struct Position {
+    pos: i32,
+    len: i32,
+}
+
+#[derive(Copy, Clone)]
+struct Chain {
+    pos: i32,
+    len: usize,
+}
+
+pub(super) fn blocks(a: &[&[u8]], b: &[&[u8]]) -> Vec<Block> {
+    let mut a: Vec<_> = a.iter().map(|line| Line::new(line)).collect();
+    let mut b: Vec<_> = b.iter().map(|line| Line::new(line)).collect();
+    equate_lines(&mut a, &mut b);
+    let mut diff = Diff {
+        a: &a,
+        b: &b,
+        // Initial positions are zero like with `calloc`, which affects how
+        // matches at the start of the first text are extended
+        positions: vec![Position::default(); b.len()],
+        blocks: Vec::new(),
+    };
+    diff.recurse(0, a.len() as i32, 0, b.len() as i32);
+    let mut blocks = diff.blocks;
+    blocks.push(Block {
+        a1: a.len(),
+        a2: a.len(),
+        b1: b.len(),
+        b2: b.len(),
+    });
+    let a: Vec<_> = a.iter().map(|line| line.text).collect();
+    let b: Vec<_> = b.iter().map(|line| line.text).collect();
+    normalize(&a, &b, &mut blocks);
+    blocks
+}
+
+/// Group equal lines in equivalence classes with a hash table, and link
+/// each line to the previous equivalent line in `b`
+fn equate_lines(a: &mut [Line], b: &mut [Line]) {
+    let mut buckets = 1;
+    while buckets < b.len() + 1 {
+        buckets *= 2
+    }
+    // A large table to avoid collisions
+    let mask = buckets * 4 - 1;
+    let mut table = vec![Chain { pos: NONE, len: 0 }; mask + 1];
+    let find = |table: &[Chain], b: &[Line], line: &Line| {
+        let mut j = line.hash as usize & mask;
+        while table[j].pos != NONE {
+            let other = &b[table[j].pos as usize];
+            if other.hash == line.hash && other.text == line.text {
+                break;
+            }
+            j = (j + 1) & mask;
+        }
+        j
+    };
+
+    for i in 0..b.len() {
+        let j = find(&table, b, &b[i]);
+        b[i].next = table[j].pos;
+        b[i].class = j;
+        table[j].pos = i as i32;
+        table[j].len += 1;
+    }
+
+    // Lines that are too popular are not used as a starting point of
+    // matches
+    let threshold = if b.len() >= 31000 {
+        b.len() / 1000
+    } else {
+        1_000_000 / (b.len() + 1)
+    };
+    for line in a.iter_mut() {
+        let j = find(&table, b, line);
+        line.class = j;
+        line.next = if table[j].len <= threshold {
+            table[j].pos
+        } else {
+            NONE
+        };
+    }
+}
+
+struct Diff<'a, 'b> {
+    a: &'b [Line<'a>],
+    b: &'b [Line<'a>],
+    positions: Vec<Position>,
+    blocks: Vec<Block>,
+}
+
+impl Diff<'_, '_> {
+    /// Return the start in `a` and `b` and the length of the longest match
+    fn longest_match(
+        &mut self,
+        a1: i32,
+        a2: i32,
+        b1: i32,
+        b2: i32,
+    ) -> (i32, i32, i32) {
+        let (a, b) = (self.a, self.b);
+        let (mut mi, mut mj, mut mk) = (a1, b1, 0);
+
+        // Window the search on large regions to better bound worst-case
+        // performance. By choosing a window at the end, we reduce skipping
+        // overhead on the `b` chains.
+        let a1 = if a2 - a1 > 30000 { a2 - 30000 } else { a1 };
+
+        let half = (a1 + a2 - 1) / 2;
+        let bhalf = (b1 + b2 - 1) / 2;
+
+        for i in a1..a2 {
+            // Skip all lines in `b` after the current block
+            let mut j = a[i as usize].next;
+            while j >= b2 {
+                j = b[j as usize].next;
+            }
+
+            // Loop through all lines matching `a[i]` in `b`
+            while j >= b1 {
+                // Does this extend an earlier match?
+                let mut k = 1;
+                while j - k >= b1 && i - k >= a1 {
+                    let earlier = self.positions[(j - k) as usize];
+                    // Reached an earlier match?
+                    if earlier.pos == i - k {
+                        k += earlier.len;
+                        break;
+                    }
+                    // Previous line mismatch?
+                    if a[(i - k) as usize].class != b[(j - k) as usize].class {
+                        break;
+                    }
+                    k += 1;
+                }
+
+                self.positions[j as usize] = Position { pos: i, len: k };
+
+                // Best match so far? Prefer matches closer to the middle
+                // to balance recursion.
+                if k > mk {
+                    // A longer match
+                    mi = i;
+                    mj = j;
+                    mk = k;
+                } else if k == mk {
+                    if i > mi && i <= half && j > b1 {
+                        // Same match but closer to half
+                        mi = i;
+                        mj = j;
+                    } else if i == mi && (mj > bhalf || i == a1) {
+                        // Same `i` but best earlier `j`
+                        mj = j;
+                    }
+                }
+                j = b[j as usize].next;
+            }
+        }
+
+        if mk > 0 {
+            mi = mi - mk + 1;
+            mj = mj - mk + 1;
+        }
+
+        // Expand the match to include subsequent popular lines
+        while mi + mk < a2
+            && mj + mk < b2
+            && a[(mi + mk) as usize].class == b[(mj + mk) as usize].class
+        {
+            mk += 1;
+        }
+        (mi, mj, mk)
+    }
+
+    fn recurse(&mut self, mut a1: i32, a2: i32, mut b1: i32, b2: i32) {
+        loop {
+            // Find the longest match in this chunk
+            let (i, j, k) = self.longest_match(a1, a2, b1, b2);
+            if k == 0 {
+                return;
+            }
+
+            // And recurse on the remaining chunks on either side
+            self.recurse(a1, i, b1, j);
+            self.blocks.push(Block {
+                a1: i as usize,
+                a2: (i + k) as usize,
+                b1: j as usize,
+                b2: (j + k) as usize,
+            });
+            a1 = i + k;
+            b1 = j + k;
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// histogram.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! The histogram diff algorithm, like `xhistogram.c` in git's copy of
+//! libxdiff.
+//!
+//! Each region of the texts is split around the longest run of common lines
+//! that contains the fewest occurrences of its rarest line in the first
+//! text. This favors matching unique lines over frequent ones such as blank
+//! lines or lone braces.
+
+use super::{bdiff, Block};
+use std::collections::HashMap;
+
+/// Lines occurring more often than this in a region are not used as a
+/// starting point of matches
+const MAX_CHAIN_LENGTH: usize = 64;
+
+pub(super) fn blocks(a: &[&[u8]], b: &[&[u8]]) -> Vec<Block> {
+    // Common lines at the start and end are matched directly
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut blocks = Vec::new();
+    push(
+        &mut blocks,
+        Block {
+            a1: 0,
+            a2: prefix,
+            b1: 0,
+            b2: prefix,
+        },
+    );
+    let mut on_block = |block| push(&mut blocks, block);
+    let (a2, b2) = (a.len() - suffix, b.len() - suffix);
+    diff(a, b, prefix, a2, prefix, b2, &mut on_block);
+    push(
+        &mut blocks,
+        Block {
+            a1: a.len() - suffix,
+            a2: a.len(),
+            b1: b.len() - suffix,
+            b2: b.len(),
+        },
+    );
+    blocks.push(Block {
+        a1: a.len(),
+        a2: a.len(),
+        b1: b.len(),
+        b2: b.len(),
+    });
+    blocks
+}
+
+/// Add a block, merging it with the previous one when they are adjacent
+fn push(blocks: &mut Vec<Block>, block: Block) {
+    if block.is_empty() {
+        return;
+    }
+    if let Some(last) = blocks.last_mut() {
+        if last.a2 == block.a1 && last.b2 == block.b1 {
+            last.a2 = block.a2;
+            last.b2 = block.b2;
+            return;
+        }
+    }
+    blocks.push(block)
+}
+
+/// Find the common lines of `a[a1..a2]` and `b[b1..b2]`
+fn diff(
+    a: &[&[u8]],
+    b: &[&[u8]],
+    mut a1: usize,
+    a2: usize,
+    mut b1: usize,
+    b2: usize,
+    on_block: &mut impl FnMut(Block),
+) {
+    while a1 < a2 && b1 < b2 {
+        let block = match longest_common_run(a, b, a1, a2, b1, b2) {
+            Some(block) => block,
+            None => {
+                // Common lines, if any, are all too frequent. Fall back to
+                // bdiff, which can match them.
+                let blocks = bdiff::blocks(&a[a1..a2], &b[b1..b2]);
+                for block in blocks {
+                    on_block(Block {
+                        a1: a1 + block.a1,
+                        a2: a1 + block.a2,
+                        b1: b1 + block.b1,
+                        b2: b1 + block.b2,
+                    })
+                }
+                return;
+            }
+        };
+        diff(a, b, a1, block.a1, b1, block.b1, on_block);
+        on_block(block);
+        a1 = block.a2;
+        b1 = block.b2;
+    }
+}
+
+/// The positions in `a[a1..a2]` of each distinct line, in increasing order
+fn occurrences<'a>(
+    a: &[&'a [u8]],
+    a1: usize,
+    a2: usize,
+) -> HashMap<&'a [u8], Vec<usize>> {
+    let mut occurrences = HashMap::<_, Vec<_>>::new();
+    for (i, &line) in a[a1..a2].iter().enumerate() {
+        occurrences.entry(line).or_default().push(a1 + i)
+    }
+    occurrences
+}
+
+fn longest_common_run(
+    a: &[&[u8]],
+    b: &[&[u8]],
+    a1: usize,
+    a2: usize,
+    b1: usize,
+    b2: usize,
+) -> Option<Block> {
+    let occurrences = occurrences(a, a1, a2);
+    let count = |i: usize| occurrences[a[i]].len();
+    let mut best: Option<Block> = None;
+    // The number of occurrences of the rarest line of `best`
+    let mut best_count = MAX_CHAIN_LENGTH + 1;
+
+    let mut j = b1;
+    while j < b2 {
+        let mut next_j = j + 1;
+        let positions = match occurrences.get(b[j]) {
+            Some(positions) if positions.len() <= best_count => positions,
+            _ => {
+                j = next_j;
+                continue;
+            }
+        };
+        let mut index = 0;
+        while index < positions.len() {
+            // Extend the match around `a[positions[index]] == b[j]`
+            let (mut start_a, mut start_b) = (positions[index], j);
+            let (mut end_a, mut end_b) = (start_a + 1, j + 1);
+            let mut rarest = positions.len();
+            while a1 < start_a
+                && b1 < start_b
+                && a[start_a - 1] == b[start_b - 1]
+            {
+                start_a -= 1;
+                start_b -= 1;
+                rarest = rarest.min(count(start_a));
+            }
+            while end_a < a2 && end_b < b2 && a[end_a] == b[end_b] {
+                rarest = rarest.min(count(end_a));
+                end_a += 1;
+                end_b += 1;
+            }
+            next_j = next_j.max(end_b);
+            let longer = best.map_or(0, |best| best.len()) < end_a - start_a;
+            if longer || rarest < best_count {
+                best = Some(Block {
+                    a1: start_a,
+                    a2: end_a,
+                    b1: start_b,
+                    b2: end_b,
+                });
+                best_count = rarest;
+            }
+            // Skip the occurrences that are part of this match
+            while index < positions.len() && positions[index] < end_a {
+                index += 1
+            }
+        }
+        j = next_j;
+    }
+    best
+}
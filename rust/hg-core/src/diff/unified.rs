@@ -0,0 +1,273 @@
+// unified.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Text output of diffs, like `mercurial/mdiff.py`
+
+use super::{bdiff_blocks, split_lines};
+use byteorder::{BigEndian, ByteOrder};
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+const MISSING_NEWLINE_MARKER: &[u8] = b"\\ No newline at end of file\n";
+
+/// A hunk being built, with the ranges of lines it covers so far
+struct Hunk {
+    a1: usize,
+    a2: usize,
+    b1: usize,
+    b2: usize,
+    lines: Vec<Vec<u8>>,
+}
+
+/// The hunks of a unified diff turning `a` into `b`, without the `---` and
+/// `+++` file headers, like `mdiff.unidiff` in Python.
+///
+/// Each hunk starts with its `@@ -a,b +c,d @@` line and has up to `context`
+/// unchanged lines around changes. The result is empty when the texts are
+/// equal.
+pub fn unified_hunks(a: &[u8], b: &[u8], context: usize) -> Vec<u8> {
+    let lines_a = split_lines(a);
+    let lines_b = split_lines(b);
+    let prefixed = |prefix: u8, line: &[u8]| [&[prefix][..], line].concat();
+
+    let mut output = Vec::new();
+    let mut hunk: Option<Hunk> = None;
+    let (mut a_done, mut b_done) = (0, 0);
+    for block in bdiff_blocks(a, b) {
+        let (a1, a2, b1, b2) = (a_done, block.a1, b_done, block.b1);
+        a_done = block.a2;
+        b_done = block.b2;
+        if a1 == a2 && b1 == b2 {
+            continue;
+        }
+        let context_start = a1.saturating_sub(context);
+        let current = match hunk.take() {
+            // Join with the previous hunk if it falls inside the context
+            Some(previous) if context_start < previous.a2 + context + 1 => {
+                previous
+            }
+            previous => {
+                if let Some(previous) = previous {
+                    write_hunk(&mut output, previous, a, b, &lines_a, context)
+                }
+                Hunk {
+                    a1: context_start,
+                    a2: context_start,
+                    b1: b1.saturating_sub(context),
+                    b2: 0,
+                    lines: Vec::new(),
+                }
+            }
+        };
+        let mut current = current;
+        for line in &lines_a[current.a2..a1] {
+            current.lines.push(prefixed(b' ', line))
+        }
+        for line in &lines_a[a1..a2] {
+            current.lines.push(prefixed(b'-', line))
+        }
+        for line in &lines_b[b1..b2] {
+            current.lines.push(prefixed(b'+', line))
+        }
+        current.a2 = a2;
+        current.b2 = b2;
+        hunk = Some(current)
+    }
+    if let Some(hunk) = hunk {
+        write_hunk(&mut output, hunk, a, b, &lines_a, context)
+    }
+    output
+}
+
+fn write_hunk(
+    output: &mut Vec<u8>,
+    mut hunk: Hunk,
+    a: &[u8],
+    b: &[u8],
+    lines_a: &[&[u8]],
+    context: usize,
+) {
+    let a_end = (hunk.a2 + context).min(lines_a.len());
+    for line in &lines_a[hunk.a2..a_end] {
+        hunk.lines.push([b" ", *line].concat())
+    }
+    let a_len = a_end - hunk.a1;
+    let b_len = hunk.b2 - hunk.b1 + a_end - hunk.a2;
+    // Ranges of zero lines report their start as one less
+    let a_start = if a_len > 0 { hunk.a1 + 1 } else { hunk.a1 };
+    let b_start = if b_len > 0 { hunk.b1 + 1 } else { hunk.b1 };
+
+    // When a text ends without a newline and its last line is part of the
+    // hunk, a marker follows that line. If both texts end with the same
+    // line without a newline, only one marker is shown.
+    let mut add_marker = |prefixes: &[u8]| -> bool {
+        let last = hunk
+            .lines
+            .iter()
+            .rposition(|line| prefixes.contains(&line[0]));
+        if let Some(last) = last {
+            hunk.lines[last].push(b'\n');
+            hunk.lines.insert(last + 1, MISSING_NEWLINE_MARKER.to_vec());
+            return hunk.lines[last][0] == b' ';
+        }
+        false
+    };
+    let mut shared_marker = false;
+    if !a.ends_with(b"\n") && a_start + a_len == lines_a.len() + 1 {
+        shared_marker = add_marker(b"- ");
+    }
+    let b_line_count = split_lines(b).len();
+    if !shared_marker
+        && !b.ends_with(b"\n")
+        && b_start + b_len == b_line_count + 1
+    {
+        add_marker(b"+");
+    }
+
+    output.extend_from_slice(
+        format!("@@ -{},{} +{},{} @@\n", a_start, a_len, b_start, b_len)
+            .as_bytes(),
+    );
+    for line in hunk.lines {
+        output.extend_from_slice(&line)
+    }
+}
+
+const BASE85_CHARACTERS: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz\
+      !#$%&()*+-;<=>?@^_`{|}~";
+
+/// Encode data in base85, padded to a multiple of four bytes, like
+/// `util.b85encode(data, True)` in Python
+fn base85_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.chunks(4).len() * 5);
+    for chunk in data.chunks(4) {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        let mut word = BigEndian::read_u32(&word);
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE85_CHARACTERS[(word % 85) as usize];
+            word /= 85;
+        }
+        encoded.extend_from_slice(&digits)
+    }
+    encoded
+}
+
+/// The `GIT binary patch` literal replacing `a` with `b` in git diffs, like
+/// `mdiff.b85diff` in Python. This is empty when the contents are equal.
+pub fn git_binary_patch(a: &[u8], b: &[u8]) -> Vec<u8> {
+    if a == b {
+        return Vec::new();
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b).expect("writing to a Vec never fails");
+    let compressed = encoder.finish().expect("writing to a Vec never fails");
+
+    let mut patch = format!("GIT binary patch\nliteral {}\n", b.len())
+        .as_bytes()
+        .to_vec();
+    for chunk in compressed.chunks(52) {
+        // The length of the line, `A` to `Z` then `a` to `z`
+        let length = chunk.len() as u8;
+        patch.push(if length <= 26 {
+            b'A' + length - 1
+        } else {
+            b'a' + length - 27
+        });
+        patch.extend_from_slice(&base85_encode(chunk));
+        patch.push(b'\n')
+    }
+    patch.push(b'\n');
+    patch
+}
+
+/// The hexadecimal ID of a file's contents as a git blob, shown in the
+/// `index` lines of git diffs, like `patch._gitindex` in Python
+pub fn git_blob_id(data: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input(format!("blob {}\0", data.len()).as_bytes());
+    hasher.input(data);
+    hasher.result_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn hunks(a: &str, b: &str, context: usize) -> String {
+        String::from_utf8(unified_hunks(a.as_bytes(), b.as_bytes(), context))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_unified_hunks() {
+        assert_eq!(hunks("a\nb\n", "a\nb\n", 3), "");
+        assert_eq!(hunks("", "a\nb\n", 3), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+        assert_eq!(hunks("a\n", "", 3), "@@ -1,1 +0,0 @@\n-a\n");
+        assert_eq!(
+            hunks(
+                "1\n2\n3\n4\n5\n6\n7\n8\n9\n",
+                "1\n2\n3\n4\n5\n6\nx\n8\n9\n",
+                1
+            ),
+            "@@ -6,3 +6,3 @@\n 6\n-7\n+x\n 8\n"
+        );
+        // Changes separated by at most twice the context share a hunk
+        assert_eq!(
+            hunks("1\n2\n3\n4\n5\n", "x\n2\n3\n4\ny\n", 1),
+            "@@ -1,2 +1,2 @@\n-1\n+x\n 2\n@@ -4,2 +4,2 @@\n 4\n-5\n+y\n"
+        );
+        assert_eq!(
+            hunks("1\n2\n3\n4\n", "x\n2\n3\ny\n", 1),
+            "@@ -1,4 +1,4 @@\n-1\n+x\n 2\n 3\n-4\n+y\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_hunks_missing_newline() {
+        assert_eq!(
+            hunks("a\nb", "a\nc", 3),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n\
+             +c\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            hunks("a\nb", "a\nb\n", 3),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+        // A shared last line only has one marker
+        assert_eq!(
+            hunks("a\nb", "c\nb", 3),
+            "@@ -1,2 +1,2 @@\n-a\n+c\n b\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn test_git_blob_id() {
+        assert_eq!(
+            git_blob_id(b""),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+        assert_eq!(
+            git_blob_id(b"a\n"),
+            "78981922613b2afb6025042ff6bd878ac1994e85"
+        );
+    }
+
+    #[test]
+    fn test_base85_encode() {
+        assert_eq!(base85_encode(b""), b"");
+        assert_eq!(base85_encode(b"\0\0\0\0"), b"00000");
+        assert_eq!(base85_encode(b"abc"), b"VPazd");
+        assert_eq!(base85_encode(b"\xff\xff\xff\xff"), b"|NsC0");
+    }
+}
@@ -292,7 +292,7 @@ impl fmt::Display for StatusError {
 
 /// Gives information about which files are changed in the working directory
 /// and how, compared to the revision we're based on
-pub struct Status<'a, M: Matcher + Sync> {
+pub struct Status<'a, M: Matcher + Sync + ?Sized> {
     dmap: &'a DirstateMap,
     pub(crate) matcher: &'a M,
     root_dir: PathBuf,
@@ -302,7 +302,7 @@ pub struct Status<'a, M: Matcher + Sync> {
 
 impl<'a, M> Status<'a, M>
 where
-    M: Matcher + Sync,
+    M: Matcher + Sync + ?Sized,
 {
     pub fn new(
         dmap: &'a DirstateMap,
@@ -900,7 +900,7 @@ pub fn build_response<'a>(
 #[timed]
 pub fn status<'a>(
     dmap: &'a DirstateMap,
-    matcher: &'a (impl Matcher + Sync),
+    matcher: &'a (impl Matcher + Sync + ?Sized),
     root_dir: PathBuf,
     ignore_files: Vec<PathBuf>,
     options: StatusOptions,
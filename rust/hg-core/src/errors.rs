@@ -31,6 +31,12 @@ pub enum HgError {
     /// machine-readable.
     Abort(String),
 
+    /// The user gave invalid input, such as a revset that does not parse,
+    /// like `InputError` and `ParseError` in Python.
+    ///
+    /// The given string is the whole message shown to users.
+    InputError(String),
+
     /// A configuration value is not in the expected syntax.
     ///
     /// These errors can happen in many places in the code because values are
@@ -72,13 +78,18 @@ impl HgError {
     pub fn abort(explanation: impl Into<String>) -> Self {
         HgError::Abort(explanation.into())
     }
+    pub fn input_error(explanation: impl Into<String>) -> Self {
+        HgError::InputError(explanation.into())
+    }
 }
 
 // TODO: use `DisplayBytes` instead to show non-Unicode filenames losslessly?
 impl fmt::Display for HgError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            HgError::Abort(explanation) => write!(f, "{}", explanation),
+            HgError::Abort(explanation) | HgError::InputError(explanation) => {
+                write!(f, "{}", explanation)
+            }
             HgError::IoError { error, context } => {
                 write!(f, "abort: {}: {}", context, error)
             }
@@ -135,6 +146,9 @@ pub trait IoResultExt<T> {
     /// Converts a `Result` with `std::io::Error` into one with `HgError`.
     fn when_reading_file(self, path: &std::path::Path) -> Result<T, HgError>;
 
+    /// Same as `when_reading_file`, for writing.
+    fn when_writing_file(self, path: &std::path::Path) -> Result<T, HgError>;
+
     fn with_context(
         self,
         context: impl FnOnce() -> IoErrorContext,
@@ -146,6 +160,10 @@ impl<T> IoResultExt<T> for std::io::Result<T> {
         self.with_context(|| IoErrorContext::ReadingFile(path.to_owned()))
     }
 
+    fn when_writing_file(self, path: &std::path::Path) -> Result<T, HgError> {
+        self.with_context(|| IoErrorContext::WritingFile(path.to_owned()))
+    }
+
     fn with_context(
         self,
         context: impl FnOnce() -> IoErrorContext,
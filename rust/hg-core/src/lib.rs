@@ -6,6 +6,7 @@
 
 mod ancestors;
 pub mod dagops;
+pub mod diff;
 pub mod errors;
 pub use ancestors::{AncestorsIterator, LazyAncestors, MissingAncestors};
 mod dirstate;
@@ -26,13 +27,19 @@ pub use dirstate::{
 pub mod copy_tracing;
 mod filepatterns;
 pub mod matchers;
+pub mod namespaces;
+pub mod obsolete;
 pub mod repo;
 pub mod revlog;
 pub use revlog::*;
 pub mod config;
 pub mod logging;
 pub mod operations;
+pub mod phases;
 pub mod revset;
+pub mod store;
+pub mod templater;
+pub mod transaction;
 pub mod utils;
 
 use crate::utils::hg_path::{HgPathBuf, HgPathError};
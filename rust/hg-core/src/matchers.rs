@@ -298,6 +298,182 @@ impl<'a> Matcher for IncludeMatcher<'a> {
     }
 }
 
+/// Matches the files matched by both of two matchers, like `-I` patterns
+/// restricting file arguments.
+/// ```
+/// use hg::{
+///     matchers::{FileMatcher, IncludeMatcher, IntersectionMatcher, Matcher},
+///     utils::hg_path::{HgPath, HgPathBuf},
+///     IgnorePattern, PatternSyntax,
+/// };
+/// use std::path::Path;
+///
+/// let files = [HgPathBuf::from_bytes(b"a.c"), HgPathBuf::from_bytes(b"b.h")];
+/// let m1 = FileMatcher::new(&files).unwrap();
+/// let patterns =
+///     vec![IgnorePattern::new(PatternSyntax::Glob, b"*.c", Path::new(""))];
+/// let (m2, _) = IncludeMatcher::new(patterns, "").unwrap();
+/// let matcher = IntersectionMatcher::new(&m1, &m2);
+///
+/// assert_eq!(matcher.matches(HgPath::new(b"a.c")), true);
+/// assert_eq!(matcher.matches(HgPath::new(b"b.h")), false);
+/// assert_eq!(matcher.matches(HgPath::new(b"c.c")), false);
+/// ```
+pub struct IntersectionMatcher<'a> {
+    m1: &'a (dyn Matcher + Sync),
+    m2: &'a (dyn Matcher + Sync),
+    /// The explicit files of the exact matcher that the other one matches
+    files: Option<HashSet<&'a HgPath>>,
+}
+
+impl<'a> IntersectionMatcher<'a> {
+    pub fn new(
+        m1: &'a (dyn Matcher + Sync),
+        m2: &'a (dyn Matcher + Sync),
+    ) -> Self {
+        let (exact, other) = if m1.is_exact() { (m1, m2) } else { (m2, m1) };
+        // When neither matcher is exact, their files may be directories that
+        // cannot be intersected.
+        let files = if exact.is_exact() {
+            exact.file_set().map(|files| {
+                files
+                    .iter()
+                    .filter(|file| other.matches(file))
+                    .copied()
+                    .collect()
+            })
+        } else {
+            None
+        };
+        Self { m1, m2, files }
+    }
+}
+
+impl<'a> Matcher for IntersectionMatcher<'a> {
+    fn file_set(&self) -> Option<&HashSet<&HgPath>> {
+        self.files.as_ref()
+    }
+
+    fn exact_match(&self, filename: &HgPath) -> bool {
+        match &self.files {
+            Some(files) => files.contains(filename),
+            None => false,
+        }
+    }
+
+    fn matches(&self, filename: &HgPath) -> bool {
+        self.m1.matches(filename) && self.m2.matches(filename)
+    }
+
+    fn visit_children_set(&self, directory: &HgPath) -> VisitChildrenSet<'_> {
+        let m1_set = self.m1.visit_children_set(directory);
+        if m1_set == VisitChildrenSet::Empty {
+            return VisitChildrenSet::Empty;
+        }
+        let m2_set = self.m2.visit_children_set(directory);
+        match (m1_set, m2_set) {
+            (_, VisitChildrenSet::Empty) => VisitChildrenSet::Empty,
+            (VisitChildrenSet::Recursive, m2_set) => m2_set,
+            (m1_set, VisitChildrenSet::Recursive) => m1_set,
+            (VisitChildrenSet::This, _) | (_, VisitChildrenSet::This) => {
+                VisitChildrenSet::This
+            }
+            (VisitChildrenSet::Set(m1_set), VisitChildrenSet::Set(m2_set)) => {
+                let set: HashSet<_> =
+                    m1_set.intersection(&m2_set).copied().collect();
+                if set.is_empty() {
+                    VisitChildrenSet::Empty
+                } else {
+                    VisitChildrenSet::Set(set)
+                }
+            }
+            (VisitChildrenSet::Empty, _) => unreachable!(),
+        }
+    }
+
+    fn matches_everything(&self) -> bool {
+        self.m1.matches_everything() && self.m2.matches_everything()
+    }
+
+    fn is_exact(&self) -> bool {
+        self.m1.is_exact() || self.m2.is_exact()
+    }
+}
+
+/// Matches the files matched by a matcher but not by another, like `-X`
+/// patterns excluding files.
+/// ```
+/// use hg::{
+///     matchers::{AlwaysMatcher, DifferenceMatcher, IncludeMatcher, Matcher},
+///     utils::hg_path::HgPath,
+///     IgnorePattern, PatternSyntax,
+/// };
+/// use std::path::Path;
+///
+/// let patterns =
+///     vec![IgnorePattern::new(PatternSyntax::Path, b"dir", Path::new(""))];
+/// let (excluded, _) = IncludeMatcher::new(patterns, "").unwrap();
+/// let matcher = DifferenceMatcher::new(&AlwaysMatcher, &excluded);
+///
+/// assert_eq!(matcher.matches(HgPath::new(b"file")), true);
+/// assert_eq!(matcher.matches(HgPath::new(b"dir/file")), false);
+/// ```
+pub struct DifferenceMatcher<'a> {
+    base: &'a (dyn Matcher + Sync),
+    excluded: &'a (dyn Matcher + Sync),
+}
+
+impl<'a> DifferenceMatcher<'a> {
+    pub fn new(
+        base: &'a (dyn Matcher + Sync),
+        excluded: &'a (dyn Matcher + Sync),
+    ) -> Self {
+        Self { base, excluded }
+    }
+}
+
+impl<'a> Matcher for DifferenceMatcher<'a> {
+    fn file_set(&self) -> Option<&HashSet<&HgPath>> {
+        self.base.file_set()
+    }
+
+    fn exact_match(&self, filename: &HgPath) -> bool {
+        self.base.exact_match(filename)
+    }
+
+    fn matches(&self, filename: &HgPath) -> bool {
+        self.base.matches(filename) && !self.excluded.matches(filename)
+    }
+
+    fn visit_children_set(&self, directory: &HgPath) -> VisitChildrenSet<'_> {
+        let excluded_set = self.excluded.visit_children_set(directory);
+        match excluded_set {
+            VisitChildrenSet::Recursive => return VisitChildrenSet::Empty,
+            VisitChildrenSet::Empty => {
+                return self.base.visit_children_set(directory)
+            }
+            _ => {}
+        }
+        // Subdirectories may still have files that are not excluded. The
+        // sets of the excluded matcher are not used: for `path:dir/subdir`
+        // minus `rootfilesin:dir`, both visit `dir` from the root.
+        match self.base.visit_children_set(directory) {
+            VisitChildrenSet::Recursive | VisitChildrenSet::This => {
+                VisitChildrenSet::This
+            }
+            base_set => base_set,
+        }
+    }
+
+    fn matches_everything(&self) -> bool {
+        false
+    }
+
+    fn is_exact(&self) -> bool {
+        self.base.is_exact()
+    }
+}
+
 /// Returns a function that matches an `HgPath` against the given regex
 /// pattern.
 ///
@@ -920,4 +1096,73 @@ mod tests {
             VisitChildrenSet::This
         );
     }
+
+    #[test]
+    fn test_intersectionmatcher_visit_children_set() {
+        let (m1, _) = IncludeMatcher::new(
+            vec![IgnorePattern::new(
+                PatternSyntax::Path,
+                b"dir",
+                Path::new(""),
+            )],
+            "",
+        )
+        .unwrap();
+        let (m2, _) = IncludeMatcher::new(
+            vec![IgnorePattern::new(
+                PatternSyntax::Glob,
+                b"dir/subdir/*",
+                Path::new(""),
+            )],
+            "",
+        )
+        .unwrap();
+        let matcher = IntersectionMatcher::new(&m1, &m2);
+
+        let mut set = HashSet::new();
+        set.insert(HgPath::new(b"dir"));
+        assert_eq!(
+            matcher.visit_children_set(HgPath::new(b"")),
+            VisitChildrenSet::Set(set)
+        );
+        assert_eq!(
+            matcher.visit_children_set(HgPath::new(b"dir/subdir")),
+            VisitChildrenSet::This
+        );
+        assert_eq!(
+            matcher.visit_children_set(HgPath::new(b"folder")),
+            VisitChildrenSet::Empty
+        );
+        assert!(matcher.matches(HgPath::new(b"dir/subdir/x")));
+        assert!(!matcher.matches(HgPath::new(b"dir/x")));
+    }
+
+    #[test]
+    fn test_differencematcher_visit_children_set() {
+        let (excluded, _) = IncludeMatcher::new(
+            vec![IgnorePattern::new(
+                PatternSyntax::Path,
+                b"dir/subdir",
+                Path::new(""),
+            )],
+            "",
+        )
+        .unwrap();
+        let matcher = DifferenceMatcher::new(&AlwaysMatcher, &excluded);
+
+        assert_eq!(
+            matcher.visit_children_set(HgPath::new(b"")),
+            VisitChildrenSet::This
+        );
+        assert_eq!(
+            matcher.visit_children_set(HgPath::new(b"dir/subdir")),
+            VisitChildrenSet::Empty
+        );
+        assert_eq!(
+            matcher.visit_children_set(HgPath::new(b"folder")),
+            VisitChildrenSet::Recursive
+        );
+        assert!(matcher.matches(HgPath::new(b"dir/x")));
+        assert!(!matcher.matches(HgPath::new(b"dir/subdir/x")));
+    }
 }
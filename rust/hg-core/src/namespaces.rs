@@ -0,0 +1,137 @@
+// namespaces.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Symbolic names for changesets, like `mercurial/namespaces.py`
+//!
+//! A namespace maps names to changesets. When looking up a name, namespaces
+//! are tried in precedence order: bookmarks, then tags, then branches.
+
+mod bookmarks;
+mod branchmap;
+mod tags;
+
+pub use bookmarks::Bookmarks;
+pub use branchmap::{BranchHead, BranchMap};
+pub use tags::{TagType, Tags};
+
+use crate::errors::HgError;
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The namespaces of a repository, each loaded when first used
+pub struct Namespaces<'a> {
+    repo: &'a Repo,
+    changelog: &'a Changelog,
+    bookmarks: Lazy<Bookmarks>,
+    tags: Lazy<Tags>,
+    branchmap: Lazy<BranchMap>,
+}
+
+impl<'a> Namespaces<'a> {
+    pub fn new(repo: &'a Repo, changelog: &'a Changelog) -> Self {
+        Self {
+            repo,
+            changelog,
+            bookmarks: Lazy::new(),
+            tags: Lazy::new(),
+            branchmap: Lazy::new(),
+        }
+    }
+
+    pub fn bookmarks(&self) -> Result<Rc<Bookmarks>, HgError> {
+        self.bookmarks
+            .get_or_try_init(|| Bookmarks::read(self.repo, self.changelog))
+    }
+
+    pub fn tags(&self) -> Result<Rc<Tags>, HgError> {
+        self.tags
+            .get_or_try_init(|| Tags::read(self.repo, self.changelog))
+    }
+
+    pub fn branchmap(&self) -> Result<Rc<BranchMap>, HgError> {
+        self.branchmap
+            .get_or_try_init(|| BranchMap::read(self.repo, self.changelog))
+    }
+
+    /// Return the changeset that `name` refers to in the first namespace
+    /// that has it, like `namespaces.singlenode` in Python.
+    ///
+    /// For a branch name, this is the tip-most open head of that branch.
+    pub fn lookup(
+        &self,
+        name: &[u8],
+    ) -> Result<Option<Revision>, RevlogError> {
+        if let Some(node) = self.bookmarks()?.get(name) {
+            return self.node_rev(node).map(Some);
+        }
+        if let Some(node) = self.tags()?.get(name) {
+            return self.node_rev(node).map(Some);
+        }
+        Ok(self.branchmap()?.branch_tip(name))
+    }
+
+    /// Return `name` if it is a branch, otherwise the branch of the
+    /// changeset it refers to, like `localrepository.lookupbranch` in Python.
+    ///
+    /// `name` is a single symbol rather than a revset.
+    pub fn lookup_branch(&self, name: &str) -> Result<Vec<u8>, RevlogError> {
+        if self.branchmap()?.branch_heads(name.as_bytes()).is_some() {
+            return Ok(name.as_bytes().to_vec());
+        }
+        let rev = match crate::revset::resolve_symbol(
+            name,
+            self.repo,
+            self.changelog,
+            self,
+        ) {
+            Err(RevlogError::InvalidRevision) => {
+                return Err(HgError::abort(format!(
+                    "abort: unknown revision '{}'",
+                    name
+                ))
+                .into())
+            }
+            result => result?,
+        };
+        Ok(self.changelog.get_rev(rev)?.branchinfo()?.0)
+    }
+
+    fn node_rev(&self, node: Node) -> Result<Revision, RevlogError> {
+        match self.changelog.revlog.get_node_rev(node.into()) {
+            Err(RevlogError::InvalidRevision) => Err(HgError::corrupted(
+                format!("name points to unknown changeset {:x}", node),
+            )
+            .into()),
+            result => result,
+        }
+    }
+}
+
+/// A value computed on first access
+struct Lazy<T>(RefCell<Option<Rc<T>>>);
+
+impl<T> Lazy<T> {
+    fn new() -> Self {
+        Self(RefCell::new(None))
+    }
+
+    fn get_or_try_init(
+        &self,
+        init: impl FnOnce() -> Result<T, HgError>,
+    ) -> Result<Rc<T>, HgError> {
+        if let Some(value) = &*self.0.borrow() {
+            return Ok(Rc::clone(value));
+        }
+        let value = Rc::new(init()?);
+        *self.0.borrow_mut() = Some(Rc::clone(&value));
+        Ok(value)
+    }
+}
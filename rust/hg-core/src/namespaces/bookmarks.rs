@@ -0,0 +1,72 @@
+// bookmarks.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Reading `.hg/bookmarks`, like `bmstore` in `mercurial/bookmarks.py`
+
+use crate::errors::{HgError, HgResultExt};
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::Node;
+use crate::utils::SliceExt;
+
+/// Bookmarks of a repository, in file order
+pub struct Bookmarks {
+    entries: Vec<(Vec<u8>, Node)>,
+}
+
+impl Bookmarks {
+    pub fn read(repo: &Repo, changelog: &Changelog) -> Result<Self, HgError> {
+        let shared = repo.hg_vfs().read("shared").io_not_found_as_none()?;
+        if let Some(shared) = shared {
+            if shared
+                .split(|b| *b == b'\n')
+                .any(|line| line == b"bookmarks")
+            {
+                return Err(HgError::unsupported("shared bookmarks"));
+            }
+        }
+        let mut entries = Vec::new();
+        let bytes = repo.hg_vfs().read("bookmarks").io_not_found_as_none()?;
+        for line in bytes.as_deref().unwrap_or(b"").split(|b| *b == b'\n') {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            // Like in Python, ignore malformed lines and bookmarks that
+            // point to unknown changesets.
+            if let Some((hex, name)) = line.split_2(b' ') {
+                if let Ok(node) = Node::from_hex(hex) {
+                    if changelog.revlog.get_node_rev(node.into()).is_ok() {
+                        entries.push((name.to_vec(), node))
+                    }
+                }
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn get(&self, name: &[u8]) -> Option<Node> {
+        self.entries
+            .iter()
+            .find(|(bookmark, _)| bookmark == name)
+            .map(|&(_, node)| node)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], Node)> {
+        self.entries
+            .iter()
+            .map(|(name, node)| (name.as_slice(), *node))
+    }
+
+    /// The bookmarks pointing to `node`, in file order
+    pub fn names_for(&self, node: &Node) -> Vec<&[u8]> {
+        self.iter()
+            .filter(|(_, bookmark_node)| bookmark_node == node)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
@@ -0,0 +1,189 @@
+// branchmap.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Heads of named branches, like `mercurial/branchmap.py`
+//!
+//! The most up-to-date `.hg/cache/branch2-*` cache file is used as a starting
+//! point, and updated in memory with the revisions added since it was
+//! written. The cache files are never written to.
+
+use crate::errors::{HgError, HgResultExt};
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision, NULL_REVISION};
+use crate::utils::SliceExt;
+use crate::{AncestorsIterator, Graph, GraphError};
+use std::collections::BTreeMap;
+
+/// Cache files of filtered views that only differ from the `visible` view
+/// when their key has a filtered hash. Such caches are not used.
+const CACHE_FILES: &[&str] = &[
+    "cache/branch2-visible",
+    "cache/branch2-served",
+    "cache/branch2-immutable",
+    "cache/branch2-base",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BranchHead {
+    pub rev: Revision,
+    /// Whether this head has the `close` extra
+    pub closed: bool,
+}
+
+/// Branch heads of all named branches
+pub struct BranchMap {
+    /// Heads of each branch, in ascending revision order
+    entries: BTreeMap<Vec<u8>, Vec<BranchHead>>,
+}
+
+impl BranchMap {
+    pub fn read(repo: &Repo, changelog: &Changelog) -> Result<Self, HgError> {
+        let mut best: Option<(Revision, Self)> = None;
+        for file in CACHE_FILES {
+            if let Some((tip_rev, map)) = read_cache(repo, changelog, file)? {
+                match &best {
+                    Some((best_rev, _)) if *best_rev >= tip_rev => {}
+                    _ => best = Some((tip_rev, map)),
+                }
+            }
+        }
+        let (cache_tip_rev, mut map) = best.unwrap_or((
+            NULL_REVISION,
+            Self {
+                entries: BTreeMap::new(),
+            },
+        ));
+        let len = changelog.revlog.len() as Revision;
+        map.update(changelog, cache_tip_rev + 1..len)
+            .map_err(|error| match error {
+                RevlogError::Other(error) => error,
+                _ => HgError::corrupted("invalid changelog parent"),
+            })?;
+        Ok(map)
+    }
+
+    /// Add the given revisions, in ascending order, to the branch heads.
+    ///
+    /// Like `branchcache.update` in Python, heads are computed in revision
+    /// order so parents are processed before their children: a parent on the
+    /// same branch is no longer a head, and heads on that branch that are
+    /// ancestors of parents on other branches are removed.
+    fn update(
+        &mut self,
+        changelog: &Changelog,
+        revs: impl Iterator<Item = Revision>,
+    ) -> Result<(), RevlogError> {
+        for rev in revs {
+            let (branch, closed) = changelog.get_rev(rev)?.branchinfo()?;
+            let heads = self.entries.entry(branch).or_default();
+            let mut uncertain = Vec::new();
+            for &parent in changelog.parents(rev)?.iter() {
+                if parent == NULL_REVISION {
+                    continue;
+                }
+                match heads.iter().position(|head| head.rev == parent) {
+                    Some(position) => {
+                        heads.remove(position);
+                    }
+                    None => uncertain.push(parent),
+                }
+            }
+            if let (Some(first), false) = (heads.first(), uncertain.is_empty())
+            {
+                let ancestors = AncestorsIterator::new(
+                    changelog, uncertain, first.rev, true,
+                )?
+                .collect::<Result<Vec<_>, GraphError>>()?;
+                heads.retain(|head| !ancestors.contains(&head.rev))
+            }
+            heads.push(BranchHead { rev, closed });
+        }
+        Ok(())
+    }
+
+    /// The tipmost open head of `branch`, otherwise its tipmost closed head
+    pub fn branch_tip(&self, branch: &[u8]) -> Option<Revision> {
+        let heads = self.entries.get(branch)?;
+        heads
+            .iter()
+            .rev()
+            .find(|head| !head.closed)
+            .or_else(|| heads.last())
+            .map(|head| head.rev)
+    }
+
+    /// Heads of `branch` in ascending revision order, if it exists
+    pub fn branch_heads(&self, branch: &[u8]) -> Option<&[BranchHead]> {
+        self.entries.get(branch).map(|heads| heads.as_slice())
+    }
+
+    /// All branches and their heads, sorted by branch name
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[BranchHead])> {
+        self.entries
+            .iter()
+            .map(|(branch, heads)| (branch.as_slice(), heads.as_slice()))
+    }
+}
+
+/// Read a branch cache file if it is valid for the current changelog, and
+/// return it with the revision it was last updated for.
+///
+/// The format is a `<tip node> <tip rev> [filtered hash]` line, followed by
+/// one `<head node> <o|c> <branch name>` line for each branch head.
+fn read_cache(
+    repo: &Repo,
+    changelog: &Changelog,
+    file: &str,
+) -> Result<Option<(Revision, BranchMap)>, HgError> {
+    let bytes = match repo.hg_vfs().read(file).io_not_found_as_none()? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let mut lines = bytes.split(|b| *b == b'\n');
+    let mut key = lines.next().unwrap_or(b"").split(|b| *b == b' ');
+    let tip_node = key.next().and_then(|node| Node::from_hex(node).ok());
+    let tip_rev = key
+        .next()
+        .and_then(|rev| std::str::from_utf8(rev).ok())
+        .and_then(|rev| rev.parse::<Revision>().ok());
+    let (tip_node, tip_rev) = match (tip_node, tip_rev, key.next()) {
+        (Some(node), Some(rev), None) => (node, rev),
+        _ => return Ok(None),
+    };
+    if changelog.node_from_rev(tip_rev) != Some(&tip_node) {
+        return Ok(None);
+    }
+
+    let mut entries: BTreeMap<_, Vec<BranchHead>> = BTreeMap::new();
+    for line in lines {
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.splitn(3, |b| *b == b' ');
+        let node = fields.next().and_then(|node| Node::from_hex(node).ok());
+        let rev = node
+            .and_then(|node| changelog.revlog.get_node_rev(node.into()).ok());
+        let closed = match fields.next() {
+            Some(b"o") => false,
+            Some(b"c") => true,
+            _ => return Ok(None),
+        };
+        match (rev, fields.next()) {
+            (Some(rev), Some(branch)) => entries
+                .entry(branch.trim().to_vec())
+                .or_default()
+                .push(BranchHead { rev, closed }),
+            _ => return Ok(None),
+        }
+    }
+    for heads in entries.values_mut() {
+        heads.sort_by_key(|head: &BranchHead| head.rev)
+    }
+    Ok(Some((tip_rev, BranchMap { entries })))
+}
@@ -0,0 +1,352 @@
+// tags.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Finding tags, like `mercurial/tags.py`
+//!
+//! Global tags are read from the `.hgtags` file of every head, unless the
+//! `.hg/cache/tags2-visible` cache is up to date. Local tags come from
+//! `.hg/localtags`.
+
+use crate::errors::{HgError, HgResultExt};
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::filelog::Filelog;
+use crate::revlog::manifest::Manifest;
+use crate::revlog::node::{NODE_BYTES_LENGTH, NULL_NODE};
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision};
+use crate::utils::hg_path::HgPath;
+use crate::utils::SliceExt;
+use crate::Graph;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TagType {
+    Global,
+    Local,
+}
+
+/// Tags of a repository, including `tip`
+pub struct Tags {
+    tags: HashMap<Vec<u8>, (Node, TagType)>,
+}
+
+/// The node of a tag, and the nodes it was previously associated with
+type TagHistory = (Node, Vec<Node>);
+
+type TagMap = HashMap<Vec<u8>, TagHistory>;
+
+const CACHE_FILE: &str = "cache/tags2-visible";
+const FNODES_CACHE_FILE: &str = "cache/hgtagsfnodes1";
+
+impl Tags {
+    pub fn read(repo: &Repo, changelog: &Changelog) -> Result<Self, HgError> {
+        let tip_rev = changelog.revlog.len() as Revision - 1;
+        let tip_node = changelog
+            .node_from_rev(tip_rev)
+            .copied()
+            .unwrap_or(NULL_NODE);
+        let global_tags = match read_cache(repo, tip_rev, &tip_node)? {
+            Some(tags) => tags,
+            None => {
+                find_global_tags(repo, changelog).map_err(
+                    |error| match error {
+                        RevlogError::Other(error) => error,
+                        _ => HgError::corrupted("invalid .hgtags file node"),
+                    },
+                )?
+            }
+        };
+        let mut types = HashMap::new();
+        for name in global_tags.keys() {
+            types.insert(name.clone(), TagType::Global);
+        }
+        let mut all_tags = global_tags;
+        if let Some(bytes) =
+            repo.hg_vfs().read("localtags").io_not_found_as_none()?
+        {
+            let mut local_tags = parse_tags(&bytes);
+            local_tags.retain(|_, (node, _)| {
+                changelog.revlog.get_node_rev((*node).into()).is_ok()
+            });
+            update_tags(
+                local_tags,
+                &mut all_tags,
+                Some((&mut types, TagType::Local)),
+            );
+        }
+
+        // Like `localrepo.tags()`, ignore deleted tags and tags pointing to
+        // unknown changesets
+        let mut tags: HashMap<_, _> = all_tags
+            .into_iter()
+            .filter(|(_, (node, _))| {
+                *node != NULL_NODE
+                    && changelog.revlog.get_node_rev((*node).into()).is_ok()
+            })
+            .map(|(name, (node, _))| {
+                let tag_type = types[&name];
+                (name, (node, tag_type))
+            })
+            .collect();
+        tags.insert(b"tip".to_vec(), (tip_node, TagType::Global));
+        Ok(Self { tags })
+    }
+
+    pub fn get(&self, name: &[u8]) -> Option<Node> {
+        self.tags.get(name).map(|&(node, _)| node)
+    }
+
+    pub fn tag_type(&self, name: &[u8]) -> Option<TagType> {
+        self.tags.get(name).map(|&(_, tag_type)| tag_type)
+    }
+
+    /// All tags, in arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], Node)> {
+        self.tags
+            .iter()
+            .map(|(name, &(node, _))| (name.as_slice(), node))
+    }
+
+    /// The tags pointing to `node`, sorted by name
+    pub fn names_for(&self, node: &Node) -> Vec<&[u8]> {
+        let mut names: Vec<_> = self
+            .iter()
+            .filter(|(_, tag_node)| tag_node == node)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+/// Return the global tags stored in the cache if it is up to date.
+///
+/// The first line of the cache is `<tip rev> <tip node> [filtered hash]`,
+/// followed by lines in the `.hgtags` format. A filtered hash means that
+/// some revisions were hidden when the cache was written, so it is not used.
+fn read_cache(
+    repo: &Repo,
+    tip_rev: Revision,
+    tip_node: &Node,
+) -> Result<Option<TagMap>, HgError> {
+    let bytes = match repo.hg_vfs().read(CACHE_FILE).io_not_found_as_none()? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let mut lines = bytes.splitn(2, |b| *b == b'\n');
+    let key = lines.next().unwrap_or(b"");
+    let mut key = key.split(|b| *b == b' ');
+    let rev = key.next().and_then(|rev| std::str::from_utf8(rev).ok());
+    let node = key.next().and_then(|node| Node::from_hex(node).ok());
+    let is_valid = key.next().is_none()
+        && rev.and_then(|rev| rev.parse().ok()) == Some(tip_rev)
+        && node.as_ref() == Some(tip_node);
+    if !is_valid {
+        return Ok(None);
+    }
+    Ok(Some(parse_tags(lines.next().unwrap_or(b""))))
+}
+
+/// Read tags from the `.hgtags` file of each head, from oldest to newest,
+/// like `_tagsfromfnodes` in Python.
+fn find_global_tags(
+    repo: &Repo,
+    changelog: &Changelog,
+) -> Result<TagMap, RevlogError> {
+    let mut file_nodes = Vec::new();
+    let fnodes_cache = repo
+        .hg_vfs()
+        .read(FNODES_CACHE_FILE)
+        .io_not_found_as_none()?;
+    let mut manifest = None;
+    for head in heads(changelog)? {
+        let cached =
+            cached_file_node(fnodes_cache.as_deref(), changelog, head);
+        let file_node = match cached {
+            Some(file_node) => file_node,
+            None => {
+                if manifest.is_none() {
+                    manifest = Some(Manifest::open(repo)?)
+                }
+                let manifest = manifest.as_ref().expect("opened above");
+                let manifest_node =
+                    changelog.get_rev(head)?.manifest_node()?;
+                let entry = manifest.get_node(manifest_node.into())?;
+                let file_node = entry
+                    .find_file(HgPath::new(b".hgtags"))?
+                    .map(|file| file.node_id())
+                    .transpose()?;
+                file_node.unwrap_or(NULL_NODE)
+            }
+        };
+        if file_node != NULL_NODE && !file_nodes.contains(&file_node) {
+            file_nodes.push(file_node)
+        }
+    }
+
+    let mut all_tags = HashMap::new();
+    if file_nodes.is_empty() {
+        return Ok(all_tags);
+    }
+    let filelog = Filelog::open(repo, HgPath::new(b".hgtags"))?;
+    for file_node in file_nodes {
+        let entry = filelog.get_node(file_node.into())?;
+        update_tags(parse_tags(entry.data()?), &mut all_tags, None)
+    }
+    Ok(all_tags)
+}
+
+/// Heads of the changelog, in ascending order
+fn heads(changelog: &Changelog) -> Result<Vec<Revision>, RevlogError> {
+    let len = changelog.revlog.len();
+    let mut is_head = vec![true; len];
+    for rev in 0..len as Revision {
+        for parent in changelog.parents(rev)?.iter() {
+            if *parent >= 0 {
+                is_head[*parent as usize] = false
+            }
+        }
+    }
+    Ok((0..len as Revision)
+        .filter(|&rev| is_head[rev as usize])
+        .collect())
+}
+
+/// Look up the `.hgtags` file node of a changeset in the
+/// `.hg/cache/hgtagsfnodes1` cache.
+///
+/// Each record is made of the first 4 bytes of the changeset node, followed
+/// by the file node (null when there is no `.hgtags` file). Missing records
+/// are filled with `0xff` bytes.
+fn cached_file_node(
+    cache: Option<&[u8]>,
+    changelog: &Changelog,
+    rev: Revision,
+) -> Option<Node> {
+    const PREFIX_LENGTH: usize = 4;
+    const RECORD_SIZE: usize = PREFIX_LENGTH + NODE_BYTES_LENGTH;
+    let start = rev as usize * RECORD_SIZE;
+    let record = cache?.get(start..start + RECORD_SIZE)?;
+    let node = changelog.node_from_rev(rev)?;
+    if record[..PREFIX_LENGTH] != node.as_bytes()[..PREFIX_LENGTH] {
+        return None;
+    }
+    let file_node = &record[PREFIX_LENGTH..];
+    if file_node.iter().all(|b| *b == 0xff) {
+        return None;
+    }
+    Node::try_from(file_node).ok()
+}
+
+/// Parse tag definitions in the `.hgtags` format, like `_readtags` in Python.
+///
+/// Malformed lines are ignored.
+fn parse_tags(bytes: &[u8]) -> TagMap {
+    let mut tags: TagMap = HashMap::new();
+    for line in bytes.split(|b| *b == b'\n') {
+        let line = line.trim_end_newlines();
+        if let Some((hex, name)) = line.split_2(b' ') {
+            if let Ok(node) = Node::from_hex(hex) {
+                match tags.get_mut(name.trim()) {
+                    Some((last, history)) => {
+                        history.push(*last);
+                        *last = node;
+                    }
+                    None => {
+                        tags.insert(name.trim().to_vec(), (node, Vec::new()));
+                    }
+                }
+            }
+        }
+    }
+    tags
+}
+
+/// Merge the tags read from one file into `all_tags`, like `_updatetags` in
+/// Python.
+///
+/// When both define a tag, the one from `all_tags` is kept if it supersedes
+/// the new one, otherwise the new one wins because it is tip-most.
+fn update_tags(
+    file_tags: TagMap,
+    all_tags: &mut TagMap,
+    mut types: Option<(&mut HashMap<Vec<u8>, TagType>, TagType)>,
+) {
+    for (name, (mut node, mut history)) in file_tags {
+        let mut is_new = true;
+        if let Some((other_node, other_history)) = all_tags.get(&name) {
+            if *other_node != node
+                && other_history.contains(&node)
+                && (!history.contains(other_node)
+                    || other_history.len() > history.len())
+            {
+                node = *other_node;
+                is_new = false;
+            }
+            for previous in other_history {
+                if !history.contains(previous) {
+                    history.push(*previous)
+                }
+            }
+        }
+        if let (true, Some((types, tag_type))) = (is_new, &mut types) {
+            types.insert(name.clone(), *tag_type);
+        }
+        all_tags.insert(name, (node, history));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(hex_digit: char) -> Node {
+        Node::from_hex(hex_digit.to_string().repeat(40)).unwrap()
+    }
+
+    fn tags_file(lines: &[(char, &str)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (hex_digit, name) in lines {
+            bytes.extend(hex_digit.to_string().repeat(40).bytes());
+            bytes.push(b' ');
+            bytes.extend(name.bytes());
+            bytes.push(b'\n');
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        let bytes = tags_file(&[('a', "v1"), ('b', "v2 "), ('c', "v1")]);
+        let tags = parse_tags(&[&bytes[..], b"garbage\n\nxyz v3\n"].concat());
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[&b"v1"[..]], (node('c'), vec![node('a')]));
+        assert_eq!(tags[&b"v2"[..]], (node('b'), vec![]));
+    }
+
+    #[test]
+    fn test_update_tags() {
+        // A newer head moving a tag wins
+        let mut all_tags = parse_tags(&tags_file(&[('a', "t")]));
+        update_tags(
+            parse_tags(&tags_file(&[('a', "t"), ('b', "t")])),
+            &mut all_tags,
+            None,
+        );
+        assert_eq!(all_tags[&b"t"[..]], (node('b'), vec![node('a')]));
+
+        // An older definition does not override one that supersedes it
+        update_tags(
+            parse_tags(&tags_file(&[('a', "t")])),
+            &mut all_tags,
+            None,
+        );
+        assert_eq!(all_tags[&b"t"[..]].0, node('b'));
+    }
+}
@@ -0,0 +1,714 @@
+//! Reading obsolescence markers, like `obsstore` in `mercurial/obsolete.py`
+//!
+//! The `obsstore` file of the store starts with a format version byte,
+//! followed by markers. Each marker records that a changeset, its
+//! predecessor, was rewritten into zero or more successors. Draft and
+//! secret changesets that have successors, or were pruned, are obsolete.
+//! Changesets that depend on them, or compete with them, are unstable.
+
+use crate::errors::{HgError, HgResultExt};
+use crate::phases::{Phase, Phases};
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::node::{NODE_BYTES_LENGTH, NULL_NODE};
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision};
+use crate::Graph;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+
+/// Markers with this flag, which fix phase-divergence, do not make their
+/// successors phase-divergent
+pub const BUMPED_FIX: u16 = 1;
+/// Node IDs of markers with this flag are 32 bytes long
+pub const USING_SHA_256: u16 = 2;
+
+/// The marker of a rewritten changeset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub predecessor: Node,
+    /// Empty when the predecessor was pruned
+    pub successors: Vec<Node>,
+    pub flags: u16,
+    /// Sorted by key in version 0, in the order of the file in version 1
+    pub metadata: Vec<(Vec<u8>, Vec<u8>)>,
+    /// When the marker was created, in seconds since the Unix epoch
+    pub timestamp: f64,
+    /// In seconds *west* of UTC, like `ChangesetDate::timezone_offset`
+    pub timezone_offset: i32,
+    /// The parents of the predecessor, when recorded
+    pub parents: Option<Vec<Node>>,
+}
+
+/// The obsolescence markers of a repository
+pub struct ObsStore {
+    markers: Vec<Marker>,
+    /// Indices of the markers of each predecessor
+    by_predecessor: HashMap<Node, Vec<usize>>,
+    /// Indices of the markers that list each successor
+    by_successor: HashMap<Node, Vec<usize>>,
+}
+
+impl ObsStore {
+    /// Read the `obsstore` file, empty if there is none
+    pub fn read(repo: &Repo) -> Result<Self, HgError> {
+        let bytes =
+            repo.store_vfs().read("obsstore").io_not_found_as_none()?;
+        Self::parse(bytes.as_deref().unwrap_or(b""))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, HgError> {
+        let markers = match bytes.split_first() {
+            None => Vec::new(),
+            Some((0, markers)) => parse_markers(markers, parse_v0_marker)?,
+            Some((1, markers)) => parse_markers(markers, parse_v1_marker)?,
+            Some((version, _)) => {
+                return Err(HgError::abort(format!(
+                    "abort: parsing obsolete marker: unknown version {}",
+                    version
+                )))
+            }
+        };
+        if markers.iter().any(|m| m.successors.contains(&NULL_NODE)) {
+            return Err(HgError::abort(
+                "abort: bad obsolescence marker detected: \
+                 invalid successors nullid",
+            ));
+        }
+        let mut by_predecessor = HashMap::new();
+        let mut by_successor = HashMap::new();
+        for (index, marker) in markers.iter().enumerate() {
+            by_predecessor
+                .entry(marker.predecessor)
+                .or_insert_with(Vec::new)
+                .push(index);
+            for &successor in &marker.successors {
+                by_successor
+                    .entry(successor)
+                    .or_insert_with(Vec::new)
+                    .push(index);
+            }
+        }
+        Ok(Self {
+            markers,
+            by_predecessor,
+            by_successor,
+        })
+    }
+
+    /// All the markers, in the order of the file
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    /// The markers that rewrote `node`, like `obsstore.successors[node]` in
+    /// Python
+    pub fn successor_markers(
+        &self,
+        node: &Node,
+    ) -> impl Iterator<Item = &Marker> {
+        self.indexed_markers(self.by_predecessor.get(node))
+    }
+
+    /// The markers that have `node` among their successors, like
+    /// `obsstore.predecessors[node]` in Python
+    pub fn predecessor_markers(
+        &self,
+        node: &Node,
+    ) -> impl Iterator<Item = &Marker> {
+        self.indexed_markers(self.by_successor.get(node))
+    }
+
+    fn indexed_markers<'a>(
+        &'a self,
+        indices: Option<&'a Vec<usize>>,
+    ) -> impl Iterator<Item = &'a Marker> {
+        indices
+            .into_iter()
+            .flatten()
+            .map(move |&index| &self.markers[index])
+    }
+
+    /// Whether `node` was rewritten or pruned
+    pub fn has_successor_markers(&self, node: &Node) -> bool {
+        self.by_predecessor.contains_key(node)
+    }
+
+    /// `nodes` and their predecessors, recursively, like
+    /// `obsutil.allpredecessors` in Python. Markers that have some of
+    /// `ignored_flags` are not followed.
+    pub fn all_predecessors(
+        &self,
+        nodes: &[Node],
+        ignored_flags: u16,
+    ) -> HashSet<Node> {
+        let mut seen: HashSet<Node> = nodes.iter().copied().collect();
+        let mut remaining: Vec<Node> = nodes.to_vec();
+        while let Some(current) = remaining.pop() {
+            for marker in self.predecessor_markers(&current) {
+                if marker.flags & ignored_flags == 0
+                    && seen.insert(marker.predecessor)
+                {
+                    remaining.push(marker.predecessor)
+                }
+            }
+        }
+        seen
+    }
+
+    /// The latest successors of `node`, like `obsutil.successorssets` in
+    /// Python.
+    ///
+    /// Each set is a group of changesets that together replace `node`.
+    /// There are several when `node` was rewritten in divergent ways, none
+    /// when it was pruned, and `node` itself when it is not obsolete.
+    /// Successors unknown to `changelog` count as pruned. `cache` keeps the
+    /// sets of the changesets visited on the way, for later calls.
+    pub fn successors_sets(
+        &self,
+        node: Node,
+        changelog: &Changelog,
+        cache: &mut HashMap<Node, Vec<Vec<Node>>>,
+    ) -> Result<Vec<Vec<Node>>, RevlogError> {
+        let mut to_proceed = vec![node];
+        let mut stacked: HashSet<Node> = to_proceed.iter().copied().collect();
+        while let Some(&current) = to_proceed.last() {
+            if cache.contains_key(&current) {
+                to_proceed.pop();
+                stacked.remove(&current);
+                continue;
+            }
+            if !self.has_successor_markers(&current) {
+                let sets = if is_known(changelog, &current)? {
+                    vec![vec![current]]
+                } else {
+                    Vec::new()
+                };
+                cache.insert(current, sets);
+                continue;
+            }
+            let mut markers: Vec<&Marker> =
+                self.successor_markers(&current).collect();
+            markers.sort_by(|m1, m2| compare_markers(m1, m2));
+
+            // Successors are processed first, depth-first. Those already on
+            // the stack form a cycle, and count as pruned.
+            let mut next = None;
+            'markers: for marker in &markers {
+                for successor in &marker.successors {
+                    if cache.contains_key(successor) {
+                        continue;
+                    }
+                    if stacked.contains(successor) {
+                        cache.insert(*successor, Vec::new());
+                    } else {
+                        next = Some(*successor);
+                        break 'markers;
+                    }
+                }
+            }
+            if let Some(successor) = next {
+                to_proceed.push(successor);
+                stacked.insert(successor);
+                continue;
+            }
+
+            // Each marker gives the product of the sets of its successors
+            let mut candidates = Vec::new();
+            for marker in &markers {
+                let mut product: Vec<Vec<Node>> = vec![Vec::new()];
+                for successor in &marker.successors {
+                    let mut result = Vec::new();
+                    for prefix in &product {
+                        for suffix in &cache[successor] {
+                            let mut set = prefix.clone();
+                            for part in suffix {
+                                if !set.contains(part) {
+                                    set.push(*part)
+                                }
+                            }
+                            result.push(set)
+                        }
+                    }
+                    if !result.is_empty() {
+                        product = result
+                    }
+                }
+                candidates
+                    .extend(product.into_iter().filter(|s| !s.is_empty()))
+            }
+            // Sets included in larger ones are dropped, the smallest sets
+            // come first
+            candidates.sort_by_key(|set| Reverse(set.len()));
+            let mut sets: Vec<Vec<Node>> = Vec::new();
+            for candidate in candidates {
+                if !sets
+                    .iter()
+                    .any(|set| candidate.iter().all(|n| set.contains(n)))
+                {
+                    sets.push(candidate)
+                }
+            }
+            sets.reverse();
+            cache.insert(current, sets);
+        }
+        Ok(cache[&node].clone())
+    }
+}
+
+/// The sets of obsolete and unstable changesets, like `obsolete.getrevs` in
+/// Python
+#[derive(Debug, Default, PartialEq)]
+pub struct ObsoleteSets {
+    /// Draft and secret changesets that were rewritten or pruned
+    pub obsolete: BTreeSet<Revision>,
+    /// Changesets that are not obsolete but have obsolete ancestors
+    pub orphan: BTreeSet<Revision>,
+    /// Changesets that rewrite public changesets
+    pub phase_divergent: BTreeSet<Revision>,
+    /// Changesets that compete with other successors of their predecessors
+    pub content_divergent: BTreeSet<Revision>,
+}
+
+impl ObsoleteSets {
+    pub fn compute(
+        obsstore: &ObsStore,
+        changelog: &Changelog,
+        phases: &Phases,
+    ) -> Result<Self, RevlogError> {
+        let mut sets = Self::default();
+        if obsstore.is_empty() {
+            return Ok(sets);
+        }
+        let node = |rev| {
+            changelog
+                .node_from_rev(rev)
+                .copied()
+                .ok_or(RevlogError::InvalidRevision)
+        };
+        let mut mutable = Vec::new();
+        for rev in 0..changelog.len() as Revision {
+            if phases.phase(rev)? != Phase::Public {
+                mutable.push(rev)
+            }
+        }
+        for &rev in &mutable {
+            if obsstore.has_successor_markers(&node(rev)?) {
+                sets.obsolete.insert(rev);
+            }
+        }
+        let mut successors_sets = HashMap::new();
+        // Parents come before their children
+        for &rev in &mutable {
+            if sets.obsolete.contains(&rev) {
+                continue;
+            }
+            let parents = changelog
+                .parents(rev)
+                .map_err(|_| HgError::corrupted("invalid changelog parent"))?;
+            if parents.iter().any(|parent| {
+                sets.obsolete.contains(parent) || sets.orphan.contains(parent)
+            }) {
+                sets.orphan.insert(rev);
+            }
+
+            let node = node(rev)?;
+            for predecessor in obsstore.all_predecessors(&[node], BUMPED_FIX) {
+                // Predecessors may be unknown locally
+                if let Some(predecessor) = rev_of(changelog, &predecessor)? {
+                    if phases.phase(predecessor)? == Phase::Public {
+                        sets.phase_divergent.insert(rev);
+                        break;
+                    }
+                }
+            }
+
+            let mut to_process: Vec<Node> = obsstore
+                .predecessor_markers(&node)
+                .map(|marker| marker.predecessor)
+                .collect();
+            let mut seen = HashSet::new();
+            while let Some(predecessor) = to_process.pop() {
+                if !seen.insert(predecessor) {
+                    continue;
+                }
+                let newer = obsstore.successors_sets(
+                    predecessor,
+                    changelog,
+                    &mut successors_sets,
+                )?;
+                if newer.len() > 1 {
+                    sets.content_divergent.insert(rev);
+                    break;
+                }
+                to_process.extend(
+                    obsstore
+                        .predecessor_markers(&predecessor)
+                        .map(|marker| marker.predecessor),
+                )
+            }
+        }
+        Ok(sets)
+    }
+}
+
+/// Whether a changeset is in the `obsolete` set, without computing it
+pub fn is_obsolete(
+    obsstore: &ObsStore,
+    changelog: &Changelog,
+    phases: &Phases,
+    rev: Revision,
+) -> Result<bool, RevlogError> {
+    if phases.phase(rev)? == Phase::Public {
+        return Ok(false);
+    }
+    let node = changelog
+        .node_from_rev(rev)
+        .ok_or(RevlogError::InvalidRevision)?;
+    Ok(obsstore.has_successor_markers(node))
+}
+
+/// The revision of `node`, if the changelog has it
+fn rev_of(
+    changelog: &Changelog,
+    node: &Node,
+) -> Result<Option<Revision>, RevlogError> {
+    match changelog.rev_from_node(node.into()) {
+        Ok(rev) => Ok(Some(rev)),
+        Err(RevlogError::InvalidRevision) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn is_known(changelog: &Changelog, node: &Node) -> Result<bool, RevlogError> {
+    Ok(rev_of(changelog, node)?.is_some())
+}
+
+/// The order of `obsutil.sortedmarkers` in Python, which compares all the
+/// fields in turn
+fn compare_markers(marker1: &Marker, marker2: &Marker) -> Ordering {
+    fn nodes(nodes: &[Node]) -> impl Iterator<Item = &[u8]> {
+        nodes.iter().map(|node| node.as_bytes())
+    }
+    fn parents(marker: &Marker) -> impl Iterator<Item = &[u8]> {
+        nodes(marker.parents.as_deref().unwrap_or(&[]))
+    }
+    marker1
+        .predecessor
+        .as_bytes()
+        .cmp(marker2.predecessor.as_bytes())
+        .then_with(|| {
+            nodes(&marker1.successors).cmp(nodes(&marker2.successors))
+        })
+        .then_with(|| marker1.flags.cmp(&marker2.flags))
+        .then_with(|| marker1.metadata.cmp(&marker2.metadata))
+        .then_with(|| {
+            marker1
+                .timestamp
+                .partial_cmp(&marker2.timestamp)
+                .unwrap_or(Ordering::Equal)
+        })
+        .then_with(|| marker1.timezone_offset.cmp(&marker2.timezone_offset))
+        .then_with(|| parents(marker1).cmp(parents(marker2)))
+}
+
+fn parse_markers(
+    mut bytes: &[u8],
+    parse_marker: fn(&mut &[u8]) -> Result<Marker, HgError>,
+) -> Result<Vec<Marker>, HgError> {
+    let mut markers = Vec::new();
+    while !bytes.is_empty() {
+        markers.push(parse_marker(&mut bytes)?)
+    }
+    Ok(markers)
+}
+
+/// Version 0: the number of successors as `u8`, the size of the metadata as
+/// `u32`, the flags as `u8`, the predecessor and successors, then the
+/// metadata as `key:value` strings separated by null bytes. The date and
+/// parents are in the metadata.
+fn parse_v0_marker(bytes: &mut &[u8]) -> Result<Marker, HgError> {
+    let successors_count = take(bytes, 1)?[0] as usize;
+    let metadata_size = u32::from_be_bytes(
+        <[u8; 4]>::try_from(take(bytes, 4)?).expect("4 bytes"),
+    ) as usize;
+    let flags = take(bytes, 1)?[0] as u16;
+    let predecessor = take_node(bytes)?;
+    let mut successors = Vec::with_capacity(successors_count);
+    for _ in 0..successors_count {
+        successors.push(take_node(bytes)?)
+    }
+    if bytes.len() < metadata_size {
+        return Err(HgError::corrupted(format!(
+            "parsing obsolete marker: metadata is too short, \
+             {} bytes expected, got {}",
+            metadata_size,
+            bytes.len()
+        )));
+    }
+    let mut metadata = HashMap::new();
+    for entry in take(bytes, metadata_size)?.split(|b| *b == b'\0') {
+        if entry.is_empty() {
+            continue;
+        }
+        let colon =
+            entry.iter().position(|b| *b == b':').ok_or_else(|| {
+                HgError::corrupted("invalid obsolescence marker metadata")
+            })?;
+        metadata.insert(entry[..colon].to_vec(), entry[colon + 1..].to_vec());
+    }
+
+    let (timestamp, timezone_offset) = metadata
+        .remove(&b"date"[..])
+        .and_then(|date| parse_v0_date(&date))
+        .unwrap_or((0., 0));
+    let parent_keys: &[&[u8]] = if metadata.contains_key(&b"p2"[..]) {
+        &[b"p1", b"p2"]
+    } else if metadata.contains_key(&b"p1"[..]) {
+        &[b"p1"]
+    } else {
+        &[]
+    };
+    let parents = if parent_keys.is_empty() {
+        if metadata.contains_key(&b"p0"[..]) {
+            Some(Vec::new())
+        } else {
+            None
+        }
+    } else {
+        // Like in Python, invalid parents are dropped
+        let parents: Vec<_> = parent_keys
+            .iter()
+            .map(|key| metadata.remove(*key))
+            .collect();
+        parents
+            .into_iter()
+            .map(|hex| Node::from_hex(hex?).ok())
+            .collect()
+    };
+    let mut metadata: Vec<_> = metadata.into_iter().collect();
+    metadata.sort();
+    Ok(Marker {
+        predecessor,
+        successors,
+        flags,
+        metadata,
+        timestamp,
+        timezone_offset,
+        parents,
+    })
+}
+
+/// The `<unixtime> <offset>` date in the metadata of version 0 markers
+fn parse_v0_date(date: &[u8]) -> Option<(f64, i32)> {
+    let date = std::str::from_utf8(date).ok()?;
+    let mut fields = date.split(' ');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(timestamp), Some(offset), None) => {
+            Some((timestamp.parse().ok()?, offset.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Version 1: the size of the marker as `u32`, the date as a `f64` and its
+/// timezone offset in minutes as `i16`, the flags as `u16`, the numbers of
+/// successors, parents and metadata entries as `u8`, the predecessor,
+/// successors and parents, the sizes of the metadata keys and values as
+/// `u8`, then the metadata.
+fn parse_v1_marker(bytes: &mut &[u8]) -> Result<Marker, HgError> {
+    let fixed = take(bytes, 19)?;
+    let timestamp = f64::from_be_bytes(
+        <[u8; 8]>::try_from(&fixed[4..12]).expect("8 bytes"),
+    );
+    let timezone_minutes = i16::from_be_bytes([fixed[12], fixed[13]]);
+    let flags = u16::from_be_bytes([fixed[14], fixed[15]]);
+    let successors_count = fixed[16] as usize;
+    let parents_count = fixed[17];
+    let metadata_count = fixed[18] as usize;
+    if flags & USING_SHA_256 != 0 {
+        return Err(HgError::UnsupportedFeature(
+            "obsolescence markers with SHA-256 node IDs".to_owned(),
+        ));
+    }
+    let predecessor = take_node(bytes)?;
+    let mut successors = Vec::with_capacity(successors_count);
+    for _ in 0..successors_count {
+        successors.push(take_node(bytes)?)
+    }
+    // 3 means that parents are not recorded
+    let parents = if parents_count == 3 {
+        None
+    } else {
+        let mut parents = Vec::with_capacity(parents_count.into());
+        for _ in 0..parents_count {
+            parents.push(take_node(bytes)?)
+        }
+        Some(parents)
+    };
+    let sizes = take(bytes, 2 * metadata_count)?;
+    let mut metadata = Vec::with_capacity(metadata_count);
+    for size in sizes.chunks(2) {
+        let key = take(bytes, size[0].into())?.to_vec();
+        let value = take(bytes, size[1].into())?.to_vec();
+        metadata.push((key, value))
+    }
+    Ok(Marker {
+        predecessor,
+        successors,
+        flags,
+        metadata,
+        timestamp,
+        timezone_offset: i32::from(timezone_minutes) * 60,
+        parents,
+    })
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], HgError> {
+    if bytes.len() < len {
+        return Err(HgError::corrupted("truncated obsolescence marker"));
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn take_node(bytes: &mut &[u8]) -> Result<Node, HgError> {
+    let node = take(bytes, NODE_BYTES_LENGTH)?;
+    Ok(Node::try_from(node).expect("node size"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(byte: u8) -> Node {
+        Node::from([byte; NODE_BYTES_LENGTH])
+    }
+
+    fn v0_marker(
+        predecessor: u8,
+        successors: &[u8],
+        metadata: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = vec![successors.len() as u8];
+        bytes.extend(&(metadata.len() as u32).to_be_bytes());
+        bytes.push(0);
+        bytes.extend(node(predecessor).as_bytes());
+        for &successor in successors {
+            bytes.extend(node(successor).as_bytes())
+        }
+        bytes.extend(metadata);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_v0() {
+        let parent = "01".repeat(NODE_BYTES_LENGTH);
+        let metadata = format!("user:test\0date:1000.5 -3600\0p1:{}", parent);
+        let mut bytes = vec![0];
+        bytes.extend(v0_marker(1, &[2, 3], metadata.as_bytes()));
+        bytes.extend(v0_marker(4, &[], b"p0:\0date:invalid"));
+        let obsstore = ObsStore::parse(&bytes).unwrap();
+        assert_eq!(
+            obsstore.markers(),
+            [
+                Marker {
+                    predecessor: node(1),
+                    successors: vec![node(2), node(3)],
+                    flags: 0,
+                    metadata: vec![(b"user".to_vec(), b"test".to_vec())],
+                    timestamp: 1000.5,
+                    timezone_offset: -3600,
+                    parents: Some(vec![node(1)]),
+                },
+                Marker {
+                    predecessor: node(4),
+                    successors: vec![],
+                    flags: 0,
+                    metadata: vec![(b"p0".to_vec(), b"".to_vec())],
+                    timestamp: 0.,
+                    timezone_offset: 0,
+                    parents: Some(vec![]),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let mut bytes = vec![1];
+        bytes.extend(&(19 + 4 * 20 + 2 + 7u32).to_be_bytes());
+        bytes.extend(&1000.5f64.to_be_bytes());
+        bytes.extend(&(-60i16).to_be_bytes());
+        bytes.extend(&BUMPED_FIX.to_be_bytes());
+        bytes.extend(&[2, 1, 1]);
+        for &byte in &[1, 2, 3, 4] {
+            bytes.extend(node(byte).as_bytes())
+        }
+        bytes.extend(&[4, 3]);
+        bytes.extend(b"userbob");
+        let obsstore = ObsStore::parse(&bytes).unwrap();
+        assert_eq!(
+            obsstore.markers(),
+            [Marker {
+                predecessor: node(1),
+                successors: vec![node(2), node(3)],
+                flags: BUMPED_FIX,
+                metadata: vec![(b"user".to_vec(), b"bob".to_vec())],
+                timestamp: 1000.5,
+                timezone_offset: -3600,
+                parents: Some(vec![node(4)]),
+            }]
+        );
+        assert!(ObsStore::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(ObsStore::parse(b"").unwrap().is_empty());
+        let error = ObsStore::parse(b"\x02").err().unwrap();
+        assert!(error.to_string().contains("unknown version 2"));
+        let mut bytes = vec![0];
+        bytes.extend(v0_marker(1, &[0], b""));
+        let error = ObsStore::parse(&bytes).err().unwrap();
+        assert!(error.to_string().contains("invalid successors nullid"));
+        let mut bytes = vec![0];
+        bytes.extend(v0_marker(1, &[], b"date"));
+        bytes.pop();
+        let error = ObsStore::parse(&bytes).err().unwrap();
+        assert!(error.to_string().contains("metadata is too short"));
+    }
+
+    #[test]
+    fn test_predecessors() {
+        let mut bytes = vec![0];
+        bytes.extend(v0_marker(1, &[2], b""));
+        bytes.extend(v0_marker(2, &[3, 4], b""));
+        bytes.extend(v0_marker(5, &[3], b""));
+        let mut fix = v0_marker(6, &[5], b"");
+        fix[5] = BUMPED_FIX as u8;
+        bytes.extend(fix);
+        let obsstore = ObsStore::parse(&bytes).unwrap();
+
+        assert_eq!(obsstore.successor_markers(&node(2)).count(), 1);
+        assert_eq!(obsstore.successor_markers(&node(3)).count(), 0);
+        let predecessors: Vec<_> = obsstore
+            .predecessor_markers(&node(3))
+            .map(|marker| marker.predecessor)
+            .collect();
+        assert_eq!(predecessors, [node(2), node(5)]);
+        assert!(obsstore.has_successor_markers(&node(6)));
+
+        let all: HashSet<_> =
+            [1, 2, 3, 5, 6].iter().map(|&b| node(b)).collect();
+        assert_eq!(obsstore.all_predecessors(&[node(3)], 0), all);
+        let all: HashSet<_> = [1, 2, 3, 5].iter().map(|&b| node(b)).collect();
+        assert_eq!(obsstore.all_predecessors(&[node(3)], BUMPED_FIX), all);
+    }
+}
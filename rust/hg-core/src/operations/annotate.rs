@@ -0,0 +1,417 @@
+// annotate.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Finding the changeset that last changed each line of a file, like
+//! `dagop.annotate` in Python
+
+use super::log::FileHistory;
+use crate::diff::{bdiff_blocks, split_lines, Block};
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision};
+use crate::utils::hg_path::{HgPath, HgPathBuf};
+use crate::LazyAncestors;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// How to walk the history of annotated files
+#[derive(Debug, Default)]
+pub struct AnnotateOptions {
+    /// Follow copies and renames
+    pub follow: bool,
+    /// Changesets whose changes are attributed to their parents when
+    /// possible, like `--skip`
+    pub skip: HashSet<Revision>,
+}
+
+/// A line of an annotated file
+#[derive(Debug, PartialEq)]
+pub struct AnnotatedLine {
+    /// The changeset that last changed the line
+    pub revision: Revision,
+    /// The path of the file in that changeset
+    pub path: HgPathBuf,
+    /// The line number in that changeset, starting at 1
+    pub line_number: usize,
+    /// Whether the line was changed by a skipped changeset, in which case
+    /// `revision` is a guess
+    pub skipped: bool,
+    /// The contents of the line, with its `\n` terminator if any
+    pub line: Vec<u8>,
+}
+
+/// A revision of a file, and the changeset it is attributed to
+#[derive(Debug)]
+struct FileContext {
+    path: HgPathBuf,
+    node: Node,
+    rev: Revision,
+}
+
+impl FileContext {
+    fn key(&self) -> (HgPathBuf, Node) {
+        (self.path.clone(), self.node)
+    }
+}
+
+/// The contents of a file revision with, for each line, the file context
+/// that introduced it, its line number there, and whether it was skipped
+struct AnnotatedText {
+    contexts: Vec<Rc<FileContext>>,
+    line_numbers: Vec<usize>,
+    skips: Vec<bool>,
+    text: Vec<u8>,
+}
+
+impl AnnotatedText {
+    /// Attribute all the lines to `context`, like `_decoratelines`
+    fn new(text: Vec<u8>, context: &Rc<FileContext>) -> Self {
+        let count = split_lines(&text).len();
+        Self {
+            contexts: vec![context.clone(); count],
+            line_numbers: (1..=count).collect(),
+            skips: vec![false; count],
+            text,
+        }
+    }
+
+    fn copy_line(&mut self, line: usize, parent: &Self, parent_line: usize) {
+        self.contexts[line] = parent.contexts[parent_line].clone();
+        self.line_numbers[line] = parent.line_numbers[parent_line];
+        self.skips[line] = parent.skips[parent_line];
+    }
+}
+
+/// Annotates files of a repository, keeping their filelogs open
+pub struct Annotator<'a> {
+    changelog: &'a Changelog,
+    history: FileHistory<'a>,
+}
+
+impl<'a> Annotator<'a> {
+    pub fn new(
+        repo: &'a Repo,
+        changelog: &'a Changelog,
+    ) -> Result<Self, RevlogError> {
+        Ok(Self {
+            changelog,
+            history: FileHistory::new(repo, changelog)?,
+        })
+    }
+
+    /// The contents of a file revision, without its copy metadata
+    pub fn data(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+    ) -> Result<Vec<u8>, RevlogError> {
+        self.history.data(path, node)
+    }
+
+    /// The lines of the file revision `node` of `path` found in changeset
+    /// `rev`, like `basefilectx.annotate` in Python.
+    ///
+    /// Each line is attributed to the closest changeset among the file
+    /// ancestors where the line was not changed since. When a line is
+    /// identical in several parents of a merge, the last parent wins.
+    pub fn annotate(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+        rev: Revision,
+        options: &AnnotateOptions,
+    ) -> Result<Vec<AnnotatedLine>, RevlogError> {
+        let base = Rc::new(FileContext {
+            path: path.to_owned(),
+            node,
+            rev: self.history.introduction_revision(path, node, rev, true)?,
+        });
+        // The link revisions of ancestors are used as is when they are
+        // ancestors of the base changeset
+        let mut ancestry =
+            LazyAncestors::new(self.changelog, vec![base.rev], 0, true)?;
+
+        // A first walk finds the parents of each file context, and how many
+        // children need its annotations
+        let mut visit = vec![base.clone()];
+        let mut parents: HashMap<_, Vec<Rc<FileContext>>> = HashMap::new();
+        let mut needed = HashMap::new();
+        needed.insert(base.key(), 1);
+        while let Some(context) = visit.pop() {
+            if parents.contains_key(&context.key()) {
+                continue;
+            }
+            let context_parents =
+                self.parents(&context, options.follow, &mut ancestry)?;
+            for parent in &context_parents {
+                *needed.entry(parent.key()).or_insert(0) += 1;
+                if !parents.contains_key(&parent.key()) {
+                    visit.push(parent.clone());
+                }
+            }
+            parents.insert(context.key(), context_parents);
+        }
+
+        // A second walk annotates each file context after its parents
+        let mut visit = vec![base.clone()];
+        let mut annotated: HashMap<_, AnnotatedText> = HashMap::new();
+        while let Some(context) = visit.last().cloned() {
+            if annotated.contains_key(&context.key()) {
+                visit.pop();
+                continue;
+            }
+            let context_parents = &parents[&context.key()];
+            let mut ready = true;
+            for parent in context_parents {
+                if !annotated.contains_key(&parent.key()) {
+                    ready = false;
+                    visit.push(parent.clone());
+                }
+            }
+            if !ready {
+                continue;
+            }
+            visit.pop();
+            let data = self.history.data(&context.path, context.node)?;
+            let mut text = AnnotatedText::new(data, &context);
+            let skip = options.skip.contains(&context.rev);
+            let parent_texts: Vec<_> = context_parents
+                .iter()
+                .map(|parent| &annotated[&parent.key()])
+                .collect();
+            annotate_pair(&parent_texts, &context, &mut text, skip);
+            for parent in context_parents {
+                let key = parent.key();
+                let count = needed.get_mut(&key).expect("counted parent");
+                if *count == 1 {
+                    annotated.remove(&key);
+                    needed.remove(&key);
+                } else {
+                    *count -= 1
+                }
+            }
+            annotated.insert(context.key(), text);
+            parents.remove(&context.key());
+        }
+
+        let text = annotated.remove(&base.key()).expect("annotated base");
+        let lines = split_lines(&text.text);
+        Ok(lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| AnnotatedLine {
+                revision: text.contexts[i].rev,
+                path: text.contexts[i].path.clone(),
+                line_number: text.line_numbers[i],
+                skipped: text.skips[i],
+                line: line.to_vec(),
+            })
+            .collect())
+    }
+
+    /// The parents of a file context, attributed to changesets that are
+    /// ancestors of its own, like `basefilectx._parentfilectx`
+    fn parents(
+        &mut self,
+        context: &FileContext,
+        follow: bool,
+        ancestry: &mut LazyAncestors<&'a Changelog>,
+    ) -> Result<Vec<Rc<FileContext>>, RevlogError> {
+        let mut parents = Vec::new();
+        for (path, node) in
+            self.history.parents(&context.path, context.node)?
+        {
+            if !follow && path != context.path {
+                continue;
+            }
+            let link_rev = self.history.link_revision(&path, node)?;
+            let rev =
+                if link_rev == context.rev || ancestry.contains(link_rev)? {
+                    link_rev
+                } else {
+                    self.history.introduction_revision(
+                        &path,
+                        node,
+                        context.rev,
+                        false,
+                    )?
+                };
+            parents.push(Rc::new(FileContext { path, node, rev }))
+        }
+        Ok(parents)
+    }
+}
+
+/// Attribute the lines of `child` that are unchanged from its parents to
+/// them, like `_annotatepair` in Python.
+///
+/// With `skip`, lines changed by the child are also attributed to the lines
+/// of a parent that they replaced.
+fn annotate_pair(
+    parents: &[&AnnotatedText],
+    child_context: &Rc<FileContext>,
+    child: &mut AnnotatedText,
+    skip: bool,
+) {
+    let parent_blocks: Vec<_> = parents
+        .iter()
+        .map(|parent| (parent, all_blocks(&parent.text, &child.text)))
+        .collect();
+    for (parent, blocks) in &parent_blocks {
+        for (block, equal) in blocks {
+            if *equal {
+                for i in 0..block.len() {
+                    child.copy_line(block.b1 + i, parent, block.a1 + i);
+                }
+            }
+        }
+    }
+    if !skip {
+        return;
+    }
+    let is_child = |child: &AnnotatedText, line: usize| {
+        child.contexts[line].key() == child_context.key()
+    };
+    let skip_line =
+        |child: &mut AnnotatedText, parent: &AnnotatedText, block: &Block| {
+            for line in block.b1..block.b2 {
+                if !is_child(child, line) {
+                    continue;
+                }
+                let parent_line = if block.a2 == 0 {
+                    // Like the `-1` index in Python, lines inserted at the
+                    // start are attributed to the last line of the parent
+                    match parent.contexts.len().checked_sub(1) {
+                        Some(last) => last,
+                        None => continue,
+                    }
+                } else {
+                    (block.a1 + line - block.b1).min(block.a2 - 1)
+                };
+                child.copy_line(line, parent, parent_line);
+                child.skips[line] = true;
+            }
+        };
+    // Keep the bias towards the last parent by visiting it first. First
+    // match changed lines one to one without repeating the last line of a
+    // parent, then anything left.
+    let mut remaining = Vec::new();
+    for (parent, blocks) in parent_blocks.iter().rev() {
+        let mut parent_remaining = Vec::new();
+        for (block, _equal) in blocks {
+            if block.a2 - block.a1 >= block.b2 - block.b1 {
+                skip_line(child, parent, block)
+            } else {
+                parent_remaining.push(*block)
+            }
+        }
+        remaining.push((parent, parent_remaining));
+    }
+    for (parent, blocks) in remaining {
+        for block in &blocks {
+            skip_line(child, parent, block)
+        }
+    }
+}
+
+/// The blocks of lines that are equal between two texts, and the changed
+/// blocks between them, like `mdiff.allblocks`
+fn all_blocks(a: &[u8], b: &[u8]) -> Vec<(Block, bool)> {
+    let mut blocks = Vec::new();
+    let mut previous_end = (0, 0);
+    for block in bdiff_blocks(a, b) {
+        let changed = Block {
+            a1: previous_end.0,
+            a2: block.a1,
+            b1: previous_end.1,
+            b2: block.b1,
+        };
+        if changed.a1 != changed.a2 || changed.b1 != changed.b2 {
+            blocks.push((changed, false));
+        }
+        blocks.push((block, true));
+        previous_end = (block.a2, block.b2);
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(path: &str, rev: Revision) -> Rc<FileContext> {
+        Rc::new(FileContext {
+            path: HgPathBuf::from_bytes(path.as_bytes()),
+            node: Node::from_hex(&format!("{:040x}", rev)).unwrap(),
+            rev,
+        })
+    }
+
+    fn revisions(text: &AnnotatedText) -> Vec<(Revision, usize, bool)> {
+        (0..text.contexts.len())
+            .map(|i| {
+                (text.contexts[i].rev, text.line_numbers[i], text.skips[i])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_all_blocks() {
+        let block = |a1, a2, b1, b2| Block { a1, a2, b1, b2 };
+        assert_eq!(
+            all_blocks(b"a\nb\nc\n", b"a\nx\nc\nd\n"),
+            vec![
+                (block(0, 1, 0, 1), true),
+                (block(1, 2, 1, 2), false),
+                (block(2, 3, 2, 3), true),
+                (block(3, 3, 3, 4), false),
+                (block(3, 3, 4, 4), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_annotate_pair() {
+        let context0 = context("f", 0);
+        let parent = AnnotatedText::new(b"a\nb\nc\n".to_vec(), &context0);
+        let context1 = context("f", 1);
+        let mut child =
+            AnnotatedText::new(b"a\nx\nc\nd\n".to_vec(), &context1);
+        annotate_pair(&[&parent], &context1, &mut child, false);
+        assert_eq!(
+            revisions(&child),
+            vec![(0, 1, false), (1, 2, false), (0, 3, false), (1, 4, false)]
+        );
+
+        // The changed line replaces a parent line, the added one repeats the
+        // last line of the parent
+        let mut child =
+            AnnotatedText::new(b"a\nx\nc\nd\n".to_vec(), &context1);
+        annotate_pair(&[&parent], &context1, &mut child, true);
+        assert_eq!(
+            revisions(&child),
+            vec![(0, 1, false), (0, 2, true), (0, 3, false), (0, 3, true)]
+        );
+    }
+
+    #[test]
+    fn test_annotate_pair_merge() {
+        let context1 = context("f", 1);
+        let parent1 = AnnotatedText::new(b"a\nb\n".to_vec(), &context1);
+        let context2 = context("f", 2);
+        let parent2 = AnnotatedText::new(b"a\nc\n".to_vec(), &context2);
+        let context3 = context("f", 3);
+        let mut child = AnnotatedText::new(b"a\nb\nc\n".to_vec(), &context3);
+        annotate_pair(&[&parent1, &parent2], &context3, &mut child, false);
+        // The second parent wins for the line common to both
+        assert_eq!(
+            revisions(&child),
+            vec![(2, 1, false), (1, 2, false), (2, 2, false)]
+        );
+    }
+}
@@ -5,36 +5,36 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
-use std::path::PathBuf;
-
+use super::log::path_matcher;
+use crate::errors::HgError;
+use crate::matchers::{AlwaysMatcher, Matcher};
 use crate::repo::Repo;
 use crate::revlog::changelog::Changelog;
+use crate::revlog::filelog::Filelog;
 use crate::revlog::manifest::Manifest;
-use crate::revlog::path_encode::path_encode;
-use crate::revlog::revlog::Revlog;
+use crate::revlog::node::NULL_NODE;
 use crate::revlog::revlog::RevlogError;
-use crate::revlog::Node;
-use crate::utils::files::get_path_from_bytes;
-use crate::utils::hg_path::{HgPath, HgPathBuf};
+use crate::revlog::{Node, Revision, NULL_REVISION};
+use crate::utils::hg_path::HgPathBuf;
 
 pub struct CatOutput {
     /// Whether any file in the manifest matched the paths given as CLI
     /// arguments
     pub found_any: bool,
-    /// The contents of matching files, in manifest order
-    pub concatenated: Vec<u8>,
+    /// The paths and contents of matching files, in manifest order
+    pub results: Vec<(HgPathBuf, Vec<u8>)>,
     /// Which of the CLI arguments did not match any manifest file
     pub missing: Vec<HgPathBuf>,
+    /// The revision that the given revset was resolved to
+    pub rev: Revision,
     /// The node ID that the given revset was resolved to
     pub node: Node,
 }
 
-const METADATA_DELIMITER: [u8; 2] = [b'\x01', b'\n'];
-
 /// Output the given revision of files
 ///
 /// * `root`: Repository root
-/// * `rev`: The revision to cat the files from.
+/// * `revset`: The revision to cat the files from.
 /// * `files`: The files to output.
 pub fn cat<'a>(
     repo: &Repo,
@@ -45,44 +45,64 @@ pub fn cat<'a>(
     let changelog = Changelog::open(repo)?;
     let manifest = Manifest::open(repo)?;
     let changelog_entry = changelog.get_rev(rev)?;
-    let node = *changelog
-        .node_from_rev(rev)
-        .expect("should succeed when changelog.get_rev did");
-    let manifest_node =
-        Node::from_hex_for_repo(&changelog_entry.manifest_node()?)?;
-    let manifest_entry = manifest.get_node(manifest_node.into())?;
-    let mut bytes = vec![];
+    let node = if rev == NULL_REVISION {
+        NULL_NODE
+    } else {
+        *changelog
+            .node_from_rev(rev)
+            .expect("should succeed when changelog.get_rev did")
+    };
+    let manifest_node = changelog_entry.manifest_node()?;
+    // The null revision has an empty manifest
+    let manifest_entry = if manifest_node == NULL_NODE {
+        None
+    } else {
+        // Only the directories of the files are needed from tree manifests,
+        // and the files under them in case they name directories
+        let file_matcher = path_matcher(repo, files);
+        let matcher: &dyn Matcher = match &file_matcher {
+            Some(file_matcher) => file_matcher,
+            None => &AlwaysMatcher,
+        };
+        Some(manifest.get_node_matching(manifest_node.into(), matcher)?)
+    };
+    let mut results = vec![];
     let mut matched = vec![false; files.len()];
     let mut found_any = false;
+    let ignore_censored =
+        repo.config().get(b"censor", b"policy") == Some(b"ignore");
 
-    for (manifest_file, node_bytes) in manifest_entry.files_with_nodes() {
-        for (cat_file, is_matched) in files.iter().zip(&mut matched) {
-            if cat_file.as_bytes() == manifest_file.as_bytes() {
-                *is_matched = true;
-                found_any = true;
-                let index_path = store_path(manifest_file, b".i");
-                let data_path = store_path(manifest_file, b".d");
-
-                let file_log =
-                    Revlog::open(repo, &index_path, Some(&data_path))?;
-                let file_node = Node::from_hex_for_repo(node_bytes)?;
-                let file_rev = file_log.get_node_rev(file_node.into())?;
-                let data = file_log.get_rev_data(file_rev)?;
-                if data.starts_with(&METADATA_DELIMITER) {
-                    let end_delimiter_position = data
-                        [METADATA_DELIMITER.len()..]
-                        .windows(METADATA_DELIMITER.len())
-                        .position(|bytes| bytes == METADATA_DELIMITER);
-                    if let Some(position) = end_delimiter_position {
-                        let offset = METADATA_DELIMITER.len() * 2;
-                        bytes.extend(data[position + offset..].iter());
-                    }
-                } else {
-                    bytes.extend(data);
-                }
+    for (cat_file, is_matched) in files.iter().zip(&mut matched) {
+        let manifest_file = match &manifest_entry {
+            Some(entry) => entry.find_file(cat_file)?,
+            None => None,
+        };
+        if let (None, Some(entry)) = (&manifest_file, &manifest_entry) {
+            // Python outputs all the files of a directory
+            if entry.has_dir(cat_file)? {
+                return Err(
+                    HgError::unsupported("`rhg cat` of a directory").into()
+                );
             }
         }
+        if let Some(manifest_file) = manifest_file {
+            *is_matched = true;
+            found_any = true;
+            let file_log = Filelog::open(repo, cat_file)?;
+            let data = match file_log.get_node(manifest_file.node_id()?.into())
+            {
+                Ok(entry) => entry.into_data()?,
+                // Like `filectx.data` in Python
+                Err(RevlogError::Censored { .. }) if ignore_censored => {
+                    Vec::new()
+                }
+                Err(error) => return Err(error),
+            };
+            results.push((cat_file.clone(), data));
+        }
     }
+    // Manifests are sorted by path
+    results.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
 
     let missing: Vec<_> = files
         .iter()
@@ -92,14 +112,9 @@ pub fn cat<'a>(
         .collect();
     Ok(CatOutput {
         found_any,
-        concatenated: bytes,
+        results,
         missing,
+        rev,
         node,
     })
 }
-
-fn store_path(hg_path: &HgPath, suffix: &[u8]) -> PathBuf {
-    let encoded_bytes =
-        path_encode(&[b"data/", hg_path.as_bytes(), suffix].concat());
-    get_path_from_bytes(&encoded_bytes).into()
-}
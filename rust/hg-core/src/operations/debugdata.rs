@@ -28,6 +28,6 @@ pub fn debug_data(
     let revlog = Revlog::open(repo, index_file, None)?;
     let rev =
         crate::revset::resolve_rev_number_or_hex_prefix(revset, &revlog)?;
-    let data = revlog.get_rev_data(rev)?;
+    let data = revlog.get_raw_rev_data(rev)?;
     Ok(data)
 }
@@ -0,0 +1,448 @@
+// debugrevlog.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! How revisions are stored in a revlog, for `debugindex`,
+//! `debugdeltachain` and `debugrevlogstats`
+
+use crate::errors::HgError;
+use crate::repo::Repo;
+use crate::revlog::filelog::Filelog;
+use crate::revlog::index::REVLOGV2;
+use crate::revlog::node::{Node, NULL_NODE};
+use crate::revlog::revlog::{Revlog, RevlogError, SparseReadOptions};
+use crate::revlog::{Revision, NULL_REVISION};
+use crate::utils::hg_path::HgPath;
+use std::collections::{BTreeMap, HashMap};
+
+/// Which revlog to inspect, like the `-c|-m|FILE` arguments in Python
+#[derive(Debug, Copy, Clone)]
+pub enum DebugRevlogKind<'a> {
+    Changelog,
+    Manifest,
+    File(&'a HgPath),
+}
+
+/// Open the revlog to inspect
+pub fn debug_open_revlog(
+    repo: &Repo,
+    kind: DebugRevlogKind,
+) -> Result<Revlog, RevlogError> {
+    match kind {
+        DebugRevlogKind::Changelog => {
+            Revlog::open(repo, "00changelog.i", None)
+        }
+        DebugRevlogKind::Manifest => Revlog::open(repo, "00manifest.i", None),
+        DebugRevlogKind::File(path) => {
+            let filelog = Filelog::open(repo, path)?;
+            // Python then looks for a revlog file outside of the store
+            if filelog.is_empty() {
+                return Err(HgError::unsupported(
+                    "debug commands on revlogs outside of the store",
+                )
+                .into());
+            }
+            Ok(filelog.revlog)
+        }
+    }
+}
+
+/// A revision in the index of a revlog
+pub struct IndexLine {
+    pub rev: Revision,
+    pub link_rev: Revision,
+    pub node: Node,
+    pub p1: Node,
+    pub p2: Node,
+}
+
+/// List the revisions of a revlog, like `debugindex` in Python
+pub fn debug_index(revlog: &Revlog) -> Result<Vec<IndexLine>, RevlogError> {
+    let node = |rev| -> Result<Node, RevlogError> {
+        if rev == NULL_REVISION {
+            Ok(NULL_NODE)
+        } else {
+            Ok(*revlog.index_entry(rev)?.hash())
+        }
+    };
+    (0..revlog.len() as Revision)
+        .map(|rev| {
+            let entry = revlog.index_entry(rev)?;
+            Ok(IndexLine {
+                rev,
+                link_rev: entry.link_revision(),
+                node: *entry.hash(),
+                p1: node(entry.p1())?,
+                p2: node(entry.p2())?,
+            })
+        })
+        .collect()
+}
+
+/// What a revision is stored as a delta against
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeltaType {
+    P1,
+    P2,
+    Prev,
+    /// A full snapshot
+    Base,
+    Other,
+}
+
+impl DeltaType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeltaType::P1 => "p1",
+            DeltaType::P2 => "p2",
+            DeltaType::Prev => "prev",
+            DeltaType::Base => "base",
+            DeltaType::Other => "other",
+        }
+    }
+}
+
+/// The delta chain of a revision
+pub struct DeltaChainLine {
+    pub rev: Revision,
+    /// Delta chains are numbered from 1 by their base, in revision order
+    pub chain_id: usize,
+    pub chain_len: usize,
+    /// The revision before this one in the chain, if any
+    pub prev_rev: Revision,
+    pub delta_type: DeltaType,
+    pub compressed_size: usize,
+    pub uncompressed_size: usize,
+    /// Total compressed size of the revisions in the chain
+    pub chain_size: usize,
+    /// Bytes from the start of the chain base to the end of this revision
+    pub linear_distance: usize,
+    /// Bytes of other revisions in `linear_distance`
+    pub extra_distance: isize,
+    /// With sparse reads
+    pub sparse_read: Option<SparseRead>,
+}
+
+impl DeltaChainLine {
+    /// New delta chains start at about 2
+    pub fn chain_ratio(&self) -> f64 {
+        ratio(self.chain_size as f64, self.uncompressed_size)
+    }
+
+    /// Unrelated bytes read for each byte of the chain, without sparse reads
+    pub fn extra_ratio(&self) -> f64 {
+        ratio(self.extra_distance as f64, self.chain_size)
+    }
+}
+
+/// How a delta chain is read with sparse reads
+pub struct SparseRead {
+    /// Total size of the chunks of data read
+    pub read_size: usize,
+    pub largest_block: usize,
+    /// Number of chunks read
+    pub chunks: usize,
+    /// How much of the data read belongs to the chain
+    pub density: f64,
+}
+
+/// Like Python, fall back to the numerator when dividing by zero
+fn ratio(numerator: f64, denominator: usize) -> f64 {
+    if denominator == 0 {
+        numerator
+    } else {
+        numerator / denominator as f64
+    }
+}
+
+/// Describe the delta chain of each revision, like `debugdeltachain` in
+/// Python. Sparse reads are described with `sparse_read` options.
+pub fn debug_delta_chain(
+    revlog: &Revlog,
+    sparse_read: Option<&SparseReadOptions>,
+) -> Result<Vec<DeltaChainLine>, RevlogError> {
+    let generaldelta = revlog.index.uses_generaldelta();
+    let mut chain_ids = HashMap::new();
+    (0..revlog.len() as Revision)
+        .map(|rev| {
+            let entry = revlog.index_entry(rev)?;
+            let base = entry.base_revision();
+            let delta_type = if base == rev {
+                DeltaType::Base
+            } else if !generaldelta {
+                DeltaType::Prev
+            } else if base == entry.p1() {
+                DeltaType::P1
+            } else if base == entry.p2() {
+                DeltaType::P2
+            } else if base == rev - 1 {
+                DeltaType::Prev
+            } else {
+                DeltaType::Other
+            };
+            let chain = revlog.delta_chain(rev)?;
+            let mut chain_size = 0;
+            for &rev in &chain {
+                chain_size += revlog.index_entry(rev)?.compressed_len();
+            }
+            let chain_base = chain[0];
+            let next_id = chain_ids.len() + 1;
+            let chain_id = *chain_ids.entry(chain_base).or_insert(next_id);
+            let linear_distance =
+                revlog.end(rev)? - revlog.start(chain_base)?;
+            let sparse_read = match sparse_read {
+                Some(options) => {
+                    let ranges = revlog.sparse_read_ranges(rev, options)?;
+                    let sizes = ranges.iter().map(|range| range.len());
+                    let read_size = sizes.clone().sum();
+                    Some(SparseRead {
+                        read_size,
+                        largest_block: sizes.max().unwrap_or(0),
+                        chunks: ranges.len(),
+                        density: if read_size == 0 {
+                            1.0
+                        } else {
+                            chain_size as f64 / read_size as f64
+                        },
+                    })
+                }
+                None => None,
+            };
+            Ok(DeltaChainLine {
+                rev,
+                chain_id,
+                chain_len: chain.len(),
+                prev_rev: if chain.len() > 1 {
+                    chain[chain.len() - 2]
+                } else {
+                    NULL_REVISION
+                },
+                delta_type,
+                compressed_size: entry.compressed_len(),
+                uncompressed_size: entry.uncompressed_len(),
+                chain_size,
+                linear_distance,
+                extra_distance: linear_distance as isize - chain_size as isize,
+                sparse_read,
+            })
+        })
+        .collect()
+}
+
+/// The number of some chunks of data and their sizes
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SizeStats {
+    pub count: usize,
+    /// Zero when there are none
+    pub min: usize,
+    pub max: usize,
+    pub total: usize,
+}
+
+impl SizeStats {
+    fn add(&mut self, size: usize) {
+        if self.count == 0 || size < self.min {
+            self.min = size
+        }
+        self.max = self.max.max(size);
+        self.total += size;
+        self.count += 1;
+    }
+
+    /// The rounded down average size, zero when there are none
+    pub fn average(&self) -> usize {
+        self.total.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+/// Statistics about how the revisions of a revlog are stored, like
+/// `debugrevlog` in Python
+#[derive(Debug, Default)]
+pub struct RevlogStats {
+    /// The version of the revlog format
+    pub format: u16,
+    pub inline: bool,
+    pub generaldelta: bool,
+    pub revisions: usize,
+    pub merges: usize,
+    /// Empty full texts
+    pub empty_texts: usize,
+    /// Empty deltas
+    pub empty_deltas: usize,
+    /// Stored sizes of full snapshots at depth 0, and of intermediate
+    /// snapshots at the depth of the snapshots they are stored against
+    pub snapshots: BTreeMap<usize, SizeStats>,
+    /// Stored sizes of the other deltas
+    pub deltas: SizeStats,
+    /// Sizes of the full texts, before flag processors
+    pub raw_sizes: SizeStats,
+    /// Deltas against the previous revision, that also are one of its
+    /// parents or not
+    pub deltas_against_prev: usize,
+    pub deltas_against_prev_p1: usize,
+    pub deltas_against_prev_p2: usize,
+    /// Deltas against a parent that is not the previous revision
+    pub deltas_against_p1: usize,
+    pub deltas_against_p2: usize,
+    pub deltas_against_other: usize,
+    /// Number and total size of the chunks by first byte, which tells the
+    /// compression engine used: `None` for empty chunks
+    pub chunk_types: BTreeMap<Option<u8>, (usize, usize)>,
+    /// Number of deltas in all delta chains together
+    pub total_chain_length: usize,
+    pub max_chain_length: usize,
+    /// The largest span of data from the start of a delta chain to the end
+    /// of one of its revisions
+    pub max_chain_reach: usize,
+}
+
+impl RevlogStats {
+    /// Sizes of the intermediate snapshots, of all depths
+    pub fn intermediate_snapshots(&self) -> SizeStats {
+        let mut stats = SizeStats::default();
+        for (_, depth) in self.snapshots.range(1..) {
+            if depth.count > 0 {
+                stats.min = if stats.count == 0 {
+                    depth.min
+                } else {
+                    stats.min.min(depth.min)
+                };
+            }
+            stats.max = stats.max.max(depth.max);
+            stats.total += depth.total;
+            stats.count += depth.count;
+        }
+        stats
+    }
+
+    /// The total stored size of the revisions
+    pub fn total_size(&self) -> usize {
+        self.snapshots
+            .values()
+            .map(|stats| stats.total)
+            .sum::<usize>()
+            + self.deltas.total
+    }
+
+    /// How many times smaller the stored data is than the full texts, or 1
+    /// when nothing is stored
+    pub fn compression_ratio(&self) -> f64 {
+        match self.total_size() {
+            0 => 1.0,
+            total => self.raw_sizes.total as f64 / total as f64,
+        }
+    }
+
+    pub fn average_chain_length(&self) -> f64 {
+        ratio(self.total_chain_length as f64, self.revisions)
+    }
+}
+
+/// Compute statistics about how the revisions of a revlog are stored
+/// (`sparse_revlog` tells whether there can be intermediate snapshots)
+pub fn debug_revlog_stats(
+    revlog: &Revlog,
+    sparse_revlog: bool,
+) -> Result<RevlogStats, RevlogError> {
+    let mut stats = RevlogStats {
+        format: if revlog.index.is_v2() { REVLOGV2 } else { 1 },
+        inline: revlog.index.is_inline(),
+        generaldelta: revlog.index.uses_generaldelta(),
+        revisions: revlog.len(),
+        ..RevlogStats::default()
+    };
+    // Chain length and start of the chain base of each revision
+    let mut chains: Vec<(usize, usize)> = Vec::with_capacity(revlog.len());
+    for rev in 0..revlog.len() as Revision {
+        let entry = revlog.index_entry(rev)?;
+        let (p1, p2) = (entry.p1(), entry.p2());
+        let delta_parent = revlog.delta_parent(rev)?;
+        let size = entry.compressed_len();
+        let start = revlog.start(rev)?;
+        stats.raw_sizes.add(entry.uncompressed_len());
+        if p2 != NULL_REVISION {
+            stats.merges += 1
+        }
+        let (chain_length, chain_start) = if delta_parent == NULL_REVISION {
+            if size == 0 {
+                stats.empty_texts += 1
+            } else {
+                stats.snapshots.entry(0).or_default().add(size)
+            }
+            (0, start)
+        } else {
+            let (length, chain_start) =
+                *chains.get(delta_parent as usize).ok_or_else(|| {
+                    HgError::corrupted("delta against a later revision")
+                })?;
+            let is_snapshot = if sparse_revlog {
+                revlog.is_snapshot(rev)?
+            } else {
+                false
+            };
+            if size == 0 {
+                stats.empty_deltas += 1
+            } else if is_snapshot {
+                let depth = revlog.delta_chain(rev)?.len() - 1;
+                stats.snapshots.entry(depth).or_default().add(size)
+            } else {
+                stats.deltas.add(size);
+                if delta_parent == rev - 1 {
+                    stats.deltas_against_prev += 1;
+                    if delta_parent == p1 {
+                        stats.deltas_against_prev_p1 += 1
+                    } else if delta_parent == p2 {
+                        stats.deltas_against_prev_p2 += 1
+                    }
+                } else if delta_parent == p1 {
+                    stats.deltas_against_p1 += 1
+                } else if delta_parent == p2 {
+                    stats.deltas_against_p2 += 1
+                } else {
+                    stats.deltas_against_other += 1
+                }
+            }
+            (length + 1, chain_start)
+        };
+        chains.push((chain_length, chain_start));
+        stats.total_chain_length += chain_length;
+        stats.max_chain_length = stats.max_chain_length.max(chain_length);
+        stats.max_chain_reach =
+            stats.max_chain_reach.max(start + size - chain_start);
+
+        let chunk_type = stats
+            .chunk_types
+            .entry(revlog.chunk_header(rev)?)
+            .or_default();
+        chunk_type.0 += 1;
+        chunk_type.1 += size;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_stats() {
+        let mut stats = SizeStats::default();
+        assert_eq!(stats.average(), 0);
+        for &size in &[12, 3, 40] {
+            stats.add(size)
+        }
+        assert_eq!(
+            stats,
+            SizeStats {
+                count: 3,
+                min: 3,
+                max: 40,
+                total: 55,
+            }
+        );
+        assert_eq!(stats.average(), 18);
+    }
+}
@@ -0,0 +1,736 @@
+// diff.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Finding the files that differ between two changesets, or between a
+//! changeset and the working directory, and where they were copied from,
+//! like `patch.diffhunks` and `copies.pathcopies` in Python
+
+use super::log::{FileHistory, FileRevision};
+use crate::errors::HgError;
+use crate::matchers::{AlwaysMatcher, FileMatcher, Matcher};
+use crate::repo::Repo;
+use crate::requirements;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::manifest::{
+    Manifest, ManifestChange, ManifestEntry, ManifestFlag,
+};
+use crate::revlog::node::NULL_NODE;
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision, NULL_REVISION};
+use crate::utils::hg_path::{HgPath, HgPathBuf};
+use crate::{dagops, AncestorsIterator, DirstateMap, EntryState, Graph};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// A file on one side of a comparison
+#[derive(Debug, Clone)]
+pub struct DiffFile {
+    /// The node ID of the file revision, or `None` for a file of the working
+    /// directory that was added or may have been modified since the first
+    /// parent
+    pub node: Option<Node>,
+    /// Whether the file is executable or a symbolic link
+    pub flag: ManifestFlag,
+}
+
+/// One side of a comparison
+pub struct DiffSide {
+    /// The changeset, or `None` for the working directory
+    pub rev: Option<Revision>,
+    pub files: HashMap<HgPathBuf, DiffFile>,
+}
+
+/// The files that differ between the two sides of a comparison, each list
+/// being sorted
+#[derive(Debug, Default)]
+pub struct ChangedFiles {
+    pub modified: Vec<HgPathBuf>,
+    pub added: Vec<HgPathBuf>,
+    pub removed: Vec<HgPathBuf>,
+}
+
+/// Whether a file of a diff was copied or renamed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CopyKind {
+    Copy,
+    Rename,
+}
+
+/// A file to show in a diff
+#[derive(Debug, PartialEq)]
+pub struct FilePair {
+    /// The path on the first side, or `None` for an added file
+    pub path1: Option<HgPathBuf>,
+    /// The path on the second side, or `None` for a removed file
+    pub path2: Option<HgPathBuf>,
+    /// Set when `path2` was copied or renamed from `path1`
+    pub copy: Option<CopyKind>,
+}
+
+/// Compare the files of two sides, like `basectx._buildstatus` in Python.
+///
+/// `is_modified` compares the contents of files of the working directory
+/// that have no node ID and the same flags on both sides.
+pub fn changed_files<E>(
+    side1: &DiffSide,
+    side2: &DiffSide,
+    matcher: &dyn Matcher,
+    mut is_modified: impl FnMut(&HgPath, &DiffFile) -> Result<bool, E>,
+) -> Result<ChangedFiles, E> {
+    let mut changed = ChangedFiles::default();
+    for (path, file2) in &side2.files {
+        if !matcher.matches(path) {
+            continue;
+        }
+        match side1.files.get(path) {
+            None => changed.added.push(path.clone()),
+            Some(file1) => {
+                let modified = if file1.flag != file2.flag {
+                    true
+                } else if let Some(node2) = file2.node {
+                    file1.node != Some(node2)
+                } else {
+                    is_modified(path, file1)?
+                };
+                if modified {
+                    changed.modified.push(path.clone())
+                }
+            }
+        }
+    }
+    for path in side1.files.keys() {
+        if matcher.matches(path) && !side2.files.contains_key(path) {
+            changed.removed.push(path.clone())
+        }
+    }
+    changed.modified.sort();
+    changed.added.sort();
+    changed.removed.sort();
+    Ok(changed)
+}
+
+/// The files to show in a diff in order, like `patch._filepairs` in Python.
+///
+/// Added files with an entry in `copies` are shown as copies, or as renames
+/// when their source was removed. The diff of a rename replaces that of the
+/// removed file.
+pub fn file_pairs(
+    changed: &ChangedFiles,
+    copies: &HashMap<HgPathBuf, HgPathBuf>,
+) -> Vec<FilePair> {
+    let added: HashSet<_> = changed.added.iter().collect();
+    let removed: HashSet<_> = changed.removed.iter().collect();
+    let renamed: HashSet<_> = copies
+        .iter()
+        .filter(|(destination, _)| added.contains(destination))
+        .map(|(_, source)| source)
+        .collect();
+    let mut all: Vec<_> = changed
+        .modified
+        .iter()
+        .chain(&changed.added)
+        .chain(&changed.removed)
+        .collect();
+    all.sort();
+
+    let mut gone = HashSet::new();
+    let mut pairs = Vec::new();
+    for path in all {
+        let (path1, path2, copy) = if added.contains(path) {
+            match copies.get(path) {
+                Some(source) => {
+                    let kind =
+                        if removed.contains(source) && gone.insert(source) {
+                            CopyKind::Rename
+                        } else {
+                            CopyKind::Copy
+                        };
+                    (Some(source.clone()), Some(path.clone()), Some(kind))
+                }
+                None => (None, Some(path.clone()), None),
+            }
+        } else if removed.contains(path) {
+            if renamed.contains(path) {
+                continue;
+            }
+            (Some(path.clone()), None, None)
+        } else {
+            (Some(path.clone()), Some(path.clone()), None)
+        };
+        pairs.push(FilePair { path1, path2, copy })
+    }
+    pairs
+}
+
+/// Reads the files of changesets to compare them
+pub struct Comparison<'a> {
+    repo: &'a Repo,
+    changelog: &'a Changelog,
+    manifest: Manifest<'a>,
+    history: FileHistory<'a>,
+    /// Whether copies are recorded in the sidedata of changesets rather
+    /// than traced through filelogs
+    changeset_centric: bool,
+}
+
+impl<'a> Comparison<'a> {
+    pub fn new(
+        repo: &'a Repo,
+        changelog: &'a Changelog,
+    ) -> Result<Self, RevlogError> {
+        Ok(Self {
+            repo,
+            changelog,
+            manifest: Manifest::open(repo)?,
+            history: FileHistory::new(repo, changelog)?,
+            changeset_centric: repo
+                .requirements()
+                .contains(requirements::COPIESSDC_REQUIREMENT),
+        })
+    }
+
+    /// The manifest of a changeset, only with the directories that
+    /// `matcher` visits for tree manifests
+    fn manifest(
+        &self,
+        rev: Revision,
+        matcher: &dyn Matcher,
+    ) -> Result<ManifestEntry, RevlogError> {
+        let manifest_node = self.changelog.get_rev(rev)?.manifest_node()?;
+        self.manifest
+            .get_node_matching(manifest_node.into(), matcher)
+    }
+
+    /// The files of a changeset, only those of the directories that
+    /// `matcher` visits for tree manifests
+    pub fn changeset(
+        &self,
+        rev: Revision,
+        matcher: &dyn Matcher,
+    ) -> Result<DiffSide, RevlogError> {
+        let mut files = HashMap::new();
+        for file in self.manifest(rev, matcher)?.iter() {
+            let file = file?;
+            let diff_file = DiffFile {
+                node: Some(file.node_id()?),
+                flag: file.flag,
+            };
+            files.insert(file.path.to_owned(), diff_file);
+        }
+        Ok(DiffSide {
+            rev: Some(rev),
+            files,
+        })
+    }
+
+    /// The files that differ between two changesets, like `changed_files`
+    /// for their sides but only walking the parts of their manifests that
+    /// `matcher` can match
+    pub fn changed_files_between(
+        &self,
+        rev1: Revision,
+        rev2: Revision,
+        matcher: &dyn Matcher,
+    ) -> Result<ChangedFiles, RevlogError> {
+        let manifest1 = self.manifest(rev1, matcher)?;
+        let manifest2 = self.manifest(rev2, matcher)?;
+        let mut changed = ChangedFiles::default();
+        for change in manifest1.diff(&manifest2, matcher, false) {
+            match change? {
+                ManifestChange::Added(file) => {
+                    changed.added.push(file.path.to_owned())
+                }
+                ManifestChange::Removed(file) => {
+                    changed.removed.push(file.path.to_owned())
+                }
+                ManifestChange::Modified { new, .. }
+                | ManifestChange::FlagChanged { new, .. } => {
+                    changed.modified.push(new.path.to_owned())
+                }
+                ManifestChange::Clean(_) => {}
+            }
+        }
+        Ok(changed)
+    }
+
+    /// The contents of a file revision, without its copy metadata
+    pub fn file_data(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+    ) -> Result<Vec<u8>, RevlogError> {
+        self.history.data(path, node)
+    }
+
+    /// Where the files of the second side were copied from on the first
+    /// side, like `copies.pathcopies` in Python.
+    ///
+    /// `dirstate` is needed when the second side is the working directory.
+    /// The sides may only have the files of the directories that `matcher`
+    /// visits.
+    pub fn copies(
+        &mut self,
+        side1: &DiffSide,
+        side2: &DiffSide,
+        dirstate: Option<&DirstateMap>,
+        matcher: &dyn Matcher,
+    ) -> Result<HashMap<HgPathBuf, HgPathBuf>, RevlogError> {
+        let read_from =
+            self.repo.config().get(b"experimental", b"copies.read-from");
+        if read_from == Some(b"changeset-only")
+            || read_from == Some(b"compatibility")
+        {
+            return Err(
+                HgError::unsupported("copies stored in changesets").into()
+            );
+        }
+        let rev1 = side1.rev.ok_or_else(|| {
+            HgError::unsupported("copies from the working directory")
+        })?;
+        if rev1 == NULL_REVISION
+            || side2.rev == Some(NULL_REVISION)
+            || side2.rev == Some(rev1)
+        {
+            return Ok(HashMap::new());
+        }
+        // Copies to the working directory are those to its parent followed
+        // by those recorded in the dirstate
+        let (rev2, p1_side, dirstate_copies) = match side2.rev {
+            Some(rev2) => (rev2, None, HashMap::new()),
+            None => {
+                let dirstate = dirstate.ok_or_else(|| {
+                    HgError::unsupported(
+                        "copies to the working directory without a dirstate",
+                    )
+                })?;
+                let dirstate_copies = dirstate_copies(dirstate, matcher);
+                let p1 = self.repo.dirstate_parents()?.p1;
+                let p1 = if p1 == NULL_NODE {
+                    NULL_REVISION
+                } else {
+                    self.changelog.revlog.get_node_rev(p1.into())?
+                };
+                if p1 == rev1 {
+                    return Ok(dirstate_copies);
+                }
+                (p1, Some(self.changeset(p1, matcher)?), dirstate_copies)
+            }
+        };
+        let committed_side2 = p1_side.as_ref().unwrap_or(side2);
+
+        let ancestor = self.common_ancestor(rev1, rev2)?;
+        let mut copies = if ancestor == rev1 {
+            let copies =
+                self.forward_copies(side1, committed_side2, None, matcher)?;
+            chain(copies, dirstate_copies)
+        } else if side2.rev == Some(ancestor) {
+            let full_side1 = self.full_changeset(side1, matcher)?;
+            let full_side2 = self.full_changeset(side2, matcher)?;
+            self.backward_renames(
+                full_side1.as_ref().unwrap_or(side1),
+                full_side2.as_ref().unwrap_or(side2),
+                matcher,
+            )?
+        } else {
+            // Go back from the first side to the common ancestor, then
+            // forward to the second side
+            let full_side1 = self.full_changeset(side1, matcher)?;
+            let side1 = full_side1.as_ref().unwrap_or(side1);
+            let ancestor_side = self.changeset(ancestor, &AlwaysMatcher)?;
+            let base = if ancestor == NULL_REVISION {
+                None
+            } else {
+                Some(side1)
+            };
+            let mut copies1 = self.forward_copies(
+                &ancestor_side,
+                side1,
+                None,
+                &AlwaysMatcher,
+            )?;
+            let copies2 = self.forward_copies(
+                &ancestor_side,
+                committed_side2,
+                base,
+                matcher,
+            )?;
+            let mut copies2 = chain(copies2, dirstate_copies);
+            copies1.retain(|destination, source| {
+                if copies2.get(destination) == Some(source) {
+                    copies2.remove(destination);
+                    false
+                } else {
+                    true
+                }
+            });
+            let renames1 = reverse_renames(copies1, side1, matcher);
+            chain(renames1, copies2)
+        };
+        // Chaining may give copies of files that were then removed, or
+        // that did not exist on the first side
+        let mut kept = HashMap::new();
+        for (destination, source) in copies.drain() {
+            if destination != source
+                && side2.files.contains_key(&destination)
+                && self.find_file(side1, &source, matcher)?.is_some()
+            {
+                kept.insert(destination, source);
+            }
+        }
+        Ok(kept)
+    }
+
+    /// A changeset side with all of its files, if `matcher` may have left
+    /// some of them out
+    fn full_changeset(
+        &self,
+        side: &DiffSide,
+        matcher: &dyn Matcher,
+    ) -> Result<Option<DiffSide>, RevlogError> {
+        match side.rev {
+            Some(rev) if !matcher.matches_everything() => {
+                Ok(Some(self.changeset(rev, &AlwaysMatcher)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// A file of a side read with `matcher`. Like the lazy manifests of
+    /// Python, only the directories of the path are read for a file that
+    /// `matcher` left out of a tree manifest.
+    pub fn find_file(
+        &self,
+        side: &DiffSide,
+        path: &HgPath,
+        matcher: &dyn Matcher,
+    ) -> Result<Option<DiffFile>, RevlogError> {
+        if let Some(file) = side.files.get(path) {
+            return Ok(Some(file.clone()));
+        }
+        let rev = match side.rev {
+            Some(rev) if self.manifest.is_tree() && !matcher.matches(path) => {
+                rev
+            }
+            _ => return Ok(None),
+        };
+        let paths = [path.to_owned()];
+        let file_matcher = FileMatcher::new(&paths).ok();
+        let file_matcher: &dyn Matcher = match &file_matcher {
+            Some(file_matcher) => file_matcher,
+            None => &AlwaysMatcher,
+        };
+        match self.manifest(rev, file_matcher)?.find_file(path)? {
+            Some(file) => Ok(Some(DiffFile {
+                node: Some(file.node_id()?),
+                flag: file.flag,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// The head of the common ancestors of two changesets, like
+    /// `changectx.ancestor` in Python
+    fn common_ancestor(
+        &self,
+        rev1: Revision,
+        rev2: Revision,
+    ) -> Result<Revision, RevlogError> {
+        let graph = self.changelog;
+        let ancestors1: HashSet<_> =
+            AncestorsIterator::new(graph, vec![rev1], 0, true)?
+                .collect::<Result<_, _>>()?;
+        let mut common = Vec::new();
+        for ancestor in AncestorsIterator::new(graph, vec![rev2], 0, true)? {
+            let ancestor = ancestor?;
+            if ancestors1.contains(&ancestor) {
+                common.push(ancestor)
+            }
+        }
+        let heads = dagops::heads(&graph, common.iter())?;
+        let mut heads = heads.into_iter();
+        match (heads.next(), heads.next()) {
+            (None, _) => Ok(NULL_REVISION),
+            (Some(head), None) => Ok(head),
+            // Python picks the one with the smallest node ID among the
+            // "deepest" ones
+            (Some(_), Some(_)) => Err(HgError::unsupported(
+                "copies between changesets with several common ancestors",
+            )
+            .into()),
+        }
+    }
+
+    /// The copies from changeset `side1` to its descendant `side2`, like
+    /// `copies._committedforwardcopies` in Python.
+    ///
+    /// Copy sources are looked for in `side1`, and in `base` if given.
+    fn forward_copies(
+        &mut self,
+        side1: &DiffSide,
+        side2: &DiffSide,
+        base: Option<&DiffSide>,
+        matcher: &dyn Matcher,
+    ) -> Result<HashMap<HgPathBuf, HgPathBuf>, RevlogError> {
+        if self.changeset_centric {
+            let rev = |side: &DiffSide| {
+                side.rev.expect("copies are traced between changesets")
+            };
+            return self.changeset_forward_copies(
+                rev(side1),
+                rev(side2),
+                matcher,
+            );
+        }
+        let mut missing: Vec<_> = side2
+            .files
+            .iter()
+            .filter(|(path, _)| {
+                !side1.files.contains_key(*path) && matcher.matches(path)
+            })
+            .collect();
+        missing.sort_by_key(|(path, _)| *path);
+        let mut copies = HashMap::new();
+        for (path, file) in missing {
+            let node =
+                file.node.expect("changeset files should have a node ID");
+            if let Some(source) =
+                self.trace_file(path, node, side1, base, matcher)?
+            {
+                copies.insert(path.clone(), source);
+            }
+        }
+        Ok(copies)
+    }
+
+    /// The copies from changeset `rev1` to its descendant `rev2` recorded in
+    /// the sidedata of the changesets between them, like
+    /// `copies._changesetforwardcopies` in Python
+    fn changeset_forward_copies(
+        &self,
+        rev1: Revision,
+        rev2: Revision,
+        matcher: &dyn Matcher,
+    ) -> Result<HashMap<HgPathBuf, HgPathBuf>, RevlogError> {
+        if rev1 == NULL_REVISION || rev1 == rev2 {
+            return Ok(HashMap::new());
+        }
+        let mut revs = Vec::new();
+        let mut rev = rev2;
+        while rev != rev1 {
+            let [p1, p2] = self.changelog.parents(rev)?;
+            if p2 != NULL_REVISION {
+                return Err(HgError::unsupported(
+                    "copies stored in changesets across merges",
+                )
+                .into());
+            }
+            if p1 == NULL_REVISION {
+                return Ok(HashMap::new());
+            }
+            revs.push(rev);
+            rev = p1;
+        }
+        // Sources by destination, `None` once the destination is removed
+        let mut copies: HashMap<HgPathBuf, Option<HgPathBuf>> = HashMap::new();
+        for &rev in revs.iter().rev() {
+            let changes = match self.changelog.changing_files(rev)? {
+                Some(changes) => changes,
+                None => continue,
+            };
+            if !changes.copied_from_p1.is_empty() {
+                let previous = copies.clone();
+                for (destination, source) in changes.copied_from_p1 {
+                    let source = match previous.get(&source) {
+                        Some(Some(previous_source)) => previous_source.clone(),
+                        _ => source,
+                    };
+                    copies.insert(destination, Some(source));
+                }
+            }
+            for path in &changes.removed {
+                if let Some(source) = copies.get_mut(path) {
+                    *source = None
+                }
+            }
+        }
+        Ok(copies
+            .into_iter()
+            .filter(|(destination, _)| matcher.matches(destination))
+            .filter_map(|(destination, source)| Some((destination, source?)))
+            .collect())
+    }
+
+    /// The renames from changeset `side1` to its ancestor `side2`, like
+    /// `copies._backwardrenames` in Python
+    fn backward_renames(
+        &mut self,
+        side1: &DiffSide,
+        side2: &DiffSide,
+        matcher: &dyn Matcher,
+    ) -> Result<HashMap<HgPathBuf, HgPathBuf>, RevlogError> {
+        let copy_trace = self.repo.config().get(b"experimental", b"copytrace");
+        if copy_trace == Some(b"off") {
+            return Ok(HashMap::new());
+        }
+        let copies =
+            self.forward_copies(side2, side1, None, &AlwaysMatcher)?;
+        Ok(reverse_renames(copies, side1, matcher))
+    }
+
+    /// The path of the closest ancestor of a file revision that is in
+    /// `side` or `base`, like `copies._tracefile` in Python. Both sides are
+    /// read with `matcher`.
+    fn trace_file(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+        side: &DiffSide,
+        base: Option<&DiffSide>,
+        matcher: &dyn Matcher,
+    ) -> Result<Option<HgPathBuf>, RevlogError> {
+        // Visit ancestors by decreasing link revision
+        let mut visit: BTreeMap<(Revision, Vec<u8>), FileRevision> =
+            BTreeMap::new();
+        let mut current = (path.to_owned(), node);
+        loop {
+            for (parent_path, parent_node) in
+                self.history.parents(&current.0, current.1)?
+            {
+                let link_rev =
+                    self.history.link_revision(&parent_path, parent_node)?;
+                let key = (link_rev, parent_node.as_bytes().to_vec());
+                visit.insert(key, (parent_path, parent_node));
+            }
+            let key = match visit.keys().next_back() {
+                Some(key) => key.clone(),
+                None => return Ok(None),
+            };
+            current = visit.remove(&key).expect("key just found");
+            let (ancestor_path, ancestor_node) = &current;
+            for side in std::iter::once(side).chain(base) {
+                if let Some(file) =
+                    self.find_file(side, ancestor_path, matcher)?
+                {
+                    if file.node == Some(*ancestor_node) {
+                        return Ok(Some(ancestor_path.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Follow the copies of `prefix` with those of `suffix`, like
+/// `copies._chain` in Python
+fn chain(
+    mut prefix: HashMap<HgPathBuf, HgPathBuf>,
+    suffix: HashMap<HgPathBuf, HgPathBuf>,
+) -> HashMap<HgPathBuf, HgPathBuf> {
+    for (destination, source) in suffix {
+        let source = prefix.get(&source).cloned().unwrap_or(source);
+        prefix.insert(destination, source);
+    }
+    prefix
+}
+
+/// Reverse the copies to `side` whose source is not in `side` any more,
+/// like `copies._reverse_renames` in Python.
+///
+/// When a file was renamed to several files, the last one wins.
+fn reverse_renames(
+    copies: HashMap<HgPathBuf, HgPathBuf>,
+    side: &DiffSide,
+    matcher: &dyn Matcher,
+) -> HashMap<HgPathBuf, HgPathBuf> {
+    let mut copies: Vec<_> = copies.into_iter().collect();
+    copies.sort();
+    let mut renames = HashMap::new();
+    for (destination, source) in copies {
+        if matcher.matches(&source) && !side.files.contains_key(&source) {
+            renames.insert(source, destination);
+        }
+    }
+    renames
+}
+
+/// The copies recorded in the dirstate for files that are still tracked
+fn dirstate_copies(
+    dirstate: &DirstateMap,
+    matcher: &dyn Matcher,
+) -> HashMap<HgPathBuf, HgPathBuf> {
+    dirstate
+        .copy_map
+        .iter()
+        .filter(|(destination, _)| {
+            let tracked = match dirstate.get(*destination) {
+                Some(entry) => {
+                    entry.state == EntryState::Added
+                        || entry.state == EntryState::Normal
+                        || entry.state == EntryState::Merged
+                }
+                None => false,
+            };
+            tracked && matcher.matches(destination)
+        })
+        .map(|(destination, source)| (destination.clone(), source.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn paths(paths: &[&str]) -> Vec<HgPathBuf> {
+        paths
+            .iter()
+            .map(|path| HgPathBuf::from_bytes(path.as_bytes()))
+            .collect()
+    }
+
+    fn pair(
+        path1: Option<&str>,
+        path2: Option<&str>,
+        copy: Option<CopyKind>,
+    ) -> FilePair {
+        let path = |path: &str| HgPathBuf::from_bytes(path.as_bytes());
+        FilePair {
+            path1: path1.map(path),
+            path2: path2.map(path),
+            copy,
+        }
+    }
+
+    #[test]
+    fn test_file_pairs() {
+        let changed = ChangedFiles {
+            modified: paths(&["m"]),
+            added: paths(&["b", "c", "new"]),
+            removed: paths(&["a", "gone"]),
+        };
+        let mut copies = HashMap::new();
+        for &(destination, source) in &[("b", "a"), ("c", "a")] {
+            copies.insert(
+                HgPathBuf::from_bytes(destination.as_bytes()),
+                HgPathBuf::from_bytes(source.as_bytes()),
+            );
+        }
+        assert_eq!(
+            file_pairs(&changed, &copies),
+            vec![
+                pair(Some("a"), Some("b"), Some(CopyKind::Rename)),
+                pair(Some("a"), Some("c"), Some(CopyKind::Copy)),
+                pair(Some("gone"), None, None),
+                pair(Some("m"), Some("m"), None),
+                pair(None, Some("new"), None),
+            ]
+        );
+        assert_eq!(
+            &file_pairs(&changed, &HashMap::new())[..2],
+            &[pair(Some("a"), None, None), pair(None, Some("b"), None)]
+        );
+    }
+}
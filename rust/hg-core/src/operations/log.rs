@@ -0,0 +1,514 @@
+// log.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Selecting the changesets shown by `hg log`, like `getrevs` in
+//! `mercurial/logcmdutil.py`
+
+use crate::errors::HgError;
+use crate::matchers::{AlwaysMatcher, FileMatcher, IncludeMatcher, Matcher};
+use crate::repo::Repo;
+use crate::revlog::changelog::{Changelog, ChangelogRevisionData};
+use crate::revlog::filelog::Filelog;
+use crate::revlog::manifest::{Manifest, ManifestEntry};
+use crate::revlog::node::NULL_NODE;
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision, NULL_REVISION};
+use crate::utils::find_slice_in_slice;
+use crate::utils::hg_path::{HgPath, HgPathBuf};
+use crate::{AncestorsIterator, Graph, IgnorePattern, PatternSyntax};
+use std::collections::{BTreeMap, HashMap};
+
+/// Which changesets to show and in what order
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// The changesets given with `--rev`, in order. When `follow` is set,
+    /// these are where history is followed from.
+    ///
+    /// Without them, all changesets are shown from tip to `0`, or history is
+    /// followed from the first parent of the working directory.
+    pub revisions: Option<Vec<Revision>>,
+    /// Show the ancestors of the starting changesets instead of the
+    /// changesets themselves
+    pub follow: bool,
+    /// With `follow`, only show changesets that touched the history of these
+    /// files, following copies and renames
+    pub files: Vec<HgPathBuf>,
+    /// Only show changesets with one of these case-insensitive strings in
+    /// their user, description, or files
+    pub keywords: Vec<String>,
+    /// Only show changesets with one of these case-insensitive strings in
+    /// their user
+    pub users: Vec<String>,
+    /// Only show changesets on one of these branches
+    pub branches: Vec<Vec<u8>>,
+    /// Show at most this many changesets
+    pub limit: Option<usize>,
+}
+
+pub struct LogOutput {
+    /// The changesets to show, in order
+    pub revisions: Vec<Revision>,
+    /// Files followed from several changesets that are missing in some of
+    /// them, with the node ID of that changeset
+    pub missing: Vec<(HgPathBuf, Node)>,
+}
+
+/// Return the changesets to show in `hg log` output, in order.
+pub fn log_revisions(
+    repo: &Repo,
+    options: &LogOptions,
+) -> Result<LogOutput, RevlogError> {
+    let changelog = Changelog::open(repo)?;
+    let mut revs = initial_revisions(repo, &changelog, options)?;
+    let mut missing = Vec::new();
+    if revs.is_empty() {
+        return Ok(LogOutput {
+            revisions: revs,
+            missing,
+        });
+    }
+    if options.follow {
+        revs = if options.files.is_empty() {
+            let has_null = revs.contains(&NULL_REVISION);
+            let mut ancestors =
+                AncestorsIterator::new(&changelog, revs, 0, true)?
+                    .collect::<Result<Vec<_>, _>>()?;
+            if has_null {
+                ancestors.push(NULL_REVISION)
+            }
+            ancestors
+        } else {
+            FileHistory::new(repo, &changelog)?.ancestors(
+                &revs,
+                &options.files,
+                options.revisions.is_some(),
+                &mut missing,
+            )?
+        };
+    }
+
+    let filters = Filters::new(options)?;
+    let mut selected = Vec::new();
+    for rev in revs {
+        if Some(selected.len()) == options.limit {
+            break;
+        }
+        if filters.matches(&changelog, rev)? {
+            selected.push(rev)
+        }
+    }
+    Ok(LogOutput {
+        revisions: selected,
+        missing,
+    })
+}
+
+/// The changesets to show or to follow from, like `_initialrevs` in Python
+fn initial_revisions(
+    repo: &Repo,
+    changelog: &Changelog,
+    options: &LogOptions,
+) -> Result<Vec<Revision>, RevlogError> {
+    if let Some(revisions) = &options.revisions {
+        Ok(revisions.clone())
+    } else if options.follow {
+        let p1 = repo.dirstate_parents()?.p1;
+        if p1 == NULL_NODE {
+            Ok(Vec::new())
+        } else {
+            Ok(vec![changelog.revlog.get_node_rev(p1.into())?])
+        }
+    } else {
+        Ok((0..changelog.revlog.len() as Revision).rev().collect())
+    }
+}
+
+/// The `--keyword`, `--user` and `--branch` conditions, each satisfied when
+/// any of its values matches
+struct Filters {
+    keywords: Vec<Vec<u8>>,
+    users: Vec<Vec<u8>>,
+    branches: Vec<Vec<u8>>,
+}
+
+impl Filters {
+    fn new(options: &LogOptions) -> Result<Self, HgError> {
+        let lowercase = |values: &[String]| -> Result<Vec<Vec<u8>>, HgError> {
+            values
+                .iter()
+                .map(|value| {
+                    // Python lowers non-ASCII text according to the locale
+                    if value.is_ascii() {
+                        Ok(value.to_ascii_lowercase().into_bytes())
+                    } else {
+                        Err(HgError::unsupported("non-ASCII log filters"))
+                    }
+                })
+                .collect()
+        };
+        Ok(Self {
+            keywords: lowercase(&options.keywords)?,
+            users: lowercase(&options.users)?,
+            branches: options.branches.clone(),
+        })
+    }
+
+    fn matches(
+        &self,
+        changelog: &Changelog,
+        rev: Revision,
+    ) -> Result<bool, RevlogError> {
+        if self.keywords.is_empty()
+            && self.users.is_empty()
+            && self.branches.is_empty()
+        {
+            return Ok(true);
+        }
+        let entry = changelog.get_rev(rev)?;
+        let user = entry.user().to_ascii_lowercase();
+        if !self.users.is_empty()
+            && !self.users.iter().any(|u| contains(&user, u))
+        {
+            return Ok(false);
+        }
+        if !self.keywords.is_empty() && !self.match_keywords(&entry, &user) {
+            return Ok(false);
+        }
+        if !self.branches.is_empty() {
+            let (branch, _closed) = entry.branchinfo()?;
+            if !self.branches.contains(&branch) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Like the `keyword()` revset predicate
+    fn match_keywords(
+        &self,
+        entry: &ChangelogRevisionData,
+        user: &[u8],
+    ) -> bool {
+        let mut texts: Vec<Vec<u8>> = entry
+            .files()
+            .map(|file| file.as_bytes().to_ascii_lowercase())
+            .collect();
+        texts.push(user.to_vec());
+        texts.push(entry.description().to_ascii_lowercase());
+        self.keywords
+            .iter()
+            .any(|keyword| texts.iter().any(|text| contains(text, keyword)))
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    find_slice_in_slice(haystack, needle).is_some()
+}
+
+/// A revision of a file, identified by its path and file node
+pub(crate) type FileRevision = (HgPathBuf, Node);
+
+/// Walks the history of files through copies and renames, like
+/// `dagop.filectxancestors` in Python
+pub(crate) struct FileHistory<'a> {
+    repo: &'a Repo,
+    changelog: &'a Changelog,
+    manifest: Manifest<'a>,
+    filelogs: HashMap<HgPathBuf, Filelog>,
+    /// Whether censored contents are read as empty, with
+    /// `censor.policy=ignore`
+    ignore_censored: bool,
+}
+
+impl<'a> FileHistory<'a> {
+    pub(crate) fn new(
+        repo: &'a Repo,
+        changelog: &'a Changelog,
+    ) -> Result<Self, RevlogError> {
+        Ok(Self {
+            repo,
+            changelog,
+            manifest: Manifest::open(repo)?,
+            filelogs: HashMap::new(),
+            ignore_censored: repo.config().get(b"censor", b"policy")
+                == Some(b"ignore"),
+        })
+    }
+
+    /// The changesets that introduced the revisions of `files` found in the
+    /// `start` changesets, and all of their file ancestors, in descending
+    /// order.
+    ///
+    /// Files missing in some of the `start` changesets are added to
+    /// `missing`.
+    fn ancestors(
+        &mut self,
+        start: &[Revision],
+        files: &[HgPathBuf],
+        explicit_start: bool,
+        missing: &mut Vec<(HgPathBuf, Node)>,
+    ) -> Result<Vec<Revision>, RevlogError> {
+        let mut visit: BTreeMap<Revision, Vec<FileRevision>> = BTreeMap::new();
+        let mut found = vec![false; files.len()];
+        let mut missing_in_start = Vec::new();
+        let path_matcher = path_matcher(self.repo, files);
+        let path_matcher: &dyn Matcher = match &path_matcher {
+            Some(path_matcher) => path_matcher,
+            None => &AlwaysMatcher,
+        };
+        for &rev in start {
+            if rev == NULL_REVISION {
+                // No file in the null changeset
+                for file in files {
+                    missing_in_start.push((file.clone(), rev))
+                }
+                continue;
+            }
+            let manifest = self.manifest_for(rev, path_matcher)?;
+            for (file, found) in files.iter().zip(&mut found) {
+                match manifest_file_node(&manifest, file)? {
+                    Some(node) => {
+                        *found = true;
+                        let intro =
+                            self.introduction_revision(file, node, rev, true)?;
+                        add_visit(&mut visit, intro, (file.clone(), node))
+                    }
+                    None if manifest.has_dir(file)? => {
+                        return Err(HgError::unsupported(
+                            "following directory history",
+                        )
+                        .into())
+                    }
+                    None => missing_in_start.push((file.clone(), rev)),
+                }
+            }
+        }
+        if let Some((file, _)) =
+            files.iter().zip(&found).find(|(_, found)| !**found)
+        {
+            if !explicit_start {
+                // Python also looks at files added in the working directory
+                return Err(HgError::unsupported(
+                    "following a file not in the parent revision",
+                )
+                .into());
+            }
+            return Err(HgError::abort(format!(
+                "abort: cannot follow file not in any of the specified \
+                 revisions: \"{}\"",
+                file
+            ))
+            .into());
+        }
+        for (file, rev) in missing_in_start {
+            let node = if rev == NULL_REVISION {
+                NULL_NODE
+            } else {
+                *self
+                    .changelog
+                    .node_from_rev(rev)
+                    .ok_or(RevlogError::InvalidRevision)?
+            };
+            missing.push((file, node))
+        }
+
+        let mut revs = Vec::new();
+        while let Some(&rev) = visit.keys().next_back() {
+            let file_revisions = visit.remove(&rev).expect("key just found");
+            revs.push(rev);
+            for (path, node) in file_revisions {
+                for (parent_path, parent_node) in self.parents(&path, node)? {
+                    let parent_rev = self.introduction_revision(
+                        &parent_path,
+                        parent_node,
+                        rev,
+                        false,
+                    )?;
+                    add_visit(
+                        &mut visit,
+                        parent_rev,
+                        (parent_path, parent_node),
+                    )
+                }
+            }
+        }
+        Ok(revs)
+    }
+
+    pub(crate) fn filelog(
+        &mut self,
+        path: &HgPath,
+    ) -> Result<&Filelog, RevlogError> {
+        if !self.filelogs.contains_key(path) {
+            let filelog = Filelog::open(self.repo, path)?;
+            self.filelogs.insert(path.to_owned(), filelog);
+        }
+        Ok(&self.filelogs[path])
+    }
+
+    /// The manifest of a changeset, only with the directories that
+    /// `matcher` visits for tree manifests
+    fn manifest_for(
+        &self,
+        rev: Revision,
+        matcher: &dyn Matcher,
+    ) -> Result<ManifestEntry, RevlogError> {
+        let manifest_node = self.changelog.get_rev(rev)?.manifest_node()?;
+        self.manifest
+            .get_node_matching(manifest_node.into(), matcher)
+    }
+
+    /// The parent revisions of a file revision. When the file was copied or
+    /// renamed, the copy source replaces the null first parent.
+    pub(crate) fn parents(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+    ) -> Result<Vec<FileRevision>, RevlogError> {
+        let filelog = self.filelog(path)?;
+        let rev = filelog.rev_from_node(node.into())?;
+        let [p1, p2] = filelog
+            .parents(rev)
+            .map_err(|_| HgError::corrupted("filelog revision not found"))?;
+        let copy_source = filelog.copy_source(rev).map_err(|error| {
+            match error {
+                // Reading the metadata of a censored revision fails whatever
+                // the policy, like `CensoredNodeError` in Python
+                RevlogError::Censored { node, .. } => HgError::abort(format!(
+                    "abort: file censored data/{}.i:{:x}",
+                    path,
+                    node.short()
+                ))
+                .into(),
+                error => error,
+            }
+        })?;
+        let mut parents = Vec::new();
+        parents.extend(copy_source);
+        for &parent in &[p1, p2] {
+            if parent != NULL_REVISION {
+                let parent_node =
+                    *filelog.node_from_rev(parent).ok_or_else(|| {
+                        HgError::corrupted("filelog parent not found")
+                    })?;
+                parents.push((path.to_owned(), parent_node))
+            }
+        }
+        Ok(parents)
+    }
+
+    /// The contents of a file revision, without its copy metadata, like
+    /// `filectx.data` in Python
+    pub(crate) fn data(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+    ) -> Result<Vec<u8>, RevlogError> {
+        let ignore_censored = self.ignore_censored;
+        let filelog = self.filelog(path)?;
+        match filelog.get_node(node.into()) {
+            Ok(entry) => Ok(entry.into_data()?),
+            Err(RevlogError::Censored { .. }) if ignore_censored => {
+                Ok(Vec::new())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The changeset that a file revision was first added with
+    pub(crate) fn link_revision(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+    ) -> Result<Revision, RevlogError> {
+        let filelog = self.filelog(path)?;
+        filelog.link_revision(filelog.rev_from_node(node.into())?)
+    }
+
+    /// The changeset that introduced a file revision, among `start` and its
+    /// ancestors, like `basefilectx._adjustlinkrev` in Python.
+    ///
+    /// This is usually the link revision of the file revision, but when the
+    /// same file revision was introduced by several changesets the link
+    /// revision only points to the first one.
+    pub(crate) fn introduction_revision(
+        &mut self,
+        path: &HgPath,
+        node: Node,
+        start: Revision,
+        inclusive: bool,
+    ) -> Result<Revision, RevlogError> {
+        let link_rev = self.link_revision(path, node)?;
+        if link_rev == start {
+            return Ok(link_rev);
+        }
+        let graph = self.changelog;
+        if AncestorsIterator::new(graph, vec![start], link_rev, inclusive)?
+            .contains(link_rev)?
+        {
+            return Ok(link_rev);
+        }
+        let files = [path.to_owned()];
+        let file_matcher = FileMatcher::new(&files).ok();
+        let file_matcher: &dyn Matcher = match &file_matcher {
+            Some(file_matcher) => file_matcher,
+            None => &AlwaysMatcher,
+        };
+        for ancestor in
+            AncestorsIterator::new(graph, vec![start], link_rev, inclusive)?
+        {
+            let ancestor = ancestor?;
+            let entry = self.changelog.get_rev(ancestor)?;
+            if entry.files().any(|file| file == path) {
+                let manifest = self.manifest_for(ancestor, file_matcher)?;
+                if manifest_file_node(&manifest, path)? == Some(node) {
+                    return Ok(ancestor);
+                }
+            }
+        }
+        Ok(link_rev)
+    }
+}
+
+/// A matcher for the given paths and the files under them when they are
+/// directories, like `path:` patterns, or `None` if one of them cannot be a
+/// pattern
+pub(crate) fn path_matcher(
+    repo: &Repo,
+    paths: &[HgPathBuf],
+) -> Option<IncludeMatcher<'static>> {
+    let patterns = paths
+        .iter()
+        .map(|path| {
+            IgnorePattern::new(PatternSyntax::Path, path.as_bytes(), "")
+        })
+        .collect();
+    let (matcher, _) =
+        IncludeMatcher::new(patterns, repo.working_directory_path()).ok()?;
+    Some(matcher)
+}
+
+fn add_visit(
+    visit: &mut BTreeMap<Revision, Vec<FileRevision>>,
+    rev: Revision,
+    file_revision: FileRevision,
+) {
+    let file_revisions = visit.entry(rev).or_default();
+    if !file_revisions.contains(&file_revision) {
+        file_revisions.push(file_revision)
+    }
+}
+
+fn manifest_file_node(
+    manifest: &ManifestEntry,
+    path: &HgPath,
+) -> Result<Option<Node>, HgError> {
+    manifest
+        .find_file(path)?
+        .map(|file| file.node_id())
+        .transpose()
+}
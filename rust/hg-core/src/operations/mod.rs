@@ -2,11 +2,28 @@
 //! An operation is what can be done whereas a command is what is exposed by
 //! the cli. A single command can use several operations to achieve its goal.
 
+mod annotate;
 mod cat;
 mod debugdata;
+mod debugrevlog;
+mod diff;
 mod dirstate_status;
 mod list_tracked_files;
+mod log;
+mod verify;
+pub use annotate::{AnnotateOptions, AnnotatedLine, Annotator};
 pub use cat::{cat, CatOutput};
 pub use debugdata::{debug_data, DebugDataKind};
+pub use debugrevlog::{
+    debug_delta_chain, debug_index, debug_open_revlog, debug_revlog_stats,
+    DebugRevlogKind, DeltaChainLine, DeltaType, IndexLine, RevlogStats,
+    SizeStats, SparseRead,
+};
+pub use diff::{
+    changed_files, file_pairs, ChangedFiles, Comparison, CopyKind, DiffFile,
+    DiffSide, FilePair,
+};
 pub use list_tracked_files::Dirstate;
 pub use list_tracked_files::{list_rev_tracked_files, FilesForRev};
+pub use log::{log_revisions, LogOptions, LogOutput};
+pub use verify::{verify, VerifyOutput, VerifyProblem, VerifyStep};
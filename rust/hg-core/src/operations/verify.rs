@@ -0,0 +1,1090 @@
+// verify.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Checking the integrity of a repository, like `mercurial/verify.py`
+//!
+//! Changesets are checked first, collecting the manifests and files they
+//! refer to, then manifests, collecting the file revisions they refer to.
+//! Filelogs are checked last and in parallel, being independent from each
+//! other, after the files of changesets and manifests are crosschecked.
+
+use crate::errors::HgError;
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::filelog::Filelog;
+use crate::revlog::manifest::Manifest;
+use crate::revlog::node::{Node, NULL_NODE};
+use crate::revlog::revlog::{Revlog, RevlogError};
+use crate::revlog::{Revision, NULL_REVISION};
+use crate::store::{self, Fncache};
+use crate::utils::hg_path::{HgPath, HgPathBuf};
+use format_bytes::format_bytes;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Changesets read by each parallel task
+const CHANGESETS_PER_TASK: usize = 1000;
+
+/// An issue found when verifying a repository
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyProblem {
+    /// Something suspicious that does not make the repository unusable
+    Warning(Vec<u8>),
+    /// Corrupted or inconsistent data
+    Error {
+        /// The changeset affected by the error, if known
+        link_rev: Option<Revision>,
+        /// The revlog or file where the error was found, if any
+        label: Option<Vec<u8>>,
+        message: Vec<u8>,
+    },
+}
+
+impl VerifyProblem {
+    /// The line reporting the problem, like `hg verify` prints it
+    pub fn format(&self) -> Vec<u8> {
+        match self {
+            VerifyProblem::Warning(message) => format_bytes!(b"{}\n", message),
+            VerifyProblem::Error {
+                link_rev,
+                label,
+                message,
+            } => {
+                let link_rev = match link_rev {
+                    Some(rev) => rev.to_string().into_bytes(),
+                    None => b"?".to_vec(),
+                };
+                match label {
+                    Some(label) => format_bytes!(
+                        b" {}@{}: {}\n",
+                        label,
+                        link_rev,
+                        message
+                    ),
+                    None => format_bytes!(b" {}: {}\n", link_rev, message),
+                }
+            }
+        }
+    }
+}
+
+/// The problems found by one of the steps of `verify`, in order
+pub struct VerifyStep {
+    /// What the step checks, like "checking changesets"
+    pub description: &'static str,
+    pub problems: Vec<VerifyProblem>,
+}
+
+pub struct VerifyOutput {
+    /// Whether an interrupted transaction left a journal in the store
+    pub abandoned_transaction: bool,
+    pub steps: Vec<VerifyStep>,
+    /// The number of changesets checked
+    pub changesets: usize,
+    /// The number of file revisions checked
+    pub file_revisions: usize,
+    /// The number of files checked
+    pub files: usize,
+    /// Whether some revlogs are missing from the fncache
+    pub fncache_warned: bool,
+}
+
+impl VerifyOutput {
+    fn problems(&self) -> impl Iterator<Item = &VerifyProblem> {
+        self.steps.iter().flat_map(|step| &step.problems)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.problems()
+            .filter(|problem| match problem {
+                VerifyProblem::Warning(_) => true,
+                VerifyProblem::Error { .. } => false,
+            })
+            .count()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.problems().count() - self.warnings()
+    }
+
+    /// The first changeset affected by errors, if known
+    pub fn first_damaged(&self) -> Option<Revision> {
+        self.problems()
+            .filter_map(|problem| match problem {
+                VerifyProblem::Error { link_rev, .. } => *link_rev,
+                VerifyProblem::Warning(_) => None,
+            })
+            .min()
+    }
+}
+
+/// Verify the integrity of a repository, like `hg verify`.
+///
+/// With `full`, manifests are also read entirely to check their hashes.
+/// Problems in the repository are reported in the output, errors are only
+/// returned when they prevent checking at all.
+pub fn verify(repo: &Repo, full: bool) -> Result<VerifyOutput, HgError> {
+    // Subrepositories are verified along with the repository
+    if !Filelog::open(repo, HgPath::new(b".hgsubstate"))
+        .map_err(revlog_error)?
+        .is_empty()
+    {
+        return Err(HgError::unsupported("verifying subrepositories"));
+    }
+    let changelog = Changelog::open(repo).map_err(revlog_error)?;
+    let manifest = Manifest::open(repo).map_err(revlog_error)?;
+    if manifest.is_tree() {
+        return Err(HgError::unsupported("verifying tree manifests"));
+    }
+    let verifier = Verifier {
+        repo,
+        changelog_len: changelog.len(),
+        have_manifest: !manifest.revlog.is_empty(),
+        error_on_censored: repo.config().get(b"censor", b"policy")
+            != Some(b"ignore"),
+        skip_flags: repo
+            .config()
+            .get_u32(b"verify", b"skipflags")?
+            .unwrap_or(0) as u16,
+    };
+    let mut steps = Vec::new();
+
+    let mut problems = Vec::new();
+    let (mut manifest_link_revs, file_link_revs, refers_manifest) =
+        verifier.verify_changelog(&changelog, &mut problems)?;
+    steps.push(VerifyStep {
+        description: "checking changesets",
+        problems,
+    });
+
+    let mut problems = Vec::new();
+    let mut file_nodes = verifier.verify_manifest(
+        &manifest,
+        &mut manifest_link_revs,
+        refers_manifest,
+        full,
+        &mut problems,
+    )?;
+    steps.push(VerifyStep {
+        description: "checking manifests",
+        problems,
+    });
+
+    let mut problems = Vec::new();
+    verifier.crosscheck_files(&file_link_revs, &file_nodes, &mut problems);
+    steps.push(VerifyStep {
+        description: "crosschecking files in changesets and manifests",
+        problems,
+    });
+
+    let mut problems = Vec::new();
+    let files: BTreeSet<HgPathBuf> = file_link_revs
+        .keys()
+        .chain(file_nodes.keys())
+        .cloned()
+        .collect();
+    let tasks: Vec<_> = files
+        .iter()
+        .map(|path| {
+            let link_revs = file_link_revs
+                .get(path)
+                .map(|revs| &revs[..])
+                .unwrap_or(&[]);
+            (path, link_revs, file_nodes.remove(path))
+        })
+        .collect();
+    let results = tasks
+        .into_par_iter()
+        .map(|(path, link_revs, nodes)| {
+            verifier.verify_file(path, link_revs, nodes)
+        })
+        .collect::<Result<Vec<_>, HgError>>()?;
+    let mut store_files = verifier.store_files()?;
+    let mut file_revisions = 0;
+    let mut fncache_warned = false;
+    for result in results {
+        for revlog_file in result.revlog_files {
+            if !store_files.remove(&revlog_file) {
+                problems.push(VerifyProblem::Warning(format_bytes!(
+                    b" warning: revlog '{}' not in fncache!",
+                    revlog_file
+                )));
+                fncache_warned = true
+            }
+        }
+        problems.extend(result.problems);
+        file_revisions += result.revisions
+    }
+    for store_file in store_files {
+        problems.push(VerifyProblem::Warning(format_bytes!(
+            b"warning: orphan data file '{}'",
+            store_file
+        )))
+    }
+    steps.push(VerifyStep {
+        description: "checking files",
+        problems,
+    });
+
+    Ok(VerifyOutput {
+        abandoned_transaction: repo.store_vfs().join("journal").exists(),
+        steps,
+        changesets: changelog.len(),
+        file_revisions,
+        files: files.len(),
+        fncache_warned,
+    })
+}
+
+/// What is needed by all steps of `verify`, shared by parallel tasks
+struct Verifier<'a> {
+    repo: &'a Repo,
+    changelog_len: usize,
+    have_manifest: bool,
+    /// Whether censored file revisions are errors, like with the default
+    /// `censor.policy`
+    error_on_censored: bool,
+    /// Flags of file revisions that are not read, from `verify.skipflags`
+    skip_flags: u16,
+}
+
+/// The problems found in a filelog
+struct FileResult {
+    /// The index and data files of the filelog, as in the fncache
+    revlog_files: Vec<Vec<u8>>,
+    problems: Vec<VerifyProblem>,
+    /// The number of revisions of the filelog
+    revisions: usize,
+}
+
+impl Verifier<'_> {
+    fn have_changelog(&self) -> bool {
+        self.changelog_len > 0
+    }
+
+    /// Check the changelog, returning the changesets that refer to each
+    /// manifest and file, and whether any refers to a manifest
+    #[allow(clippy::type_complexity)]
+    fn verify_changelog(
+        &self,
+        changelog: &Changelog,
+        problems: &mut Vec<VerifyProblem>,
+    ) -> Result<
+        (
+            HashMap<Node, Vec<Revision>>,
+            BTreeMap<HgPathBuf, Vec<Revision>>,
+            bool,
+        ),
+        HgError,
+    > {
+        self.check_revlog(&changelog.revlog, b"changelog", 0, problems)?;
+        // Reading changesets checks their hash, which is the costly part
+        let revs: Vec<Revision> = (0..changelog.len() as Revision).collect();
+        let changesets = revs
+            .par_chunks(CHANGESETS_PER_TASK)
+            .map(|revs| {
+                let changelog =
+                    Changelog::open(self.repo).map_err(revlog_error)?;
+                Ok(revs
+                    .iter()
+                    .map(|&rev| {
+                        let changeset = changelog.get_rev(rev)?;
+                        let files: Vec<_> =
+                            changeset.files().map(HgPath::to_owned).collect();
+                        Ok((changeset.manifest_node()?, files))
+                    })
+                    .collect::<Vec<Result<_, RevlogError>>>())
+            })
+            .collect::<Result<Vec<_>, HgError>>()?;
+
+        let mut manifest_link_revs = HashMap::new();
+        let mut file_link_revs = BTreeMap::new();
+        let mut refers_manifest = false;
+        let mut seen = HashMap::new();
+        let changesets = changesets.into_iter().flatten();
+        for (rev, changeset) in (0..).zip(changesets) {
+            let node = self.check_entry(
+                &changelog.revlog,
+                rev,
+                &mut seen,
+                &[rev],
+                b"changelog",
+                None,
+                problems,
+            )?;
+            match changeset {
+                Ok((manifest_node, files)) => {
+                    if manifest_node != NULL_NODE {
+                        manifest_link_revs
+                            .entry(manifest_node)
+                            .or_insert_with(Vec::new)
+                            .push(rev);
+                        refers_manifest = true
+                    }
+                    for file in files {
+                        file_link_revs
+                            .entry(file)
+                            .or_insert_with(Vec::new)
+                            .push(rev)
+                    }
+                }
+                Err(error) => {
+                    refers_manifest = true;
+                    problems.push(exception(
+                        Some(rev),
+                        format_bytes!(b"unpacking changeset {}", short(&node)),
+                        error,
+                        None,
+                    )?)
+                }
+            }
+        }
+        Ok((manifest_link_revs, file_link_revs, refers_manifest))
+    }
+
+    /// Check the manifest log, returning the changeset that first refers to
+    /// each file revision.
+    ///
+    /// Manifests found are removed from `link_revs`.
+    fn verify_manifest(
+        &self,
+        manifest: &Manifest,
+        link_revs: &mut HashMap<Node, Vec<Revision>>,
+        refers_manifest: bool,
+        full: bool,
+        problems: &mut Vec<VerifyProblem>,
+    ) -> Result<BTreeMap<HgPathBuf, HashMap<Node, Option<Revision>>>, HgError>
+    {
+        let label = b"manifest";
+        if refers_manifest {
+            // Only changesets with a null manifest do not need any
+            self.check_revlog(&manifest.revlog, label, 0, problems)?;
+        }
+        let mut file_nodes = BTreeMap::new();
+        let mut seen = HashMap::new();
+        for rev in 0..manifest.revlog.len() as Revision {
+            let node = *manifest
+                .revlog
+                .index
+                .get_entry(rev)
+                .expect("revision in range")
+                .hash();
+            let entry_link_revs = link_revs.remove(&node);
+            let link_rev = self.check_entry_link_rev(
+                &manifest.revlog,
+                rev,
+                &mut seen,
+                entry_link_revs.as_deref().unwrap_or(&[]),
+                label,
+                None,
+                problems,
+            )?;
+            if entry_link_revs.is_none() {
+                problems.push(error(
+                    link_rev,
+                    format_bytes!(b"{} not in changesets", short(&node)),
+                    Some(label),
+                ))
+            }
+            // Only reading what each revision adds to its delta parent is
+            // enough to find all file revisions, and much cheaper
+            let files = manifest.read_delta(rev).and_then(|delta| {
+                let mut files = Vec::new();
+                for file in delta.iter() {
+                    let file = file?;
+                    files.push((file.path.to_owned(), file.node_id()?))
+                }
+                Ok(files)
+            });
+            match files {
+                Ok(files) => {
+                    for (path, file_node) in files {
+                        if path.is_empty() {
+                            problems.push(error(
+                                link_rev,
+                                b"entry without name in manifest".to_vec(),
+                                None,
+                            ))
+                        // Ignored in very old repositories
+                        } else if path.as_bytes() != b"/dev/null" {
+                            file_nodes
+                                .entry(path)
+                                .or_insert_with(HashMap::new)
+                                .entry(file_node)
+                                .or_insert(link_rev);
+                        }
+                    }
+                }
+                Err(revlog_error) => problems.push(exception(
+                    link_rev,
+                    format_bytes!(b"reading delta {}", short(&node)),
+                    revlog_error,
+                    Some(label),
+                )?),
+            }
+            if full {
+                if let Err(revlog_error) = manifest.get_rev(rev) {
+                    problems.push(exception(
+                        link_rev,
+                        format_bytes!(
+                            b"reading full manifest {}",
+                            short(&node)
+                        ),
+                        revlog_error,
+                        Some(label),
+                    )?)
+                }
+            }
+        }
+        if self.have_manifest {
+            let mut missing: Vec<_> = link_revs
+                .iter()
+                .flat_map(|(node, revs)| {
+                    revs.iter().map(move |&rev| (rev, node))
+                })
+                .collect();
+            missing
+                .sort_by_key(|&(rev, node)| (rev, node.as_bytes().to_owned()));
+            for (rev, node) in missing {
+                problems.push(error(
+                    Some(rev),
+                    format_bytes!(
+                        b"changeset refers to unknown revision {}",
+                        short(node)
+                    ),
+                    Some(label),
+                ))
+            }
+        }
+        Ok(file_nodes)
+    }
+
+    /// Check that files changed by changesets are in manifests, and the
+    /// other way around
+    fn crosscheck_files(
+        &self,
+        file_link_revs: &BTreeMap<HgPathBuf, Vec<Revision>>,
+        file_nodes: &BTreeMap<HgPathBuf, HashMap<Node, Option<Revision>>>,
+        problems: &mut Vec<VerifyProblem>,
+    ) {
+        if self.have_manifest {
+            for (path, link_revs) in file_link_revs {
+                if !file_nodes.contains_key(path) {
+                    problems.push(error(
+                        link_revs.first().copied(),
+                        b"in changeset but not in manifest".to_vec(),
+                        Some(path.as_bytes()),
+                    ))
+                }
+            }
+        }
+        if self.have_changelog() {
+            for (path, nodes) in file_nodes {
+                if !file_link_revs.contains_key(path) {
+                    let link_rev = self.first_link_rev(path, nodes.keys());
+                    problems.push(error(
+                        link_rev,
+                        b"in manifest but not in changeset".to_vec(),
+                        Some(path.as_bytes()),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// The first changeset that introduced any of the given revisions of a
+    /// file, if they can be found
+    fn first_link_rev<'a>(
+        &self,
+        path: &HgPath,
+        nodes: impl Iterator<Item = &'a Node>,
+    ) -> Option<Revision> {
+        let filelog = Filelog::open(self.repo, path).ok()?;
+        let mut link_revs = Vec::new();
+        for node in nodes {
+            let rev = filelog.rev_from_node((*node).into()).ok()?;
+            link_revs.push(filelog.link_revision(rev).ok()?)
+        }
+        link_revs.into_iter().min()
+    }
+
+    /// Check the filelog of a file, given the changesets that changed it
+    /// and the revisions that manifests refer to
+    fn verify_file(
+        &self,
+        path: &HgPath,
+        link_revs: &[Revision],
+        mut nodes: Option<HashMap<Node, Option<Revision>>>,
+    ) -> Result<FileResult, HgError> {
+        let label = path.as_bytes();
+        let first_link_rev = link_revs.first().copied();
+        let mut result = FileResult {
+            revlog_files: Vec::new(),
+            problems: Vec::new(),
+            revisions: 0,
+        };
+        let problems = &mut result.problems;
+        let filelog = match Filelog::open(self.repo, path) {
+            Ok(filelog) => filelog,
+            Err(revlog_error) => {
+                let message = describe(revlog_error)?;
+                problems.push(error(
+                    first_link_rev,
+                    format_bytes!(b"broken revlog! ({})", message),
+                    Some(label),
+                ));
+                return Ok(result);
+            }
+        };
+        let revlog = &filelog.revlog;
+        result
+            .revlog_files
+            .push(format_bytes!(b"data/{}.i", path.as_bytes()));
+        if !revlog.index.is_inline() {
+            result
+                .revlog_files
+                .push(format_bytes!(b"data/{}.d", path.as_bytes()));
+        }
+
+        let mut skip_read = HashSet::new();
+        if filelog.is_empty() && (self.have_changelog() || self.have_manifest)
+        {
+            problems.push(error(
+                first_link_rev,
+                format_bytes!(b"empty or missing {}", label),
+                None,
+            ))
+        } else {
+            self.check_integrity(
+                revlog,
+                label,
+                first_link_rev,
+                &mut skip_read,
+                problems,
+            )?
+        }
+
+        let mut seen = HashMap::new();
+        for rev in 0..filelog.len() as Revision {
+            result.revisions += 1;
+            let node = *filelog.node_from_rev(rev).expect("revision in range");
+            let link_rev = self.check_entry_link_rev(
+                revlog,
+                rev,
+                &mut seen,
+                link_revs,
+                label,
+                Some(path),
+                problems,
+            )?;
+            if let Some(nodes) = &mut nodes {
+                if self.have_manifest && !nodes.contains_key(&node) {
+                    problems.push(error(
+                        link_rev,
+                        format_bytes!(b"{} not in manifests", short(&node)),
+                        Some(label),
+                    ))
+                } else {
+                    nodes.remove(&node);
+                }
+            }
+            if !skip_read.contains(&node) {
+                match self.check_copy(&filelog, rev, link_rev, label) {
+                    Ok(problem) => problems.extend(problem),
+                    Err(revlog_error) => problems.push(exception(
+                        link_rev,
+                        format_bytes!(b"checking rename of {}", short(&node)),
+                        revlog_error,
+                        Some(label),
+                    )?),
+                }
+            }
+        }
+
+        if let Some(nodes) = nodes {
+            let mut unknown: Vec<_> = nodes.into_iter().collect();
+            unknown.sort_by_key(|&(node, link_rev)| {
+                (link_rev, node.as_bytes().to_owned())
+            });
+            for (node, link_rev) in unknown {
+                problems.push(error(
+                    link_rev,
+                    format_bytes!(
+                        b"manifest refers to unknown revision {}",
+                        short(&node)
+                    ),
+                    Some(label),
+                ))
+            }
+        }
+        Ok(result)
+    }
+
+    /// Check the sizes of a revlog and that its revisions can be read, like
+    /// `verifyintegrity` in Python.
+    ///
+    /// Revisions that cannot be read are added to `skip_read`.
+    fn check_integrity(
+        &self,
+        revlog: &Revlog,
+        label: &[u8],
+        link_rev: Option<Revision>,
+        skip_read: &mut HashSet<Node>,
+        problems: &mut Vec<VerifyProblem>,
+    ) -> Result<(), HgError> {
+        self.check_size(revlog, label, link_rev, problems)?;
+        let mut valid_chains = Vec::with_capacity(revlog.len());
+        for rev in 0..revlog.len() as Revision {
+            let entry =
+                revlog.index.get_entry(rev).expect("revision in range");
+            let node = *entry.hash();
+            let link_rev = Some(entry.link_revision());
+            let storage_problem =
+                check_storage(revlog, rev, &mut valid_chains)
+                    .map_err(revlog_error)?;
+            if let Some(message) = storage_problem {
+                problems.push(error(
+                    link_rev,
+                    format_bytes!(b"unpacking {}: {}", short(&node), message),
+                    Some(label),
+                ));
+                skip_read.insert(node);
+                continue;
+            }
+            let mut read = || {
+                if entry.flags() & self.skip_flags != 0 {
+                    skip_read.insert(node);
+                } else {
+                    // Check the hash
+                    revlog.get_rev_data(rev)?;
+                }
+                Ok(revlog.get_raw_rev_data(rev)?.len())
+            };
+            match read() {
+                Ok(len) if len != entry.uncompressed_len() => {
+                    problems.push(error(
+                        link_rev,
+                        format_bytes!(
+                            b"unpacked size is {}, {} expected",
+                            len,
+                            entry.uncompressed_len()
+                        ),
+                        Some(label),
+                    ))
+                }
+                Ok(_) => {}
+                Err(RevlogError::Censored { .. }) => {
+                    if self.error_on_censored {
+                        problems.push(error(
+                            link_rev,
+                            b"censored file data".to_vec(),
+                            Some(label),
+                        ));
+                        skip_read.insert(node);
+                    }
+                }
+                Err(revlog_error) => {
+                    let message = describe(revlog_error)?;
+                    problems.push(error(
+                        link_rev,
+                        format_bytes!(
+                            b"unpacking {}: {}",
+                            short(&node),
+                            message
+                        ),
+                        Some(label),
+                    ));
+                    skip_read.insert(node);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the source of a copy exists
+    fn check_copy(
+        &self,
+        filelog: &Filelog,
+        rev: Revision,
+        link_rev: Option<Revision>,
+        label: &[u8],
+    ) -> Result<Option<VerifyProblem>, RevlogError> {
+        let copy_source = filelog.copy_source(rev).map_err(|error| {
+            match error {
+                // Named like `CensoredNodeError` in Python
+                RevlogError::Censored { node, .. } => {
+                    HgError::corrupted(format!(
+                        "data/{}.i:{:x}",
+                        HgPath::new(label),
+                        node.short()
+                    ))
+                    .into()
+                }
+                error => error,
+            }
+        })?;
+        let (source, source_node) = match copy_source {
+            Some(copy_source) => copy_source,
+            None => return Ok(None),
+        };
+        let source_filelog = Filelog::open(self.repo, &source)?;
+        if source_filelog.is_empty() {
+            return Ok(Some(error(
+                link_rev,
+                format_bytes!(
+                    b"empty or missing copy source revlog {}:{}",
+                    source.as_bytes(),
+                    short(&source_node)
+                ),
+                Some(label),
+            )));
+        }
+        if source_node != NULL_NODE {
+            match source_filelog.rev_from_node(source_node.into()) {
+                Err(RevlogError::InvalidRevision) => {
+                    return Err(HgError::corrupted(format!(
+                        "data/{}.i@{:x}: no node",
+                        source,
+                        source_node.short()
+                    ))
+                    .into())
+                }
+                result => {
+                    result?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check a changelog, manifest or filelog, like `_checkrevlog` in
+    /// Python
+    fn check_revlog(
+        &self,
+        revlog: &Revlog,
+        label: &[u8],
+        link_rev: Revision,
+        problems: &mut Vec<VerifyProblem>,
+    ) -> Result<(), HgError> {
+        if revlog.is_empty() && (self.have_changelog() || self.have_manifest) {
+            problems.push(error(
+                Some(link_rev),
+                format_bytes!(b"empty or missing {}", label),
+                None,
+            ));
+            return Ok(());
+        }
+        self.check_size(revlog, label, None, problems)
+    }
+
+    /// Check that the index and data files of a revlog have the expected
+    /// sizes
+    fn check_size(
+        &self,
+        revlog: &Revlog,
+        label: &[u8],
+        link_rev: Option<Revision>,
+        problems: &mut Vec<VerifyProblem>,
+    ) -> Result<(), HgError> {
+        let (data_extra, index_extra) =
+            revlog.check_size().map_err(revlog_error)?;
+        if data_extra != 0 {
+            problems.push(error(
+                link_rev,
+                format_bytes!(
+                    b"data length off by {} bytes",
+                    (data_extra as i64)
+                ),
+                Some(label),
+            ))
+        }
+        if index_extra != 0 {
+            problems.push(error(
+                link_rev,
+                format_bytes!(
+                    b"index contains {} extra bytes",
+                    (index_extra as i64)
+                ),
+                Some(label),
+            ))
+        }
+        Ok(())
+    }
+
+    /// Check a revision of a revlog, returning its node ID
+    #[allow(clippy::too_many_arguments)]
+    fn check_entry(
+        &self,
+        revlog: &Revlog,
+        rev: Revision,
+        seen: &mut HashMap<Node, Revision>,
+        link_revs: &[Revision],
+        label: &[u8],
+        path: Option<&HgPath>,
+        problems: &mut Vec<VerifyProblem>,
+    ) -> Result<Node, HgError> {
+        self.check_entry_link_rev(
+            revlog, rev, seen, link_revs, label, path, problems,
+        )?;
+        Ok(*revlog
+            .index
+            .get_entry(rev)
+            .expect("revision in range")
+            .hash())
+    }
+
+    /// Check a revision of a revlog, like `_checkentry` in Python:
+    ///
+    /// - its link revision is one of the changesets that refer to it,
+    /// - its parents come before it,
+    /// - it is not a duplicate.
+    ///
+    /// Return its link revision, unless it cannot be trusted.
+    #[allow(clippy::too_many_arguments)]
+    fn check_entry_link_rev(
+        &self,
+        revlog: &Revlog,
+        rev: Revision,
+        seen: &mut HashMap<Node, Revision>,
+        link_revs: &[Revision],
+        label: &[u8],
+        path: Option<&HgPath>,
+        problems: &mut Vec<VerifyProblem>,
+    ) -> Result<Option<Revision>, HgError> {
+        let entry = revlog.index.get_entry(rev).expect("revision in range");
+        let node = *entry.hash();
+        let mut link_rev = Some(entry.link_revision());
+        let raw_link_rev = entry.link_revision();
+        if raw_link_rev < 0
+            || (self.have_changelog() && !link_revs.contains(&raw_link_rev))
+        {
+            let kind: &[u8] = if raw_link_rev < 0
+                || raw_link_rev as usize >= self.changelog_len
+            {
+                b"nonexistent"
+            } else {
+                b"unexpected"
+            };
+            problems.push(error(
+                None,
+                format_bytes!(
+                    b"rev {} points to {} changeset {}",
+                    rev,
+                    kind,
+                    raw_link_rev
+                ),
+                Some(label),
+            ));
+            if !link_revs.is_empty() {
+                let link_revs = match path {
+                    Some(path) if link_revs.len() > 1 => {
+                        self.filter_link_revs(path, &node, link_revs)
+                    }
+                    _ => link_revs.to_vec(),
+                };
+                let link_revs: Vec<_> =
+                    link_revs.iter().map(|rev| rev.to_string()).collect();
+                problems.push(VerifyProblem::Warning(
+                    format!(" (expected {})", link_revs.join(" "))
+                        .into_bytes(),
+                ))
+            }
+            // It cannot be trusted
+            link_rev = None
+        }
+
+        let parents = [(1, entry.p1()), (2, entry.p2())];
+        for &(number, parent) in &parents {
+            if parent == NULL_REVISION {
+                continue;
+            }
+            let parent_node = match revlog.index.get_entry(parent) {
+                Some(parent_entry) if parent >= 0 => *parent_entry.hash(),
+                _ => {
+                    problems.push(error(
+                        link_rev,
+                        format_bytes!(
+                            b"checking parents of {}: \
+                              revlog index out of range",
+                            short(&node)
+                        ),
+                        Some(label),
+                    ));
+                    break;
+                }
+            };
+            if !seen.contains_key(&parent_node) && parent_node != NULL_NODE {
+                problems.push(error(
+                    link_rev,
+                    format_bytes!(
+                        b"unknown parent {} {} of {}",
+                        number,
+                        short(&parent_node),
+                        short(&node)
+                    ),
+                    Some(label),
+                ))
+            }
+        }
+
+        if let Some(&first) = seen.get(&node) {
+            problems.push(error(
+                link_rev,
+                format_bytes!(b"duplicate revision {} ({})", rev, first),
+                Some(label),
+            ))
+        }
+        seen.insert(node, rev);
+        Ok(link_rev)
+    }
+
+    /// The changesets among `link_revs` where a file has the given revision,
+    /// or all of them if that cannot be found
+    fn filter_link_revs(
+        &self,
+        path: &HgPath,
+        node: &Node,
+        link_revs: &[Revision],
+    ) -> Vec<Revision> {
+        let filter = || -> Result<Vec<Revision>, RevlogError> {
+            let changelog = Changelog::open(self.repo)?;
+            let manifest = Manifest::open(self.repo)?;
+            let mut filtered = Vec::new();
+            for &rev in link_revs {
+                let manifest_node = changelog.get_rev(rev)?.manifest_node()?;
+                let file_node = manifest
+                    .get_node(manifest_node.into())?
+                    .find_file(path)?
+                    .ok_or(RevlogError::InvalidRevision)?
+                    .node_id()?;
+                if file_node == *node {
+                    filtered.push(rev)
+                }
+            }
+            Ok(filtered)
+        };
+        filter().unwrap_or_else(|_| link_revs.to_vec())
+    }
+
+    /// The revlog files of tracked files, as listed in the fncache, when
+    /// they are not empty
+    fn store_files(&self) -> Result<BTreeSet<Vec<u8>>, HgError> {
+        let fncache = Fncache::read(self.repo)?;
+        Ok(store::data_files(self.repo, &fncache)?
+            .into_iter()
+            .filter(|file| file.path.starts_with(b"data/"))
+            .filter(|file| file.size.unwrap_or(0) > 0)
+            .map(|file| file.path)
+            .collect())
+    }
+}
+
+/// Why a revision cannot be read whatever its data: its delta chain does
+/// not end at a snapshot, or its offset in an inline revlog is not where
+/// its data is, which Python relies on.
+///
+/// `valid_chains` tells whether the delta chain of each previous revision
+/// is valid, and is extended with `rev`.
+fn check_storage(
+    revlog: &Revlog,
+    rev: Revision,
+    valid_chains: &mut Vec<bool>,
+) -> Result<Option<Vec<u8>>, RevlogError> {
+    let entry = revlog.index_entry(rev)?;
+    let delta_parent = revlog.delta_parent(rev)?;
+    let problem = if entry.base_revision() == rev {
+        None
+    } else if delta_parent >= rev || delta_parent < NULL_REVISION {
+        Some(format_bytes!(
+            b"delta base {} is not an earlier revision",
+            delta_parent
+        ))
+    } else if delta_parent == NULL_REVISION
+        || !valid_chains[delta_parent as usize]
+    {
+        Some(b"delta chain does not end at a snapshot".to_vec())
+    } else {
+        None
+    };
+    valid_chains.push(problem.is_none());
+    if problem.is_some() || !revlog.index.is_inline() || rev == 0 {
+        // The offset of the first revision is mixed with the index header
+        return Ok(problem);
+    }
+    let offset = revlog.start(rev)?;
+    Ok(if entry.stored_offset() != offset {
+        Some(format_bytes!(
+            b"data offset is {}, {} expected",
+            (entry.stored_offset() as u64),
+            (offset as u64)
+        ))
+    } else {
+        None
+    })
+}
+
+fn error(
+    link_rev: Option<Revision>,
+    message: Vec<u8>,
+    label: Option<&[u8]>,
+) -> VerifyProblem {
+    VerifyProblem::Error {
+        link_rev,
+        label: label.map(ToOwned::to_owned),
+        message,
+    }
+}
+
+/// An error about something that failed, like `_exc` in Python
+fn exception(
+    link_rev: Option<Revision>,
+    message: Vec<u8>,
+    revlog_error: RevlogError,
+    label: Option<&[u8]>,
+) -> Result<VerifyProblem, HgError> {
+    let reason = describe(revlog_error)?;
+    Ok(error(
+        link_rev,
+        format_bytes!(b"{}: {}", message, reason),
+        label,
+    ))
+}
+
+/// The explanation of an error found in the repository. Unsupported
+/// features are returned instead, to fall back to Python.
+fn describe(revlog_error: RevlogError) -> Result<Vec<u8>, HgError> {
+    let reason = match revlog_error {
+        RevlogError::Other(HgError::UnsupportedFeature(feature)) => {
+            return Err(HgError::UnsupportedFeature(feature))
+        }
+        RevlogError::Other(HgError::CorruptedRepository(explanation)) => {
+            explanation
+        }
+        RevlogError::Other(error) => error.to_string(),
+        RevlogError::Censored { node, .. } => {
+            format!("censored node: {:x}", node.short())
+        }
+        RevlogError::InvalidRevision => "invalid revision".to_owned(),
+        RevlogError::AmbiguousPrefix => "ambiguous prefix".to_owned(),
+        RevlogError::WDirUnsupported => {
+            "working directory revision".to_owned()
+        }
+    };
+    Ok(reason.into_bytes())
+}
+
+/// Errors that prevent checking a repository at all
+fn revlog_error(revlog_error: RevlogError) -> HgError {
+    match revlog_error {
+        RevlogError::Other(error) => error,
+        _ => HgError::corrupted("corrupted revlog"),
+    }
+}
+
+fn short(node: &Node) -> Vec<u8> {
+    format!("{:x}", node.short()).into_bytes()
+}
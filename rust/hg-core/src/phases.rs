@@ -0,0 +1,121 @@
+// phases.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Reading the phases of changesets, like `phasecache` in
+//! `mercurial/phases.py`
+//!
+//! The `phaseroots` file of the store lists the roots of each phase other
+//! than public. A changeset has the highest phase of the roots among its
+//! ancestors, including itself.
+
+use crate::errors::{HgError, HgResultExt};
+use crate::repo::Repo;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::revlog::RevlogError;
+use crate::revlog::{Node, Revision, NULL_REVISION};
+use crate::Graph;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Public = 0,
+    Draft = 1,
+    Secret = 2,
+    Archived = 32,
+    Internal = 96,
+}
+
+impl Phase {
+    fn from_number(number: u32) -> Option<Self> {
+        Some(match number {
+            0 => Phase::Public,
+            1 => Phase::Draft,
+            2 => Phase::Secret,
+            32 => Phase::Archived,
+            96 => Phase::Internal,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Public => "public",
+            Phase::Draft => "draft",
+            Phase::Secret => "secret",
+            Phase::Archived => "archived",
+            Phase::Internal => "internal",
+        }
+    }
+}
+
+/// The phase of every revision of a changelog
+pub struct Phases {
+    phases: Vec<Phase>,
+}
+
+impl Phases {
+    pub fn read(repo: &Repo, changelog: &Changelog) -> Result<Self, HgError> {
+        let revlog = &changelog.revlog;
+        let mut phases = vec![Phase::Public; revlog.len()];
+        let roots =
+            repo.store_vfs().read("phaseroots").io_not_found_as_none()?;
+        let corrupted = || HgError::corrupted("invalid phaseroots file");
+        for line in roots.as_deref().unwrap_or(b"").split(|b| *b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split(|b| *b == b' ');
+            let (number, hex) = match (fields.next(), fields.next()) {
+                (Some(number), Some(hex)) => (number, hex),
+                _ => return Err(corrupted()),
+            };
+            let phase = std::str::from_utf8(number)
+                .ok()
+                .and_then(|number| number.parse().ok())
+                .and_then(Phase::from_number)
+                .ok_or_else(corrupted)?;
+            let node = Node::from_hex_for_repo(hex)?;
+            // Like in Python, ignore roots that are not in the changelog
+            if let Ok(rev) = revlog.get_node_rev(node.into()) {
+                let root = &mut phases[rev as usize];
+                *root = (*root).max(phase);
+            }
+        }
+        // Parents come before their children
+        for rev in 0..phases.len() {
+            let invalid_parent =
+                || HgError::corrupted("invalid changelog parent");
+            let parents = changelog
+                .parents(rev as Revision)
+                .map_err(|_| invalid_parent())?;
+            for &parent in parents.iter() {
+                if parent == NULL_REVISION {
+                    continue;
+                }
+                if parent < 0 || parent as usize >= rev {
+                    return Err(invalid_parent());
+                }
+                phases[rev] = phases[rev].max(phases[parent as usize])
+            }
+        }
+        Ok(Self { phases })
+    }
+
+    /// The number of changesets whose phase is known
+    pub(crate) fn len(&self) -> usize {
+        self.phases.len()
+    }
+
+    pub fn phase(&self, rev: Revision) -> Result<Phase, RevlogError> {
+        if rev == NULL_REVISION {
+            return Ok(Phase::Public);
+        }
+        self.phases
+            .get(rev as usize)
+            .copied()
+            .ok_or(RevlogError::InvalidRevision)
+    }
+}
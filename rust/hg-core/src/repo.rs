@@ -1,11 +1,16 @@
 use crate::config::{Config, ConfigError, ConfigParseError};
-use crate::errors::{HgError, IoErrorContext, IoResultExt};
+use crate::errors::{HgError, HgResultExt, IoErrorContext, IoResultExt};
+use crate::obsolete::ObsStore;
+use crate::phases::Phases;
 use crate::requirements;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::node::NULL_NODE;
 use crate::utils::files::get_path_from_bytes;
 use crate::utils::SliceExt;
 use memmap::{Mmap, MmapOptions};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 /// A repository on disk
 pub struct Repo {
@@ -14,6 +19,10 @@ pub struct Repo {
     store: PathBuf,
     requirements: HashSet<String>,
     config: Config,
+    /// The obsolescence markers, once read
+    obsstore: Mutex<Option<Arc<ObsStore>>>,
+    /// The phases of changesets, once read
+    phases: Mutex<Option<Arc<Phases>>>,
 }
 
 #[derive(Debug, derive_more::From)]
@@ -181,6 +190,8 @@ impl Repo {
             store: store_path,
             dot_hg,
             config: repo_config,
+            obsstore: Mutex::new(None),
+            phases: Mutex::new(None),
         };
 
         requirements::check(&repo)?;
@@ -218,14 +229,68 @@ impl Repo {
         }
     }
 
+    /// Both parents are null when there is no dirstate file, such as in a
+    /// newly created repository.
     pub fn dirstate_parents(
         &self,
     ) -> Result<crate::dirstate::DirstateParents, HgError> {
-        let dirstate = self.hg_vfs().mmap_open("dirstate")?;
+        let dirstate = match self
+            .hg_vfs()
+            .mmap_open("dirstate")
+            .io_not_found_as_none()?
+        {
+            Some(dirstate) => dirstate,
+            None => {
+                return Ok(crate::dirstate::DirstateParents {
+                    p1: NULL_NODE,
+                    p2: NULL_NODE,
+                })
+            }
+        };
         let parents =
             crate::dirstate::parsers::parse_dirstate_parents(&dirstate)?;
         Ok(parents.clone())
     }
+
+    /// The obsolescence markers of the repository, read once for all the
+    /// revsets and changesets of a command
+    pub fn obsstore(&self) -> Result<Arc<ObsStore>, HgError> {
+        let mut cache = self.obsstore.lock().unwrap();
+        if let Some(obsstore) = &*cache {
+            return Ok(Arc::clone(obsstore));
+        }
+        let obsstore = Arc::new(ObsStore::read(self)?);
+        *cache = Some(Arc::clone(&obsstore));
+        Ok(obsstore)
+    }
+
+    /// The phases of the changesets of `changelog`, read again only when it
+    /// has grown since
+    pub fn phases(
+        &self,
+        changelog: &Changelog,
+    ) -> Result<Arc<Phases>, HgError> {
+        let mut cache = self.phases.lock().unwrap();
+        if let Some(phases) = &*cache {
+            if phases.len() == changelog.len() {
+                return Ok(Arc::clone(phases));
+            }
+        }
+        let phases = Arc::new(Phases::read(self, changelog)?);
+        *cache = Some(Arc::clone(&phases));
+        Ok(phases)
+    }
+
+    /// The branch of the working directory, from `.hg/branch`
+    pub fn dirstate_branch(&self) -> Result<Vec<u8>, HgError> {
+        let branch = self.hg_vfs().read("branch").io_not_found_as_none()?;
+        Ok(match branch {
+            Some(branch) if !branch.trim().is_empty() => {
+                branch.trim().to_vec()
+            }
+            _ => b"default".to_vec(),
+        })
+    }
 }
 
 impl Vfs<'_> {
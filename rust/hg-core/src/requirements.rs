@@ -58,9 +58,14 @@ pub(crate) fn check(repo: &Repo) -> Result<(), HgError> {
             join_display(&unknown, ", ")
         )));
     }
+    let has = |feature: &str| repo.requirements().contains(feature);
     let missing: Vec<_> = REQUIRED
         .iter()
-        .filter(|&&feature| !repo.requirements().contains(feature))
+        .filter(|&&feature| !has(feature))
+        // Revlog v2 replaces v1
+        .filter(|&&feature| {
+            feature != REVLOGV1_REQUIREMENT || !has(REVLOGV2_REQUIREMENT)
+        })
         .collect();
     if !missing.is_empty() {
         return Err(HgError::unsupported(format!(
@@ -72,11 +77,12 @@ pub(crate) fn check(repo: &Repo) -> Result<(), HgError> {
 }
 
 /// rhg does not support repositories that are *missing* any of these features
-const REQUIRED: &[&str] = &["revlogv1", "store", "fncache", "dotencode"];
+const REQUIRED: &[&str] =
+    &[REVLOGV1_REQUIREMENT, "store", "fncache", "dotencode"];
 
 /// rhg supports repository with or without these
 const SUPPORTED: &[&str] = &[
-    "generaldelta",
+    GENERALDELTA_REQUIREMENT,
     SHARED_REQUIREMENT,
     SHARESAFE_REQUIREMENT,
     SPARSEREVLOG_REQUIREMENT,
@@ -86,13 +92,25 @@ const SUPPORTED: &[&str] = &[
     // When it starts writing to the repository, it’ll need to either keep the
     // persistent nodemap up to date or remove this entry:
     NODEMAP_REQUIREMENT,
+    REVLOGV2_REQUIREMENT,
+    TREEMANIFEST_REQUIREMENT,
+    SIDEDATA_REQUIREMENT,
+    COPIESSDC_REQUIREMENT,
+    LFS_REQUIREMENT,
+    COMPRESSION_NONE,
 ];
 
 // Copied from mercurial/requirements.py:
 
+/// The revlog format of repositories without `REVLOGV2_REQUIREMENT`
+pub(crate) const REVLOGV1_REQUIREMENT: &str = "revlogv1";
+
+/// Revlogs store deltas against any revision, not only the previous one
+#[allow(unused)]
+pub(crate) const GENERALDELTA_REQUIREMENT: &str = "generaldelta";
+
 /// When narrowing is finalized and no longer subject to format changes,
 /// we should move this to just "narrow" or similar.
-#[allow(unused)]
 pub(crate) const NARROW_REQUIREMENT: &str = "narrowhg-experimental";
 
 /// Enables sparse working directory usage
@@ -105,13 +123,11 @@ pub(crate) const SPARSE_REQUIREMENT: &str = "exp-sparse";
 pub(crate) const INTERNAL_PHASE_REQUIREMENT: &str = "internal-phase";
 
 /// Stores manifest in Tree structure
-#[allow(unused)]
 pub(crate) const TREEMANIFEST_REQUIREMENT: &str = "treemanifest";
 
 /// Increment the sub-version when the revlog v2 format changes to lock out old
 /// clients.
-#[allow(unused)]
-pub(crate) const REVLOGV2_REQUIREMENT: &str = "exp-revlogv2.1";
+pub(crate) const REVLOGV2_REQUIREMENT: &str = "exp-revlogv2.2";
 
 /// A repository with the sparserevlog feature will have delta chains that
 /// can spread over a larger span. Sparse reading cuts these large spans into
@@ -126,7 +142,6 @@ pub(crate) const SPARSEREVLOG_REQUIREMENT: &str = "sparserevlog";
 
 /// A repository with the sidedataflag requirement will allow to store extra
 /// information for revision without altering their original hashes.
-#[allow(unused)]
 pub(crate) const SIDEDATA_REQUIREMENT: &str = "exp-sidedata-flag";
 
 /// A repository with the the copies-sidedata-changeset requirement will store
@@ -156,3 +171,10 @@ pub(crate) const SHARESAFE_REQUIREMENT: &str = "share-safe";
 /// A repository that use zstd compression inside its revlog
 #[allow(unused)]
 pub(crate) const REVLOG_COMPRESSION_ZSTD: &str = "revlog-compression-zstd";
+
+/// A repository that does not compress its revlogs, for experimenting
+pub(crate) const COMPRESSION_NONE: &str = "exp-compression-none";
+
+/// Revisions of files may be stored outside of revlogs by the `lfs`
+/// extension, from hgext/lfs/__init__.py
+pub(crate) const LFS_REQUIREMENT: &str = "lfs";
@@ -10,11 +10,21 @@ pub mod nodemap;
 mod nodemap_docket;
 pub mod path_encode;
 pub use node::{FromHexError, Node, NodePrefix};
+mod cache;
+pub use cache::CacheStats;
 pub mod changelog;
+mod deltas;
+pub mod filelog;
+pub mod flags;
 pub mod index;
 pub mod manifest;
 pub mod patch;
 pub mod revlog;
+pub mod sidedata;
+mod treemanifest;
+
+use std::rc::Rc;
+use std::sync::Arc;
 
 /// Mercurial revision numbers
 ///
@@ -46,6 +56,30 @@ pub trait Graph {
     fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError>;
 }
 
+/// References and reference-counted pointers to a graph are graphs too.
+///
+/// They are cheap to copy or clone, which algorithms taking a
+/// `Graph + Clone` by value rely on: `&Changelog`, or `Rc<Revlog>` to
+/// share a filelog without borrowing it, can be given to
+/// `AncestorsIterator` or `dagops::range`.
+impl<G: Graph + ?Sized> Graph for &G {
+    fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError> {
+        (**self).parents(rev)
+    }
+}
+
+impl<G: Graph + ?Sized> Graph for Rc<G> {
+    fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError> {
+        (**self).parents(rev)
+    }
+}
+
+impl<G: Graph + ?Sized> Graph for Arc<G> {
+    fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError> {
+        (**self).parents(rev)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum GraphError {
     ParentOutOfRange(Revision),
@@ -0,0 +1,184 @@
+//! Caches of the data read from a revlog, like `_revisioncache` and
+//! `_chunkcache` in Python, but for more than one revision at a time
+
+use super::Revision;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Bound on the total size of the full texts in the cache of a revlog
+const TEXTS_MAX_SIZE: usize = 8 * 1024 * 1024;
+
+/// Bound on the total size of the decompressed chunks in the cache of a
+/// revlog
+const CHUNKS_MAX_SIZE: usize = 4 * 1024 * 1024;
+
+/// How often the caches of a revlog were useful, for benchmarking
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// Revisions read from their full text in the cache, or from that of a
+    /// revision in their delta chain
+    pub text_hits: u64,
+    /// Revisions read from the snapshot at the start of their delta chain
+    pub text_misses: u64,
+    /// Snapshots and deltas found decompressed in the cache
+    pub chunk_hits: u64,
+    /// Snapshots and deltas decompressed
+    pub chunk_misses: u64,
+}
+
+/// The full texts and decompressed chunks most recently used
+pub(super) struct RevlogCache {
+    texts: LruCache,
+    chunks: LruCache,
+    stats: CacheStats,
+}
+
+impl RevlogCache {
+    pub fn new() -> Self {
+        Self {
+            texts: LruCache::new(TEXTS_MAX_SIZE),
+            chunks: LruCache::new(CHUNKS_MAX_SIZE),
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// The full text of a revision, counted as a hit when present
+    pub fn text(&mut self, rev: Revision) -> Option<Arc<Vec<u8>>> {
+        let text = self.texts.get(rev);
+        if text.is_some() {
+            self.stats.text_hits += 1
+        }
+        text
+    }
+
+    /// Count a revision that was read without any full text in the cache
+    pub fn text_missed(&mut self) {
+        self.stats.text_misses += 1
+    }
+
+    /// Keep the full text of a revision, after checking its hash
+    pub fn insert_text(&mut self, rev: Revision, text: Vec<u8>) {
+        self.texts.insert(rev, Arc::new(text))
+    }
+
+    /// The decompressed chunk of a revision, from the cache or else from
+    /// `decompress`
+    pub fn chunk<E>(
+        &mut self,
+        rev: Revision,
+        decompress: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Arc<Vec<u8>>, E> {
+        if let Some(chunk) = self.chunks.get(rev) {
+            self.stats.chunk_hits += 1;
+            return Ok(chunk);
+        }
+        self.stats.chunk_misses += 1;
+        let chunk = Arc::new(decompress()?);
+        self.chunks.insert(rev, chunk.clone());
+        Ok(chunk)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+/// Values by revision, where the least recently used ones are evicted past
+/// a total size
+struct LruCache {
+    values: HashMap<Revision, (Arc<Vec<u8>>, u64)>,
+    /// Revisions in `values` by the time they were last used
+    usage: BTreeMap<u64, Revision>,
+    clock: u64,
+    size: usize,
+    max_size: usize,
+}
+
+impl LruCache {
+    fn new(max_size: usize) -> Self {
+        Self {
+            values: HashMap::new(),
+            usage: BTreeMap::new(),
+            clock: 0,
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn get(&mut self, rev: Revision) -> Option<Arc<Vec<u8>>> {
+        let (value, last_used) = self.values.get_mut(&rev)?;
+        self.usage.remove(last_used);
+        self.clock += 1;
+        *last_used = self.clock;
+        self.usage.insert(self.clock, rev);
+        Some(value.clone())
+    }
+
+    /// Values larger than the whole cache are not kept
+    fn insert(&mut self, rev: Revision, value: Arc<Vec<u8>>) {
+        if value.len() > self.max_size || self.values.contains_key(&rev) {
+            return;
+        }
+        while self.size + value.len() > self.max_size {
+            let (&oldest, &evicted) =
+                self.usage.iter().next().expect("cache not empty");
+            self.usage.remove(&oldest);
+            if let Some((evicted, _)) = self.values.remove(&evicted) {
+                self.size -= evicted.len()
+            }
+        }
+        self.clock += 1;
+        self.usage.insert(self.clock, rev);
+        self.size += value.len();
+        self.values.insert(rev, (value, self.clock));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let value = |size| Arc::new(vec![0; size]);
+        let mut cache = LruCache::new(10);
+        cache.insert(1, value(4));
+        cache.insert(2, value(4));
+        assert!(cache.get(1).is_some());
+        // The least recently used value makes room for the new one
+        cache.insert(3, value(4));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        // Both are evicted for a larger one
+        cache.insert(4, value(9));
+        assert!(cache.get(1).is_none());
+        assert!(cache.get(3).is_none());
+        assert_eq!(cache.size, 9);
+        // Values larger than the cache are not kept
+        cache.insert(5, value(11));
+        assert!(cache.get(5).is_none());
+        assert!(cache.get(4).is_some());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut cache = RevlogCache::new();
+        assert!(cache.text(0).is_none());
+        cache.text_missed();
+        let decompress = || Ok::<_, ()>(b"chunk".to_vec());
+        assert_eq!(&cache.chunk(0, decompress).unwrap()[..], b"chunk");
+        assert_eq!(&cache.chunk(0, || Err(())).unwrap()[..], b"chunk");
+        cache.insert_text(0, b"text".to_vec());
+        assert_eq!(&cache.text(0).unwrap()[..], b"text");
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                text_hits: 1,
+                text_misses: 1,
+                chunk_hits: 1,
+                chunk_misses: 1,
+            }
+        );
+    }
+}
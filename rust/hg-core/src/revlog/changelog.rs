@@ -1,8 +1,14 @@
 use crate::errors::HgError;
 use crate::repo::Repo;
+use crate::revlog::flags::REVIDX_HASCOPIESINFO;
 use crate::revlog::revlog::{Revlog, RevlogError};
-use crate::revlog::Revision;
+use crate::revlog::sidedata::{self, ChangingFiles};
 use crate::revlog::{Node, NodePrefix};
+use crate::revlog::{Revision, NULL_REVISION};
+use crate::utils::hg_path::HgPath;
+use crate::utils::SliceExt;
+use crate::{Graph, GraphError};
+use std::collections::BTreeMap;
 
 /// A specialized `Revlog` to work with `changelog` data format.
 pub struct Changelog {
@@ -17,49 +23,409 @@ impl Changelog {
         Ok(Self { revlog })
     }
 
-    /// Return the `ChangelogEntry` a given node id.
+    /// Return the `ChangelogRevisionData` of a given node id.
     pub fn get_node(
         &self,
         node: NodePrefix,
-    ) -> Result<ChangelogEntry, RevlogError> {
+    ) -> Result<ChangelogRevisionData, RevlogError> {
         let rev = self.revlog.get_node_rev(node)?;
         self.get_rev(rev)
     }
 
-    /// Return the `ChangelogEntry` of a given node revision.
+    /// Return the `ChangelogRevisionData` of a given node revision.
     pub fn get_rev(
         &self,
         rev: Revision,
-    ) -> Result<ChangelogEntry, RevlogError> {
+    ) -> Result<ChangelogRevisionData, RevlogError> {
+        if rev == NULL_REVISION {
+            return Ok(ChangelogRevisionData::null());
+        }
         let bytes = self.revlog.get_rev_data(rev)?;
-        Ok(ChangelogEntry { bytes })
+        Ok(ChangelogRevisionData::new(bytes)?)
+    }
+
+    /// The copy tracing information of a changeset, for repositories that
+    /// store it in sidedata. `None` when the changeset has none.
+    pub fn changing_files(
+        &self,
+        rev: Revision,
+    ) -> Result<Option<ChangingFiles>, RevlogError> {
+        let entry = self
+            .revlog
+            .index
+            .get_entry(rev)
+            .ok_or(RevlogError::InvalidRevision)?;
+        if entry.flags() & REVIDX_HASCOPIESINFO == 0 {
+            return Ok(None);
+        }
+        let sidedata = self.revlog.get_sidedata(rev)?;
+        Ok(Some(sidedata::decode_files(&sidedata)?))
     }
 
     pub fn node_from_rev(&self, rev: Revision) -> Option<&Node> {
         Some(self.revlog.index.get_entry(rev)?.hash())
     }
+
+    /// Return the revision number of the changeset with the given node ID
+    /// or unambiguous prefix.
+    pub fn rev_from_node(
+        &self,
+        node: NodePrefix,
+    ) -> Result<Revision, RevlogError> {
+        self.revlog.get_node_rev(node)
+    }
+
+    /// The number of changesets
+    pub fn len(&self) -> usize {
+        self.revlog.len()
+    }
+
+    /// Whether the repository has no changesets
+    pub fn is_empty(&self) -> bool {
+        self.revlog.is_empty()
+    }
+}
+
+impl Graph for Changelog {
+    /// Like `parentrevs` in Python, the parents of the null revision are
+    /// null rather than an error.
+    fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError> {
+        if rev == NULL_REVISION {
+            return Ok([NULL_REVISION; 2]);
+        }
+        self.revlog.parents(rev)
+    }
 }
 
-/// `Changelog` entry which knows how to interpret the `changelog` data bytes.
+/// The data of a changeset, parsed lazily: only the position of each field
+/// is found when created, like `changelogrevision` in Python.
+///
+/// The format is:
+///
+/// ```text
+/// <manifest node in hexadecimal>
+/// <user>
+/// <time> <timezone> [extras]
+/// <file 1>
+/// <file 2>
+/// ...
+///
+/// <description>
+/// ```
 #[derive(Debug)]
-pub struct ChangelogEntry {
+pub struct ChangelogRevisionData {
     /// The data bytes of the `changelog` entry.
     bytes: Vec<u8>,
+    /// The end offset for the hex manifest (not including the newline)
+    manifest_end: usize,
+    /// The end offset for the user (not including the newline)
+    user_end: usize,
+    /// The end offset for the timestamp, timezone and extras (not including
+    /// the newline)
+    timestamp_end: usize,
+    /// The end offset for the file list (not including the newline)
+    files_end: usize,
+}
+
+/// The date of a changeset
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChangesetDate {
+    /// Seconds since the Unix epoch. Fractional seconds, found in some old
+    /// changesets, are truncated.
+    pub timestamp: i64,
+    /// Offset of the committer's timezone, in seconds *west* of UTC like in
+    /// Mercurial's `(unixtime, offset)` date tuples.
+    pub timezone_offset: i32,
+}
+
+impl ChangesetDate {
+    /// Format the date in the committer's timezone, like `dateutil.datestr`.
+    ///
+    /// `format` is a `strftime` format where `%1` and `%2` are the hours and
+    /// minutes of the timezone offset, such as `+01` and `00`, and `%z` is
+    /// `%1%2`.
+    pub fn format(&self, format: &str) -> String {
+        let sign = if self.timezone_offset > 0 { '-' } else { '+' };
+        let minutes = self.timezone_offset.abs() / 60;
+        let format = format
+            .replace("%z", "%1%2")
+            .replace("%1", &format!("{}{:02}", sign, minutes / 60))
+            .replace("%2", &format!("{:02}", minutes % 60));
+        // Like Python, clamp to what 32-bit platforms support
+        let local = (self.timestamp - i64::from(self.timezone_offset))
+            .max(i64::from(i32::min_value()))
+            .min(i64::from(i32::max_value()));
+        // Deprecated in later versions of chrono than the one required
+        #[allow(deprecated)]
+        let date = chrono::NaiveDateTime::from_timestamp(local, 0);
+        date.format(&format).to_string()
+    }
 }
 
-impl ChangelogEntry {
-    /// Return an iterator over the lines of the entry.
-    pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
-        self.bytes
-            .split(|b| b == &b'\n')
-            .filter(|line| !line.is_empty())
+impl ChangelogRevisionData {
+    fn new(bytes: Vec<u8>) -> Result<Self, HgError> {
+        if bytes.is_empty() {
+            // The null revision
+            return Ok(Self::null());
+        }
+        let corrupted = || HgError::corrupted("invalid changelog entry");
+        let find_newline = |start: usize| {
+            bytes[start..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map(|position| start + position)
+                .ok_or_else(corrupted)
+        };
+        let manifest_end = find_newline(0)?;
+        let user_end = find_newline(manifest_end + 1)?;
+        let timestamp_end = find_newline(user_end + 1)?;
+        // The list of files may be empty, in which case `timestamp_end` is
+        // the first of the two newlines preceding the description.
+        let files_end = if bytes.get(timestamp_end + 1) == Some(&b'\n') {
+            timestamp_end
+        } else {
+            bytes[timestamp_end + 1..]
+                .windows(2)
+                .position(|window| window == b"\n\n")
+                .map(|position| timestamp_end + 1 + position)
+                .ok_or_else(corrupted)?
+        };
+        Ok(Self {
+            bytes,
+            manifest_end,
+            user_end,
+            timestamp_end,
+            files_end,
+        })
+    }
+
+    fn null() -> Self {
+        Self {
+            bytes: b"0000000000000000000000000000000000000000\n\n0 0\n\n"
+                .to_vec(),
+            manifest_end: 40,
+            user_end: 41,
+            timestamp_end: 45,
+            files_end: 45,
+        }
     }
 
     /// Return the node id of the `manifest` referenced by this `changelog`
     /// entry.
-    pub fn manifest_node(&self) -> Result<&[u8], RevlogError> {
-        self.lines()
+    pub fn manifest_node(&self) -> Result<Node, HgError> {
+        Node::from_hex_for_repo(&self.bytes[..self.manifest_end])
+    }
+
+    /// The full user string, usually a name and an e-mail address
+    pub fn user(&self) -> &[u8] {
+        &self.bytes[self.manifest_end + 1..self.user_end]
+    }
+
+    /// The line with the timestamp, timezone, and extras
+    fn timestamp_line(&self) -> &[u8] {
+        &self.bytes[self.user_end + 1..self.timestamp_end]
+    }
+
+    pub fn date(&self) -> Result<ChangesetDate, HgError> {
+        let mut fields = self.timestamp_line().splitn(3, |b| *b == b' ');
+        let timestamp = fields.next().unwrap_or(b"");
+        // Truncate rather than round, like `int(float(...))` in Python
+        let timestamp = std::str::from_utf8(timestamp)
+            .ok()
+            .and_then(|timestamp| timestamp.parse::<f64>().ok())
+            .ok_or_else(|| {
+                HgError::corrupted("invalid timestamp in changelog entry")
+            })? as i64;
+        // Like Python, tolerate tools that did silly things with the
+        // timezone.
+        let timezone_offset = fields
             .next()
-            .ok_or_else(|| HgError::corrupted("empty changelog entry").into())
+            .and_then(|timezone| std::str::from_utf8(timezone).ok())
+            .and_then(|timezone| timezone.parse().ok())
+            .unwrap_or(0);
+        Ok(ChangesetDate {
+            timestamp,
+            timezone_offset,
+        })
+    }
+
+    /// The extra metadata of this changeset, decoded, with a `branch` entry
+    /// even if it is not stored.
+    pub fn extra(&self) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, HgError> {
+        let mut extra = BTreeMap::new();
+        extra.insert(b"branch".to_vec(), b"default".to_vec());
+        if let Some(encoded) =
+            self.timestamp_line().splitn(3, |b| *b == b' ').nth(2)
+        {
+            for item in encoded.split(|b| *b == b'\0') {
+                if item.is_empty() {
+                    continue;
+                }
+                let item = unescape_extra(item);
+                let (key, value) = item.split_2(b':').ok_or_else(|| {
+                    HgError::corrupted("invalid extra in changelog entry")
+                })?;
+                extra.insert(key.to_vec(), value.to_vec());
+            }
+        }
+        Ok(extra)
+    }
+
+    /// Return the branch name of this changeset, and whether it closes that
+    /// branch, like `changelog.branchinfo` in Python.
+    pub fn branchinfo(&self) -> Result<(Vec<u8>, bool), HgError> {
+        let mut extra = self.extra()?;
+        let closed = extra.contains_key(&b"close"[..]);
+        let branch = extra.remove(&b"branch"[..]).unwrap_or_default();
+        Ok((branch, closed))
+    }
+
+    /// The files changed by this changeset, in stored order
+    pub fn files(&self) -> impl Iterator<Item = &HgPath> {
+        let files = if self.timestamp_end == self.files_end {
+            &b""[..]
+        } else {
+            &self.bytes[self.timestamp_end + 1..self.files_end]
+        };
+        files
+            .split(|b| *b == b'\n')
+            .filter(|path| !path.is_empty())
+            .map(HgPath::new)
+    }
+
+    pub fn description(&self) -> &[u8] {
+        &self.bytes[self.files_end + 2..]
+    }
+}
+
+/// Reverse the escaping of extras done by `_string_escape` in
+/// `mercurial/changelog.py`
+fn unescape_extra(escaped: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'\\' {
+            unescaped.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'0') => unescaped.push(b'\0'),
+            Some(b'n') => unescaped.push(b'\n'),
+            Some(b'r') => unescaped.push(b'\r'),
+            Some(b't') => unescaped.push(b'\t'),
+            Some(b'\\') => unescaped.push(b'\\'),
+            Some(b'x') if bytes.as_slice().len() >= 2 => {
+                let hex = std::str::from_utf8(&bytes.as_slice()[..2]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(decoded) => {
+                        unescaped.push(decoded);
+                        bytes.nth(1);
+                    }
+                    None => unescaped.extend_from_slice(b"\\x"),
+                }
+            }
+            Some(&other) => unescaped.extend_from_slice(&[b'\\', other]),
+            None => unescaped.push(b'\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::revlog::node::NULL_NODE;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_changelog_revision_data() {
+        let data = ChangelogRevisionData::new(
+            b"0123456789abcdef0123456789abcdef01234567\n\
+              Jane Doe <jane@example.com>\n\
+              1609459200 -3600 branch:stable\0close:1\0foo:a\\\\b\\0c\\nd\n\
+              dir/file\n\
+              other file\n\
+              \n\
+              Summary\n\nDetails"
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            data.manifest_node().unwrap(),
+            Node::from_hex("0123456789abcdef0123456789abcdef01234567")
+                .unwrap()
+        );
+        assert_eq!(data.user(), b"Jane Doe <jane@example.com>");
+        assert_eq!(
+            data.date().unwrap(),
+            ChangesetDate {
+                timestamp: 1609459200,
+                timezone_offset: -3600
+            }
+        );
+        let extra = data.extra().unwrap();
+        assert_eq!(extra[&b"foo"[..]], b"a\\b\0c\nd");
+        assert_eq!(data.branchinfo().unwrap(), (b"stable".to_vec(), true));
+        assert_eq!(
+            data.files().collect::<Vec<_>>(),
+            vec![HgPath::new(b"dir/file"), HgPath::new(b"other file")]
+        );
+        assert_eq!(data.description(), b"Summary\n\nDetails");
+    }
+
+    #[test]
+    fn test_format_date() {
+        let date = ChangesetDate {
+            timestamp: 1609459200,
+            timezone_offset: -5400,
+        };
+        assert_eq!(
+            date.format("%a %b %d %H:%M:%S %Y %1%2"),
+            "Fri Jan 01 01:30:00 2021 +0130"
+        );
+        let date = ChangesetDate {
+            timestamp: -(1 << 40),
+            timezone_offset: 3600,
+        };
+        assert_eq!(date.format("%Y-%m-%d %z"), "1901-12-13 -0100");
+    }
+
+    #[test]
+    fn test_parse_without_files_and_extras() {
+        let data = ChangelogRevisionData::new(
+            b"0123456789abcdef0123456789abcdef01234567\n\
+              user\n\
+              12.5 garbage\n\
+              \n\
+              description"
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            data.date().unwrap(),
+            ChangesetDate {
+                timestamp: 12,
+                timezone_offset: 0
+            }
+        );
+        assert_eq!(data.branchinfo().unwrap(), (b"default".to_vec(), false));
+        assert_eq!(data.files().count(), 0);
+        assert_eq!(data.description(), b"description");
+    }
+
+    #[test]
+    fn test_null_revision_data() {
+        let data = ChangelogRevisionData::new(Vec::new()).unwrap();
+        assert_eq!(data.manifest_node().unwrap(), NULL_NODE);
+        assert_eq!(data.user(), b"");
+        assert_eq!(data.files().count(), 0);
+        assert_eq!(data.description(), b"");
+    }
+
+    #[test]
+    fn test_truncated_data_is_corrupted() {
+        assert!(ChangelogRevisionData::new(b"abc\nuser\n".to_vec()).is_err());
+        assert!(ChangelogRevisionData::new(b"abc\nuser\n0 0\nfile".to_vec())
+            .is_err());
     }
 }
@@ -0,0 +1,507 @@
+//! Choosing how to store a new revision: as a delta against an existing one,
+//! or as a full snapshot. Ported from `mercurial/revlogutils/deltas.py`.
+
+use crate::diff::text_delta;
+use crate::revlog::flags::REVIDX_RAWTEXT_CHANGING_FLAGS;
+use crate::revlog::revlog::{
+    compress, Revlog, RevlogError, SparseReadOptions, WriteOptions,
+};
+use crate::revlog::{Revision, NULL_REVISION};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// The sum of the deltas to apply must stay below that many times the size
+/// of the full text.
+const LIMIT_DELTA2TEXT: usize = 2;
+
+/// If a revision's full text is that much bigger than a base candidate full
+/// text's, it is very unlikely that it will produce a valid delta.
+const LIMIT_BASE2TEXT: usize = 500;
+
+/// How to store a new revision
+pub(super) struct DeltaInfo {
+    /// The revision to store as the base of the new one, which is the new
+    /// revision itself for a full snapshot
+    pub base: Revision,
+    /// The compressed delta or full text, with its header
+    pub data: Vec<u8>,
+    /// Bytes to read from the start of the chain to the end of this delta
+    distance: usize,
+    /// Number of deltas to apply, including this one
+    chain_length: usize,
+    /// Size of the data to read to rebuild the text, including this one
+    chain_size: usize,
+    /// `Some` when the new revision is an intermediate snapshot, with the
+    /// number of snapshots before it
+    snapshot_depth: Option<usize>,
+}
+
+/// Find how to store a new revision at the end of `revlog`, like
+/// `deltacomputer.finddeltainfo` in Python.
+pub(super) fn find_delta_info(
+    revlog: &Revlog,
+    options: &WriteOptions,
+    generaldelta: bool,
+    text: &[u8],
+    p1: Revision,
+    p2: Revision,
+) -> Result<DeltaInfo, RevlogError> {
+    let mut search = DeltaSearch {
+        revlog,
+        options,
+        generaldelta,
+        sparse: generaldelta && options.sparse_revlog,
+        text,
+        p1,
+        p2,
+        tested: HashSet::new(),
+        snapshots: BTreeMap::new(),
+        best: None,
+    };
+    if !text.is_empty() && !revlog.is_empty() {
+        let good = search.raw_groups()?;
+        search.refine(good)?;
+    }
+    match search.best {
+        Some(best) => Ok(best),
+        None => search.full_snapshot(),
+    }
+}
+
+/// State of the search for the best delta base
+///
+/// In Python, candidate revisions are produced by nested generators that
+/// are sent the base of the best delta so far. Here they call `try_group`
+/// instead, which returns that base.
+struct DeltaSearch<'a> {
+    revlog: &'a Revlog,
+    options: &'a WriteOptions,
+    generaldelta: bool,
+    sparse: bool,
+    text: &'a [u8],
+    p1: Revision,
+    p2: Revision,
+    /// Candidates that were already considered
+    tested: HashSet<Revision>,
+    /// Snapshots found so far, by the revision they are stored against
+    snapshots: BTreeMap<Revision, Vec<Revision>>,
+    best: Option<DeltaInfo>,
+}
+
+impl DeltaSearch<'_> {
+    /// Candidate groups of `_rawgroups` in Python: the parents, then for
+    /// sparse revlogs the snapshots of their delta chains and their
+    /// siblings, then the previous revision.
+    ///
+    /// Returns early with the base of a good delta, once there is one.
+    fn raw_groups(&mut self) -> Result<Option<Revision>, RevlogError> {
+        let previous = self.revlog.len() as Revision - 1;
+        let parents: Vec<Revision> = [self.p1, self.p2]
+            .iter()
+            .copied()
+            .filter(|&parent| parent != NULL_REVISION)
+            .collect();
+
+        if self.generaldelta {
+            if !self.options.delta_both_parents && parents.len() == 2 {
+                // To minimize the chance of having to build a full text,
+                // first pick the parent closest to us
+                let (low, high) = if parents[0] < parents[1] {
+                    (parents[0], parents[1])
+                } else {
+                    (parents[1], parents[0])
+                };
+                if let Some(good) = self.try_group(vec![high])? {
+                    return Ok(Some(good));
+                }
+                if let Some(good) = self.try_group(vec![low])? {
+                    return Ok(Some(good));
+                }
+            } else if !parents.is_empty() {
+                if let Some(good) = self.try_group(parents.clone())? {
+                    return Ok(Some(good));
+                }
+            }
+        }
+
+        if self.sparse && !parents.is_empty() {
+            // Existing snapshots of the delta chains of the parents may be
+            // used as the base of a new intermediate snapshot, by level
+            let mut parents_snapshots: BTreeMap<usize, BTreeSet<Revision>> =
+                BTreeMap::new();
+            let mut chains = Vec::new();
+            for &parent in &parents {
+                chains.push(self.revlog.delta_chain(parent)?);
+            }
+            for chain in &chains {
+                for (level, &rev) in chain.iter().enumerate() {
+                    if !self.revlog.is_snapshot(rev)? {
+                        break;
+                    }
+                    parents_snapshots.entry(level).or_default().insert(rev);
+                }
+            }
+            let floor = match parents_snapshots.get(&0) {
+                Some(full_snapshots) => full_snapshots.iter().next(),
+                None => None,
+            };
+            let snapshot_floor = match floor {
+                Some(&full_snapshot) => full_snapshot + 1,
+                None => return Err(RevlogError::corrupted()),
+            };
+            self.find_snapshots(snapshot_floor)?;
+
+            // Snapshots of the highest unrelated revision make it more
+            // likely to reuse an independent, yet better, chain
+            let related: HashSet<Revision> =
+                chains.iter().flatten().copied().collect();
+            let other = (snapshot_floor..=previous)
+                .rev()
+                .find(|rev| !related.contains(rev));
+            if let Some(other) = other {
+                // Unrelated snapshots deeper than those of the chains of the
+                // parents would compete unfairly
+                let max_level =
+                    parents_snapshots.keys().next_back().copied().unwrap_or(0);
+                for (level, rev) in
+                    self.revlog.delta_chain(other)?.into_iter().enumerate()
+                {
+                    if rev < snapshot_floor {
+                        continue;
+                    }
+                    if max_level < level || !self.revlog.is_snapshot(rev)? {
+                        break;
+                    }
+                    parents_snapshots.entry(level).or_default().insert(rev);
+                }
+            }
+
+            // Higher levels are more likely to give a small delta
+            let mut floor = None;
+            for snapshots in parents_snapshots.values().rev() {
+                // Before making a new intermediate snapshot, try reusing an
+                // existing one stored against the same base, but only those
+                // more recent than the one in the delta chain of a parent
+                let mut siblings: BTreeSet<Revision> = BTreeSet::new();
+                for snapshot in snapshots {
+                    if let Some(children) = self.snapshots.get(snapshot) {
+                        siblings.extend(children)
+                    }
+                }
+                let siblings = siblings
+                    .into_iter()
+                    .filter(|&sibling| match floor {
+                        Some(floor) => floor < sibling,
+                        None => true,
+                    })
+                    .collect();
+                if let Some(good) = self.try_group(siblings)? {
+                    return Ok(Some(good));
+                }
+                if let Some(good) =
+                    self.try_group(snapshots.iter().copied().collect())?
+                {
+                    return Ok(Some(good));
+                }
+                floor = snapshots.iter().next().copied();
+            }
+
+            // Full snapshots made since the base of the parents may be the
+            // base of a new intermediate snapshot, instead of a new full one
+            let full_snapshots = self
+                .snapshots
+                .get(&NULL_REVISION)
+                .cloned()
+                .unwrap_or_default();
+            if let Some(good) = self.try_group(full_snapshots)? {
+                return Ok(Some(good));
+            }
+        }
+
+        if !self.sparse {
+            // Try against the previous revision to hopefully save a full text
+            if let Some(good) = self.try_group(vec![previous])? {
+                return Ok(Some(good));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Try to find a better delta around a good snapshot base, like
+    /// `_refinedgroups` in Python: its own bases, then its children.
+    fn refine(&mut self, good: Option<Revision>) -> Result<(), RevlogError> {
+        let mut good = match good {
+            Some(good) if self.sparse => good,
+            _ => return Ok(()),
+        };
+        if good == self.p1
+            || good == self.p2
+            || !self.revlog.is_snapshot(good)?
+        {
+            return Ok(());
+        }
+        let mut previous = None;
+        while previous != Some(good) {
+            previous = Some(good);
+            let base = self.revlog.delta_parent(good)?;
+            if base == NULL_REVISION {
+                break;
+            }
+            good = self.try_group(vec![base])?.unwrap_or(good);
+        }
+        if self.snapshots.is_empty() {
+            self.find_snapshots(good + 1)?;
+        }
+        let mut previous = None;
+        while previous != Some(good) {
+            previous = Some(good);
+            let children =
+                self.snapshots.get(&good).cloned().unwrap_or_default();
+            good = self.try_group(children)?.unwrap_or(good);
+        }
+        Ok(())
+    }
+
+    /// Try the deltas against a group of candidate revisions, after skipping
+    /// those that cannot give a good delta, like `_candidategroups` in
+    /// Python. Returns the base of the best delta so far.
+    fn try_group(
+        &mut self,
+        group: Vec<Revision>,
+    ) -> Result<Option<Revision>, RevlogError> {
+        let text_len = self.text.len();
+        let deltas_limit = text_len * LIMIT_DELTA2TEXT;
+        let mut nominated: Vec<DeltaInfo> =
+            self.best.take().into_iter().collect();
+        for mut rev in group {
+            // Empty deltas are not worth including in a chain
+            while self.generaldelta
+                && !(rev == NULL_REVISION
+                    || self.tested.contains(&rev)
+                    || self.revlog.index_entry(rev)?.compressed_len() != 0)
+            {
+                self.tested.insert(rev);
+                rev = self.revlog.delta_parent(rev)?;
+            }
+            // A full snapshot is the last resort
+            if rev == NULL_REVISION || !self.tested.insert(rev) {
+                continue;
+            }
+            let entry = self.revlog.index_entry(rev)?;
+            if deltas_limit < entry.compressed_len()
+                || (self.sparse
+                    && entry.uncompressed_len() < text_len / LIMIT_BASE2TEXT)
+                || entry.flags() & REVIDX_RAWTEXT_CHANGING_FLAGS != 0
+            {
+                continue;
+            }
+            let (chain_length, chain_size) = self.revlog.chain_info(rev)?;
+            if let Some(max_chain_length) = self.options.max_chain_length {
+                if chain_length >= max_chain_length {
+                    continue;
+                }
+            }
+            if deltas_limit < chain_size {
+                continue;
+            }
+            let delta = self.build_delta_info(rev)?;
+            if self.is_good(&delta)? {
+                nominated.push(delta)
+            }
+        }
+        // The first of the smallest deltas
+        let mut best: Option<DeltaInfo> = None;
+        for delta in nominated {
+            match &best {
+                Some(best) if best.data.len() <= delta.data.len() => {}
+                _ => best = Some(delta),
+            }
+        }
+        self.best = best;
+        Ok(self.best.as_ref().map(|best| best.base))
+    }
+
+    /// Like `_builddeltainfo` in Python
+    fn build_delta_info(
+        &self,
+        base: Revision,
+    ) -> Result<DeltaInfo, RevlogError> {
+        let revlog = self.revlog;
+        let chain_base = revlog.chain_base(base)?;
+        // Without generaldelta, the stored base is the start of the chain
+        let delta_base = if self.generaldelta { base } else { chain_base };
+        let snapshot_depth = if self.sparse
+            && delta_base != self.p1
+            && delta_base != self.p2
+            && self.revlog.is_snapshot(delta_base)?
+        {
+            Some(revlog.delta_chain(delta_base)?.len())
+        } else {
+            None
+        };
+        let delta = text_delta(&revlog.get_raw_rev_data(base)?, self.text);
+        let data = compress(&delta, self.options.compression);
+        let end = revlog.end(revlog.len() as Revision - 1)?;
+        let distance = data.len() + end - revlog.start(chain_base)?;
+        let (chain_length, chain_size) = revlog.chain_info(base)?;
+        Ok(DeltaInfo {
+            base: delta_base,
+            distance,
+            chain_length: chain_length + 1,
+            chain_size: chain_size + data.len(),
+            snapshot_depth,
+            data,
+        })
+    }
+
+    /// Like `isgooddeltainfo` in Python
+    fn is_good(&self, delta: &DeltaInfo) -> Result<bool, RevlogError> {
+        let text_len = self.text.len();
+        let delta_len = delta.data.len();
+        // Bounding the span of data to read limits I/O. Sparse revlogs rely
+        // on sparse reading instead.
+        let max_distance = match self.options.max_delta_chain_span {
+            Some(0) => delta.distance,
+            Some(span) => span,
+            None => 0,
+        }
+        .max(text_len * 4);
+        if !self.sparse && max_distance < delta.distance {
+            return Ok(false);
+        }
+        // Bounding the data to decompress and patches to apply limits CPU
+        if text_len < delta_len
+            || text_len * LIMIT_DELTA2TEXT < delta.chain_size
+        {
+            return Ok(false);
+        }
+        if let Some(max_chain_length) = self.options.max_chain_length {
+            if max_chain_length < delta.chain_length {
+                return Ok(false);
+            }
+        }
+        // Intermediate snapshots must get smaller with each level, to avoid
+        // endless chains of them
+        if let Some(depth) = delta.snapshot_depth {
+            if text_len.checked_shr(depth as u32).unwrap_or(0) < delta_len {
+                return Ok(false);
+            }
+            let base_len =
+                self.revlog.index_entry(delta.base)?.compressed_len();
+            if depth > 0 && base_len < delta_len {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Like `_fullsnapshotinfo` in Python
+    fn full_snapshot(&self) -> Result<DeltaInfo, RevlogError> {
+        let data = compress(self.text, self.options.compression);
+        Ok(DeltaInfo {
+            base: self.revlog.len() as Revision,
+            distance: data.len(),
+            chain_length: 1,
+            chain_size: data.len(),
+            snapshot_depth: Some(0),
+            data,
+        })
+    }
+
+    /// Record the snapshots from `start` to the tip, like `_findsnapshots`
+    fn find_snapshots(&mut self, start: Revision) -> Result<(), RevlogError> {
+        for rev in start..self.revlog.len() as Revision {
+            if self.revlog.is_snapshot(rev)? {
+                let base = self.revlog.delta_parent(rev)?;
+                self.snapshots.entry(base).or_default().push(rev)
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split the revisions of a delta chain, in order, into chunks of data to
+/// read at once, skipping the largest gaps of unrelated data between them
+/// until enough of the data read is useful, like `slicechunk` in Python
+/// without a target size.
+pub(super) fn slice_chunk<'a>(
+    revlog: &Revlog,
+    revs: &'a [Revision],
+    options: &SparseReadOptions,
+) -> Result<Vec<&'a [Revision]>, RevlogError> {
+    if revs.len() <= 1 {
+        return Ok(vec![revs]);
+    }
+    let span = revlog.end(revs[revs.len() - 1])? - revlog.start(revs[0])?;
+    if span < options.min_gap_size {
+        return Ok(vec![revs]);
+    }
+    let mut payload = 0;
+    for &rev in revs {
+        payload += revlog.index_entry(rev)?.compressed_len();
+    }
+    let density = |read: usize| {
+        if read > 0 {
+            payload as f64 / read as f64
+        } else {
+            1.0
+        }
+    };
+    let mut read = span;
+    if density(read) >= options.density_threshold {
+        return Ok(vec![revs]);
+    }
+
+    // Gaps before each revision, skipping empty ones to form larger gaps
+    let mut gaps = Vec::new();
+    let mut previous_end = None;
+    for (index, &rev) in revs.iter().enumerate() {
+        let start = revlog.start(rev)?;
+        let length = revlog.index_entry(rev)?.compressed_len();
+        if length == 0 {
+            continue;
+        }
+        if let Some(previous_end) = previous_end {
+            let gap = start - previous_end;
+            if gap > options.min_gap_size {
+                gaps.push((gap, index))
+            }
+        }
+        previous_end = Some(start + length);
+    }
+    // Skip the largest gaps first
+    gaps.sort();
+    let mut cuts = Vec::new();
+    while let Some((gap, index)) = gaps.pop() {
+        if density(read) >= options.density_threshold {
+            break;
+        }
+        cuts.push(index);
+        read -= gap;
+    }
+    cuts.sort();
+
+    let mut chunks = Vec::new();
+    let mut previous_cut = 0;
+    for cut in cuts.into_iter().chain(std::iter::once(revs.len())) {
+        let chunk = trim_chunk(revlog, &revs[previous_cut..cut])?;
+        if !chunk.is_empty() {
+            chunks.push(chunk)
+        }
+        previous_cut = cut;
+    }
+    Ok(chunks)
+}
+
+/// Remove the empty revisions at the end of a chunk, except the first one,
+/// like `_trimchunk` in Python
+fn trim_chunk<'a>(
+    revlog: &Revlog,
+    mut chunk: &'a [Revision],
+) -> Result<&'a [Revision], RevlogError> {
+    while chunk.len() > 1
+        && revlog.index_entry(chunk[chunk.len() - 1])?.compressed_len() == 0
+    {
+        chunk = &chunk[..chunk.len() - 1]
+    }
+    Ok(chunk)
+}
@@ -0,0 +1,377 @@
+use crate::errors::HgError;
+use crate::repo::Repo;
+use crate::revlog::flags::REVIDX_ISCENSORED;
+use crate::revlog::path_encode::path_encode;
+use crate::revlog::revlog::{Revlog, RevlogError};
+use crate::revlog::{Node, NodePrefix};
+use crate::revlog::{Revision, NULL_REVISION};
+use crate::utils::files::get_path_from_bytes;
+use crate::utils::find_slice_in_slice;
+use crate::utils::hg_path::{HgPath, HgPathBuf};
+use crate::{Graph, GraphError};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Delimits the metadata at the start of the data of a file revision
+const METADATA_DELIMITER: &[u8] = b"\x01\n";
+
+/// A specialized `Revlog` to work with the revisions of a tracked file.
+pub struct Filelog {
+    /// The generic `revlog` format.
+    pub(crate) revlog: Revlog,
+}
+
+impl Filelog {
+    /// Open the `filelog` of a file given by its path in the repository.
+    pub fn open(repo: &Repo, file_path: &HgPath) -> Result<Self, RevlogError> {
+        let index_path = store_path(file_path, b".i");
+        let data_path = store_path(file_path, b".d");
+        let revlog = Revlog::open(repo, index_path, Some(&data_path))?;
+        Ok(Self { revlog })
+    }
+
+    /// Return the `FilelogEntry` of a given node id.
+    pub fn get_node(
+        &self,
+        file_node: NodePrefix,
+    ) -> Result<FilelogEntry, RevlogError> {
+        let rev = self.revlog.get_node_rev(file_node)?;
+        self.get_rev(rev)
+    }
+
+    /// Return the `FilelogEntry` of a given node revision.
+    pub fn get_rev(&self, rev: Revision) -> Result<FilelogEntry, RevlogError> {
+        let bytes = self.revlog.get_rev_data(rev)?;
+        Ok(FilelogEntry { bytes })
+    }
+
+    pub fn node_from_rev(&self, rev: Revision) -> Option<&Node> {
+        Some(self.revlog.index.get_entry(rev)?.hash())
+    }
+
+    /// Return the revision number of the file revision with the given node
+    /// ID or unambiguous prefix.
+    pub fn rev_from_node(
+        &self,
+        file_node: NodePrefix,
+    ) -> Result<Revision, RevlogError> {
+        self.revlog.get_node_rev(file_node)
+    }
+
+    /// The number of revisions of the file
+    pub fn len(&self) -> usize {
+        self.revlog.len()
+    }
+
+    /// Whether the file has no revisions
+    pub fn is_empty(&self) -> bool {
+        self.revlog.is_empty()
+    }
+
+    /// The changeset that first added a file revision, like `linkrev` in
+    /// Python.
+    ///
+    /// Other changesets may introduce the same file revision later.
+    pub fn link_revision(
+        &self,
+        rev: Revision,
+    ) -> Result<Revision, RevlogError> {
+        Ok(self
+            .revlog
+            .index
+            .get_entry(rev)
+            .ok_or(RevlogError::InvalidRevision)?
+            .link_revision())
+    }
+
+    /// The file revision that a file revision was copied or renamed from,
+    /// like `renamed` in Python.
+    ///
+    /// Only revisions without a first parent can be copies: others are read
+    /// from their metadata but never considered to be.
+    pub fn copy_source(
+        &self,
+        rev: Revision,
+    ) -> Result<Option<(HgPathBuf, Node)>, RevlogError> {
+        if self.revlog.parents(rev)?[0] != NULL_REVISION {
+            return Ok(None);
+        }
+        Ok(self.get_rev(rev)?.copy_source()?)
+    }
+
+    /// The size of the contents of a file revision, like `size` in Python.
+    ///
+    /// This is the stored size, only reading the revision for copies whose
+    /// metadata must not be counted. Contents that start with `\1\n` are
+    /// escaped with empty metadata, counted as 4 more bytes.
+    pub fn size(&self, rev: Revision) -> Result<usize, RevlogError> {
+        if self.copy_source(rev)?.is_some() {
+            return Ok(self.get_rev(rev)?.data()?.len());
+        }
+        if self.is_censored(rev)? {
+            return Ok(0);
+        }
+        self.revlog.size(rev)
+    }
+
+    /// Whether `data` differs from the contents of a file revision, like
+    /// `filectx.cmp` in Python.
+    ///
+    /// The revision is not read when the sizes differ, and most often only
+    /// its hash is needed otherwise.
+    pub fn cmp(
+        &self,
+        rev: Revision,
+        data: &[u8],
+    ) -> Result<bool, RevlogError> {
+        let size = self.size(rev)?;
+        if size != data.len() && size != data.len() + 4 {
+            return Ok(true);
+        }
+        // Like `filedataequivalent` in Python
+        let text = if data.starts_with(METADATA_DELIMITER) {
+            [METADATA_DELIMITER, METADATA_DELIMITER, data].concat()
+        } else {
+            data.to_vec()
+        };
+        let [p1, p2] = self.revlog.parents(rev)?;
+        let node = self
+            .node_from_rev(rev)
+            .ok_or(RevlogError::InvalidRevision)?;
+        if self.revlog.check_hash(p1, p2, node.as_bytes(), &text) {
+            return Ok(false);
+        }
+        // Censored contents are compared as empty
+        if self.is_censored(rev)? {
+            return Ok(!data.is_empty());
+        }
+        // The hash of copies covers their metadata
+        if self.copy_source(rev)?.is_some() {
+            return Ok(self.get_rev(rev)?.data()? != data);
+        }
+        Ok(true)
+    }
+
+    /// Whether a file revision was censored, like `iscensored` in Python
+    fn is_censored(&self, rev: Revision) -> Result<bool, RevlogError> {
+        let entry = self
+            .revlog
+            .index
+            .get_entry(rev)
+            .ok_or(RevlogError::InvalidRevision)?;
+        Ok(entry.flags() & REVIDX_ISCENSORED != 0)
+    }
+}
+
+impl Graph for Filelog {
+    fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError> {
+        self.revlog.parents(rev)
+    }
+}
+
+/// The data of a file revision: optional metadata, like the source of a
+/// copy, followed by the contents of the file.
+///
+/// The format is:
+///
+/// ```text
+/// \1
+/// <key>: <value>
+/// ...
+/// \1
+/// <contents>
+/// ```
+///
+/// Contents that start with `\1\n` always have metadata, possibly empty.
+#[derive(Debug)]
+pub struct FilelogEntry {
+    bytes: Vec<u8>,
+}
+
+impl FilelogEntry {
+    /// The metadata, if any, and the contents of the file, like
+    /// `parsemeta` in Python
+    pub fn split(&self) -> Result<(Option<&[u8]>, &[u8]), HgError> {
+        if !self.bytes.starts_with(METADATA_DELIMITER) {
+            return Ok((None, &self.bytes));
+        }
+        let metadata = &self.bytes[METADATA_DELIMITER.len()..];
+        let end = find_slice_in_slice(metadata, METADATA_DELIMITER)
+            .ok_or_else(|| {
+                HgError::corrupted("unterminated filelog metadata")
+            })?;
+        Ok((
+            Some(&metadata[..end]),
+            &metadata[end + METADATA_DELIMITER.len()..],
+        ))
+    }
+
+    /// The entries of the metadata, empty without metadata
+    pub fn metadata(&self) -> Result<BTreeMap<&[u8], &[u8]>, HgError> {
+        let mut entries = BTreeMap::new();
+        if let (Some(metadata), _) = self.split()? {
+            for line in metadata.split(|&byte| byte == b'\n') {
+                if let Some(position) = find_slice_in_slice(line, b": ") {
+                    entries.insert(&line[..position], &line[position + 2..]);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The contents of the file, without metadata
+    pub fn data(&self) -> Result<&[u8], HgError> {
+        Ok(self.split()?.1)
+    }
+
+    /// The contents of the file, without metadata
+    pub fn into_data(mut self) -> Result<Vec<u8>, HgError> {
+        let metadata_len = self.bytes.len() - self.data()?.len();
+        self.bytes.drain(..metadata_len);
+        Ok(self.bytes)
+    }
+
+    /// The `copy` and `copyrev` entries of the metadata, if any.
+    ///
+    /// See `Filelog::copy_source` for whether the revision is a copy.
+    pub fn copy_source(&self) -> Result<Option<(HgPathBuf, Node)>, HgError> {
+        let metadata = self.metadata()?;
+        match (metadata.get(&b"copy"[..]), metadata.get(&b"copyrev"[..])) {
+            (Some(copy), Some(copy_rev)) => Ok(Some((
+                HgPathBuf::from_bytes(copy),
+                Node::from_hex_for_repo(copy_rev)?,
+            ))),
+            // Like in Python, ignore one of these entries without the other
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The path of the index or data file of the filelog of a tracked file,
+/// relative to the store
+fn store_path(hg_path: &HgPath, suffix: &[u8]) -> PathBuf {
+    let encoded_bytes =
+        path_encode(&[b"data/", hg_path.as_bytes(), suffix].concat());
+    get_path_from_bytes(&encoded_bytes).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::revlog::revlog::WriteOptions;
+    use crate::testing::repo_with;
+    use crate::transaction::Transaction;
+
+    fn entry(bytes: &[u8]) -> FilelogEntry {
+        FilelogEntry {
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            entry(b"content").split().unwrap(),
+            (None, &b"content"[..])
+        );
+        assert_eq!(
+            entry(b"\x01\n\x01\ncontent").split().unwrap(),
+            (Some(&b""[..]), &b"content"[..])
+        );
+        assert_eq!(
+            entry(b"\x01\n\x01\n\x01\ncontent").into_data().unwrap(),
+            b"\x01\ncontent"
+        );
+        assert!(entry(b"\x01\ncopy: some/file\n").split().is_err());
+    }
+
+    #[test]
+    fn test_copy_source() {
+        assert_eq!(entry(b"content").copy_source().unwrap(), None);
+        assert_eq!(entry(b"\x01\n\x01\ncontent").copy_source().unwrap(), None);
+        let node = "0123456789abcdef0123456789abcdef01234567";
+        let data =
+            format!("\x01\ncopy: some/file\ncopyrev: {}\n\x01\ncontent", node);
+        assert_eq!(
+            entry(data.as_bytes()).copy_source().unwrap(),
+            Some((
+                HgPathBuf::from_bytes(b"some/file"),
+                Node::from_hex(node).unwrap()
+            ))
+        );
+        let data = "\x01\ncopy: some/file\n\x01\ncontent";
+        assert_eq!(entry(data.as_bytes()).copy_source().unwrap(), None);
+        assert!(entry(b"\x01\ncopy: some/file\n").copy_source().is_err());
+    }
+
+    #[test]
+    fn test_size_and_cmp() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &["generaldelta"]);
+        let options = WriteOptions::from_repo(&repo).unwrap();
+        let path = HgPath::new(b"some/file");
+
+        let copy = format!(
+            "\x01\ncopy: other\ncopyrev: {}\n\x01\ncontent\n",
+            "0".repeat(40)
+        );
+        let texts: [&[u8]; 4] = [
+            b"content\n",
+            b"\x01\n\x01\n\x01\nescaped\n",
+            copy.as_bytes(),
+            b"changed\n",
+        ];
+        let mut transaction = Transaction::start(&repo).unwrap();
+        let mut revlog = Filelog::open(&repo, path).ok().unwrap().revlog;
+        for (rev, text) in texts.iter().enumerate() {
+            let p1 = if rev == 2 {
+                NULL_REVISION
+            } else {
+                rev as i32 - 1
+            };
+            revlog
+                .add_revision(
+                    &mut transaction,
+                    &options,
+                    text,
+                    rev as Revision,
+                    p1,
+                    NULL_REVISION,
+                )
+                .ok()
+                .unwrap();
+        }
+        transaction.close().unwrap();
+        let file = root.path().join(".hg/store/data/some/file.i");
+        assert!(file.exists());
+
+        let filelog = Filelog::open(&repo, path).ok().unwrap();
+        assert_eq!(filelog.len(), 4);
+        assert_eq!(filelog.link_revision(3).ok(), Some(3));
+        let sizes: Vec<_> =
+            (0..4).map(|rev| filelog.size(rev).ok().unwrap()).collect();
+        assert_eq!(sizes, vec![8, 14, 8, 8]);
+        assert_eq!(
+            filelog.get_rev(1).ok().unwrap().data().unwrap(),
+            b"\x01\nescaped\n"
+        );
+        assert_eq!(filelog.copy_source(0).ok(), Some(None));
+        assert_eq!(
+            filelog.copy_source(2).ok(),
+            Some(Some((
+                HgPathBuf::from_bytes(b"other"),
+                Node::from_hex(&"0".repeat(40)).unwrap()
+            )))
+        );
+
+        let cmp = |rev, data: &[u8]| filelog.cmp(rev, data).ok().unwrap();
+        assert!(!cmp(0, b"content\n"));
+        assert!(cmp(0, b"Content\n"));
+        assert!(cmp(0, b"content"));
+        assert!(!cmp(1, b"\x01\nescaped\n"));
+        assert!(cmp(1, b"\x01\nEscaped\n"));
+        assert!(!cmp(2, b"content\n"));
+        assert!(cmp(2, b"changed\n"));
+        assert!(!cmp(3, b"changed\n"));
+    }
+}
@@ -0,0 +1,444 @@
+//! Flags of revisions in the index and the processors that turn the text
+//! stored in a revlog into the text of the revision, like
+//! `revlogutils/flagutil.py` in Python
+
+use crate::config::Config;
+use crate::errors::{HgError, HgResultExt, IoResultExt};
+use crate::repo::Repo;
+use crate::requirements;
+use crate::utils::files::get_path_from_bytes;
+use crate::utils::{expand_vars, strip_prefix};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The revision was censored, its text replaced with a tombstone
+pub const REVIDX_ISCENSORED: u16 = 1 << 15;
+/// The revision is part of an ellipsis in a narrow clone: its parents are
+/// not those it was hashed with
+pub const REVIDX_ELLIPSIS: u16 = 1 << 14;
+/// The text of the revision is stored outside of the revlog, which only has
+/// a pointer to it
+pub const REVIDX_EXTSTORED: u16 = 1 << 13;
+/// The revision has sidedata, in older formats that stored them with the
+/// text
+pub const REVIDX_SIDEDATA: u16 = 1 << 12;
+/// The changeset has copy tracing information in its sidedata
+pub const REVIDX_HASCOPIESINFO: u16 = 1 << 11;
+
+/// The order in which processors apply to the text when reading
+const REVIDX_FLAGS_ORDER: [u16; 5] = [
+    REVIDX_ISCENSORED,
+    REVIDX_ELLIPSIS,
+    REVIDX_EXTSTORED,
+    REVIDX_SIDEDATA,
+    REVIDX_HASCOPIESINFO,
+];
+
+const REVIDX_KNOWN_FLAGS: u16 = REVIDX_ISCENSORED
+    | REVIDX_ELLIPSIS
+    | REVIDX_EXTSTORED
+    | REVIDX_SIDEDATA
+    | REVIDX_HASCOPIESINFO;
+
+/// Flags whose processors make the text differ in size from the stored one
+pub const REVIDX_RAWTEXT_CHANGING_FLAGS: u16 =
+    REVIDX_ISCENSORED | REVIDX_EXTSTORED | REVIDX_SIDEDATA;
+
+/// Transforms the text of revisions with a given flag
+pub trait FlagProcessor: Send + Sync {
+    /// The text of the revision from the text stored in the revlog, and
+    /// whether the hash of the revision should be checked against it
+    fn read(&self, raw_text: Vec<u8>) -> Result<(Vec<u8>, bool), HgError>;
+
+    /// Whether the hash of the revision can be checked against the text
+    /// stored in the revlog
+    fn raw(&self, raw_text: &[u8]) -> bool;
+}
+
+/// The processors of the flags a revlog can have.
+///
+/// Known flags without a processor, like `REVIDX_ISCENSORED`, leave the
+/// text as is. Revisions with a flag that has no entry at all cannot be
+/// read.
+#[derive(Clone)]
+pub struct FlagProcessors {
+    processors: HashMap<u16, Option<Arc<dyn FlagProcessor>>>,
+}
+
+impl Default for FlagProcessors {
+    fn default() -> Self {
+        let mut processors = HashMap::new();
+        processors.insert(REVIDX_ISCENSORED, None);
+        processors.insert(REVIDX_HASCOPIESINFO, None);
+        Self { processors }
+    }
+}
+
+impl FlagProcessors {
+    /// The processors for the revlogs of a repository, depending on its
+    /// requirements
+    pub fn for_repo(repo: &Repo) -> Result<Self, HgError> {
+        let mut processors = Self::default();
+        if repo
+            .requirements()
+            .contains(requirements::NARROW_REQUIREMENT)
+        {
+            processors
+                .processors
+                .insert(REVIDX_ELLIPSIS, Some(Arc::new(EllipsisProcessor)));
+        }
+        if repo.requirements().contains(requirements::LFS_REQUIREMENT) {
+            let processor = LfsProcessor {
+                objects: repo.store_vfs().join("lfs/objects"),
+                usercache: lfs_usercache(repo.config())?,
+            };
+            processors
+                .processors
+                .insert(REVIDX_EXTSTORED, Some(Arc::new(processor)));
+        }
+        Ok(processors)
+    }
+
+    /// Register the processor of a known flag, like `addflagprocessor` in
+    /// Python
+    pub fn insert(
+        &mut self,
+        flag: u16,
+        processor: Arc<dyn FlagProcessor>,
+    ) -> Result<(), HgError> {
+        if !REVIDX_FLAGS_ORDER.contains(&flag) {
+            return Err(HgError::abort(format!(
+                "abort: cannot register processor on unknown flag '{:#x}'.",
+                flag
+            )));
+        }
+        if self.processors.contains_key(&flag) {
+            return Err(HgError::abort(format!(
+                "abort: cannot register multiple processors on flag '{:#x}'.",
+                flag
+            )));
+        }
+        self.processors.insert(flag, Some(processor));
+        Ok(())
+    }
+
+    /// Apply the processors of the given flags to the text stored in a
+    /// revlog, like `processflagsread` in Python
+    pub fn read(
+        &self,
+        mut text: Vec<u8>,
+        flags: u16,
+    ) -> Result<(Vec<u8>, bool), HgError> {
+        let mut validate_hash = true;
+        for processor in self.processors_for(flags)?.into_iter().flatten() {
+            let (processed, validate) = processor.read(text)?;
+            text = processed;
+            validate_hash = validate_hash && validate
+        }
+        Ok((text, validate_hash))
+    }
+
+    /// Whether the hash can be checked against the text stored in a revlog,
+    /// like `processflagsraw` in Python
+    pub fn raw(&self, text: &[u8], flags: u16) -> Result<bool, HgError> {
+        let mut validate_hash = true;
+        for processor in self.processors_for(flags)?.into_iter().flatten() {
+            validate_hash = processor.raw(text) && validate_hash
+        }
+        Ok(validate_hash)
+    }
+
+    fn processors_for(
+        &self,
+        flags: u16,
+    ) -> Result<Vec<&Option<Arc<dyn FlagProcessor>>>, HgError> {
+        if flags & !REVIDX_KNOWN_FLAGS != 0 {
+            return Err(HgError::corrupted(format!(
+                "incompatible revision flag '{:#x}'",
+                flags & !REVIDX_KNOWN_FLAGS
+            )));
+        }
+        REVIDX_FLAGS_ORDER
+            .iter()
+            .filter(|&&flag| flags & flag != 0)
+            .map(|flag| {
+                self.processors.get(flag).ok_or_else(|| {
+                    HgError::unsupported(format!(
+                        "missing processor for flag '{:#x}'",
+                        flag
+                    ))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Revisions of an ellipsis are hashed with other parents than theirs, so
+/// their hash cannot be checked
+struct EllipsisProcessor;
+
+impl FlagProcessor for EllipsisProcessor {
+    fn read(&self, raw_text: Vec<u8>) -> Result<(Vec<u8>, bool), HgError> {
+        Ok((raw_text, false))
+    }
+
+    fn raw(&self, _raw_text: &[u8]) -> bool {
+        false
+    }
+}
+
+/// Reads the text of revisions stored by the `lfs` extension from the
+/// local blob store or the usercache, like `readfromstore` in
+/// `hgext/lfs/wrapper.py`.
+///
+/// The revlog has a Git LFS pointer, the hash is that of the file.
+struct LfsProcessor {
+    /// The `lfs/objects` directory of the store
+    objects: PathBuf,
+    /// The blobs shared by the repositories of the user, if any
+    usercache: Option<PathBuf>,
+}
+
+/// Where the `lfs` extension shares blobs between repositories, like
+/// `lfutil._usercachedir` in Python. `None` when it is disabled or the
+/// environment does not tell where it is, which Python aborts on.
+fn lfs_usercache(config: &Config) -> Result<Option<PathBuf>, HgError> {
+    if config.get_bool(b"experimental", b"lfs.disableusercache")? {
+        return Ok(None);
+    }
+    if let Some(path) = config.get(b"lfs", b"usercache") {
+        let path = expand_vars(path);
+        let path = match (path.starts_with(b"~/"), home::home_dir()) {
+            (true, Some(home)) => home.join(get_path_from_bytes(&path[2..])),
+            _ => get_path_from_bytes(&path).to_owned(),
+        };
+        return Ok(Some(path));
+    }
+    let var = |name| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    let cache = if cfg!(windows) {
+        var("LOCALAPPDATA").or_else(|| var("APPDATA"))
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Caches"))
+    } else {
+        var("XDG_CACHE_HOME")
+            .or_else(|| var("HOME").map(|home| home.join(".cache")))
+    };
+    Ok(cache.map(|cache| cache.join("lfs")))
+}
+
+impl LfsProcessor {
+    /// The blob with the given hash, from the local store first like in
+    /// Python
+    fn read_blob(&self, oid: &str) -> Result<Option<Vec<u8>>, HgError> {
+        let directories =
+            std::iter::once(&self.objects).chain(&self.usercache);
+        for directory in directories {
+            let path = directory.join(&oid[..2]).join(&oid[2..]);
+            let blob = std::fs::read(&path)
+                .when_reading_file(&path)
+                .io_not_found_as_none()?;
+            if blob.is_some() {
+                return Ok(blob);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl FlagProcessor for LfsProcessor {
+    fn read(&self, raw_text: Vec<u8>) -> Result<(Vec<u8>, bool), HgError> {
+        let mut oid = None;
+        let mut metadata = Vec::new();
+        for line in raw_text.split(|&byte| byte == b'\n') {
+            let space = line.iter().position(|&byte| byte == b' ');
+            let (key, value) = match space {
+                Some(space) => (&line[..space], &line[space + 1..]),
+                None => continue,
+            };
+            if key == b"oid" {
+                oid = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| strip_prefix(value, "sha256:"))
+            } else if key.starts_with(b"x-hg-") {
+                metadata.push((&key[b"x-hg-".len()..], value))
+            }
+        }
+        let oid = match oid {
+            Some(oid) if oid.len() == 64 => oid,
+            _ => return Err(HgError::corrupted("invalid lfs pointer")),
+        };
+        // Python would download a missing blob
+        let text = match self.read_blob(oid)? {
+            Some(text) => text,
+            None => {
+                return Err(HgError::unsupported("lfs blob missing locally"))
+            }
+        };
+        if metadata.is_empty() && !text.starts_with(b"\x01\n") {
+            return Ok((text, true));
+        }
+        // Copy information in the pointer is filelog metadata for the text
+        metadata.sort();
+        let mut packed = b"\x01\n".to_vec();
+        for (key, value) in metadata {
+            packed.extend(key);
+            packed.extend(b": ");
+            packed.extend(value);
+            packed.push(b'\n');
+        }
+        packed.extend(b"\x01\n");
+        packed.extend(text);
+        Ok((packed, true))
+    }
+
+    fn raw(&self, _raw_text: &[u8]) -> bool {
+        false
+    }
+}
+
+/// Whether the text of a file revision is a censorship tombstone: filelog
+/// metadata with a `censored` key, like `iscensoredtext` in Python
+pub fn is_censored_text(text: &[u8]) -> bool {
+    let metadata = match text.get(2..) {
+        Some(rest) if text.starts_with(b"\x01\n") => rest,
+        _ => return false,
+    };
+    let end = metadata
+        .windows(2)
+        .position(|window| window == b"\x01\n")
+        .unwrap_or(0);
+    metadata[..end]
+        .split(|&byte| byte == b'\n')
+        .any(|line| line.starts_with(b"censored: "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Appends a suffix, without changing the hash to check
+    struct Suffix(&'static [u8]);
+
+    impl FlagProcessor for Suffix {
+        fn read(&self, raw_text: Vec<u8>) -> Result<(Vec<u8>, bool), HgError> {
+            let mut text = raw_text;
+            text.extend(self.0);
+            Ok((text, true))
+        }
+
+        fn raw(&self, _raw_text: &[u8]) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_processors() {
+        let mut processors = FlagProcessors::default();
+        let read = |processors: &FlagProcessors, flags| {
+            processors.read(b"text".to_vec(), flags).ok()
+        };
+        assert_eq!(read(&processors, 0), Some((b"text".to_vec(), true)));
+        assert_eq!(
+            read(&processors, REVIDX_ISCENSORED | REVIDX_HASCOPIESINFO),
+            Some((b"text".to_vec(), true))
+        );
+        // Without a processor
+        assert_eq!(read(&processors, REVIDX_EXTSTORED), None);
+        // Unknown flags
+        assert_eq!(read(&processors, 1), None);
+        assert!(processors.insert(1, Arc::new(Suffix(b""))).is_err());
+
+        processors
+            .insert(REVIDX_EXTSTORED, Arc::new(Suffix(b" extstored")))
+            .unwrap();
+        processors
+            .insert(REVIDX_ELLIPSIS, Arc::new(EllipsisProcessor))
+            .unwrap();
+        assert!(processors
+            .insert(REVIDX_ELLIPSIS, Arc::new(EllipsisProcessor))
+            .is_err());
+        assert_eq!(
+            read(&processors, REVIDX_EXTSTORED),
+            Some((b"text extstored".to_vec(), true))
+        );
+        assert_eq!(
+            read(&processors, REVIDX_EXTSTORED | REVIDX_ELLIPSIS),
+            Some((b"text extstored".to_vec(), false))
+        );
+        assert_eq!(processors.raw(b"text", REVIDX_EXTSTORED).ok(), Some(true));
+        assert_eq!(processors.raw(b"text", REVIDX_ELLIPSIS).ok(), Some(false));
+    }
+
+    #[test]
+    fn test_lfs_processor() {
+        let root = tempfile::tempdir().unwrap();
+        let oid =
+            "31cf46fbc4ecd458a0943c5b4881f1f5a6dd36c53d6167d5b69ac45149b38e5b";
+        let objects = root.path().join("lfs/objects");
+        std::fs::create_dir_all(objects.join(&oid[..2])).unwrap();
+        std::fs::write(objects.join(&oid[..2]).join(&oid[2..]), b"large\n")
+            .unwrap();
+        let processor = LfsProcessor {
+            objects,
+            usercache: None,
+        };
+
+        let pointer = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\n\
+             size 6\nx-is-binary 0\n",
+            oid
+        );
+        let read = |pointer: String| processor.read(pointer.into_bytes()).ok();
+        assert_eq!(read(pointer.clone()), Some((b"large\n".to_vec(), true)));
+        assert_eq!(
+            read(format!(
+                "{}x-hg-copy a\nx-hg-copyrev {}\n",
+                pointer,
+                "0".repeat(40)
+            )),
+            Some((
+                format!(
+                    "\x01\ncopy: a\ncopyrev: {}\n\x01\nlarge\n",
+                    "0".repeat(40)
+                )
+                .into_bytes(),
+                true
+            ))
+        );
+        assert_eq!(read(pointer.replace(&oid[..4], "0000")), None);
+        assert_eq!(read("version 1\n".to_owned()), None);
+
+        // Blobs only in the usercache
+        let processor = LfsProcessor {
+            objects: root.path().join("other/objects"),
+            usercache: Some(root.path().join("lfs/objects")),
+        };
+        assert_eq!(
+            processor.read(pointer.clone().into_bytes()).ok(),
+            Some((b"large\n".to_vec(), true))
+        );
+        // Missing from both, which Python would download
+        let processor = LfsProcessor {
+            objects: root.path().join("other/objects"),
+            usercache: None,
+        };
+        match processor.read(pointer.into_bytes()) {
+            Err(HgError::UnsupportedFeature(_)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_is_censored_text() {
+        assert!(is_censored_text(b"\x01\ncensored: reason\n\x01\n"));
+        assert!(is_censored_text(b"\x01\ncensored: \n\x01\n"));
+        assert!(!is_censored_text(b"\x01\ncopy: a\n\x01\ncensored: \n"));
+        assert!(!is_censored_text(b"censored: reason\n"));
+        assert!(!is_censored_text(b""));
+    }
+}
@@ -6,16 +6,27 @@ use byteorder::{BigEndian, ByteOrder};
 use crate::errors::HgError;
 use crate::revlog::node::Node;
 use crate::revlog::revlog::RevlogError;
-use crate::revlog::{Revision, NULL_REVISION};
+use crate::revlog::{Revision, NULL_REVISION, WORKING_DIRECTORY_REVISION};
+use crate::{Graph, GraphError};
 
 pub const INDEX_ENTRY_SIZE: usize = 64;
+/// Size of the entries of revlog v2, which also locate the sidedata
+pub const INDEX_ENTRY_SIZE_V2: usize = 96;
+
+/// Version number of the revlog v2 format, until it is finalized
+pub const REVLOGV2: u16 = 0xDEAD;
 
 /// A Revlog index
 pub struct Index {
     bytes: Box<dyn Deref<Target = [u8]> + Send>,
+    /// Entries appended to the index file since `bytes` was read, followed
+    /// by their data when inline, like the added entries of the C index
+    added: Vec<u8>,
     /// Offsets of starts of index blocks.
     /// Only needed when the index is interleaved with data.
     offsets: Option<Vec<usize>>,
+    /// `INDEX_ENTRY_SIZE` or `INDEX_ENTRY_SIZE_V2`
+    entry_size: usize,
 }
 
 impl Index {
@@ -24,7 +35,23 @@ impl Index {
     pub fn new(
         bytes: Box<dyn Deref<Target = [u8]> + Send>,
     ) -> Result<Self, RevlogError> {
-        if is_inline(&bytes) {
+        if bytes.is_empty() {
+            // A missing revlog is empty and considered inline, like in Python
+            return Ok(Self {
+                bytes,
+                added: Vec::new(),
+                offsets: Some(Vec::new()),
+                entry_size: INDEX_ENTRY_SIZE,
+            });
+        }
+        if is_v2(&bytes) {
+            Ok(Self {
+                bytes,
+                added: Vec::new(),
+                offsets: None,
+                entry_size: INDEX_ENTRY_SIZE_V2,
+            })
+        } else if is_inline(&bytes) {
             let mut offset: usize = 0;
             let mut offsets = Vec::new();
 
@@ -42,7 +69,9 @@ impl Index {
             if offset == bytes.len() {
                 Ok(Self {
                     bytes,
+                    added: Vec::new(),
                     offsets: Some(offsets),
+                    entry_size: INDEX_ENTRY_SIZE,
                 })
             } else {
                 Err(HgError::corrupted("unexpected inline revlog length")
@@ -51,14 +80,60 @@ impl Index {
         } else {
             Ok(Self {
                 bytes,
+                added: Vec::new(),
                 offsets: None,
+                entry_size: INDEX_ENTRY_SIZE,
             })
         }
     }
 
+    /// Add an entry that was appended to the index file, with its data if
+    /// the revlog is inline.
+    pub fn append(&mut self, entry: &[u8], inline_data: &[u8]) {
+        let offset = self.bytes_len();
+        if let Some(offsets) = &mut self.offsets {
+            offsets.push(offset)
+        }
+        self.added.extend_from_slice(entry);
+        self.added.extend_from_slice(inline_data);
+    }
+
+    fn bytes_len(&self) -> usize {
+        self.bytes.len() + self.added.len()
+    }
+
+    /// The bytes from `start` to `end`, which are never partly read and
+    /// partly appended
+    fn get_bytes(&self, start: usize, end: usize) -> Option<&[u8]> {
+        let read = self.bytes.len();
+        if start < read {
+            self.bytes.get(start..end)
+        } else {
+            self.added.get(start - read..end.checked_sub(read)?)
+        }
+    }
+
     /// Value of the inline flag.
     pub fn is_inline(&self) -> bool {
-        is_inline(&self.bytes)
+        self.offsets.is_some()
+    }
+
+    /// Whether entries have the revlog v2 layout, with sidedata
+    pub fn is_v2(&self) -> bool {
+        self.entry_size == INDEX_ENTRY_SIZE_V2
+    }
+
+    /// Value of the generaldelta flag: whether deltas can be against any
+    /// revision, rather than only the previous one. It is implied by revlog
+    /// v2.
+    pub fn uses_generaldelta(&self) -> bool {
+        if self.is_v2() {
+            return true;
+        }
+        match self.get_bytes(0, 2) {
+            Some([_, flags]) => flags & 2 != 0,
+            _ => false,
+        }
     }
 
     /// Return a slice of bytes if `revlog` is inline. Panic if not.
@@ -66,7 +141,8 @@ impl Index {
         if !self.is_inline() {
             panic!("tried to access data in the index of a revlog that is not inline");
         }
-        &self.bytes[start..end]
+        self.get_bytes(start, end)
+            .expect("inline data out of the index bounds")
     }
 
     /// Return number of entries of the revlog index.
@@ -74,7 +150,17 @@ impl Index {
         if let Some(offsets) = &self.offsets {
             offsets.len()
         } else {
-            self.bytes.len() / INDEX_ENTRY_SIZE
+            self.bytes_len() / self.entry_size
+        }
+    }
+
+    /// Bytes at the end of a separated index that do not make an entry,
+    /// which a healthy revlog does not have
+    pub fn extra_bytes(&self) -> usize {
+        if self.is_inline() {
+            0
+        } else {
+            self.bytes_len() % self.entry_size
         }
     }
 
@@ -103,7 +189,7 @@ impl Index {
     ) -> Option<IndexEntry> {
         let start = *offsets.get(rev as usize)?;
         let end = start.checked_add(INDEX_ENTRY_SIZE)?;
-        let bytes = &self.bytes[start..end];
+        let bytes = self.get_bytes(start, end)?;
 
         // See IndexEntry for an explanation of this override.
         let offset_override = Some(end);
@@ -115,13 +201,13 @@ impl Index {
     }
 
     fn get_entry_separated(&self, rev: Revision) -> Option<IndexEntry> {
-        let max_rev = self.bytes.len() / INDEX_ENTRY_SIZE;
+        let max_rev = self.bytes_len() / self.entry_size;
         if rev as usize >= max_rev {
             return None;
         }
-        let start = rev as usize * INDEX_ENTRY_SIZE;
-        let end = start + INDEX_ENTRY_SIZE;
-        let bytes = &self.bytes[start..end];
+        let start = rev as usize * self.entry_size;
+        let end = start + self.entry_size;
+        let bytes = self.get_bytes(start, end)?;
 
         // Override the offset of the first revision as its bytes are used
         // for the index's metadata (saving space because it is always 0)
@@ -144,6 +230,18 @@ impl super::RevlogIndex for Index {
     }
 }
 
+impl Graph for Index {
+    fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError> {
+        if rev == WORKING_DIRECTORY_REVISION {
+            return Err(GraphError::WorkingDirectoryUnsupported);
+        }
+        let entry = self
+            .get_entry(rev)
+            .ok_or(GraphError::ParentOutOfRange(rev))?;
+        Ok([entry.p1(), entry.p2()])
+    }
+}
+
 #[derive(Debug)]
 pub struct IndexEntry<'a> {
     bytes: &'a [u8],
@@ -166,12 +264,23 @@ impl<'a> IndexEntry<'a> {
         if let Some(offset_override) = self.offset_override {
             offset_override
         } else {
-            let mut bytes = [0; 8];
-            bytes[2..8].copy_from_slice(&self.bytes[0..=5]);
-            BigEndian::read_u64(&bytes[..]) as usize
+            self.stored_offset()
         }
     }
 
+    /// Return the offset of the data as stored in the index, even when it is
+    /// overridden. Python reads inline revlogs with it.
+    pub fn stored_offset(&self) -> usize {
+        let mut bytes = [0; 8];
+        bytes[2..8].copy_from_slice(&self.bytes[0..=5]);
+        BigEndian::read_u64(&bytes[..]) as usize
+    }
+
+    /// Return the flags of the revision, like censored.
+    pub fn flags(&self) -> u16 {
+        BigEndian::read_u16(&self.bytes[6..=7])
+    }
+
     /// Return the compressed length of the data.
     pub fn compressed_len(&self) -> usize {
         BigEndian::read_u32(&self.bytes[8..=11]) as usize
@@ -190,6 +299,11 @@ impl<'a> IndexEntry<'a> {
         BigEndian::read_i32(&self.bytes[16..])
     }
 
+    /// Return the changelog revision that introduced this revision.
+    pub fn link_revision(&self) -> Revision {
+        BigEndian::read_i32(&self.bytes[20..])
+    }
+
     pub fn p1(&self) -> Revision {
         BigEndian::read_i32(&self.bytes[24..])
     }
@@ -198,6 +312,11 @@ impl<'a> IndexEntry<'a> {
         BigEndian::read_i32(&self.bytes[28..])
     }
 
+    /// Return the bytes of the entry, as stored in the index.
+    pub(super) fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
     /// Return the hash of revision's full text.
     ///
     /// Currently, SHA-1 is used and only the first 20 bytes of this field
@@ -205,16 +324,44 @@ impl<'a> IndexEntry<'a> {
     pub fn hash(&self) -> &'a Node {
         (&self.bytes[32..52]).try_into().unwrap()
     }
+
+    /// Return the offset of the sidedata in the data file, zero before
+    /// revlog v2.
+    pub fn sidedata_offset(&self) -> usize {
+        match self.bytes.get(64..72) {
+            Some(bytes) => BigEndian::read_u64(bytes) as usize,
+            None => 0,
+        }
+    }
+
+    /// Return the length of the sidedata, zero when there is none or before
+    /// revlog v2.
+    pub fn sidedata_compressed_len(&self) -> usize {
+        match self.bytes.get(72..76) {
+            Some(bytes) => BigEndian::read_u32(bytes) as usize,
+            None => 0,
+        }
+    }
 }
 
 /// Value of the inline flag.
+///
+/// Like in Python, revlog v2 is never inline whatever its flags.
 pub fn is_inline(index_bytes: &[u8]) -> bool {
+    if is_v2(index_bytes) {
+        return false;
+    }
     match &index_bytes[0..=1] {
         [0, 0] | [0, 2] => false,
         _ => true,
     }
 }
 
+/// Whether the version in the header is revlog v2.
+fn is_v2(index_bytes: &[u8]) -> bool {
+    BigEndian::read_u16(&index_bytes[2..=3]) == REVLOGV2
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -230,6 +377,10 @@ mod tests {
         compressed_len: usize,
         uncompressed_len: usize,
         base_revision: Revision,
+        link_revision: Revision,
+        p1: Revision,
+        p2: Revision,
+        sidedata: Option<(usize, usize)>,
     }
 
     #[cfg(test)]
@@ -244,6 +395,10 @@ mod tests {
                 compressed_len: 0,
                 uncompressed_len: 0,
                 base_revision: 0,
+                link_revision: 0,
+                p1: NULL_REVISION,
+                p2: NULL_REVISION,
+                sidedata: None,
             }
         }
 
@@ -287,6 +442,31 @@ mod tests {
             self
         }
 
+        pub fn with_link_revision(&mut self, value: Revision) -> &mut Self {
+            self.link_revision = value;
+            self
+        }
+
+        pub fn with_p1(&mut self, value: Revision) -> &mut Self {
+            self.p1 = value;
+            self
+        }
+
+        pub fn with_p2(&mut self, value: Revision) -> &mut Self {
+            self.p2 = value;
+            self
+        }
+
+        /// Build a revlog v2 entry with the given sidedata offset and length
+        pub fn with_sidedata(
+            &mut self,
+            offset: usize,
+            compressed_len: usize,
+        ) -> &mut Self {
+            self.sidedata = Some((offset, compressed_len));
+            self
+        }
+
         pub fn build(&self) -> Vec<u8> {
             let mut bytes = Vec::with_capacity(INDEX_ENTRY_SIZE);
             if self.is_first {
@@ -307,6 +487,16 @@ mod tests {
             bytes.extend(&(self.compressed_len as u32).to_be_bytes());
             bytes.extend(&(self.uncompressed_len as u32).to_be_bytes());
             bytes.extend(&self.base_revision.to_be_bytes());
+            bytes.extend(&self.link_revision.to_be_bytes());
+            bytes.extend(&self.p1.to_be_bytes());
+            bytes.extend(&self.p2.to_be_bytes());
+            // Node ID and padding
+            bytes.resize(INDEX_ENTRY_SIZE, 0);
+            if let Some((offset, compressed_len)) = self.sidedata {
+                bytes.extend(&(offset as u64).to_be_bytes());
+                bytes.extend(&(compressed_len as u32).to_be_bytes());
+                bytes.resize(INDEX_ENTRY_SIZE_V2, 0);
+            }
             bytes
         }
     }
@@ -344,6 +534,70 @@ mod tests {
         assert_eq!(is_inline(&bytes), true)
     }
 
+    #[test]
+    fn is_not_inline_when_v2_test() {
+        let bytes = IndexEntryBuilder::new()
+            .is_first(true)
+            .with_inline(true)
+            .with_version(REVLOGV2)
+            .with_sidedata(0, 0)
+            .build();
+
+        assert_eq!(is_inline(&bytes), false)
+    }
+
+    #[test]
+    fn test_sidedata() {
+        let bytes = IndexEntryBuilder::new().with_sidedata(42, 7).build();
+        let entry = IndexEntry {
+            bytes: &bytes,
+            offset_override: None,
+        };
+
+        assert_eq!(entry.sidedata_offset(), 42);
+        assert_eq!(entry.sidedata_compressed_len(), 7);
+
+        let bytes = IndexEntryBuilder::new().build();
+        let entry = IndexEntry {
+            bytes: &bytes,
+            offset_override: None,
+        };
+
+        assert_eq!(entry.sidedata_offset(), 0);
+        assert_eq!(entry.sidedata_compressed_len(), 0);
+    }
+
+    #[test]
+    fn test_v2_index() {
+        let mut bytes = IndexEntryBuilder::new()
+            .is_first(true)
+            .with_inline(true)
+            .with_version(REVLOGV2)
+            .with_general_delta(false)
+            .with_compressed_len(10)
+            .with_sidedata(10, 5)
+            .build();
+        bytes.extend(
+            IndexEntryBuilder::new()
+                .with_offset(15)
+                .with_base_revision(1)
+                .with_p1(0)
+                .with_sidedata(0, 0)
+                .build(),
+        );
+        let index = Index::new(Box::new(bytes)).ok().unwrap();
+
+        assert!(index.is_v2());
+        assert!(!index.is_inline());
+        assert!(index.uses_generaldelta());
+        assert_eq!(index.len(), 2);
+        let entry = index.get_entry(1).unwrap();
+        assert_eq!(entry.offset(), 15);
+        assert_eq!(entry.p1(), 0);
+        assert_eq!(index.get_entry(0).unwrap().sidedata_offset(), 10);
+        assert!(index.get_entry(2).is_none());
+    }
+
     #[test]
     fn test_offset() {
         let bytes = IndexEntryBuilder::new().with_offset(1).build();
@@ -398,6 +652,72 @@ mod tests {
 
         assert_eq!(entry.base_revision(), 1)
     }
+
+    #[test]
+    fn test_link_revision() {
+        let bytes = IndexEntryBuilder::new().with_link_revision(3).build();
+        let entry = IndexEntry {
+            bytes: &bytes,
+            offset_override: None,
+        };
+
+        assert_eq!(entry.link_revision(), 3)
+    }
+
+    #[test]
+    fn test_parents() {
+        let bytes = IndexEntryBuilder::new().with_p1(1).with_p2(2).build();
+        let entry = IndexEntry {
+            bytes: &bytes,
+            offset_override: None,
+        };
+
+        assert_eq!(entry.p1(), 1);
+        assert_eq!(entry.p2(), 2);
+    }
+
+    /// A non-inline index where revision 2 merges revisions 0 and 1
+    fn merge_index() -> Index {
+        let mut bytes = IndexEntryBuilder::new().is_first(true).build();
+        bytes.extend(IndexEntryBuilder::new().with_p1(0).build());
+        bytes.extend(IndexEntryBuilder::new().with_p1(0).with_p2(1).build());
+        match Index::new(Box::new(bytes)) {
+            Ok(index) => index,
+            Err(_) => panic!("invalid index"),
+        }
+    }
+
+    #[test]
+    fn test_graph() {
+        let index = merge_index();
+
+        assert_eq!(index.parents(0), Ok([NULL_REVISION, NULL_REVISION]));
+        assert_eq!(index.parents(2), Ok([0, 1]));
+        assert_eq!(index.parents(3), Err(GraphError::ParentOutOfRange(3)));
+        assert_eq!(
+            index.parents(WORKING_DIRECTORY_REVISION),
+            Err(GraphError::WorkingDirectoryUnsupported)
+        );
+    }
+
+    #[test]
+    fn test_graph_handles() {
+        use crate::AncestorsIterator;
+        use std::rc::Rc;
+
+        let ancestors = |graph| -> Vec<Revision> {
+            AncestorsIterator::new(graph, vec![2], 0, true)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        let index = merge_index();
+        assert_eq!(ancestors(&index), vec![2, 1, 0]);
+        let shared = Rc::new(merge_index());
+        let range =
+            crate::dagops::range(&Rc::clone(&shared), vec![1], vec![2]);
+        assert_eq!(range.unwrap().into_iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
 }
 
 #[cfg(test)]
@@ -1,20 +1,46 @@
+use crate::errors::HgError;
+use crate::matchers::{AlwaysMatcher, Matcher, VisitChildrenSet};
 use crate::repo::Repo;
+use crate::requirements::TREEMANIFEST_REQUIREMENT;
+use crate::revlog::node::NODE_BYTES_LENGTH;
 use crate::revlog::revlog::{Revlog, RevlogError};
-use crate::revlog::NodePrefix;
-use crate::revlog::Revision;
+use crate::revlog::treemanifest::DirectoryManifests;
+use crate::revlog::{Node, NodePrefix};
+use crate::revlog::{Revision, NULL_REVISION};
 use crate::utils::hg_path::HgPath;
+use std::collections::HashMap;
+
+/// Length of node IDs in manifest lines, in hexadecimal
+pub(super) const NODE_HEX_LENGTH: usize = NODE_BYTES_LENGTH * 2;
 
 /// A specialized `Revlog` to work with `manifest` data format.
-pub struct Manifest {
-    /// The generic `revlog` format.
-    revlog: Revlog,
+pub struct Manifest<'repo> {
+    /// The generic `revlog` format, of the root directory for tree
+    /// manifests.
+    pub(crate) revlog: Revlog,
+    /// The other directories, for repositories with tree manifests
+    directories: Option<DirectoryManifests<'repo>>,
 }
 
-impl Manifest {
+impl<'repo> Manifest<'repo> {
     /// Open the `manifest` of a repository given by its root.
-    pub fn open(repo: &Repo) -> Result<Self, RevlogError> {
+    pub fn open(repo: &'repo Repo) -> Result<Self, RevlogError> {
         let revlog = Revlog::open(repo, "00manifest.i", None)?;
-        Ok(Self { revlog })
+        let directories =
+            if repo.requirements().contains(TREEMANIFEST_REQUIREMENT) {
+                Some(DirectoryManifests::new(repo))
+            } else {
+                None
+            };
+        Ok(Self {
+            revlog,
+            directories,
+        })
+    }
+
+    /// Whether the manifest of each directory is stored separately
+    pub fn is_tree(&self) -> bool {
+        self.directories.is_some()
     }
 
     /// Return the `ManifestEntry` of a given node id.
@@ -22,21 +48,66 @@ impl Manifest {
         &self,
         node: NodePrefix,
     ) -> Result<ManifestEntry, RevlogError> {
-        let rev = self.revlog.get_node_rev(node)?;
-        self.get_rev(rev)
+        self.get_node_matching(node, &AlwaysMatcher)
     }
 
-    /// Return the `ManifestEntry` of a given node revision.
+    /// Return the `ManifestEntry` of a given node revision, empty for the
+    /// null revision.
     pub fn get_rev(
         &self,
         rev: Revision,
     ) -> Result<ManifestEntry, RevlogError> {
+        self.get_rev_matching(rev, &AlwaysMatcher)
+    }
+
+    /// Like `get_node`, but tree manifests only have the files of the
+    /// directories that `matcher` visits.
+    pub fn get_node_matching(
+        &self,
+        node: NodePrefix,
+        matcher: &dyn Matcher,
+    ) -> Result<ManifestEntry, RevlogError> {
+        let rev = self.revlog.get_node_rev(node)?;
+        self.get_rev_matching(rev, matcher)
+    }
+
+    /// Like `get_rev`, but tree manifests only have the files of the
+    /// directories that `matcher` visits, the manifests of the others are
+    /// not read.
+    pub fn get_rev_matching(
+        &self,
+        rev: Revision,
+        matcher: &dyn Matcher,
+    ) -> Result<ManifestEntry, RevlogError> {
+        if rev == NULL_REVISION {
+            return Ok(ManifestEntry { bytes: Vec::new() });
+        }
         let bytes = self.revlog.get_rev_data(rev)?;
+        let bytes = match &self.directories {
+            Some(directories) => directories.flatten(&bytes, matcher)?,
+            None => bytes,
+        };
+        Ok(ManifestEntry { bytes })
+    }
+
+    /// Return the entries that a manifest revision adds to its delta parent,
+    /// like `readdelta(shallow=True)` in Python: all of them for full
+    /// snapshots. For tree manifests, only those of the root directory are
+    /// returned, with subdirectories as entries with the `t` flag, which
+    /// `ManifestEntry` rejects.
+    pub fn read_delta(
+        &self,
+        rev: Revision,
+    ) -> Result<ManifestEntry, RevlogError> {
+        let bytes = self.revlog.get_delta_text(rev)?;
         Ok(ManifestEntry { bytes })
     }
 }
 
 /// `Manifest` entry which knows how to interpret the `manifest` data bytes.
+///
+/// Its lines are sorted by path, which makes finding a file or the files of
+/// a directory a binary search.
 #[derive(Debug)]
 pub struct ManifestEntry {
     bytes: Vec<u8>,
@@ -44,33 +115,577 @@ pub struct ManifestEntry {
 
 impl ManifestEntry {
     /// Return an iterator over the lines of the entry.
-    pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
+    fn lines(&self) -> impl Iterator<Item = &[u8]> {
         self.bytes
             .split(|b| b == &b'\n')
             .filter(|line| !line.is_empty())
     }
 
-    /// Return an iterator over the files of the entry.
-    pub fn files(&self) -> impl Iterator<Item = &HgPath> {
-        self.lines().filter(|line| !line.is_empty()).map(|line| {
-            let pos = line
+    /// Return an iterator over the files of the entry, with their node IDs
+    /// and flags.
+    pub fn iter(&self) -> impl Iterator<Item = ManifestFileResult<'_>> {
+        self.lines().map(ManifestFile::parse)
+    }
+
+    /// Return an iterator over the paths of the files of the entry.
+    pub fn files(&self) -> impl Iterator<Item = Result<&HgPath, HgError>> {
+        self.lines().map(|line| Ok(split_path(line)?.0))
+    }
+
+    /// Find a file by its path, if the entry has it.
+    pub fn find_file(
+        &self,
+        path: &HgPath,
+    ) -> Result<Option<ManifestFile<'_>>, HgError> {
+        let start = self.lower_bound(0, path.as_bytes())?;
+        match self.file_at(start)? {
+            Some((file, _)) if file.path == path => Ok(Some(file)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Return an iterator over the files in a directory and its
+    /// subdirectories, all of them for the root directory.
+    pub fn iter_dir(
+        &self,
+        dir: &HgPath,
+    ) -> Result<impl Iterator<Item = ManifestFileResult<'_>>, HgError> {
+        let prefix = if dir.is_empty() {
+            Vec::new()
+        } else {
+            [dir.as_bytes(), b"/"].concat()
+        };
+        let start = self.lower_bound(0, &prefix)?;
+        Ok(self.bytes[start..]
+            .split(|b| b == &b'\n')
+            .filter(|line| !line.is_empty())
+            .take_while(move |line| line.starts_with(&prefix))
+            .map(ManifestFile::parse))
+    }
+
+    /// Whether the entry has files in a directory, which the root directory
+    /// always is.
+    pub fn has_dir(&self, dir: &HgPath) -> Result<bool, HgError> {
+        Ok(dir.is_empty() || self.iter_dir(dir)?.next().is_some())
+    }
+
+    /// Compare with the entry of another revision, like
+    /// `manifestdict.diff` in Python.
+    ///
+    /// Both entries are walked in order at the same time, only considering
+    /// the files of `matcher`. The directories that its `visit_children_set`
+    /// excludes are skipped with a binary search instead of being walked.
+    /// Files that are the same on both sides are only listed if
+    /// `list_clean` is set.
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a ManifestEntry,
+        matcher: &'a dyn Matcher,
+        list_clean: bool,
+    ) -> ManifestDiff<'a> {
+        ManifestDiff {
+            old: self,
+            new: other,
+            old_position: 0,
+            new_position: 0,
+            matcher,
+            visit_children_sets: HashMap::new(),
+            list_clean,
+            done: false,
+        }
+    }
+
+    /// The file of the line starting at `position` with the position of
+    /// the next line, if it is not the end of the data
+    fn file_at(
+        &self,
+        position: usize,
+    ) -> Result<Option<(ManifestFile<'_>, usize)>, HgError> {
+        let bytes = &self.bytes[position..];
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        let line_length = bytes
+            .iter()
+            .position(|b| b == &b'\n')
+            .unwrap_or(bytes.len());
+        let file = ManifestFile::parse(&bytes[..line_length])?;
+        let next = (position + line_length + 1).min(self.bytes.len());
+        Ok(Some((file, next)))
+    }
+
+    /// The offset of the first line from `start` whose path is not smaller
+    /// than `path`, the end of the data if there is none. `start` must be
+    /// the start of a line.
+    fn lower_bound(
+        &self,
+        start: usize,
+        path: &[u8],
+    ) -> Result<usize, HgError> {
+        let bytes = &self.bytes;
+        // Both bounds are always at the start of a line, or at the end
+        let (mut low, mut high) = (start, bytes.len());
+        while low < high {
+            let mut middle = low + (high - low) / 2;
+            while middle > low && bytes[middle - 1] != b'\n' {
+                middle -= 1
+            }
+            let line_end = bytes[middle..]
                 .iter()
-                .position(|x| x == &b'\0')
-                .expect("manifest line should contain \\0");
-            HgPath::new(&line[..pos])
+                .position(|b| b == &b'\n')
+                .map_or(bytes.len(), |position| middle + position);
+            let (line_path, _) = split_path(&bytes[middle..line_end])?;
+            if line_path.as_bytes() < path {
+                low = (line_end + 1).min(bytes.len())
+            } else {
+                high = middle
+            }
+        }
+        Ok(low)
+    }
+}
+
+/// A difference between the entries of two manifest revisions
+#[derive(Debug, Copy, Clone)]
+pub enum ManifestChange<'a> {
+    Added(ManifestFile<'a>),
+    Removed(ManifestFile<'a>),
+    /// The file has another node ID, and maybe other flags
+    Modified {
+        old: ManifestFile<'a>,
+        new: ManifestFile<'a>,
+    },
+    /// The file has the same node ID, but other flags
+    FlagChanged {
+        old: ManifestFile<'a>,
+        new: ManifestFile<'a>,
+    },
+    /// The file is the same on both sides
+    Clean(ManifestFile<'a>),
+}
+
+impl<'a> ManifestChange<'a> {
+    pub fn path(&self) -> &'a HgPath {
+        match self {
+            ManifestChange::Added(file)
+            | ManifestChange::Removed(file)
+            | ManifestChange::Clean(file) => file.path,
+            ManifestChange::Modified { new, .. }
+            | ManifestChange::FlagChanged { new, .. } => new.path,
+        }
+    }
+}
+
+/// Iterator over the differences between two manifest entries, in path
+/// order. See `ManifestEntry::diff`.
+pub struct ManifestDiff<'a> {
+    old: &'a ManifestEntry,
+    new: &'a ManifestEntry,
+    old_position: usize,
+    new_position: usize,
+    matcher: &'a dyn Matcher,
+    /// The directories that were already given to the matcher
+    visit_children_sets: HashMap<&'a HgPath, VisitChildrenSet<'a>>,
+    list_clean: bool,
+    done: bool,
+}
+
+impl<'a> ManifestDiff<'a> {
+    fn next_change(&mut self) -> Result<Option<ManifestChange<'a>>, HgError> {
+        loop {
+            let old = self.old.file_at(self.old_position)?;
+            let new = self.new.file_at(self.new_position)?;
+            let path = match (&old, &new) {
+                (None, None) => return Ok(None),
+                (Some((old, _)), None) => old.path,
+                (None, Some((new, _))) => new.path,
+                (Some((old, _)), Some((new, _))) => {
+                    if old.path.as_bytes() < new.path.as_bytes() {
+                        old.path
+                    } else {
+                        new.path
+                    }
+                }
+            };
+            if let Some(dir) = self.skipped_dir(path) {
+                if dir.is_empty() {
+                    return Ok(None);
+                }
+                // Paths of the directory are below this bound, since `0`
+                // comes after `/`
+                let bound = [&dir[..dir.len() - 1], b"0"].concat();
+                self.old_position =
+                    self.old.lower_bound(self.old_position, &bound)?;
+                self.new_position =
+                    self.new.lower_bound(self.new_position, &bound)?;
+                continue;
+            }
+            let old = match old {
+                Some((old, next)) if old.path == path => {
+                    self.old_position = next;
+                    Some(old)
+                }
+                _ => None,
+            };
+            let new = match new {
+                Some((new, next)) if new.path == path => {
+                    self.new_position = next;
+                    Some(new)
+                }
+                _ => None,
+            };
+            if !self.matcher.matches(path) {
+                continue;
+            }
+            let change = match (old, new) {
+                (Some(old), None) => ManifestChange::Removed(old),
+                (None, Some(new)) => ManifestChange::Added(new),
+                (Some(old), Some(new)) => {
+                    if old.hex_node != new.hex_node {
+                        ManifestChange::Modified { old, new }
+                    } else if old.flag != new.flag {
+                        ManifestChange::FlagChanged { old, new }
+                    } else if self.list_clean {
+                        ManifestChange::Clean(new)
+                    } else {
+                        continue;
+                    }
+                }
+                (None, None) => unreachable!("the path is on one side"),
+            };
+            return Ok(Some(change));
+        }
+    }
+
+    /// The directory of `path`, with a trailing slash, that the matcher
+    /// does not visit, if any. The root directory is empty.
+    fn skipped_dir(&mut self, path: &'a HgPath) -> Option<&'a [u8]> {
+        if self.matcher.matches_everything() {
+            return None;
+        }
+        let bytes = path.as_bytes();
+        // The directory is `bytes[..dir_end]`, without its trailing slash
+        let mut dir_end = 0;
+        loop {
+            let dir = if dir_end == 0 {
+                HgPath::new(b"")
+            } else {
+                HgPath::new(&bytes[..dir_end - 1])
+            };
+            let matcher = self.matcher;
+            let visit = self
+                .visit_children_sets
+                .entry(dir)
+                .or_insert_with(|| matcher.visit_children_set(dir));
+            if visit == &VisitChildrenSet::Empty {
+                return Some(&bytes[..dir_end]);
+            }
+            let child_end =
+                match bytes[dir_end..].iter().position(|b| b == &b'/') {
+                    Some(position) => dir_end + position,
+                    // A file of the directory
+                    None => return None,
+                };
+            let child = HgPath::new(&bytes[dir_end..child_end]);
+            // Like in `dirstate::status`, `This` does not exclude
+            // subdirectories
+            let visit_child = match visit {
+                VisitChildrenSet::Recursive => return None,
+                VisitChildrenSet::This => true,
+                VisitChildrenSet::Set(children) => children.contains(child),
+                VisitChildrenSet::Empty => unreachable!("checked above"),
+            };
+            if !visit_child {
+                return Some(&bytes[..child_end + 1]);
+            }
+            dir_end = child_end + 1;
+        }
+    }
+}
+
+impl<'a> Iterator for ManifestDiff<'a> {
+    type Item = Result<ManifestChange<'a>, HgError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let change = self.next_change();
+        // Stop after the end or an error
+        self.done = match &change {
+            Ok(Some(_)) => false,
+            Ok(None) | Err(_) => true,
+        };
+        change.transpose()
+    }
+}
+
+/// The type of a file in a manifest, given by the flags after its node ID
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ManifestFlag {
+    Regular,
+    Executable,
+    Symlink,
+}
+
+impl ManifestFlag {
+    fn parse(flags: &[u8]) -> Result<Self, HgError> {
+        match flags {
+            b"" => Ok(ManifestFlag::Regular),
+            b"x" => Ok(ManifestFlag::Executable),
+            b"l" => Ok(ManifestFlag::Symlink),
+            _ => Err(HgError::corrupted(format!(
+                "unknown manifest flags {}",
+                String::from_utf8_lossy(flags)
+            ))),
+        }
+    }
+
+    /// The flags as written in manifests: empty, `x` or `l`
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            ManifestFlag::Regular => b"",
+            ManifestFlag::Executable => b"x",
+            ManifestFlag::Symlink => b"l",
+        }
+    }
+}
+
+/// A file in a `ManifestEntry`
+#[derive(Debug, Copy, Clone)]
+pub struct ManifestFile<'a> {
+    pub path: &'a HgPath,
+    /// The node ID of the file revision, in hexadecimal
+    pub hex_node: &'a [u8],
+    pub flag: ManifestFlag,
+}
+
+/// An item of the iterators over the files of a `ManifestEntry`
+pub type ManifestFileResult<'a> = Result<ManifestFile<'a>, HgError>;
+
+impl<'a> ManifestFile<'a> {
+    fn parse(line: &'a [u8]) -> Result<Self, HgError> {
+        let (path, rest) = split_path(line)?;
+        if rest.len() < NODE_HEX_LENGTH {
+            return Err(HgError::corrupted(format!(
+                "manifest line for {} without a full node ID",
+                path
+            )));
+        }
+        let (hex_node, flags) = rest.split_at(NODE_HEX_LENGTH);
+        Ok(Self {
+            path,
+            hex_node,
+            flag: ManifestFlag::parse(flags)?,
         })
     }
 
-    /// Return an iterator over the files of the entry.
-    pub fn files_with_nodes(&self) -> impl Iterator<Item = (&HgPath, &[u8])> {
-        self.lines().filter(|line| !line.is_empty()).map(|line| {
-            let pos = line
+    /// The node ID of the file revision
+    pub fn node_id(&self) -> Result<Node, HgError> {
+        Node::from_hex_for_repo(self.hex_node)
+    }
+}
+
+/// Split a manifest line into its path and the rest after the `\0`
+pub(super) fn split_path(line: &[u8]) -> Result<(&HgPath, &[u8]), HgError> {
+    let position = line
+        .iter()
+        .position(|b| b == &b'\0')
+        .ok_or_else(|| HgError::corrupted("manifest line without \\0"))?;
+    Ok((HgPath::new(&line[..position]), &line[position + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matchers::{AlwaysMatcher, DifferenceMatcher, FileMatcher};
+    use crate::utils::hg_path::HgPathBuf;
+
+    const NODE: &str = "0123456789abcdef0123456789abcdef01234567";
+    const OTHER_NODE: &str = "76543210fedcba9876543210fedcba9876543210";
+
+    fn entry(lines: &[(&str, &str)]) -> ManifestEntry {
+        entry_with_nodes(
+            &lines
                 .iter()
-                .position(|x| x == &b'\0')
-                .expect("manifest line should contain \\0");
-            let hash_start = pos + 1;
-            let hash_end = hash_start + 40;
-            (HgPath::new(&line[..pos]), &line[hash_start..hash_end])
+                .map(|&(path, flags)| (path, NODE, flags))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn entry_with_nodes(lines: &[(&str, &str, &str)]) -> ManifestEntry {
+        let mut bytes = Vec::new();
+        for (path, node, flags) in lines {
+            bytes.extend(format!("{}\0{}{}\n", path, node, flags).bytes())
+        }
+        ManifestEntry { bytes }
+    }
+
+    /// The changes of a diff with their status letter like in `hg status`,
+    /// `F` for flag changes
+    fn changes(diff: ManifestDiff) -> Vec<(char, String)> {
+        diff.map(|change| {
+            let change = change.unwrap();
+            let status = match change {
+                ManifestChange::Added(_) => 'A',
+                ManifestChange::Removed(_) => 'R',
+                ManifestChange::Modified { .. } => 'M',
+                ManifestChange::FlagChanged { .. } => 'F',
+                ManifestChange::Clean(_) => 'C',
+            };
+            (status, change.path().to_string())
         })
+        .collect()
+    }
+
+    fn paths<'a>(
+        files: impl Iterator<Item = Result<ManifestFile<'a>, HgError>>,
+    ) -> Vec<&'a [u8]> {
+        files.map(|file| file.unwrap().path.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_find_file() {
+        let manifest =
+            entry(&[("a", ""), ("a.txt", "x"), ("a/b", "l"), ("c", "")]);
+        for (path, flag) in &[
+            ("a", ManifestFlag::Regular),
+            ("a.txt", ManifestFlag::Executable),
+            ("a/b", ManifestFlag::Symlink),
+            ("c", ManifestFlag::Regular),
+        ] {
+            let file = manifest.find_file(HgPath::new(path)).unwrap().unwrap();
+            assert_eq!(file.path, HgPath::new(path));
+            assert_eq!(file.flag, *flag);
+            assert_eq!(file.hex_node, NODE.as_bytes());
+        }
+        for path in &["", "0", "a/", "b", "d"] {
+            assert!(manifest.find_file(HgPath::new(path)).unwrap().is_none());
+        }
+        assert!(entry(&[]).find_file(HgPath::new("a")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_iter_dir() {
+        let manifest = entry(&[
+            ("a", ""),
+            ("a-b/c", ""),
+            ("a/b", ""),
+            ("a/c/d", ""),
+            ("ab/c", ""),
+        ]);
+        let dir =
+            |dir: &str| paths(manifest.iter_dir(HgPath::new(dir)).unwrap());
+        assert_eq!(dir("a"), vec![&b"a/b"[..], b"a/c/d"]);
+        assert_eq!(dir("a/c"), vec![&b"a/c/d"[..]]);
+        assert_eq!(dir("").len(), 5);
+        assert!(dir("a/b").is_empty());
+        assert!(dir("b").is_empty());
+        assert!(manifest.has_dir(HgPath::new("ab")).unwrap());
+        assert!(!manifest.has_dir(HgPath::new("a/b")).unwrap());
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let manifest = ManifestEntry {
+            bytes: b"a\0abc\n".to_vec(),
+        };
+        assert!(manifest.iter().next().unwrap().is_err());
+        assert!(manifest.find_file(HgPath::new("a")).is_err());
+        let manifest = ManifestEntry {
+            bytes: b"no separator\n".to_vec(),
+        };
+        assert!(manifest.find_file(HgPath::new("a")).is_err());
+        let manifest = entry(&[("a", "z")]);
+        assert!(manifest.find_file(HgPath::new("a")).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = entry_with_nodes(&[
+            ("a", NODE, ""),
+            ("b/c", NODE, ""),
+            ("b/d", NODE, ""),
+            ("e", NODE, ""),
+            ("f", NODE, "x"),
+        ]);
+        let new = entry_with_nodes(&[
+            ("a", NODE, ""),
+            ("b/c", OTHER_NODE, "l"),
+            ("b/e", NODE, ""),
+            ("f", NODE, ""),
+            ("g", NODE, ""),
+        ]);
+        let expected = [
+            ('M', "b/c"),
+            ('R', "b/d"),
+            ('A', "b/e"),
+            ('R', "e"),
+            ('F', "f"),
+            ('A', "g"),
+        ];
+        let to_owned = |changes: &[(char, &str)]| -> Vec<(char, String)> {
+            changes
+                .iter()
+                .map(|&(status, path)| (status, path.to_owned()))
+                .collect()
+        };
+        assert_eq!(
+            changes(old.diff(&new, &AlwaysMatcher, false)),
+            to_owned(&expected)
+        );
+        let with_clean = changes(old.diff(&new, &AlwaysMatcher, true));
+        assert_eq!(with_clean[0], ('C', "a".to_owned()));
+        assert_eq!(with_clean[1..], to_owned(&expected)[..]);
+        assert!(changes(old.diff(&old, &AlwaysMatcher, false)).is_empty());
+        assert_eq!(
+            changes(entry(&[]).diff(&old, &AlwaysMatcher, false)).len(),
+            5
+        );
+    }
+
+    #[test]
+    fn test_diff_matcher() {
+        let old = entry(&[
+            ("a/b/c", ""),
+            ("a/c", ""),
+            ("a/d/e", ""),
+            ("ab", ""),
+            ("b/c", ""),
+        ]);
+        let new = entry(&[]);
+        let files = vec![
+            HgPathBuf::from_bytes(b"a/d/e"),
+            HgPathBuf::from_bytes(b"ab"),
+        ];
+        let matcher = FileMatcher::new(&files).unwrap();
+        let diff = old.diff(&new, &matcher, false);
+        assert_eq!(
+            changes(diff),
+            vec![('R', "a/d/e".to_owned()), ('R', "ab".to_owned())]
+        );
+        let files = vec![HgPathBuf::from_bytes(b"a/c")];
+        let matcher = FileMatcher::new(&files).unwrap();
+        let diff = new.diff(&old, &matcher, false);
+        assert_eq!(changes(diff), vec![('A', "a/c".to_owned())]);
+
+        // Visiting only the root directory does not exclude subdirectories
+        let matcher = DifferenceMatcher::new(&AlwaysMatcher, &matcher);
+        assert_eq!(
+            matcher.visit_children_set(HgPath::new(b"")),
+            VisitChildrenSet::This
+        );
+        assert_eq!(changes(old.diff(&new, &matcher, false)).len(), 4);
+    }
+
+    #[test]
+    fn test_diff_corrupted() {
+        let old = entry(&[("a", "")]);
+        let new = ManifestEntry {
+            bytes: b"a\0abc\n".to_vec(),
+        };
+        let mut diff = old.diff(&new, &AlwaysMatcher, false);
+        assert!(diff.next().unwrap().is_err());
+        assert!(diff.next().is_none());
     }
 }
@@ -53,7 +53,9 @@ type NodeData = [u8; NODE_BYTES_LENGTH];
 /// the size or return an error at runtime.
 ///
 /// [`nybbles_len`]: #method.nybbles_len
-#[derive(Copy, Clone, Debug, PartialEq, BytesCast, derive_more::From)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Hash, BytesCast, derive_more::From,
+)]
 #[repr(transparent)]
 pub struct Node {
     data: NodeData,
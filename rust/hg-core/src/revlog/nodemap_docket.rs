@@ -1,27 +1,43 @@
-use crate::errors::{HgError, HgResultExt};
+use crate::errors::{HgError, HgResultExt, IoResultExt};
 use crate::requirements;
 use bytes_cast::{unaligned, BytesCast};
 use memmap::Mmap;
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
 
+use super::node::Node;
 use super::revlog::RevlogError;
-use crate::repo::Repo;
-use crate::utils::strip_suffix;
+use super::Revision;
+use crate::repo::{Repo, Vfs};
+use crate::utils::{strip_prefix, strip_suffix};
 
 const ONDISK_VERSION: u8 = 1;
+/// Number of random bytes in the identifier of a data file, before
+/// hexadecimal encoding
+const ID_SIZE: usize = 8;
 
+/// The metadata of a persistent nodemap, stored in its `.n` docket file
 pub(super) struct NodeMapDocket {
+    /// Identifies the data file. It is kept when data is appended, a new
+    /// data file gets a new one.
+    pub uid: String,
+    /// The last revision in the nodemap data
+    pub tip_rev: Revision,
+    /// The node of `tip_rev`, for checking that the data matches the index
+    pub tip_node: Vec<u8>,
+    /// The size of the data for `tip_rev`, any data after it is ignored
     pub data_length: usize,
-    // TODO: keep here more of the data from `parse()` when we need it
+    /// The size of the data that is no longer used by the nodemap
+    pub data_unused: usize,
 }
 
 #[derive(BytesCast)]
 #[repr(C)]
 struct DocketHeader {
     uid_size: u8,
-    _tip_rev: unaligned::U64Be,
+    tip_rev: unaligned::U64Be,
     data_length: unaligned::U64Be,
-    _data_unused: unaligned::U64Be,
+    data_unused: unaligned::U64Be,
     tip_node_size: unaligned::U64Be,
 }
 
@@ -39,11 +55,7 @@ impl NodeMapDocket {
         repo: &Repo,
         index_path: &Path,
     ) -> Result<Option<(Self, Mmap)>, RevlogError> {
-        if !repo
-            .requirements()
-            .contains(requirements::NODEMAP_REQUIREMENT)
-        {
-            // If .hg/requires does not opt it, don’t try to open a nodemap
+        if !uses_persistent_nodemap(repo, index_path) {
             return Ok(None);
         }
 
@@ -78,10 +90,16 @@ impl NodeMapDocket {
         let tip_node_size = header.tip_node_size.get() as usize;
         let data_length = header.data_length.get() as usize;
         let (uid, rest) = parse(u8::slice_from_bytes(rest, uid_size))?;
-        let (_tip_node, _rest) =
+        let (tip_node, _rest) =
             parse(u8::slice_from_bytes(rest, tip_node_size))?;
         let uid = parse(std::str::from_utf8(uid))?;
-        let docket = NodeMapDocket { data_length };
+        let docket = NodeMapDocket {
+            uid: uid.to_owned(),
+            tip_rev: parse(Revision::try_from(header.tip_rev.get()))?,
+            tip_node: tip_node.to_owned(),
+            data_length,
+            data_unused: header.data_unused.get() as usize,
+        };
 
         let data_path = rawdata_path(&docket_path, uid);
         // TODO: use `vfs.read()` here when the `persistent-nodemap.mmap`
@@ -102,18 +120,107 @@ impl NodeMapDocket {
             Ok(None)
         }
     }
+
+    /// The docket of a new data file, with a new identifier
+    pub fn new(
+        tip_rev: Revision,
+        tip_node: &Node,
+        data_length: usize,
+    ) -> Self {
+        let uid: [u8; ID_SIZE] = rand::random();
+        NodeMapDocket {
+            uid: uid.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            tip_rev,
+            tip_node: tip_node.as_bytes().to_owned(),
+            data_length,
+            data_unused: 0,
+        }
+    }
+
+    /// The content of the docket file
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![ONDISK_VERSION, self.uid.len() as u8];
+        bytes.extend(&(self.tip_rev as u64).to_be_bytes());
+        bytes.extend(&(self.data_length as u64).to_be_bytes());
+        bytes.extend(&(self.data_unused as u64).to_be_bytes());
+        bytes.extend(&(self.tip_node.len() as u64).to_be_bytes());
+        bytes.extend(self.uid.as_bytes());
+        bytes.extend(&self.tip_node);
+        bytes
+    }
+
+    /// The path of the data file, relative to the store like that of the
+    /// docket
+    pub fn data_path(&self, docket_path: &Path) -> PathBuf {
+        rawdata_path(docket_path, &self.uid)
+    }
 }
 
-fn rawdata_path(docket_path: &Path, uid: &str) -> PathBuf {
+/// Whether the revlog with the given index has a persistent nodemap
+///
+/// If .hg/requires opts in, only the changelog and the manifest of the root
+/// directory get one, as in Python.
+pub(super) fn uses_persistent_nodemap(repo: &Repo, index_path: &Path) -> bool {
+    repo.requirements()
+        .contains(requirements::NODEMAP_REQUIREMENT)
+        && (index_path == Path::new("00changelog.i")
+            || index_path == Path::new("00manifest.i"))
+}
+
+/// The data files of a docket, relative to the store, other than the one
+/// with the given identifier. They are left behind when a new data file
+/// replaces them.
+pub(super) fn other_data_paths(
+    vfs: Vfs,
+    docket_path: &Path,
+    uid: &str,
+) -> Result<Vec<PathBuf>, HgError> {
+    let prefix = format!("{}-", rawdata_prefix(docket_path));
+    let current = format!("{}{}.nd", prefix, uid);
+    let directory = docket_path.parent().expect("expected a non-root path");
+    let directory_path = vfs.join(directory);
+    let mut others = Vec::new();
+    for entry in std::fs::read_dir(&directory_path)
+        .when_reading_file(&directory_path)?
+    {
+        let name = entry.when_reading_file(&directory_path)?.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        let is_data_file = match strip_prefix(name, &prefix)
+            .and_then(|rest| strip_suffix(rest, ".nd"))
+        {
+            Some(id) => {
+                !id.is_empty()
+                    && id.bytes().all(|byte| match byte {
+                        b'0'..=b'9' | b'a'..=b'f' => true,
+                        _ => false,
+                    })
+            }
+            None => false,
+        };
+        if is_data_file && name != current {
+            others.push(directory.join(name))
+        }
+    }
+    Ok(others)
+}
+
+/// The start of the names of the data files of a docket
+fn rawdata_prefix(docket_path: &Path) -> &str {
     let docket_name = docket_path
         .file_name()
         .expect("expected a base name")
         .to_str()
         .expect("expected an ASCII file name in the store");
-    let prefix = strip_suffix(docket_name, ".n.a")
+    strip_suffix(docket_name, ".n.a")
         .or_else(|| strip_suffix(docket_name, ".n"))
-        .expect("expected docket path in .n or .n.a");
-    let name = format!("{}-{}.nd", prefix, uid);
+        .expect("expected docket path in .n or .n.a")
+}
+
+fn rawdata_path(docket_path: &Path, uid: &str) -> PathBuf {
+    let name = format!("{}-{}.nd", rawdata_prefix(docket_path), uid);
     docket_path
         .parent()
         .expect("expected a non-root path")
@@ -1,5 +1,9 @@
+use crate::errors::HgError;
 use byteorder::{BigEndian, ByteOrder};
 
+/// The size of the start, end and length of the data of a chunk
+const CHUNK_HEADER_SIZE: usize = 12;
+
 /// A chunk of data to insert, delete or replace in a patch
 ///
 /// A chunk is:
@@ -60,23 +64,42 @@ pub struct PatchList<'a> {
 }
 
 impl<'a> PatchList<'a> {
-    /// Create a `PatchList` from bytes.
-    pub fn new(data: &'a [u8]) -> Self {
+    /// Create a `PatchList` from bytes, which are corrupted when chunks are
+    /// truncated or not in order, like `mpatch_decode` in C.
+    pub fn new(data: &'a [u8]) -> Result<Self, HgError> {
         let mut chunks = vec![];
         let mut data = data;
+        let mut last_end = 0;
         while !data.is_empty() {
-            let start = BigEndian::read_u32(&data[0..]);
-            let end = BigEndian::read_u32(&data[4..]);
-            let len = BigEndian::read_u32(&data[8..]);
-            assert!(start <= end);
+            let header =
+                data.get(..CHUNK_HEADER_SIZE).ok_or_else(cannot_decode)?;
+            let start = BigEndian::read_u32(&header[0..]);
+            let end = BigEndian::read_u32(&header[4..]);
+            let len = BigEndian::read_u32(&header[8..]) as usize;
+            if start > end || start < last_end {
+                return Err(cannot_decode());
+            }
+            let data_end = CHUNK_HEADER_SIZE + len;
             chunks.push(Chunk {
                 start,
                 end,
-                data: &data[12..12 + (len as usize)],
+                data: data
+                    .get(CHUNK_HEADER_SIZE..data_end)
+                    .ok_or_else(cannot_decode)?,
             });
-            data = &data[12 + (len as usize)..];
+            data = &data[data_end..];
+            last_end = end;
         }
-        PatchList { chunks }
+        Ok(PatchList { chunks })
+    }
+
+    /// The data inserted by the chunks, like `mdiff.patchtext` in Python
+    pub fn inserted_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.data)
+            .copied()
+            .collect()
     }
 
     /// Return the final length of data after patching
@@ -87,18 +110,23 @@ impl<'a> PatchList<'a> {
             .fold(initial_size, |acc, chunk| acc + chunk.len_diff())
     }
 
-    /// Apply the patch to some data.
-    pub fn apply(&self, initial: &[u8]) -> Vec<u8> {
+    /// Apply the patch to some data, failing when chunks replace data beyond
+    /// its end.
+    pub fn apply(&self, initial: &[u8]) -> Result<Vec<u8>, HgError> {
+        let invalid = || HgError::corrupted("invalid patch");
         let mut last: usize = 0;
-        let mut vec =
-            Vec::with_capacity(self.size(initial.len() as i32) as usize);
+        let mut vec = Vec::with_capacity(
+            self.size(initial.len() as i32).max(0) as usize,
+        );
         for Chunk { start, end, data } in self.chunks.iter() {
-            vec.extend(&initial[last..(*start as usize)]);
+            vec.extend(
+                initial.get(last..(*start as usize)).ok_or_else(invalid)?,
+            );
             vec.extend(data.iter());
             last = *end as usize;
         }
-        vec.extend(&initial[last..]);
-        vec
+        vec.extend(initial.get(last..).ok_or_else(invalid)?);
+        Ok(vec)
     }
 
     /// Combine two patch lists into a single patch list.
@@ -211,6 +239,11 @@ impl<'a> PatchList<'a> {
     }
 }
 
+/// The error about truncated or unordered chunks, as in `mpatch.c`
+fn cannot_decode() -> HgError {
+    HgError::corrupted("patch cannot be decoded")
+}
+
 /// Combine a list of patch list into a single patch optimized patch list.
 pub fn fold_patch_lists<'a>(lists: &[PatchList<'a>]) -> PatchList<'a> {
     if lists.len() <= 1 {
@@ -264,15 +297,15 @@ mod tests {
         let data = vec![0u8, 0u8, 0u8];
         let mut patch1_data = PatchDataBuilder::new();
         patch1_data.replace(0, 1, &[1, 2]);
-        let mut patch1 = PatchList::new(patch1_data.get());
+        let mut patch1 = PatchList::new(patch1_data.get()).unwrap();
 
         let mut patch2_data = PatchDataBuilder::new();
         patch2_data.replace(2, 4, &[3, 4]);
-        let mut patch2 = PatchList::new(patch2_data.get());
+        let mut patch2 = PatchList::new(patch2_data.get()).unwrap();
 
         let patch = patch1.combine(&mut patch2);
 
-        let result = patch.apply(&data);
+        let result = patch.apply(&data).unwrap();
 
         assert_eq!(result, vec![1u8, 2, 3, 4]);
     }
@@ -282,15 +315,15 @@ mod tests {
         let data = vec![0u8, 0u8, 0u8];
         let mut patch1_data = PatchDataBuilder::new();
         patch1_data.replace(2, 3, &[3]);
-        let mut patch1 = PatchList::new(patch1_data.get());
+        let mut patch1 = PatchList::new(patch1_data.get()).unwrap();
 
         let mut patch2_data = PatchDataBuilder::new();
         patch2_data.replace(1, 2, &[1, 2]);
-        let mut patch2 = PatchList::new(patch2_data.get());
+        let mut patch2 = PatchList::new(patch2_data.get()).unwrap();
 
         let patch = patch1.combine(&mut patch2);
 
-        let result = patch.apply(&data);
+        let result = patch.apply(&data).unwrap();
 
         assert_eq!(result, vec![0u8, 1, 2, 3]);
     }
@@ -300,15 +333,15 @@ mod tests {
         let data = vec![0u8, 0, 0];
         let mut patch1_data = PatchDataBuilder::new();
         patch1_data.replace(1, 2, &[3, 4]);
-        let mut patch1 = PatchList::new(patch1_data.get());
+        let mut patch1 = PatchList::new(patch1_data.get()).unwrap();
 
         let mut patch2_data = PatchDataBuilder::new();
         patch2_data.replace(1, 4, &[1, 2, 3]);
-        let mut patch2 = PatchList::new(patch2_data.get());
+        let mut patch2 = PatchList::new(patch2_data.get()).unwrap();
 
         let patch = patch1.combine(&mut patch2);
 
-        let result = patch.apply(&data);
+        let result = patch.apply(&data).unwrap();
 
         assert_eq!(result, vec![0u8, 1, 2, 3]);
     }
@@ -318,15 +351,15 @@ mod tests {
         let data = vec![0u8, 0, 0];
         let mut patch1_data = PatchDataBuilder::new();
         patch1_data.replace(0, 1, &[1, 3]);
-        let mut patch1 = PatchList::new(patch1_data.get());
+        let mut patch1 = PatchList::new(patch1_data.get()).unwrap();
 
         let mut patch2_data = PatchDataBuilder::new();
         patch2_data.replace(1, 4, &[2, 3, 4]);
-        let mut patch2 = PatchList::new(patch2_data.get());
+        let mut patch2 = PatchList::new(patch2_data.get()).unwrap();
 
         let patch = patch1.combine(&mut patch2);
 
-        let result = patch.apply(&data);
+        let result = patch.apply(&data).unwrap();
 
         assert_eq!(result, vec![1u8, 2, 3, 4]);
     }
@@ -336,15 +369,15 @@ mod tests {
         let data = vec![0u8, 0, 0];
         let mut patch1_data = PatchDataBuilder::new();
         patch1_data.replace(1, 3, &[1, 3, 4]);
-        let mut patch1 = PatchList::new(patch1_data.get());
+        let mut patch1 = PatchList::new(patch1_data.get()).unwrap();
 
         let mut patch2_data = PatchDataBuilder::new();
         patch2_data.replace(0, 2, &[1, 2]);
-        let mut patch2 = PatchList::new(patch2_data.get());
+        let mut patch2 = PatchList::new(patch2_data.get()).unwrap();
 
         let patch = patch1.combine(&mut patch2);
 
-        let result = patch.apply(&data);
+        let result = patch.apply(&data).unwrap();
 
         assert_eq!(result, vec![1u8, 2, 3, 4]);
     }
@@ -354,16 +387,35 @@ mod tests {
         let data = vec![0u8, 0, 0];
         let mut patch1_data = PatchDataBuilder::new();
         patch1_data.replace(0, 3, &[1, 3, 3, 4]);
-        let mut patch1 = PatchList::new(patch1_data.get());
+        let mut patch1 = PatchList::new(patch1_data.get()).unwrap();
 
         let mut patch2_data = PatchDataBuilder::new();
         patch2_data.replace(1, 3, &[2, 3]);
-        let mut patch2 = PatchList::new(patch2_data.get());
+        let mut patch2 = PatchList::new(patch2_data.get()).unwrap();
 
         let patch = patch1.combine(&mut patch2);
 
-        let result = patch.apply(&data);
+        let result = patch.apply(&data).unwrap();
 
         assert_eq!(result, vec![1u8, 2, 3, 4]);
     }
+
+    #[test]
+    fn test_corrupted() {
+        let mut data = PatchDataBuilder::new();
+        data.replace(0, 1, &[1, 2]);
+        let data = data.get();
+        // Truncated data or header
+        assert!(PatchList::new(&data[..data.len() - 1]).is_err());
+        assert!(PatchList::new(&data[..4]).is_err());
+
+        let mut data = PatchDataBuilder::new();
+        data.replace(2, 3, &[1]).replace(0, 1, &[2]);
+        assert!(PatchList::new(data.get()).is_err());
+
+        let mut data = PatchDataBuilder::new();
+        data.replace(2, 4, &[1]);
+        let patch = PatchList::new(data.get()).unwrap();
+        assert!(patch.apply(&[0, 0, 0]).is_err());
+    }
 }
@@ -1,24 +1,69 @@
 use std::borrow::Cow;
-use std::io::Read;
-use std::ops::Deref;
-use std::path::Path;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use byteorder::{BigEndian, ByteOrder};
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
 use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
 use micro_timer::timed;
 use zstd;
 
-use super::index::Index;
-use super::node::{NodePrefix, NODE_BYTES_LENGTH, NULL_NODE};
+use super::cache::{CacheStats, RevlogCache};
+use super::deltas;
+use super::flags::{
+    is_censored_text, FlagProcessor, FlagProcessors,
+    REVIDX_RAWTEXT_CHANGING_FLAGS,
+};
+use super::index::{Index, IndexEntry, INDEX_ENTRY_SIZE, REVLOGV2};
+use super::node::{Node, NodePrefix, NODE_BYTES_LENGTH, NULL_NODE};
 use super::nodemap;
-use super::nodemap::{NodeMap, NodeMapError};
-use super::nodemap_docket::NodeMapDocket;
+use super::nodemap::{Block, NodeMap, NodeMapError};
+use super::nodemap_docket::{
+    other_data_paths, uses_persistent_nodemap, NodeMapDocket,
+};
 use super::patch;
-use crate::errors::HgError;
-use crate::repo::Repo;
-use crate::revlog::Revision;
+use super::sidedata::{self, Sidedata};
+use crate::errors::{HgError, HgResultExt, IoResultExt};
+use crate::repo::{Repo, Vfs};
+use crate::requirements;
+use crate::revlog::{Revision, NULL_REVISION};
+use crate::transaction::Transaction;
+use crate::{Graph, GraphError};
+
+/// Version of the revlog format read and written here
+const REVLOGV1: u32 = 1;
+/// Flag of the index header for revision data interleaved with the index
+const FLAG_INLINE_DATA: u32 = 1 << 16;
+/// Flag of the index header for deltas against any revision, rather than
+/// only the previous one
+const FLAG_GENERALDELTA: u32 = 1 << 17;
+
+/// Inline revlogs are split in an index and a data file past this size of
+/// data, like `_maxinline` in Python
+const MAX_INLINE_SIZE: usize = 131072;
+
+/// Maximum length of delta chains in sparse revlogs, unless configured
+const SPARSE_REVLOG_MAX_CHAIN_LENGTH: usize = 1000;
+
+/// Smallest gap of unrelated data skipped by sparse reads, unless
+/// configured: 65K
+const DEFAULT_SPARSE_READ_MIN_GAP_SIZE: usize = 66560;
+
+/// Lookups of full nodes that scan the index before a nodemap is built in
+/// memory, like in the C index
+const MAX_LINEAR_LOOKUPS: usize = 4;
+
+/// The persistent nodemap is written to a new data file, rather than
+/// appended to, when more than one part in this many would be unused
+const MAX_NODEMAP_UNUSED_RATIO: usize = 10;
 
 #[derive(derive_more::From)]
 pub enum RevlogError {
@@ -27,6 +72,11 @@ pub enum RevlogError {
     WDirUnsupported,
     /// Found more than one entry whose ID match the requested prefix
     AmbiguousPrefix,
+    /// The revision was censored, its text replaced with a tombstone
+    Censored {
+        node: Node,
+        tombstone: Vec<u8>,
+    },
     #[from]
     Other(HgError),
 }
@@ -40,13 +90,155 @@ impl From<NodeMapError> for RevlogError {
     }
 }
 
+impl From<GraphError> for RevlogError {
+    fn from(error: GraphError) -> Self {
+        match error {
+            GraphError::ParentOutOfRange(_) => RevlogError::corrupted(),
+            GraphError::WorkingDirectoryUnsupported => {
+                RevlogError::WDirUnsupported
+            }
+        }
+    }
+}
+
 impl RevlogError {
-    fn corrupted() -> Self {
+    pub(super) fn corrupted() -> Self {
         RevlogError::Other(HgError::corrupted("corrupted revlog"))
     }
 }
 
-/// Read only implementation of revlog.
+/// How the data of new revisions is compressed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// With the given level, or the default one of zlib
+    Zlib(Option<u32>),
+    Zstd(u32),
+    None,
+}
+
+/// Options for writing to revlogs, from the requirements and configuration
+/// of a repository like the revlog options of the store opener in Python
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// For new revlogs: whether deltas can be against any revision, rather
+    /// than only the previous one. Existing revlogs keep their own format.
+    pub generaldelta: bool,
+    /// Whether delta chains may include intermediate snapshots
+    pub sparse_revlog: bool,
+    /// Whether to try deltas against both parents at once, keeping the
+    /// smallest, rather than one after the other
+    pub delta_both_parents: bool,
+    pub max_chain_length: Option<usize>,
+    /// Maximum span of data to read to rebuild a revision, for revlogs that
+    /// are not sparse. `Some(0)` means no limit.
+    pub max_delta_chain_span: Option<usize>,
+    pub compression: Compression,
+}
+
+impl WriteOptions {
+    /// Like `resolverevlogstorevfsoptions` in Python
+    pub fn from_repo(repo: &Repo) -> Result<Self, HgError> {
+        let requirements = repo.requirements();
+        let config = repo.config();
+        let sparse_revlog =
+            requirements.contains(requirements::SPARSEREVLOG_REQUIREMENT);
+        let generaldelta = sparse_revlog
+            || requirements.contains(requirements::GENERALDELTA_REQUIREMENT);
+        let default_max_chain_length = if sparse_revlog {
+            Some(SPARSE_REVLOG_MAX_CHAIN_LENGTH)
+        } else {
+            None
+        };
+        let max_chain_length =
+            match config.get_u32(b"format", b"maxchainlen")? {
+                // Zero means no limit
+                Some(0) => None,
+                Some(length) => Some(length as usize),
+                None => default_max_chain_length,
+            };
+        let compression = if requirements
+            .contains(requirements::REVLOG_COMPRESSION_ZSTD)
+        {
+            let level = config
+                .get_u32(b"storage", b"revlog.zstd.level")?
+                .unwrap_or(3);
+            if level > 22 {
+                return Err(HgError::abort(format!(
+                    "abort: invalid value for `storage.revlog.zstd.level` \
+                     config: {}",
+                    level
+                )));
+            }
+            Compression::Zstd(level)
+        } else if requirements.contains(requirements::COMPRESSION_NONE) {
+            Compression::None
+        } else {
+            let level = config.get_u32(b"storage", b"revlog.zlib.level")?;
+            if let Some(level) = level.filter(|&level| level > 9) {
+                return Err(HgError::abort(format!(
+                    "abort: invalid value for `storage.revlog.zlib.level` \
+                     config: {}",
+                    level
+                )));
+            }
+            Compression::Zlib(level)
+        };
+        Ok(Self {
+            generaldelta,
+            sparse_revlog,
+            delta_both_parents: config
+                .get_option(
+                    b"storage",
+                    b"revlog.optimize-delta-parent-choice",
+                )?
+                .unwrap_or(true),
+            max_chain_length,
+            max_delta_chain_span: config
+                .get_byte_size(b"experimental", b"maxdeltachainspan")?
+                .map(|span| span as usize),
+            compression,
+        })
+    }
+}
+
+/// Options for reading delta chains in several chunks of data, skipping the
+/// large gaps between them, like the sparse-read options of the store opener
+/// in Python
+#[derive(Clone, Debug)]
+pub struct SparseReadOptions {
+    /// Chunks are split until this ratio of the bytes read is data of the
+    /// delta chain
+    pub density_threshold: f64,
+    /// Gaps of unrelated data smaller than this are read anyway
+    pub min_gap_size: usize,
+}
+
+impl SparseReadOptions {
+    /// `None` when sparse reads are disabled, which they are unless the
+    /// revlogs are sparse or `experimental.sparse-read` is set
+    pub fn from_repo(repo: &Repo) -> Result<Option<Self>, HgError> {
+        let config = repo.config();
+        let enabled = repo
+            .requirements()
+            .contains(requirements::SPARSEREVLOG_REQUIREMENT)
+            || config.get_bool(b"experimental", b"sparse-read")?;
+        if !enabled {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            density_threshold: config
+                .get_f64(b"experimental", b"sparse-read.density-threshold")?
+                .unwrap_or(0.5),
+            min_gap_size: config
+                .get_byte_size(b"experimental", b"sparse-read.min-gap-size")?
+                .map_or(DEFAULT_SPARSE_READ_MIN_GAP_SIZE, |size| {
+                    size as usize
+                }),
+        }))
+    }
+}
+
+/// Revlog, read and appended to.
 pub struct Revlog {
     /// When index and data are not interleaved: bytes of the revlog index.
     /// When index and data are interleaved: bytes of the revlog index and
@@ -54,8 +246,27 @@ pub struct Revlog {
     pub(crate) index: Index,
     /// When index and data are not interleaved: bytes of the revlog data
     data_bytes: Option<Box<dyn Deref<Target = [u8]> + Send>>,
+    /// Data appended to the data file since `data_bytes` was read
+    added_data: Vec<u8>,
     /// When present on disk: the persistent nodemap for this revlog
     nodemap: Option<nodemap::NodeTree>,
+    /// The docket of `nodemap`, as last read or written
+    nodemap_docket: Option<NodeMapDocket>,
+    /// Whether a persistent nodemap is written for this revlog, once it is
+    /// not inline
+    persistent_nodemap: bool,
+    /// Without a persistent nodemap: the nodemap built on demand
+    in_memory_nodemap: RefCell<Option<nodemap::NodeTree>>,
+    /// Number of lookups made so far by scanning the index
+    linear_lookups: Cell<usize>,
+    /// Recently read full texts and decompressed chunks
+    cache: RefCell<RevlogCache>,
+    /// Processors of the flags of revisions
+    flag_processors: FlagProcessors,
+    /// Path of the index file, relative to the store
+    index_path: PathBuf,
+    /// Path of the data file, relative to the store, used when not inline
+    data_path: PathBuf,
 }
 
 impl Revlog {
@@ -70,17 +281,33 @@ impl Revlog {
         data_path: Option<&Path>,
     ) -> Result<Self, RevlogError> {
         let index_path = index_path.as_ref();
-        let index_mmap = repo.store_vfs().mmap_open(&index_path)?;
-
-        let version = get_version(&index_mmap);
-        if version != 1 {
-            // A proper new version should have had a repo/store requirement.
-            return Err(RevlogError::corrupted());
-        }
+        let index_mmap = repo
+            .store_vfs()
+            .mmap_open(&index_path)
+            .io_not_found_as_none()?;
 
-        let index = Index::new(Box::new(index_mmap))?;
+        let index = match index_mmap {
+            Some(index_mmap) => {
+                let version = get_version(&index_mmap);
+                if version != 1 && version != REVLOGV2 {
+                    // A proper new version should have had a repo/store
+                    // requirement.
+                    return Err(RevlogError::corrupted());
+                }
+                // Like in Python, name the revlog in errors
+                Index::new(Box::new(index_mmap)).map_err(|_| {
+                    HgError::corrupted(format!(
+                        "index {} is corrupted",
+                        index_path.display()
+                    ))
+                })?
+            }
+            // Like in Python, a missing revlog is an empty one
+            None => Index::new(Box::new(Vec::new()))?,
+        };
 
         let default_data_path = index_path.with_extension("d");
+        let data_path = data_path.unwrap_or(&default_data_path);
 
         // type annotation required
         // won't recognize Mmap as Deref<Target = [u8]>
@@ -88,24 +315,46 @@ impl Revlog {
             if index.is_inline() {
                 None
             } else {
-                let data_path = data_path.unwrap_or(&default_data_path);
                 let data_mmap = repo.store_vfs().mmap_open(data_path)?;
                 Some(Box::new(data_mmap))
             };
 
-        let nodemap = NodeMapDocket::read_from_file(repo, index_path)?.map(
-            |(docket, data)| {
-                nodemap::NodeTree::load_bytes(
-                    Box::new(data),
-                    docket.data_length,
-                )
-            },
-        );
+        let (nodemap_docket, nodemap) =
+            match NodeMapDocket::read_from_file(repo, index_path)? {
+                Some((docket, data)) if docket_matches(&docket, &index) => {
+                    let mut nodemap = nodemap::NodeTree::load_bytes(
+                        Box::new(data),
+                        docket.data_length,
+                    );
+                    // Revisions added by writers that did not update the
+                    // nodemap
+                    for rev in docket.tip_rev + 1..index.len() as Revision {
+                        let node = index
+                            .get_entry(rev)
+                            .ok_or_else(RevlogError::corrupted)?
+                            .hash();
+                        nodemap.insert(&index, node, rev)?;
+                    }
+                    (Some(docket), Some(nodemap))
+                }
+                // Like in Python, a nodemap that does not match the index
+                // is ignored, it is written again with the next revisions
+                _ => (None, None),
+            };
 
         Ok(Revlog {
             index,
             data_bytes,
+            added_data: Vec::new(),
             nodemap,
+            nodemap_docket,
+            persistent_nodemap: uses_persistent_nodemap(repo, index_path),
+            in_memory_nodemap: RefCell::new(None),
+            linear_lookups: Cell::new(0),
+            cache: RefCell::new(RevlogCache::new()),
+            flag_processors: FlagProcessors::for_repo(repo)?,
+            index_path: index_path.to_owned(),
+            data_path: data_path.to_owned(),
         })
     }
 
@@ -125,35 +374,81 @@ impl Revlog {
         &self,
         node: NodePrefix,
     ) -> Result<Revision, RevlogError> {
-        if let Some(nodemap) = &self.nodemap {
-            return nodemap
-                .find_bin(&self.index, node)?
-                .ok_or(RevlogError::InvalidRevision);
-        }
-
-        // Fallback to linear scan when a persistent nodemap is not present.
-        // This happens when the persistent-nodemap experimental feature is not
-        // enabled, or for small revlogs.
-        //
-        // TODO: consider building a non-persistent nodemap in memory to
-        // optimize these cases.
-        let mut found_by_prefix = None;
+        // Without a persistent nodemap, because the experimental feature is
+        // not enabled or for small revlogs, the first few lookups of full
+        // nodes scan the index, like `index_find_node` in the C index. It is
+        // cheaper than building a nodemap when only a handful are needed.
+        if self.nodemap.is_none()
+            && self.in_memory_nodemap.borrow().is_none()
+            && node.nybbles_len() == NODE_BYTES_LENGTH * 2
+            && self.linear_lookups.get() < MAX_LINEAR_LOOKUPS
+        {
+            self.linear_lookups.set(self.linear_lookups.get() + 1);
+            return self.scan_node_rev(node);
+        }
+        self.with_nodemap(|nodemap, index| nodemap.find_bin(index, node))?
+            .ok_or(RevlogError::InvalidRevision)
+    }
+
+    /// Find a full node by reading the index from the tip
+    fn scan_node_rev(
+        &self,
+        node: NodePrefix,
+    ) -> Result<Revision, RevlogError> {
+        if node == NULL_NODE {
+            return Ok(NULL_REVISION);
+        }
         for rev in (0..self.len() as Revision).rev() {
-            let index_entry =
-                self.index.get_entry(rev).ok_or(HgError::corrupted(
-                    "revlog references a revision not in the index",
-                ))?;
-            if node == *index_entry.hash() {
+            if node == *self.index_entry(rev)?.hash() {
                 return Ok(rev);
             }
-            if node.is_prefix_of(index_entry.hash()) {
-                if found_by_prefix.is_some() {
-                    return Err(RevlogError::AmbiguousPrefix);
-                }
-                found_by_prefix = Some(rev)
+        }
+        Err(RevlogError::InvalidRevision)
+    }
+
+    /// The number of hexadecimal digits of the shortest prefix that
+    /// identifies `node` unambiguously, at least `min_length`, like
+    /// `revlog.shortest` in Python.
+    ///
+    /// A prefix made only of `f` digits could also be the working directory
+    /// pseudo-node, so it is extended until that is not the case.
+    pub fn shortest(
+        &self,
+        node: &Node,
+        min_length: usize,
+    ) -> Result<usize, RevlogError> {
+        let length = self
+            .with_nodemap(|nodemap, index| {
+                nodemap.unique_prefix_len_node(index, node)
+            })?
+            .ok_or(RevlogError::InvalidRevision)?;
+        let hex = format!("{:x}", node);
+        let mut length = std::cmp::max(length, min_length);
+        while length < hex.len() && hex[..length].bytes().all(|d| d == b'f') {
+            length += 1
+        }
+        Ok(length)
+    }
+
+    /// Call `f` with the persistent nodemap, or else the in-memory one,
+    /// building the latter first if needed
+    fn with_nodemap<T>(
+        &self,
+        f: impl FnOnce(&nodemap::NodeTree, &Index) -> Result<T, NodeMapError>,
+    ) -> Result<T, RevlogError> {
+        if let Some(nodemap) = &self.nodemap {
+            return Ok(f(nodemap, &self.index)?);
+        }
+        if self.in_memory_nodemap.borrow().is_none() {
+            let mut nodemap = nodemap::NodeTree::default();
+            for rev in 0..self.len() as Revision {
+                let node = self.index_entry(rev)?.hash();
+                nodemap.insert(&self.index, node, rev)?;
             }
+            *self.in_memory_nodemap.borrow_mut() = Some(nodemap);
         }
-        found_by_prefix.ok_or(RevlogError::InvalidRevision)
+        let nodemap = self.in_memory_nodemap.borrow();
+        Ok(f(nodemap.as_ref().expect("built above"), &self.index)?)
     }
 
     /// Returns whether the given revision exists in this revlog.
@@ -165,41 +460,212 @@ impl Revlog {
     ///
     /// All entries required to build the final data out of deltas will be
     /// retrieved as needed, and the deltas will be applied to the inital
-    /// snapshot to rebuild the final data.
+    /// snapshot to rebuild the final data. The processors of the flags of
+    /// the revision then turn it into the text of the revision, like
+    /// `revision` in Python.
     #[timed]
     pub fn get_rev_data(&self, rev: Revision) -> Result<Vec<u8>, RevlogError> {
-        // Todo return -> Cow
-        let mut entry = self.get_entry(rev)?;
-        let mut delta_chain = vec![];
-        while let Some(base_rev) = entry.base_rev {
-            delta_chain.push(entry);
-            entry = self
-                .get_entry(base_rev)
-                .map_err(|_| RevlogError::corrupted())?;
+        self.get_data(rev, false)
+    }
+
+    /// Return the data stored for a revision, which deltas are against,
+    /// without applying the processors of its flags, like `rawdata` in
+    /// Python.
+    pub fn get_raw_rev_data(
+        &self,
+        rev: Revision,
+    ) -> Result<Vec<u8>, RevlogError> {
+        self.get_data(rev, true)
+    }
+
+    /// The size of the text of a revision, like `size` in Python.
+    ///
+    /// This is the size recorded in the index, unless processors of the
+    /// flags of the revision change it and the text must be read.
+    pub fn size(&self, rev: Revision) -> Result<usize, RevlogError> {
+        let index_entry = self
+            .index
+            .get_entry(rev)
+            .ok_or(RevlogError::InvalidRevision)?;
+        if index_entry.flags() & REVIDX_RAWTEXT_CHANGING_FLAGS == 0 {
+            Ok(index_entry.uncompressed_len())
+        } else {
+            Ok(self.get_rev_data(rev)?.len())
         }
+    }
 
-        // TODO do not look twice in the index
+    fn get_data(
+        &self,
+        rev: Revision,
+        raw: bool,
+    ) -> Result<Vec<u8>, RevlogError> {
         let index_entry = self
             .index
             .get_entry(rev)
             .ok_or(RevlogError::InvalidRevision)?;
+        let flags = index_entry.flags();
+        let cached = self.cache.borrow_mut().text(rev);
+        let (raw_text, validated) = match cached {
+            Some(text) if flags == 0 => return Ok(text.to_vec()),
+            Some(text) => (text.to_vec(), true),
+            None => (self.build_raw_text(rev)?, false),
+        };
 
-        let data: Vec<u8> = if delta_chain.is_empty() {
-            entry.data()?.into()
+        let (text, validate_hash) = if raw {
+            let validate_hash = self.flag_processors.raw(&raw_text, flags)?;
+            (raw_text.clone(), validate_hash)
         } else {
-            Revlog::build_data_from_deltas(entry, &delta_chain)?
+            self.flag_processors.read(raw_text.clone(), flags)?
         };
+        if validate_hash
+            && !self.check_hash(
+                index_entry.p1(),
+                index_entry.p2(),
+                index_entry.hash().as_bytes(),
+                &text,
+            )
+        {
+            // Like in Python, a censored revision is found when its
+            // tombstone does not match the hash
+            return Err(if is_censored_text(&text) {
+                RevlogError::Censored {
+                    node: *index_entry.hash(),
+                    tombstone: text,
+                }
+            } else {
+                HgError::corrupted(format!(
+                    "integrity check failed on {}:{}",
+                    self.index_path.display(),
+                    rev
+                ))
+                .into()
+            });
+        }
+        if !validated {
+            self.cache.borrow_mut().insert_text(rev, raw_text);
+        }
+        Ok(text)
+    }
 
-        if self.check_hash(
-            index_entry.p1(),
-            index_entry.p2(),
-            index_entry.hash().as_bytes(),
-            &data,
-        ) {
-            Ok(data)
+    /// Build the data stored for a revision from its delta chain, without
+    /// checking its hash
+    fn build_raw_text(&self, rev: Revision) -> Result<Vec<u8>, RevlogError> {
+        let mut cache = self.cache.borrow_mut();
+        // The delta chain stops early at a revision whose full text is in
+        // the cache
+        let mut entry = self.get_entry(rev)?;
+        let mut delta_chain = vec![];
+        let base_text = loop {
+            let base_rev = match entry.base_rev {
+                Some(base_rev) => base_rev,
+                None => {
+                    cache.text_missed();
+                    break cache.chunk(entry.rev, || {
+                        entry.data().map(Cow::into_owned)
+                    })?;
+                }
+            };
+            // Without generaldelta, deltas are against the previous revision
+            let delta_parent = if self.index.uses_generaldelta() {
+                base_rev
+            } else {
+                entry.rev - 1
+            };
+            // Deltas against later revisions could make the chain loop
+            if delta_parent >= entry.rev {
+                return Err(RevlogError::corrupted());
+            }
+            delta_chain.push(entry);
+            if let Some(text) = cache.text(delta_parent) {
+                break text;
+            }
+            entry = self
+                .get_entry(delta_parent)
+                .map_err(|_| RevlogError::corrupted())?;
+        };
+        let deltas = delta_chain
+            .iter()
+            .rev()
+            .map(|entry| {
+                cache.chunk(entry.rev, || entry.data().map(Cow::into_owned))
+            })
+            .collect::<Result<Vec<_>, RevlogError>>()?;
+
+        Ok(if deltas.is_empty() {
+            base_text.to_vec()
         } else {
-            Err(RevlogError::corrupted())
+            Revlog::build_data_from_deltas(&base_text, &deltas)?
+        })
+    }
+
+    /// The data that a revision adds to its delta parent, like
+    /// `mdiff.patchtext(revdiff(deltaparent(rev), rev))` in Python: all of
+    /// the text of full snapshots.
+    ///
+    /// This is cheaper than reading the revision as deltas are not applied.
+    pub fn get_delta_text(
+        &self,
+        rev: Revision,
+    ) -> Result<Vec<u8>, RevlogError> {
+        let entry = self.get_entry(rev)?;
+        let data = entry.data()?;
+        Ok(match entry.base_rev {
+            Some(_) => patch::PatchList::new(&data)?.inserted_data(),
+            None => data.into_owned(),
+        })
+    }
+
+    /// How many bytes the data and index files have beyond the revisions
+    /// they store, like `checksize` in Python. Both are zero for a healthy
+    /// revlog, the data file may also be too short.
+    pub fn check_size(&self) -> Result<(isize, isize), RevlogError> {
+        let data_extra = if self.index.is_inline() {
+            0
+        } else {
+            let last = self.len() as Revision - 1;
+            self.data_len() as isize - self.end(last)? as isize
+        };
+        Ok((data_extra, self.index.extra_bytes() as isize))
+    }
+
+    /// Register the processor of a flag that revisions may have, like
+    /// extensions do with `addflagprocessor` in Python
+    pub fn add_flag_processor(
+        &mut self,
+        flag: u16,
+        processor: Arc<dyn FlagProcessor>,
+    ) -> Result<(), HgError> {
+        self.flag_processors.insert(flag, processor)
+    }
+
+    /// Return the sidedata of a revision, empty before revlog v2.
+    ///
+    /// It is stored uncompressed in the data file, outside of the delta
+    /// chains.
+    pub fn get_sidedata(
+        &self,
+        rev: Revision,
+    ) -> Result<Sidedata, RevlogError> {
+        let index_entry = self
+            .index
+            .get_entry(rev)
+            .ok_or(RevlogError::InvalidRevision)?;
+        let length = index_entry.sidedata_compressed_len();
+        if length == 0 {
+            return Ok(Sidedata::new());
         }
+        let start = index_entry.sidedata_offset();
+        let bytes = self
+            .data()
+            .get(start..start + length)
+            .ok_or_else(RevlogError::corrupted)?;
+        Ok(sidedata::parse(bytes)?)
+    }
+
+    /// How often the caches of full texts and decompressed chunks were
+    /// useful so far
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
     }
 
     /// Check the hash of some given data against the recorded hash.
@@ -224,23 +690,19 @@ impl Revlog {
         hash(data, h1.as_bytes(), h2.as_bytes()).as_slice() == expected
     }
 
-    /// Build the full data of a revision out its snapshot
-    /// and its deltas.
+    /// Build the full data of a revision out of the full text of a revision
+    /// in its delta chain, and the following deltas.
     #[timed]
     fn build_data_from_deltas(
-        snapshot: RevlogEntry,
-        deltas: &[RevlogEntry],
-    ) -> Result<Vec<u8>, RevlogError> {
-        let snapshot = snapshot.data()?;
-        let deltas = deltas
+        base: &[u8],
+        deltas: &[Arc<Vec<u8>>],
+    ) -> Result<Vec<u8>, HgError> {
+        let patches = deltas
             .iter()
-            .rev()
-            .map(RevlogEntry::data)
-            .collect::<Result<Vec<Cow<'_, [u8]>>, RevlogError>>()?;
-        let patches: Vec<_> =
-            deltas.iter().map(|d| patch::PatchList::new(d)).collect();
+            .map(|d| patch::PatchList::new(d))
+            .collect::<Result<Vec<_>, _>>()?;
         let patch = patch::fold_patch_lists(&patches);
-        Ok(patch.apply(&snapshot))
+        patch.apply(base)
     }
 
     /// Return the revlog data.
@@ -253,6 +715,22 @@ impl Revlog {
         }
     }
 
+    fn data_len(&self) -> usize {
+        self.data().len() + self.added_data.len()
+    }
+
+    /// The revlog data from `start` to `end`, which are never partly read
+    /// and partly appended
+    fn data_range(&self, start: usize, end: usize) -> Option<&[u8]> {
+        let data = self.data();
+        if start < data.len() {
+            data.get(start..end)
+        } else {
+            self.added_data
+                .get(start - data.len()..end.checked_sub(data.len())?)
+        }
+    }
+
     /// Get an entry of the revlog.
     fn get_entry(&self, rev: Revision) -> Result<RevlogEntry, RevlogError> {
         let index_entry = self
@@ -264,7 +742,16 @@ impl Revlog {
         let data = if self.index.is_inline() {
             self.index.data(start, end)
         } else {
-            &self.data()[start..end]
+            self.data_range(start, end).ok_or_else(|| {
+                HgError::corrupted(format!(
+                    "partial read of revlog {}; expected {} bytes from \
+                     offset {}, got {}",
+                    self.data_path.display(),
+                    end - start,
+                    start,
+                    self.data_len().saturating_sub(start)
+                ))
+            })?
         };
         let entry = RevlogEntry {
             rev,
@@ -279,6 +766,418 @@ impl Revlog {
         };
         Ok(entry)
     }
+
+    /// Append a revision with the given parents and return its number, or
+    /// the number of the existing revision with the same node ID.
+    ///
+    /// Like `addrevision` in Python, the revision is stored as a delta or as
+    /// a full snapshot, compressed as configured. The files are appended to
+    /// after recording their size in `transaction`, and an inline revlog is
+    /// split once it grows too large.
+    pub fn add_revision(
+        &mut self,
+        transaction: &mut Transaction,
+        options: &WriteOptions,
+        data: &[u8],
+        link_rev: Revision,
+        p1: Revision,
+        p2: Revision,
+    ) -> Result<Revision, RevlogError> {
+        if self.index.is_v2() {
+            return Err(HgError::unsupported(
+                "writing to revlog v2 is not supported",
+            )
+            .into());
+        }
+        let node_of = |rev| -> Result<Node, RevlogError> {
+            if rev == NULL_REVISION {
+                return Ok(NULL_NODE);
+            }
+            match self.index.get_entry(rev) {
+                Some(entry) => Ok(*entry.hash()),
+                None => Err(RevlogError::InvalidRevision),
+            }
+        };
+        let hash =
+            hash(data, node_of(p1)?.as_bytes(), node_of(p2)?.as_bytes());
+        let node = Node::try_from(&hash[..]).expect("SHA-1 of 20 bytes");
+        match self.get_node_rev(node.into()) {
+            Ok(rev) => return Ok(rev),
+            Err(RevlogError::InvalidRevision) => {}
+            Err(error) => return Err(error),
+        }
+
+        let rev = self.len() as Revision;
+        let generaldelta = if self.is_empty() {
+            options.generaldelta
+        } else {
+            self.index.uses_generaldelta()
+        };
+        let delta = deltas::find_delta_info(
+            self,
+            options,
+            generaldelta,
+            data,
+            p1,
+            p2,
+        )?;
+        let offset = self.end(rev - 1)?;
+
+        let mut entry = Vec::with_capacity(INDEX_ENTRY_SIZE);
+        // Offset on 48 bits, followed by 16 bits of flags
+        entry.extend(&((offset as u64) << 16).to_be_bytes());
+        if rev == 0 {
+            // The offset of the first revision is always zero, the index
+            // header is stored in its place
+            let mut header = REVLOGV1 | FLAG_INLINE_DATA;
+            if generaldelta {
+                header |= FLAG_GENERALDELTA
+            }
+            entry[..4].copy_from_slice(&header.to_be_bytes());
+        }
+        entry.extend(&(delta.data.len() as u32).to_be_bytes());
+        entry.extend(&(data.len() as u32).to_be_bytes());
+        entry.extend(&delta.base.to_be_bytes());
+        entry.extend(&link_rev.to_be_bytes());
+        entry.extend(&p1.to_be_bytes());
+        entry.extend(&p2.to_be_bytes());
+        entry.extend(node.as_bytes());
+        entry.resize(INDEX_ENTRY_SIZE, 0);
+
+        let vfs = transaction.store_vfs();
+        let entries_size = rev as usize * INDEX_ENTRY_SIZE;
+        if self.index.is_inline() {
+            transaction
+                .add(&self.index_path, (offset + entries_size) as u64)?;
+            append(vfs, &self.index_path, &[&entry, &delta.data])?;
+            self.index.append(&entry, &delta.data);
+        } else {
+            transaction.add(&self.data_path, offset as u64)?;
+            transaction.add(&self.index_path, entries_size as u64)?;
+            append(vfs, &self.data_path, &[&delta.data])?;
+            append(vfs, &self.index_path, &[&entry])?;
+            self.index.append(&entry, &[]);
+            self.added_data.extend_from_slice(&delta.data);
+        }
+        let nodemap = match &mut self.nodemap {
+            Some(nodemap) => Some(nodemap),
+            None => self.in_memory_nodemap.get_mut().as_mut(),
+        };
+        if let Some(nodemap) = nodemap {
+            nodemap.insert(&self.index, &node, rev)?;
+        }
+        // Likely the base of a delta for the next revision
+        self.cache.get_mut().insert_text(rev, data.to_vec());
+        self.enforce_inline_size(transaction)?;
+        Ok(rev)
+    }
+
+    /// Move the data of an inline revlog to a separate file once there is
+    /// too much of it, like `_enforceinlinesize` in Python.
+    fn enforce_inline_size(
+        &mut self,
+        transaction: &mut Transaction,
+    ) -> Result<(), RevlogError> {
+        let tip = self.len() as Revision - 1;
+        if !self.index.is_inline() || self.end(tip)? < MAX_INLINE_SIZE {
+            return Ok(());
+        }
+        let index_offset = match transaction.find_offset(&self.index_path) {
+            Some(offset) => offset as usize,
+            None => {
+                return Err(HgError::abort(format!(
+                    "abort: {} not found in the transaction",
+                    self.index_path.display()
+                ))
+                .into())
+            }
+        };
+        // Rolling back restores the former inline revlog
+        transaction.add_backup(&self.index_path)?;
+        transaction.add(&self.data_path, 0)?;
+
+        let mut data = Vec::new();
+        let mut index = Vec::with_capacity(self.len() * INDEX_ENTRY_SIZE);
+        let mut first_new_rev = None;
+        for rev in 0..=tip {
+            let entry = self.index_entry(rev)?;
+            let start = entry.offset();
+            data.extend_from_slice(
+                self.index.data(start, start + entry.compressed_len()),
+            );
+            index.extend_from_slice(entry.as_bytes());
+            if first_new_rev.is_none()
+                && start - INDEX_ENTRY_SIZE >= index_offset
+            {
+                first_new_rev = Some(rev)
+            }
+        }
+        // Clear the inline flag
+        let header = BigEndian::read_u32(&index) & !FLAG_INLINE_DATA;
+        index[..4].copy_from_slice(&header.to_be_bytes());
+
+        let vfs = transaction.store_vfs();
+        let data_path = vfs.join(&self.data_path);
+        std::fs::write(&data_path, data).when_writing_file(&data_path)?;
+        // Change the recorded size before replacing the index, so that it is
+        // never larger than the index
+        let first_new_rev = first_new_rev.unwrap_or(tip + 1);
+        transaction.replace(
+            &self.index_path,
+            (first_new_rev as usize * INDEX_ENTRY_SIZE) as u64,
+        )?;
+        write_atomically(vfs, &self.index_path, &index)?;
+        self.reload(vfs)
+    }
+
+    /// Write the persistent nodemap for the revisions added so far, like
+    /// `persist_nodemap` in Python.
+    ///
+    /// Writers call this before closing the transaction that added
+    /// revisions. It does nothing for revlogs without a persistent nodemap.
+    ///
+    /// Data for new revisions is appended to the current data file, unless
+    /// too much of it would be unused: the whole nodemap is then written to
+    /// a new data file.
+    pub fn persist_nodemap(
+        &mut self,
+        transaction: &mut Transaction,
+    ) -> Result<(), RevlogError> {
+        if !self.persistent_nodemap || self.index.is_inline() {
+            return Ok(());
+        }
+        let tip_rev = self.len() as Revision - 1;
+        let unchanged = match &self.nodemap_docket {
+            Some(docket) => docket.tip_rev == tip_rev,
+            None => tip_rev == NULL_REVISION,
+        };
+        if unchanged {
+            return Ok(());
+        }
+        let vfs = transaction.store_vfs();
+        let docket_path = self.index_path.with_extension("n");
+        let tip_node = *self.index_entry(tip_rev)?.hash();
+
+        let mut incremental = None;
+        if let (Some(docket), Some(nodemap)) =
+            (self.nodemap_docket.take(), self.nodemap.take())
+        {
+            let unused = docket.data_unused
+                + nodemap.masked_readonly_blocks() * size_of::<Block>();
+            let (_, data) = nodemap.into_readonly_and_added_bytes();
+            let length = docket.data_length + data.len();
+            if length > unused * MAX_NODEMAP_UNUSED_RATIO {
+                incremental = Some((docket, data, length, unused))
+            }
+        }
+        let docket = match incremental {
+            Some((mut docket, data, length, unused)) => {
+                let data_path = docket.data_path(&docket_path);
+                transaction.add(&data_path, docket.data_length as u64)?;
+                let path = vfs.join(&data_path);
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .when_writing_file(&path)?;
+                file.seek(SeekFrom::Start(docket.data_length as u64))
+                    .when_writing_file(&path)?;
+                file.write_all(&data).when_writing_file(&path)?;
+                docket.data_length = length;
+                docket.data_unused = unused;
+                docket.tip_rev = tip_rev;
+                docket.tip_node = tip_node.as_bytes().to_owned();
+                docket
+            }
+            None => {
+                let nodemap = match self.in_memory_nodemap.get_mut().take() {
+                    Some(nodemap) => nodemap,
+                    None => {
+                        let mut nodemap = nodemap::NodeTree::default();
+                        for rev in 0..=tip_rev {
+                            let node = self.index_entry(rev)?.hash();
+                            nodemap.insert(&self.index, node, rev)?;
+                        }
+                        nodemap
+                    }
+                };
+                let (_, data) = nodemap.into_readonly_and_added_bytes();
+                let docket =
+                    NodeMapDocket::new(tip_rev, &tip_node, data.len());
+                let data_path = docket.data_path(&docket_path);
+                transaction.add(&data_path, 0)?;
+                let path = vfs.join(&data_path);
+                std::fs::write(&path, data).when_writing_file(&path)?;
+                docket
+            }
+        };
+        transaction.add_backup(&docket_path)?;
+        write_atomically(vfs, &docket_path, &docket.serialize())?;
+
+        let data_path = docket.data_path(&docket_path);
+        for path in other_data_paths(vfs, &docket_path, &docket.uid)? {
+            transaction.remove_on_close(path)
+        }
+        let data = vfs.mmap_open(&data_path)?;
+        self.nodemap = Some(nodemap::NodeTree::load_bytes(
+            Box::new(data),
+            docket.data_length,
+        ));
+        self.nodemap_docket = Some(docket);
+        Ok(())
+    }
+
+    /// Read the files again after writing to them
+    fn reload(&mut self, vfs: Vfs) -> Result<(), RevlogError> {
+        self.index = Index::new(Box::new(vfs.mmap_open(&self.index_path)?))?;
+        self.added_data.clear();
+        self.data_bytes = if self.index.is_inline() {
+            None
+        } else {
+            Some(Box::new(vfs.mmap_open(&self.data_path)?))
+        };
+        Ok(())
+    }
+
+    /// The index entry of a revision that is expected to exist
+    pub(crate) fn index_entry(
+        &self,
+        rev: Revision,
+    ) -> Result<IndexEntry<'_>, RevlogError> {
+        self.index.get_entry(rev).ok_or_else(RevlogError::corrupted)
+    }
+
+    /// Where the data of a revision starts, not counting the interleaved
+    /// index entries of an inline revlog
+    pub(crate) fn start(&self, rev: Revision) -> Result<usize, RevlogError> {
+        let offset = self.index_entry(rev)?.offset();
+        if self.index.is_inline() {
+            Ok(offset - (rev as usize + 1) * INDEX_ENTRY_SIZE)
+        } else {
+            Ok(offset)
+        }
+    }
+
+    /// Where the data of a revision ends, zero for the null revision
+    pub(crate) fn end(&self, rev: Revision) -> Result<usize, RevlogError> {
+        if rev == NULL_REVISION {
+            return Ok(0);
+        }
+        Ok(self.start(rev)? + self.index_entry(rev)?.compressed_len())
+    }
+
+    /// The revision that a revision is stored as a delta against, if any
+    pub(crate) fn delta_parent(
+        &self,
+        rev: Revision,
+    ) -> Result<Revision, RevlogError> {
+        let base = self.index_entry(rev)?.base_revision();
+        Ok(if base == rev {
+            NULL_REVISION
+        } else if self.index.uses_generaldelta() {
+            base
+        } else {
+            rev - 1
+        })
+    }
+
+    /// The full snapshot at the start of the delta chain of a revision
+    pub(crate) fn chain_base(
+        &self,
+        mut rev: Revision,
+    ) -> Result<Revision, RevlogError> {
+        loop {
+            let base = self.index_entry(rev)?.base_revision();
+            if base == rev {
+                return Ok(rev);
+            }
+            rev = base;
+        }
+    }
+
+    /// The revisions to read to rebuild a revision, from its full snapshot
+    pub(crate) fn delta_chain(
+        &self,
+        mut rev: Revision,
+    ) -> Result<Vec<Revision>, RevlogError> {
+        let mut chain = vec![rev];
+        loop {
+            rev = self.delta_parent(rev)?;
+            if rev == NULL_REVISION {
+                chain.reverse();
+                return Ok(chain);
+            }
+            chain.push(rev)
+        }
+    }
+
+    /// The number of deltas to apply to rebuild a revision, and the size of
+    /// the data to read including its full snapshot
+    pub(super) fn chain_info(
+        &self,
+        rev: Revision,
+    ) -> Result<(usize, usize), RevlogError> {
+        let chain = self.delta_chain(rev)?;
+        let mut size = 0;
+        for &rev in &chain {
+            size += self.index_entry(rev)?.compressed_len()
+        }
+        Ok((chain.len() - 1, size))
+    }
+
+    /// The ranges of the data file to read to rebuild a revision from its
+    /// delta chain with sparse reads
+    pub(crate) fn sparse_read_ranges(
+        &self,
+        rev: Revision,
+        options: &SparseReadOptions,
+    ) -> Result<Vec<Range<usize>>, RevlogError> {
+        let chain = self.delta_chain(rev)?;
+        deltas::slice_chunk(self, &chain, options)?
+            .into_iter()
+            .map(|chunk| {
+                Ok(self.start(chunk[0])?..self.end(chunk[chunk.len() - 1])?)
+            })
+            .collect()
+    }
+
+    /// The first byte of the stored data of a revision, which tells how it
+    /// is compressed, or `None` when it is empty
+    pub(crate) fn chunk_header(
+        &self,
+        rev: Revision,
+    ) -> Result<Option<u8>, RevlogError> {
+        Ok(self.get_entry(rev)?.bytes.first().cloned())
+    }
+
+    /// Whether a revision is stored as a full snapshot, or as an intermediate
+    /// snapshot: a delta against a snapshot that is not one of its parents, as
+    /// in sparse revlogs
+    pub(crate) fn is_snapshot(
+        &self,
+        mut rev: Revision,
+    ) -> Result<bool, RevlogError> {
+        loop {
+            if rev == NULL_REVISION {
+                return Ok(true);
+            }
+            let entry = self.index_entry(rev)?;
+            let base = entry.base_revision();
+            if base == rev || base == NULL_REVISION {
+                return Ok(true);
+            }
+            if base == entry.p1() || base == entry.p2() {
+                return Ok(false);
+            }
+            rev = base;
+        }
+    }
+}
+
+/// The DAG of a revlog, such as a filelog
+impl Graph for Revlog {
+    fn parents(&self, rev: Revision) -> Result<[Revision; 2], GraphError> {
+        self.index.parents(rev)
+    }
 }
 
 /// The revlog entry's bytes and the necessary informations to extract
@@ -355,6 +1254,76 @@ impl<'a> RevlogEntry<'a> {
     }
 }
 
+/// Compress the data of a revision, with the header of the compression
+/// engine, like `compress` in Python.
+///
+/// Data is stored uncompressed, with a `u` header, when compression would
+/// not make it smaller. Data starting with a null byte needs no header.
+pub(super) fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let compressed = match compression {
+        Compression::Zlib(level) if data.len() >= 44 => {
+            let level = flate2::Compression::new(level.unwrap_or(6));
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(data).and_then(|()| encoder.finish()).ok()
+        }
+        Compression::Zstd(level) if data.len() >= 50 => {
+            zstd::block::compress(data, level as i32).ok()
+        }
+        _ => None,
+    };
+    match compressed {
+        Some(compressed) if compressed.len() < data.len() => compressed,
+        _ if data[0] == b'\0' => data.to_vec(),
+        _ => [b"u", data].concat(),
+    }
+}
+
+/// Append chunks to a file relative to the given `Vfs`, creating it and its
+/// directory if needed
+fn append(vfs: Vfs, path: &Path, chunks: &[&[u8]]) -> Result<(), HgError> {
+    let path = vfs.join(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).when_writing_file(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .when_writing_file(&path)?;
+    for chunk in chunks {
+        file.write_all(chunk).when_writing_file(&path)?;
+    }
+    Ok(())
+}
+
+/// Replace a file, relative to the given `Vfs`, so that readers see either
+/// its former or its new content
+fn write_atomically(
+    vfs: Vfs,
+    path: &Path,
+    content: &[u8],
+) -> Result<(), HgError> {
+    let mut temporary = path.to_owned().into_os_string();
+    temporary.push(".tmp");
+    let temporary_path = vfs.join(&temporary);
+    std::fs::write(&temporary_path, content)
+        .when_writing_file(&temporary_path)?;
+    vfs.rename(&temporary, path)
+}
+
+/// Whether a persistent nodemap is for the current content of the index,
+/// like the check of `_loadindex` in Python
+fn docket_matches(docket: &NodeMapDocket, index: &Index) -> bool {
+    !index.is_inline()
+        && match index.get_entry(docket.tip_rev) {
+            Some(entry) => entry.hash().as_bytes() == &docket.tip_node[..],
+            None => false,
+        }
+}
+
 /// Format version of the revlog.
 pub fn get_version(index_bytes: &[u8]) -> u16 {
     BigEndian::read_u16(&index_bytes[2..=3])
@@ -382,6 +1351,8 @@ mod tests {
     use super::*;
 
     use super::super::index::IndexEntryBuilder;
+    use super::super::nodemap_docket::NodeMapDocket;
+    use crate::testing::repo_with;
 
     #[test]
     fn version_test() {
@@ -392,4 +1363,489 @@ mod tests {
 
         assert_eq!(get_version(&bytes), 1)
     }
+
+    #[test]
+    fn test_revlogv2_sidedata() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &["exp-revlogv2.2"]);
+        let mut sidedata = Sidedata::new();
+        sidedata.insert(sidedata::SD_FILES, b"some files".to_vec());
+        let serialized = sidedata::serialize(&sidedata);
+
+        // Two uncompressed revisions, the sidedata of the first one between
+        // them in the data file
+        let mut index = Vec::new();
+        let mut data = Vec::new();
+        let mut p1 = NULL_NODE;
+        for (rev, text) in [&b"first\n"[..], b"second\n"].iter().enumerate() {
+            let node = hash(text, p1.as_bytes(), NULL_NODE.as_bytes());
+            let offset = data.len() as u64;
+            data.push(b'u');
+            data.extend(*text);
+            let (sidedata_offset, sidedata_len) = if rev == 0 {
+                let start = data.len();
+                data.extend(&serialized);
+                (start as u64, serialized.len() as u32)
+            } else {
+                (0, 0)
+            };
+            let mut entry = (offset << 16).to_be_bytes().to_vec();
+            if rev == 0 {
+                entry[..4].copy_from_slice(
+                    &(u32::from(REVLOGV2) | FLAG_INLINE_DATA).to_be_bytes(),
+                );
+            }
+            entry.extend(&(text.len() as u32 + 1).to_be_bytes());
+            entry.extend(&(text.len() as u32).to_be_bytes());
+            entry.extend(&(rev as Revision).to_be_bytes());
+            entry.extend(&(rev as Revision).to_be_bytes());
+            entry.extend(&(rev as Revision - 1).to_be_bytes());
+            entry.extend(&NULL_REVISION.to_be_bytes());
+            entry.extend(&node);
+            entry.resize(INDEX_ENTRY_SIZE, 0);
+            entry.extend(&sidedata_offset.to_be_bytes());
+            entry.extend(&sidedata_len.to_be_bytes());
+            entry.resize(super::super::index::INDEX_ENTRY_SIZE_V2, 0);
+            index.extend(entry);
+            p1 = Node::try_from(&node[..]).unwrap();
+        }
+        let store = root.path().join(".hg/store");
+        std::fs::write(store.join("00changelog.i"), index).unwrap();
+        std::fs::write(store.join("00changelog.d"), data).unwrap();
+
+        let revlog = Revlog::open(&repo, "00changelog.i", None).ok().unwrap();
+        assert!(revlog.index.is_v2());
+        assert_eq!(
+            read_all(&revlog),
+            vec![b"first\n".to_vec(), b"second\n".to_vec()]
+        );
+        assert_eq!(revlog.get_sidedata(0).ok().unwrap(), sidedata);
+        assert_eq!(revlog.get_sidedata(1).ok().unwrap(), Sidedata::new());
+        assert_eq!(revlog.get_node_rev(p1.into()).ok(), Some(1));
+    }
+
+    /// Lines of text that change a little from one version to the next
+    fn text(version: usize, lines: usize) -> Vec<u8> {
+        let mut text = Vec::new();
+        for line in 0..lines {
+            let seed = if line % 7 == version % 7 { version } else { 0 };
+            let value = (line * 7919 + seed * 104729) % 1000003;
+            text.extend(format!("line {} {}\n", line, value).as_bytes());
+        }
+        text
+    }
+
+    fn read_all(revlog: &Revlog) -> Vec<Vec<u8>> {
+        (0..revlog.len() as Revision)
+            .map(|rev| revlog.get_rev_data(rev).ok().unwrap())
+            .collect()
+    }
+
+    fn add(
+        revlog: &mut Revlog,
+        transaction: &mut Transaction,
+        options: &WriteOptions,
+        data: &[u8],
+        p1: Revision,
+    ) -> Revision {
+        let link_rev = revlog.len() as Revision;
+        match revlog.add_revision(
+            transaction,
+            options,
+            data,
+            link_rev,
+            p1,
+            NULL_REVISION,
+        ) {
+            Ok(rev) => rev,
+            Err(_) => panic!("failed to add a revision"),
+        }
+    }
+
+    #[test]
+    fn test_add_revisions() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &["generaldelta", "sparserevlog"]);
+        let options = WriteOptions::from_repo(&repo).unwrap();
+        let path = Path::new("data/file.i");
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        let mut texts = vec![b"".to_vec(), b"\0binary".to_vec()];
+        texts.extend((0..10).map(|version| text(version, 50)));
+        for (rev, text) in texts.iter().enumerate() {
+            let p1 = rev as Revision - 1;
+            assert_eq!(
+                add(&mut revlog, &mut transaction, &options, text, p1),
+                p1 + 1
+            );
+        }
+        // Adding the same revision again does nothing
+        assert_eq!(
+            add(&mut revlog, &mut transaction, &options, &texts[3], 2),
+            3
+        );
+        // Appended revisions are read without reloading the files
+        assert_eq!(read_all(&revlog), texts);
+        transaction.close().unwrap();
+
+        let revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        assert!(revlog.index.is_inline());
+        assert!(revlog.index.uses_generaldelta());
+        assert_eq!(revlog.len(), texts.len());
+        assert_eq!(read_all(&revlog), texts);
+        // Later versions are deltas against their parent
+        let entry = revlog.index.get_entry(5).unwrap();
+        assert_eq!(entry.base_revision(), 4);
+        assert!(entry.compressed_len() < texts[5].len() / 4);
+        assert_eq!(entry.link_revision(), 5);
+        assert_eq!(entry.p1(), 4);
+    }
+
+    #[test]
+    fn test_node_lookups() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &["generaldelta"]);
+        let options = WriteOptions::from_repo(&repo).unwrap();
+        let path = Path::new("data/file.i");
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        for version in 0..300 {
+            let p1 = version as Revision - 1;
+            add(
+                &mut revlog,
+                &mut transaction,
+                &options,
+                &text(version, 3),
+                p1,
+            );
+        }
+        transaction.close().unwrap();
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let nodes: Vec<Node> = (0..revlog.len() as Revision)
+            .map(|rev| *revlog.index.get_entry(rev).unwrap().hash())
+            .collect();
+        let mut all_nodes = nodes.clone();
+        all_nodes.push(NULL_NODE);
+
+        // The first lookups of full nodes scan the index, the nodemap is
+        // built in memory for the next ones
+        for (rev, node) in nodes.iter().enumerate() {
+            assert_eq!(revlog.get_node_rev(node.into()).ok(), Some(rev as _));
+            assert_eq!(
+                revlog.in_memory_nodemap.borrow().is_some(),
+                rev >= MAX_LINEAR_LOOKUPS
+            );
+        }
+        assert_eq!(revlog.get_node_rev(NULL_NODE.into()).ok(), Some(-1));
+
+        for (rev, node) in nodes.iter().enumerate() {
+            let hex = format!("{:x}", node);
+            let shortest = (1..=40)
+                .find(|&length| {
+                    let prefix = NodePrefix::from_hex(&hex[..length]).unwrap();
+                    let matches =
+                        all_nodes.iter().filter(|n| prefix.is_prefix_of(n));
+                    // Prefixes of only `f` could also be the working
+                    // directory
+                    matches.count() == 1
+                        && hex[..length].bytes().any(|d| d != b'f')
+                })
+                .unwrap();
+            assert_eq!(revlog.shortest(node, 1).ok(), Some(shortest));
+            assert_eq!(revlog.shortest(node, 6).ok(), Some(shortest.max(6)));
+            let prefix = NodePrefix::from_hex(&hex[..shortest]).unwrap();
+            assert_eq!(revlog.get_node_rev(prefix).ok(), Some(rev as _));
+            if shortest > 1 {
+                let shorter = &hex[..shortest - 1];
+                let prefix = NodePrefix::from_hex(shorter).unwrap();
+                match revlog.get_node_rev(prefix) {
+                    Err(RevlogError::AmbiguousPrefix) => {}
+                    Ok(NULL_REVISION) if prefix.is_prefix_of(&NULL_NODE) => {}
+                    Ok(found) if shorter.bytes().all(|d| d == b'f') => {
+                        assert_eq!(found, rev as Revision)
+                    }
+                    _ => panic!("expected an ambiguous prefix"),
+                }
+            }
+        }
+
+        // Added revisions are found with the nodemap built earlier
+        let mut transaction = Transaction::start(&repo).unwrap();
+        let rev = add(&mut revlog, &mut transaction, &options, b"new", 299);
+        let node = *revlog.index.get_entry(rev).unwrap().hash();
+        assert_eq!(revlog.get_node_rev(node.into()).ok(), Some(rev));
+        transaction.close().unwrap();
+    }
+
+    #[test]
+    fn test_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &["generaldelta", "sparserevlog"]);
+        let options = WriteOptions::from_repo(&repo).unwrap();
+        let path = Path::new("data/file.i");
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        let texts: Vec<_> = (0..10).map(|version| text(version, 50)).collect();
+        for (rev, text) in texts.iter().enumerate() {
+            add(
+                &mut revlog,
+                &mut transaction,
+                &options,
+                text,
+                rev as i32 - 1,
+            );
+        }
+        transaction.close().unwrap();
+
+        let revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let chain = revlog.delta_chain(8).ok().unwrap();
+        assert!(chain.len() > 2);
+        let read = |rev: Revision| {
+            assert_eq!(
+                revlog.get_rev_data(rev).ok().unwrap(),
+                texts[rev as usize]
+            )
+        };
+        // Reading the parent of a revision rebuilds its delta chain
+        let stats = |text_hits, text_misses, chunk_hits, chunk_misses| {
+            assert_eq!(
+                revlog.cache_stats(),
+                CacheStats {
+                    text_hits,
+                    text_misses,
+                    chunk_hits,
+                    chunk_misses,
+                }
+            )
+        };
+        read(8);
+        stats(0, 1, 0, chain.len() as u64);
+        // The next revision is a delta against its cached full text
+        assert_eq!(revlog.delta_parent(9).ok(), Some(8));
+        read(9);
+        stats(1, 1, 0, chain.len() as u64 + 1);
+        read(8);
+        stats(2, 1, 0, chain.len() as u64 + 1);
+        // The chunks of an earlier revision are decompressed already
+        let previous = chain[chain.len() - 2];
+        read(previous);
+        stats(2, 2, chain.len() as u64 - 1, chain.len() as u64 + 1);
+    }
+
+    #[test]
+    fn test_add_revisions_without_generaldelta() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &["revlog-compression-zstd"]);
+        let options = WriteOptions::from_repo(&repo).unwrap();
+        assert_eq!(options.compression, Compression::Zstd(3));
+        let path = Path::new("data/file.i");
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        let texts: Vec<_> = (0..5).map(|version| text(version, 50)).collect();
+        // A branch: revision 3 is a child of revision 0
+        for (rev, text) in texts.iter().enumerate() {
+            let p1 = if rev == 3 { 0 } else { rev as Revision - 1 };
+            add(&mut revlog, &mut transaction, &options, text, p1);
+        }
+        transaction.close().unwrap();
+
+        let revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        assert!(!revlog.index.uses_generaldelta());
+        assert_eq!(read_all(&revlog), texts);
+        // The stored base is the start of the chain, deltas are against the
+        // previous revision
+        for rev in 1..5 {
+            assert_eq!(
+                revlog.index.get_entry(rev).unwrap().base_revision(),
+                0
+            );
+        }
+        assert_eq!(revlog.delta_chain(4).ok().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_split_inline_revlog() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &["generaldelta"]);
+        let options = WriteOptions {
+            compression: Compression::None,
+            ..WriteOptions::from_repo(&repo).unwrap()
+        };
+        let path = Path::new("data/file.i");
+        let store = root.path().join(".hg/store");
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        // Unrelated full texts, since deltas would be larger
+        let texts: Vec<Vec<u8>> = (0..30)
+            .map(|version| {
+                (0..1000)
+                    .flat_map(|line| {
+                        format!("{} {}\n", version, line).into_bytes()
+                    })
+                    .collect()
+            })
+            .collect();
+        add(&mut revlog, &mut transaction, &options, &texts[0], -1);
+        add(&mut revlog, &mut transaction, &options, &texts[1], -1);
+        transaction.close().unwrap();
+        let inline_index = std::fs::read(store.join(path)).unwrap();
+
+        let mut transaction = Transaction::start(&repo).unwrap();
+        for text in &texts[2..] {
+            add(&mut revlog, &mut transaction, &options, text, -1);
+        }
+        assert!(!revlog.index.is_inline());
+        assert!(store.join("data/file.d").exists());
+        assert_eq!(read_all(&revlog), texts);
+        // Rolling back restores the inline revlog
+        drop(transaction);
+        assert_eq!(std::fs::read(store.join(path)).unwrap(), inline_index);
+        assert!(!store.join("data/file.d").exists());
+
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        for text in &texts[2..] {
+            add(&mut revlog, &mut transaction, &options, text, -1);
+        }
+        transaction.close().unwrap();
+        let revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        assert!(!revlog.index.is_inline());
+        assert_eq!(read_all(&revlog), texts);
+        for rev in 0..30 {
+            let entry = revlog.index.get_entry(rev).unwrap();
+            assert_eq!(entry.base_revision(), rev);
+            assert_eq!(revlog.start(rev).ok().unwrap(), entry.offset());
+        }
+    }
+
+    #[test]
+    fn test_persistent_nodemap() {
+        let root = tempfile::tempdir().unwrap();
+        let repo =
+            repo_with(root.path(), &["generaldelta", "persistent-nodemap"]);
+        let options = WriteOptions {
+            compression: Compression::None,
+            ..WriteOptions::from_repo(&repo).unwrap()
+        };
+        let path = Path::new("00changelog.i");
+        let store = root.path().join(".hg/store");
+        let docket = || match NodeMapDocket::read_from_file(&repo, path) {
+            Ok(docket) => docket.map(|(docket, _data)| docket),
+            Err(_) => panic!("failed to read the nodemap docket"),
+        };
+        let data_path = |docket: &NodeMapDocket| {
+            store.join(docket.data_path(Path::new("00changelog.n")))
+        };
+        let check_lookups = || {
+            let revlog = Revlog::open(&repo, path, None).ok().unwrap();
+            assert!(revlog.nodemap.is_some());
+            for rev in 0..revlog.len() as Revision {
+                let node = *revlog.index.get_entry(rev).unwrap().hash();
+                assert_eq!(revlog.get_node_rev(node.into()).ok(), Some(rev));
+            }
+        };
+
+        // Inline revlogs do not get a nodemap, until they are split
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        for version in 0..30 {
+            let text: Vec<u8> = (0..1000)
+                .flat_map(|line| {
+                    format!("{} {}\n", version, line).into_bytes()
+                })
+                .collect();
+            add(&mut revlog, &mut transaction, &options, &text, -1);
+            if version == 0 {
+                revlog.persist_nodemap(&mut transaction).ok().unwrap();
+                assert!(docket().is_none());
+            }
+        }
+        assert!(!revlog.index.is_inline());
+        // Enough revisions for the next ones to use little of the data
+        for version in 30..300 {
+            let text = format!("small {}", version);
+            let p1 = version - 1;
+            add(&mut revlog, &mut transaction, &options, text.as_bytes(), p1);
+        }
+        revlog.persist_nodemap(&mut transaction).ok().unwrap();
+        transaction.close().unwrap();
+        let first = docket().unwrap();
+        assert_eq!(first.tip_rev, 299);
+        assert_eq!(first.data_unused, 0);
+        check_lookups();
+
+        // Revisions are appended to the data file
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        add(&mut revlog, &mut transaction, &options, b"appended", 299);
+        revlog.persist_nodemap(&mut transaction).ok().unwrap();
+        transaction.close().unwrap();
+        let second = docket().unwrap();
+        assert_eq!(second.uid, first.uid);
+        assert_eq!(second.tip_rev, 300);
+        assert_eq!(
+            &second.tip_node,
+            revlog.index.get_entry(300).unwrap().hash().as_bytes()
+        );
+        assert!(second.data_length > first.data_length);
+        assert!(second.data_unused > 0);
+        check_lookups();
+
+        // Rolling back restores the docket
+        let docket_bytes = std::fs::read(store.join("00changelog.n")).unwrap();
+        let mut transaction = Transaction::start(&repo).unwrap();
+        add(&mut revlog, &mut transaction, &options, b"rolled back", 300);
+        revlog.persist_nodemap(&mut transaction).ok().unwrap();
+        drop(transaction);
+        assert_eq!(
+            std::fs::read(store.join("00changelog.n")).unwrap(),
+            docket_bytes
+        );
+        assert_eq!(
+            std::fs::metadata(data_path(&second)).unwrap().len() as usize,
+            second.data_length
+        );
+        check_lookups();
+
+        // A new data file replaces the former one when too much of it would
+        // be unused
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        let mut version = 0;
+        let last = loop {
+            let mut transaction = Transaction::start(&repo).unwrap();
+            let p1 = revlog.len() as Revision - 1;
+            let text = format!("small {}", version);
+            add(&mut revlog, &mut transaction, &options, text.as_bytes(), p1);
+            revlog.persist_nodemap(&mut transaction).ok().unwrap();
+            transaction.close().unwrap();
+            let docket = docket().unwrap();
+            if docket.uid != first.uid {
+                break docket;
+            }
+            assert!(docket.data_length > docket.data_unused * 10);
+            version += 1;
+        };
+        assert_eq!(last.data_unused, 0);
+        assert_eq!(last.tip_rev, revlog.len() as Revision - 1);
+        assert!(!data_path(&first).exists());
+        check_lookups();
+
+        // A docket that does not match the index is ignored, and replaced
+        // with the next revisions
+        let stale = NodeMapDocket {
+            tip_node: NULL_NODE.as_bytes().to_owned(),
+            ..last
+        };
+        std::fs::write(store.join("00changelog.n"), stale.serialize())
+            .unwrap();
+        let mut revlog = Revlog::open(&repo, path, None).ok().unwrap();
+        assert!(revlog.nodemap.is_none());
+        let mut transaction = Transaction::start(&repo).unwrap();
+        let p1 = revlog.len() as Revision - 1;
+        add(&mut revlog, &mut transaction, &options, b"after", p1);
+        revlog.persist_nodemap(&mut transaction).ok().unwrap();
+        transaction.close().unwrap();
+        assert_ne!(docket().unwrap().uid, stale.uid);
+        check_lookups();
+    }
 }
@@ -0,0 +1,213 @@
+//! Extra data stored alongside the revisions of revlog v2 without being part
+//! of their hash, like `revlogutils/sidedata.py` in Python.
+//!
+//! The sidedata of a revision starts with the number of entries, followed
+//! by their key, length and SHA-1 digest, then their values in the same
+//! order.
+
+use crate::errors::HgError;
+use crate::utils::hg_path::HgPathBuf;
+use bytes_cast::{unaligned, BytesCast};
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use std::collections::BTreeMap;
+
+/// Keys reserved for testing purposes
+pub const SD_TEST1: u16 = 1;
+pub const SD_TEST2: u16 = 2;
+pub const SD_TEST3: u16 = 3;
+pub const SD_TEST4: u16 = 4;
+pub const SD_TEST5: u16 = 5;
+pub const SD_TEST6: u16 = 6;
+pub const SD_TEST7: u16 = 7;
+
+/// Keys of the copy tracing information of changesets
+pub const SD_P1COPIES: u16 = 8;
+pub const SD_P2COPIES: u16 = 9;
+pub const SD_FILESADDED: u16 = 10;
+pub const SD_FILESREMOVED: u16 = 11;
+pub const SD_FILES: u16 = 12;
+
+/// The decoded sidedata of a revision: values by key
+pub type Sidedata = BTreeMap<u16, Vec<u8>>;
+
+/// The copy tracing information of a changeset, like `metadata.ChangingFiles`
+/// in Python
+#[derive(Debug, Default, PartialEq)]
+pub struct ChangingFiles {
+    pub removed: Vec<HgPathBuf>,
+    /// Destinations and sources of the copies from the first parent
+    pub copied_from_p1: Vec<(HgPathBuf, HgPathBuf)>,
+    /// Destinations and sources of the copies from the second parent
+    pub copied_from_p2: Vec<(HgPathBuf, HgPathBuf)>,
+}
+
+/// Bits of the flag of a file in `SD_FILES`
+const ACTION_MASK: u8 = 0b11100;
+const REMOVED_FLAG: u8 = 0b01100;
+const COPIED_MASK: u8 = 0b11;
+const COPIED_FROM_P1_FLAG: u8 = 0b10;
+const COPIED_FROM_P2_FLAG: u8 = 0b11;
+
+#[derive(BytesCast)]
+#[repr(C)]
+struct EntryHeader {
+    key: unaligned::U16Be,
+    length: unaligned::U32Be,
+    digest: [u8; 20],
+}
+
+/// Decode the sidedata of a revision, checking the digest of each value
+pub fn parse(bytes: &[u8]) -> Result<Sidedata, HgError> {
+    let parse_error = |_| HgError::corrupted("sidedata parse error");
+    let (count, rest) =
+        unaligned::U16Be::from_bytes(bytes).map_err(parse_error)?;
+    let (headers, mut values) =
+        EntryHeader::slice_from_bytes(rest, count.get() as usize)
+            .map_err(parse_error)?;
+    let mut sidedata = Sidedata::new();
+    for header in headers {
+        let key = header.key.get();
+        let length = header.length.get() as usize;
+        if values.len() < length {
+            return Err(HgError::corrupted("sidedata parse error"));
+        }
+        let (value, rest) = values.split_at(length);
+        values = rest;
+        if digest(value) != header.digest {
+            return Err(HgError::corrupted(format!(
+                "sidedata '{}' hash mismatch",
+                key
+            )));
+        }
+        sidedata.insert(key, value.to_owned());
+    }
+    Ok(sidedata)
+}
+
+#[derive(BytesCast)]
+#[repr(C)]
+struct FileEntry {
+    flag: u8,
+    /// Where the path ends, from the start of all paths
+    path_end: unaligned::U32Be,
+    /// The index of the copy source among all files
+    copy_index: unaligned::U32Be,
+}
+
+/// Decode the `SD_FILES` value of the sidedata of a changeset, like
+/// `metadata.decode_files_sidedata` in Python.
+///
+/// It starts with the number of files, followed by an entry for each one,
+/// then their paths. Copy sources are files of the list too.
+pub fn decode_files(sidedata: &Sidedata) -> Result<ChangingFiles, HgError> {
+    let mut files = ChangingFiles::default();
+    let bytes = match sidedata.get(&SD_FILES) {
+        Some(bytes) => bytes,
+        None => return Ok(files),
+    };
+    let invalid = || HgError::corrupted("invalid files sidedata");
+    let (count, rest) =
+        unaligned::U32Be::from_bytes(bytes).map_err(|_| invalid())?;
+    let (entries, all_paths) =
+        FileEntry::slice_from_bytes(rest, count.get() as usize)
+            .map_err(|_| invalid())?;
+    let mut paths = Vec::with_capacity(entries.len());
+    let mut start = 0;
+    for entry in entries {
+        let end = entry.path_end.get() as usize;
+        let path = all_paths.get(start..end).ok_or_else(invalid)?;
+        paths.push(HgPathBuf::from_bytes(path));
+        start = end;
+    }
+    for (entry, path) in entries.iter().zip(&paths) {
+        if entry.flag & ACTION_MASK == REMOVED_FLAG {
+            files.removed.push(path.clone())
+        }
+        let copies = match entry.flag & COPIED_MASK {
+            COPIED_FROM_P1_FLAG => &mut files.copied_from_p1,
+            COPIED_FROM_P2_FLAG => &mut files.copied_from_p2,
+            _ => continue,
+        };
+        let source = paths
+            .get(entry.copy_index.get() as usize)
+            .ok_or_else(invalid)?;
+        copies.push((path.clone(), source.clone()))
+    }
+    Ok(files)
+}
+
+/// Encode the sidedata of a revision, like `serialize_sidedata` in Python
+pub fn serialize(sidedata: &Sidedata) -> Vec<u8> {
+    let mut bytes = (sidedata.len() as u16).to_be_bytes().to_vec();
+    for (key, value) in sidedata {
+        bytes.extend(&key.to_be_bytes());
+        bytes.extend(&(value.len() as u32).to_be_bytes());
+        bytes.extend(&digest(value));
+    }
+    for value in sidedata.values() {
+        bytes.extend(value)
+    }
+    bytes
+}
+
+fn digest(value: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.input(value);
+    let mut digest = [0; 20];
+    hasher.result(&mut digest);
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut sidedata = Sidedata::new();
+        sidedata.insert(SD_FILES, b"files".to_vec());
+        sidedata.insert(SD_P1COPIES, Vec::new());
+        sidedata.insert(SD_TEST1, b"test".to_vec());
+        let bytes = serialize(&sidedata);
+        assert_eq!(parse(&bytes).unwrap(), sidedata);
+        assert_eq!(parse(&[0, 0]).unwrap(), Sidedata::new());
+
+        // A value that does not match its digest
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() = b'x';
+        assert!(parse(&corrupted).is_err());
+        // Missing values
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse(&[0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_decode_files() {
+        // `a` removed, `b` added as a copy of `a` from the first parent
+        let mut value = vec![0, 0, 0, 2];
+        value.extend(&[0b01100, 0, 0, 0, 1, 0, 0, 0, 0]);
+        value.extend(&[0b00110, 0, 0, 0, 2, 0, 0, 0, 0]);
+        value.extend(b"ab");
+        let mut sidedata = Sidedata::new();
+        sidedata.insert(SD_FILES, value.clone());
+        let path = |path: &[u8]| HgPathBuf::from_bytes(path);
+        assert_eq!(
+            decode_files(&sidedata).unwrap(),
+            ChangingFiles {
+                removed: vec![path(b"a")],
+                copied_from_p1: vec![(path(b"b"), path(b"a"))],
+                copied_from_p2: vec![],
+            }
+        );
+        assert_eq!(
+            decode_files(&Sidedata::new()).unwrap(),
+            ChangingFiles::default()
+        );
+
+        // A path past the end
+        value.pop();
+        sidedata.insert(SD_FILES, value);
+        assert!(decode_files(&sidedata).is_err());
+    }
+}
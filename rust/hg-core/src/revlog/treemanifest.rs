@@ -0,0 +1,150 @@
+//! Tree manifests, which store the manifest of each directory in its own
+//! revlog, like `treemanifest` in Python.
+//!
+//! The manifest of a directory lists its files like a flat manifest, and its
+//! subdirectories with their manifest node ID and the `t` flag. Their
+//! revlogs are `meta/<directory>/00manifest.i` in the store.
+
+use crate::errors::HgError;
+use crate::matchers::{Matcher, VisitChildrenSet};
+use crate::repo::Repo;
+use crate::revlog::manifest::{split_path, NODE_HEX_LENGTH};
+use crate::revlog::path_encode::path_encode;
+use crate::revlog::revlog::{Revlog, RevlogError};
+use crate::revlog::Node;
+use crate::utils::files::get_path_from_bytes;
+use crate::utils::hg_path::HgPath;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The flag of subdirectories in the manifest of a directory
+const DIRECTORY_FLAG: &[u8] = b"t";
+
+/// The revlogs of the directories of a tree manifest, opened when first
+/// needed
+pub(crate) struct DirectoryManifests<'repo> {
+    repo: &'repo Repo,
+    /// By directory path, with a trailing slash
+    revlogs: RefCell<HashMap<Vec<u8>, Rc<Revlog>>>,
+}
+
+impl<'repo> DirectoryManifests<'repo> {
+    pub(crate) fn new(repo: &'repo Repo) -> Self {
+        Self {
+            repo,
+            revlogs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The text of the flat manifest for the manifest of the root directory
+    /// given by `root_text`, with the files of the directories that
+    /// `matcher` visits.
+    ///
+    /// The manifests of other directories are not read.
+    pub(crate) fn flatten(
+        &self,
+        root_text: &[u8],
+        matcher: &dyn Matcher,
+    ) -> Result<Vec<u8>, RevlogError> {
+        let mut flat = Vec::new();
+        let visit = matcher.visit_children_set(HgPath::new(b""));
+        self.flatten_directory(b"", root_text, visit, matcher, &mut flat)?;
+        Ok(flat)
+    }
+
+    /// Add the files of a directory to `flat`, `dir` being empty or ending
+    /// with a slash.
+    fn flatten_directory(
+        &self,
+        dir: &[u8],
+        text: &[u8],
+        visit: VisitChildrenSet,
+        matcher: &dyn Matcher,
+        flat: &mut Vec<u8>,
+    ) -> Result<(), RevlogError> {
+        if visit == VisitChildrenSet::Empty {
+            return Ok(());
+        }
+        // Subdirectories are sorted by name without their trailing slash,
+        // but flat manifests sort them with it
+        let mut entries = Vec::new();
+        for line in text.split(|b| b == &b'\n').filter(|l| !l.is_empty()) {
+            let (name, rest) = split_path(line)?;
+            if rest.len() < NODE_HEX_LENGTH {
+                return Err(HgError::corrupted(format!(
+                    "manifest line for {} without a full node ID",
+                    name
+                ))
+                .into());
+            }
+            let is_directory = &rest[NODE_HEX_LENGTH..] == DIRECTORY_FLAG;
+            let key = if is_directory {
+                [name.as_bytes(), b"/"].concat()
+            } else {
+                name.as_bytes().to_vec()
+            };
+            entries.push((key, name, rest, is_directory))
+        }
+        entries.sort_by(|(key1, ..), (key2, ..)| key1.cmp(key2));
+
+        for (key, name, rest, is_directory) in entries {
+            if !is_directory {
+                flat.extend_from_slice(dir);
+                flat.extend_from_slice(key.as_slice());
+                flat.push(b'\0');
+                flat.extend_from_slice(rest);
+                flat.push(b'\n');
+                continue;
+            }
+            // Like in `dirstate::status`, `This` does not exclude
+            // subdirectories
+            let child_visit = match &visit {
+                VisitChildrenSet::Recursive => VisitChildrenSet::Recursive,
+                VisitChildrenSet::Set(children)
+                    if !children.contains(name) =>
+                {
+                    continue
+                }
+                _ => {
+                    let child = &[dir, name.as_bytes()].concat();
+                    matcher.visit_children_set(HgPath::new(child))
+                }
+            };
+            if child_visit == VisitChildrenSet::Empty {
+                continue;
+            }
+            let child_dir = [dir, key.as_slice()].concat();
+            let node = Node::from_hex_for_repo(&rest[..NODE_HEX_LENGTH])?;
+            let revlog = self.revlog(&child_dir)?;
+            let child_text =
+                revlog.get_rev_data(revlog.get_node_rev(node.into())?)?;
+            self.flatten_directory(
+                &child_dir,
+                &child_text,
+                child_visit,
+                matcher,
+                flat,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The revlog of a directory, given with a trailing slash
+    fn revlog(&self, dir: &[u8]) -> Result<Rc<Revlog>, RevlogError> {
+        if let Some(revlog) = self.revlogs.borrow().get(dir) {
+            return Ok(Rc::clone(revlog));
+        }
+        let index_path =
+            path_encode(&[b"meta/", dir, b"00manifest.i"].concat());
+        let revlog = Rc::new(Revlog::open(
+            self.repo,
+            get_path_from_bytes(&index_path),
+            None,
+        )?);
+        self.revlogs
+            .borrow_mut()
+            .insert(dir.to_vec(), Rc::clone(&revlog));
+        Ok(revlog)
+    }
+}
@@ -2,55 +2,201 @@
 //!
 //! <https://www.mercurial-scm.org/repo/hg/help/revsets>
 
+mod evaluate;
+mod parser;
+
 use crate::errors::HgError;
+use crate::namespaces::Namespaces;
+use crate::obsolete;
 use crate::repo::Repo;
 use crate::revlog::changelog::Changelog;
+use crate::revlog::node::NULL_NODE;
 use crate::revlog::revlog::{Revlog, RevlogError};
 use crate::revlog::NodePrefix;
-use crate::revlog::{Revision, NULL_REVISION, WORKING_DIRECTORY_HEX};
+use crate::revlog::{
+    Revision, NULL_REVISION, WORKING_DIRECTORY_HEX, WORKING_DIRECTORY_REVISION,
+};
 use crate::Node;
+use evaluate::Evaluator;
 
 /// Resolve a query string into a single revision.
 ///
-/// Only some of the revset language is implemented yet.
+/// When the query evaluates to several revisions, the last one is used, like
+/// `scmutil.revsingle` does in Python.
 pub fn resolve_single(
     input: &str,
     repo: &Repo,
 ) -> Result<Revision, RevlogError> {
     let changelog = Changelog::open(repo)?;
-
-    match resolve_rev_number_or_hex_prefix(input, &changelog.revlog) {
-        Err(RevlogError::InvalidRevision) => {} // Try other syntax
-        result => return result,
+    let revs = evaluate(input, repo, &changelog)?;
+    match revs.last() {
+        Some(&rev) => Ok(rev),
+        None => Err(HgError::abort("abort: empty revision set").into()),
     }
+}
 
-    if input == "null" {
-        return Ok(NULL_REVISION);
-    }
+/// Resolve a query string into a list of revisions, in the order defined by
+/// the query.
+///
+/// Functions of the revset language that are not implemented yet return
+/// `HgError::UnsupportedFeature`.
+pub fn resolve(
+    input: &str,
+    repo: &Repo,
+) -> Result<Vec<Revision>, RevlogError> {
+    let changelog = Changelog::open(repo)?;
+    evaluate(input, repo, &changelog)
+}
 
-    // TODO: support for the rest of the language here.
+/// Whether a query string is a range such as `1:3` or `:tip` at its top
+/// level, but not `(1:3)`, like `scmutil._pairspec` in Python.
+///
+/// `hg diff -r` compares the two ends of such a range even when they are
+/// the same revision.
+pub fn is_range(input: &str) -> Result<bool, RevlogError> {
+    Ok(parser::is_range(input)?)
+}
+
+fn evaluate(
+    input: &str,
+    repo: &Repo,
+    changelog: &Changelog,
+) -> Result<Vec<Revision>, RevlogError> {
+    let namespaces = Namespaces::new(repo, changelog);
+    let resolve_symbol =
+        |symbol: &str| resolve_symbol(symbol, repo, changelog, &namespaces);
+    let lookup = |symbol: &str| resolve_symbol(symbol).is_ok();
+    let expr = parser::parse(input, &lookup)?;
+    let working_directory_parents = || {
+        let parents = repo.dirstate_parents()?;
+        let rev = |node: Node| {
+            if node == NULL_NODE {
+                Ok(NULL_REVISION)
+            } else {
+                changelog.revlog.get_node_rev(node.into())
+            }
+        };
+        Ok([rev(parents.p1)?, rev(parents.p2)?])
+    };
+    let evaluator =
+        Evaluator::new(changelog, &resolve_symbol, &working_directory_parents);
+    let revs = evaluator.evaluate(&expr, &evaluator.full_set())?.into_vec();
+    check_not_obsolete(repo, changelog, &revs)?;
+    Ok(revs)
+}
+
+/// Obsolete changesets may be hidden, which is not supported yet: fail when
+/// some are resolved rather than give results that Python would not.
+fn check_not_obsolete(
+    repo: &Repo,
+    changelog: &Changelog,
+    revs: &[Revision],
+) -> Result<(), RevlogError> {
+    let obsstore = repo.obsstore()?;
+    if obsstore.is_empty() {
+        return Ok(());
+    }
+    let phases = repo.phases(changelog)?;
+    for &rev in revs {
+        if rev != NULL_REVISION
+            && obsolete::is_obsolete(&obsstore, changelog, &phases, rev)?
+        {
+            return Err(HgError::unsupported("hidden changesets").into());
+        }
+    }
+    Ok(())
+}
 
-    Err(
-        HgError::unsupported(format!("cannot parse revset '{}'", input))
-            .into(),
-    )
+/// Resolve a single identifier found in a revset expression, like
+/// `scmutil.revsymbol` in Python.
+///
+/// In order of precedence, this is:
+///
+/// * `.` for the first parent of the working directory, `tip`, or `null`
+/// * A revision number, negative ones counting from the end
+/// * A full hexadecimal node ID
+/// * A bookmark, tag, or branch name
+/// * An hexadecimal node ID prefix, which must be unambiguous
+pub(crate) fn resolve_symbol(
+    symbol: &str,
+    repo: &Repo,
+    changelog: &Changelog,
+    namespaces: &Namespaces,
+) -> Result<Revision, RevlogError> {
+    let revlog = &changelog.revlog;
+    match symbol {
+        "." => {
+            let p1 = repo.dirstate_parents()?.p1;
+            if p1 == NULL_NODE {
+                return Ok(NULL_REVISION);
+            }
+            return revlog.get_node_rev(p1.into());
+        }
+        "tip" => return Ok(revlog.len() as Revision - 1),
+        "null" => return Ok(NULL_REVISION),
+        _ => {}
+    }
+    if let Some(rev) = resolve_rev_number(symbol, revlog)? {
+        return Ok(rev);
+    }
+    if symbol.len() == 40 {
+        if let Ok(node) = Node::from_hex(symbol) {
+            if let Ok(rev) = revlog.get_node_rev(node.into()) {
+                return Ok(rev);
+            }
+        }
+    }
+    if let Some(rev) = namespaces.lookup(symbol.as_bytes())? {
+        return Ok(rev);
+    }
+    resolve_hex_prefix(symbol, revlog)
 }
 
 /// Resolve the small subset of the language suitable for revlogs other than
 /// the changelog, such as in `hg debugdata --manifest` CLI argument.
 ///
-/// * A non-negative decimal integer for a revision number, or
+/// * A decimal integer for a revision number, negative ones counting from
+///   the end of the revlog, or
 /// * An hexadecimal string, for the unique node ID that starts with this
 ///   prefix
 pub fn resolve_rev_number_or_hex_prefix(
     input: &str,
     revlog: &Revlog,
 ) -> Result<Revision, RevlogError> {
+    match resolve_rev_number(input, revlog)? {
+        Some(rev) => Ok(rev),
+        None => resolve_hex_prefix(input, revlog),
+    }
+}
+
+fn resolve_rev_number(
+    input: &str,
+    revlog: &Revlog,
+) -> Result<Option<Revision>, RevlogError> {
     if let Ok(integer) = input.parse::<i32>() {
-        if integer >= 0 && revlog.has_rev(integer) {
-            return Ok(integer);
+        // Like Python, reject non-canonical forms such as `01` or `+1` that
+        // could be node ID prefixes.
+        if integer.to_string() == input {
+            if integer == WORKING_DIRECTORY_REVISION {
+                return Err(RevlogError::WDirUnsupported);
+            }
+            let rev = if integer < 0 {
+                integer + revlog.len() as Revision
+            } else {
+                integer
+            };
+            if rev >= 0 && revlog.has_rev(rev) {
+                return Ok(Some(rev));
+            }
         }
     }
+    Ok(None)
+}
+
+fn resolve_hex_prefix(
+    input: &str,
+    revlog: &Revlog,
+) -> Result<Revision, RevlogError> {
     if let Ok(prefix) = NodePrefix::from_hex(input) {
         if prefix.is_prefix_of(&Node::from_hex(WORKING_DIRECTORY_HEX).unwrap())
         {
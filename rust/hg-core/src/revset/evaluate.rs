@@ -0,0 +1,748 @@
+// evaluate.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Evaluation of revset parse trees into lists of revisions
+//!
+//! Like `getset()` in `mercurial/revset.py`, each sub-expression is evaluated
+//! against a *subset*: the revisions it is allowed to return. Operands of
+//! `and` and `-` are evaluated against the result of their left-hand side,
+//! so each step only looks at revisions that can still be part of the
+//! result. The order of the returned revisions is significant, and matches
+//! what Python hg produces for the same expression.
+
+use super::parser::{list_misuse, parse_error, Expr};
+use crate::dagops;
+use crate::errors::HgError;
+use crate::revlog::changelog::Changelog;
+use crate::revlog::revlog::RevlogError;
+use crate::utils::strip_prefix;
+use crate::{
+    AncestorsIterator, Graph, MissingAncestors, Revision, NULL_REVISION,
+};
+use std::collections::HashSet;
+
+pub(super) struct Evaluator<'a> {
+    changelog: &'a Changelog,
+    /// Resolve a symbol or string to a single revision
+    resolve_symbol: &'a dyn Fn(&str) -> Result<Revision, RevlogError>,
+    /// The parents of the working directory, null when there are none
+    working_directory_parents:
+        &'a dyn Fn() -> Result<[Revision; 2], RevlogError>,
+}
+
+/// Revisions in the order defined by an expression, like smartsets in
+/// Python.
+///
+/// Consecutive revisions, such as the whole repository, are represented by
+/// their bounds rather than collected.
+#[derive(Clone, Debug)]
+pub(super) enum Revisions {
+    /// All the revisions of a repository of the given length, in ascending
+    /// order. Like `fullreposet` in Python, this does not yield the null
+    /// revision but contains it, so that it can be selected.
+    All(Revision),
+    /// From the first to the last revision included, in descending order
+    /// if the first one is greater.
+    Span(Revision, Revision),
+    List(Vec<Revision>),
+}
+
+impl Revisions {
+    fn empty() -> Self {
+        Revisions::List(Vec::new())
+    }
+
+    pub(super) fn iter(&self) -> Box<dyn Iterator<Item = Revision> + '_> {
+        match *self {
+            Revisions::All(len) => Box::new(0..len),
+            Revisions::Span(first, last) if first <= last => {
+                Box::new(first..=last)
+            }
+            Revisions::Span(first, last) => Box::new((last..=first).rev()),
+            Revisions::List(ref revs) => Box::new(revs.iter().copied()),
+        }
+    }
+
+    pub(super) fn into_vec(self) -> Vec<Revision> {
+        match self {
+            Revisions::List(revs) => revs,
+            revs => revs.iter().collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match *self {
+            Revisions::All(len) => len as usize,
+            Revisions::Span(first, last) => {
+                (first.max(last) - first.min(last)) as usize + 1
+            }
+            Revisions::List(ref revs) => revs.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn first(&self) -> Option<Revision> {
+        self.iter().next()
+    }
+
+    fn last(&self) -> Option<Revision> {
+        match *self {
+            Revisions::All(len) if len > 0 => Some(len - 1),
+            Revisions::All(_) => None,
+            Revisions::Span(_, last) => Some(last),
+            Revisions::List(ref revs) => revs.last().copied(),
+        }
+    }
+
+    fn min(&self) -> Option<Revision> {
+        match *self {
+            Revisions::All(_) => Some(NULL_REVISION),
+            Revisions::Span(first, last) => Some(first.min(last)),
+            Revisions::List(ref revs) => revs.iter().copied().min(),
+        }
+    }
+
+    /// The first and last revisions yielded, when they are consecutive
+    fn bounds(&self) -> Option<(Revision, Revision)> {
+        match *self {
+            Revisions::All(len) if len > 0 => Some((0, len - 1)),
+            Revisions::Span(first, last) => Some((first, last)),
+            _ => None,
+        }
+    }
+
+    /// The lowest and highest revisions contained, when all the revisions
+    /// between them are
+    fn range(&self) -> Option<(Revision, Revision)> {
+        match *self {
+            Revisions::All(len) => Some((NULL_REVISION, len - 1)),
+            Revisions::Span(first, last) => {
+                Some((first.min(last), first.max(last)))
+            }
+            Revisions::List(_) => None,
+        }
+    }
+
+    fn contains(&self, rev: Revision) -> bool {
+        match self.range() {
+            Some((low, high)) => low <= rev && rev <= high,
+            None => self.iter().any(|r| r == rev),
+        }
+    }
+
+    /// Like `contains`, without scanning a list for each lookup
+    fn contains_fn(&self) -> impl Fn(Revision) -> bool + '_ {
+        let set: Option<HashSet<Revision>> = match self {
+            Revisions::List(revs) => Some(revs.iter().copied().collect()),
+            _ => None,
+        };
+        move |rev| match &set {
+            Some(set) => set.contains(&rev),
+            None => self.contains(rev),
+        }
+    }
+
+    /// The revisions from position `start` included to `end` excluded
+    fn slice(&self, start: usize, end: usize) -> Self {
+        let end = end.min(self.len());
+        let start = start.min(end);
+        if start == end {
+            return Self::empty();
+        }
+        let (start, end) = (start as Revision, end as Revision);
+        match self.bounds() {
+            Some((first, last)) if first <= last => {
+                Revisions::Span(first + start, first + end - 1)
+            }
+            Some((first, _)) => {
+                Revisions::Span(first - start, first - end + 1)
+            }
+            None => Revisions::List(
+                self.iter()
+                    .skip(start as usize)
+                    .take((end - start) as usize)
+                    .collect(),
+            ),
+        }
+    }
+
+    fn reverse(self) -> Self {
+        match (self.bounds(), self) {
+            (Some((first, last)), _) => Revisions::Span(last, first),
+            (None, Revisions::List(mut revs)) => {
+                revs.reverse();
+                Revisions::List(revs)
+            }
+            (None, revs) => revs,
+        }
+    }
+}
+
+/// Keep the revisions of `revs` that are also in `filter`, in the order of
+/// `revs`.
+fn intersect(revs: Revisions, filter: &Revisions) -> Revisions {
+    match (revs.bounds(), filter.range()) {
+        (Some((first, last)), Some((low, high))) => {
+            let ascending = first <= last;
+            let (first, last) = if ascending {
+                (first.max(low), last.min(high))
+            } else {
+                (first.min(high), last.max(low))
+            };
+            if (ascending && first <= last) || (!ascending && first >= last) {
+                Revisions::Span(first, last)
+            } else {
+                Revisions::empty()
+            }
+        }
+        _ => {
+            let contains = filter.contains_fn();
+            Revisions::List(revs.iter().filter(|&r| contains(r)).collect())
+        }
+    }
+}
+
+fn subtract(revs: &Revisions, removed: &Revisions) -> Revisions {
+    let contains = removed.contains_fn();
+    Revisions::List(revs.iter().filter(|&r| !contains(r)).collect())
+}
+
+fn check_arguments<'e>(
+    name: &str,
+    arguments: &'e [Expr],
+    min: usize,
+    max: usize,
+) -> Result<&'e [Expr], HgError> {
+    if arguments.len() < min || arguments.len() > max {
+        let expected = match (min, max) {
+            (0, 0) => "no arguments".to_owned(),
+            (1, 1) => "one argument".to_owned(),
+            (1, 2) => "one or two arguments".to_owned(),
+            (1, 3) => "one to three arguments".to_owned(),
+            (min, max) => format!("{} to {} arguments", min, max),
+        };
+        let verb = if max == 0 { "takes" } else { "requires" };
+        return Err(parse_error(format!("{} {} {}", name, verb, expected)));
+    }
+    Ok(arguments)
+}
+
+fn get_string<'e>(expr: &'e Expr, error: &str) -> Result<&'e str, HgError> {
+    match expr {
+        Expr::Symbol(s) | Expr::String(s) => Ok(s),
+        _ => Err(parse_error(error)),
+    }
+}
+
+fn get_integer(expr: &Expr, error: &str) -> Result<i64, HgError> {
+    get_string(expr, error)?
+        .parse()
+        .map_err(|_| parse_error(error))
+}
+
+/// Parse the optional count argument of `first()`, `last()` and `limit()`
+fn get_count(
+    expr: Option<&Expr>,
+    error: &str,
+    negative_error: &str,
+) -> Result<usize, HgError> {
+    match expr {
+        None => Ok(1),
+        Some(expr) => {
+            let n = get_integer(expr, error)?;
+            if n < 0 {
+                Err(parse_error(negative_error))
+            } else {
+                Ok(n as usize)
+            }
+        }
+    }
+}
+
+impl<'a> Evaluator<'a> {
+    pub(super) fn new(
+        changelog: &'a Changelog,
+        resolve_symbol: &'a dyn Fn(&str) -> Result<Revision, RevlogError>,
+        working_directory_parents: &'a dyn Fn() -> Result<
+            [Revision; 2],
+            RevlogError,
+        >,
+    ) -> Self {
+        Self {
+            changelog,
+            resolve_symbol,
+            working_directory_parents,
+        }
+    }
+
+    fn len(&self) -> Revision {
+        self.changelog.revlog.len() as Revision
+    }
+
+    /// All revisions of the repository, in ascending order
+    pub(super) fn full_set(&self) -> Revisions {
+        Revisions::All(self.len())
+    }
+
+    /// Evaluate `expr`, keeping only revisions that are in `subset`.
+    pub(super) fn evaluate(
+        &self,
+        expr: &Expr,
+        subset: &Revisions,
+    ) -> Result<Revisions, RevlogError> {
+        match expr {
+            Expr::Symbol(symbol) | Expr::String(symbol) => {
+                let rev = (self.resolve_symbol)(symbol)?;
+                if subset.contains(rev) {
+                    Ok(Revisions::List(vec![rev]))
+                } else {
+                    Ok(Revisions::empty())
+                }
+            }
+            Expr::Not(operand) => {
+                Ok(subtract(subset, &self.evaluate(operand, subset)?))
+            }
+            Expr::And(left, right) => {
+                let left = self.evaluate(left, subset)?;
+                let right = self.evaluate(right, &left)?;
+                Ok(intersect(left, &right))
+            }
+            Expr::Minus(left, right) => {
+                let left = self.evaluate(left, subset)?;
+                let right = self.evaluate(right, &left)?;
+                Ok(subtract(&left, &right))
+            }
+            Expr::Or(operands) => {
+                let mut seen = HashSet::new();
+                let mut result = Vec::new();
+                for operand in operands {
+                    for rev in self.evaluate(operand, subset)?.iter() {
+                        if seen.insert(rev) {
+                            result.push(rev)
+                        }
+                    }
+                }
+                Ok(Revisions::List(result))
+            }
+            Expr::Only(include, exclude) => {
+                let include = self.evaluate_full(include)?;
+                let exclude = match exclude {
+                    Some(exclude) => Some(self.evaluate_full(exclude)?),
+                    None => None,
+                };
+                self.only(subset, include, exclude)
+            }
+            Expr::DagRange(None, None) => {
+                Err(parse_error("can't use '::' in this context").into())
+            }
+            Expr::DagRange(None, Some(heads)) => {
+                let heads = self.evaluate_full(heads)?;
+                self.ancestors(subset, &heads)
+            }
+            Expr::DagRange(Some(roots), None) => {
+                let roots = self.evaluate_full(roots)?;
+                self.descendants(subset, &roots)
+            }
+            Expr::DagRange(Some(roots), Some(heads)) => {
+                let roots = self.evaluate_full(roots)?;
+                let heads = self.evaluate_full(heads)?;
+                let mut range: HashSet<Revision> = dagops::range(
+                    &self.changelog,
+                    roots.iter(),
+                    heads.iter(),
+                )?
+                .into_iter()
+                .collect();
+                if roots.contains(NULL_REVISION) {
+                    // All revisions descend from the null revision, which
+                    // `dagops::range` does not walk to.
+                    range.extend(self.ancestors_set(&heads, NULL_REVISION)?);
+                    range.insert(NULL_REVISION);
+                }
+                Ok(self.filter_subset(subset, &range))
+            }
+            Expr::Range(start, end) => {
+                let start = match start {
+                    None => 0,
+                    Some(start) => match self.evaluate_full(start)?.first() {
+                        Some(rev) => rev,
+                        None => return Ok(Revisions::empty()),
+                    },
+                };
+                let end = match end {
+                    None => self.len() - 1,
+                    Some(end) => match self.evaluate_full(end)?.last() {
+                        Some(rev) => rev,
+                        None => return Ok(Revisions::empty()),
+                    },
+                };
+                Ok(intersect(Revisions::Span(start, end), subset))
+            }
+            Expr::Parent(operand, n) => {
+                let n = match n {
+                    None => None,
+                    Some(n) => match get_integer(n, "") {
+                        Ok(n @ 0..=2) => Some(n),
+                        _ => {
+                            return Err(parse_error(
+                                "^ expects a number 0, 1, or 2",
+                            )
+                            .into())
+                        }
+                    },
+                };
+                let mut parents = HashSet::new();
+                for rev in self.evaluate_full(operand)?.iter() {
+                    let [p1, p2] = self.changelog.parents(rev)?;
+                    match n {
+                        Some(0) => parents.insert(rev),
+                        None | Some(1) => parents.insert(p1),
+                        _ => p2 != NULL_REVISION && parents.insert(p2),
+                    };
+                }
+                if n.is_none() {
+                    // Like `p1()`, unlike `^1`
+                    parents.remove(&NULL_REVISION);
+                }
+                Ok(self.filter_subset(subset, &parents))
+            }
+            Expr::Ancestor(operand, n) => {
+                let n = get_integer(n, "~ expects a number")?;
+                if n < 0 {
+                    return Err(HgError::unsupported(
+                        "revset children lookup with negative ~",
+                    )
+                    .into());
+                }
+                let mut ancestors = HashSet::new();
+                for mut rev in self.evaluate_full(operand)?.iter() {
+                    for _ in 0..n {
+                        if rev == NULL_REVISION {
+                            break;
+                        }
+                        rev = self.changelog.parents(rev)?[0];
+                    }
+                    ancestors.insert(rev);
+                }
+                Ok(self.filter_subset(subset, &ancestors))
+            }
+            Expr::Func(name, arguments) => {
+                self.function(name, arguments, subset)
+            }
+        }
+    }
+
+    /// Evaluate `expr` against all the revisions of the repository
+    fn evaluate_full(&self, expr: &Expr) -> Result<Revisions, RevlogError> {
+        self.evaluate(expr, &self.full_set())
+    }
+
+    /// Keep the revisions of `subset` that are in `revs`, in subset order
+    fn filter_subset(
+        &self,
+        subset: &Revisions,
+        revs: &HashSet<Revision>,
+    ) -> Revisions {
+        if let Revisions::All(_) = subset {
+            // Unlike iterating over `subset`, this keeps the null revision
+            let mut revs: Vec<Revision> = revs
+                .iter()
+                .copied()
+                .filter(|&r| subset.contains(r))
+                .collect();
+            revs.sort();
+            return Revisions::List(revs);
+        }
+        Revisions::List(subset.iter().filter(|r| revs.contains(r)).collect())
+    }
+
+    fn ancestors_set(
+        &self,
+        heads: &Revisions,
+        stop_rev: Revision,
+    ) -> Result<HashSet<Revision>, RevlogError> {
+        let mut ancestors = AncestorsIterator::new(
+            self.changelog,
+            heads.iter(),
+            stop_rev,
+            true,
+        )?
+        .collect::<Result<HashSet<_>, _>>()?;
+        // The null revision is the parent of root changesets but not their
+        // ancestor, only its own
+        if !heads.contains(NULL_REVISION) {
+            ancestors.remove(&NULL_REVISION);
+        }
+        Ok(ancestors)
+    }
+
+    fn ancestors(
+        &self,
+        subset: &Revisions,
+        heads: &Revisions,
+    ) -> Result<Revisions, RevlogError> {
+        // No need to walk the graph below the smallest revision we might
+        // return.
+        let stop_rev = subset.min().unwrap_or(NULL_REVISION);
+        let ancestors = self.ancestors_set(heads, stop_rev)?;
+        Ok(self.filter_subset(subset, &ancestors))
+    }
+
+    fn descendants_set(
+        &self,
+        roots: &Revisions,
+    ) -> Result<HashSet<Revision>, RevlogError> {
+        let mut descendants: HashSet<Revision> = roots.iter().collect();
+        if let Some(min_root) = roots.min() {
+            for rev in (min_root + 1).max(0)..self.len() {
+                let [p1, p2] = self.changelog.parents(rev)?;
+                if descendants.contains(&p1) || descendants.contains(&p2) {
+                    descendants.insert(rev);
+                }
+            }
+        }
+        Ok(descendants)
+    }
+
+    fn descendants(
+        &self,
+        subset: &Revisions,
+        roots: &Revisions,
+    ) -> Result<Revisions, RevlogError> {
+        let descendants = self.descendants_set(roots)?;
+        Ok(self.filter_subset(subset, &descendants))
+    }
+
+    /// `only(include, exclude)`: ancestors of `include` that are not
+    /// ancestors of `exclude`.
+    ///
+    /// Without `exclude`, this excludes all repository heads that are not
+    /// descendants of `include`.
+    fn only(
+        &self,
+        subset: &Revisions,
+        include: Revisions,
+        exclude: Option<Revisions>,
+    ) -> Result<Revisions, RevlogError> {
+        let exclude = match exclude {
+            Some(exclude) => exclude,
+            None => {
+                if include.is_empty() {
+                    return Ok(Revisions::empty());
+                }
+                let descendants = self.descendants_set(&include)?;
+                Revisions::List(
+                    self.heads(&self.full_set())?
+                        .iter()
+                        .filter(|r| !descendants.contains(r))
+                        .collect(),
+                )
+            }
+        };
+        let missing = MissingAncestors::new(self.changelog, exclude.iter())
+            .missing_ancestors(include.iter())?;
+        Ok(self.filter_subset(subset, &missing.into_iter().collect()))
+    }
+
+    /// Revisions of `revs` that have no children in `revs`, in the order of
+    /// `revs`
+    fn heads(&self, revs: &Revisions) -> Result<Revisions, RevlogError> {
+        let revs = revs.iter().collect::<Vec<_>>();
+        let heads = dagops::heads(&self.changelog, revs.iter())?;
+        Ok(Revisions::List(
+            revs.into_iter().filter(|r| heads.contains(r)).collect(),
+        ))
+    }
+
+    fn function(
+        &self,
+        name: &str,
+        arguments: &[Expr],
+        subset: &Revisions,
+    ) -> Result<Revisions, RevlogError> {
+        match name {
+            "all" => {
+                check_arguments(name, arguments, 0, 0)?;
+                if self.len() == 0 {
+                    return Ok(Revisions::empty());
+                }
+                // Unlike the full set, this does not contain `null`
+                let all = Revisions::Span(0, self.len() - 1);
+                Ok(intersect(subset.clone(), &all))
+            }
+            "none" => {
+                check_arguments(name, arguments, 0, 0)?;
+                Ok(Revisions::empty())
+            }
+            "ancestors" => {
+                let arguments = check_arguments(name, arguments, 1, 1)?;
+                let heads = self.evaluate_full(&arguments[0])?;
+                self.ancestors(subset, &heads)
+            }
+            "descendants" => {
+                let arguments = check_arguments(name, arguments, 1, 1)?;
+                let roots = self.evaluate_full(&arguments[0])?;
+                self.descendants(subset, &roots)
+            }
+            "heads" => {
+                let arguments = check_arguments(name, arguments, 1, 1)?;
+                let revs = self.evaluate(&arguments[0], subset)?;
+                self.heads(&revs)
+            }
+            "roots" => {
+                let arguments = check_arguments(name, arguments, 1, 1)?;
+                let revs: HashSet<Revision> =
+                    self.evaluate_full(&arguments[0])?.iter().collect();
+                let roots = dagops::roots(&self.changelog, &revs)?;
+                Ok(self.filter_subset(subset, &roots.into_iter().collect()))
+            }
+            "only" => {
+                let arguments = check_arguments(name, arguments, 1, 2)?;
+                let include = self.evaluate_full(&arguments[0])?;
+                let exclude = match arguments.get(1) {
+                    Some(exclude) => Some(self.evaluate_full(exclude)?),
+                    None => None,
+                };
+                self.only(subset, include, exclude)
+            }
+            "parents" | "p1" | "p2" => {
+                // Like Python, take a single revset rather than arguments
+                if arguments.len() > 1 {
+                    return Err(list_misuse().into());
+                }
+                // Without argument, the parents of the working directory
+                let all_parents = match arguments.first() {
+                    Some(argument) => self
+                        .evaluate_full(argument)?
+                        .iter()
+                        .map(|rev| self.changelog.parents(rev))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None => vec![(self.working_directory_parents)()?],
+                };
+                let mut parents = HashSet::new();
+                for [p1, p2] in all_parents {
+                    if name != "p2" {
+                        parents.insert(p1);
+                    }
+                    if name != "p1" {
+                        parents.insert(p2);
+                    }
+                }
+                parents.remove(&NULL_REVISION);
+                Ok(self.filter_subset(subset, &parents))
+            }
+            "children" => {
+                let arguments = check_arguments(name, arguments, 1, 1)?;
+                let parents = self.evaluate_full(&arguments[0])?;
+                let mut children = HashSet::new();
+                if let Some(min_parent) = parents.min() {
+                    let parents: HashSet<Revision> = parents.iter().collect();
+                    for rev in (min_parent + 1).max(0)..self.len() {
+                        let [p1, p2] = self.changelog.parents(rev)?;
+                        if parents.contains(&p1)
+                            || (p2 != NULL_REVISION && parents.contains(&p2))
+                        {
+                            children.insert(rev);
+                        }
+                    }
+                }
+                Ok(self.filter_subset(subset, &children))
+            }
+            "first" | "last" => {
+                let arguments = check_arguments(name, arguments, 1, 2)?;
+                let count = get_count(
+                    arguments.get(1),
+                    &format!("{} expects a number", name),
+                    "negative number to select",
+                )?;
+                let revs = self.evaluate_full(&arguments[0])?;
+                let selected = if name == "first" {
+                    revs.slice(0, count)
+                } else {
+                    revs.slice(revs.len().saturating_sub(count), revs.len())
+                };
+                Ok(intersect(selected, subset))
+            }
+            "limit" => {
+                let arguments = check_arguments(name, arguments, 1, 3)?;
+                let count = get_count(
+                    arguments.get(1),
+                    "limit expects a number",
+                    "negative number to select",
+                )?;
+                let offset = match arguments.get(2) {
+                    None => 0,
+                    Some(offset) => {
+                        let offset =
+                            get_integer(offset, "limit expects a number")?;
+                        if offset < 0 {
+                            return Err(parse_error("negative offset").into());
+                        }
+                        offset as usize
+                    }
+                };
+                let revs = self.evaluate_full(&arguments[0])?;
+                let selected =
+                    revs.slice(offset, offset.saturating_add(count));
+                Ok(intersect(selected, subset))
+            }
+            "reverse" => {
+                let arguments = check_arguments(name, arguments, 1, 1)?;
+                Ok(self.evaluate(&arguments[0], subset)?.reverse())
+            }
+            "sort" => {
+                let arguments = check_arguments(name, arguments, 1, 2)?;
+                let keys = match arguments.get(1) {
+                    Some(keys) => {
+                        get_string(keys, "sort spec must be a string")?
+                    }
+                    None => "rev",
+                };
+                let mut revs =
+                    self.evaluate(&arguments[0], subset)?.into_vec();
+                // Sort by the last key first: the sort is stable so earlier
+                // keys take precedence.
+                for key in keys.split_whitespace().rev() {
+                    let (reverse, key) = match strip_prefix(key, "-") {
+                        Some(key) => (true, key),
+                        None => (false, key),
+                    };
+                    let node = |rev| {
+                        self.changelog
+                            .node_from_rev(rev)
+                            .map(|node| node.as_bytes())
+                    };
+                    let compare = |a: &Revision, b: &Revision| match key {
+                        "node" => node(*a).cmp(&node(*b)),
+                        _ => a.cmp(b),
+                    };
+                    if key != "rev" && key != "node" {
+                        return Err(HgError::unsupported(format!(
+                            "revset sort key '{}'",
+                            key
+                        ))
+                        .into());
+                    }
+                    if reverse {
+                        revs.sort_by(|a, b| compare(b, a))
+                    } else {
+                        revs.sort_by(compare)
+                    }
+                }
+                Ok(Revisions::List(revs))
+            }
+            _ => Err(HgError::unsupported(format!(
+                "revset function '{}'",
+                name
+            ))
+            .into()),
+        }
+    }
+}
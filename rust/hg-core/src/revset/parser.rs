@@ -0,0 +1,778 @@
+// parser.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tokenizer and parser for the revset language
+//!
+//! This follows `mercurial/revsetlang.py` and the generic Pratt parser of
+//! `mercurial/parser.py`: each token has a binding strength, and may act as
+//! a primary expression, a prefix operator, an infix operator and/or a
+//! suffix operator.
+
+use crate::errors::HgError;
+use std::fmt;
+
+/// A revset error found without knowing where in the expression
+pub(super) fn parse_error(message: impl fmt::Display) -> HgError {
+    HgError::input_error(format!("hg: parse error: {}", message))
+}
+
+/// A revset syntax error at a byte position of `program`, with a hint
+/// pointing at it like `revsetlang.parse` in Python
+fn syntax_error(
+    program: &str,
+    message: impl fmt::Display,
+    position: usize,
+) -> HgError {
+    HgError::input_error(format!(
+        "hg: parse error at {}: {}\n({}\n{}^ here)",
+        position,
+        message,
+        program.replace('\n', " "),
+        " ".repeat(position + 1)
+    ))
+}
+
+/// Parse tree of a revset expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// An unquoted identifier: a revision number, node ID prefix or name
+    Symbol(String),
+    /// A quoted string
+    String(String),
+    /// `not x` or `!x`
+    Not(Box<Expr>),
+    /// `x and y` or `x & y`
+    And(Box<Expr>, Box<Expr>),
+    /// `x or y`, `x | y` or `x + y`, flattened
+    Or(Vec<Expr>),
+    /// `x - y`
+    Minus(Box<Expr>, Box<Expr>),
+    /// `x % y`, or `x%` with no right-hand side
+    Only(Box<Expr>, Option<Box<Expr>>),
+    /// `x::y`, `::y`, `x::` or `::`
+    DagRange(Option<Box<Expr>>, Option<Box<Expr>>),
+    /// `x:y`, `:y`, `x:` or `:`
+    Range(Option<Box<Expr>>, Option<Box<Expr>>),
+    /// `x^` or `x^n`
+    Parent(Box<Expr>, Option<Box<Expr>>),
+    /// `x~n`
+    Ancestor(Box<Expr>, Box<Expr>),
+    /// `name(arguments, …)`
+    Func(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    /// An operator or keyword
+    Op(&'static str),
+    Symbol(String),
+    String(String),
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+const KEYWORDS: &[&str] = &["and", "or", "not"];
+
+const SIMPLE_OPERATORS: &[&str] = &[
+    "(", ")", "[", "]", "#", ":", "=", ",", "-", "|", "&", "+", "!", "~", "^",
+    "%",
+];
+
+fn is_symbol_initial_letter(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"._@".contains(&byte) || byte >= 128
+}
+
+fn is_symbol_letter(byte: u8) -> bool {
+    is_symbol_initial_letter(byte) || b"-/".contains(&byte)
+}
+
+/// Undo Python-style backslash escapes in a quoted string
+fn unescape(input: &[u8]) -> Result<String, HgError> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut bytes = input.iter().copied();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            output.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'n') => output.push(b'\n'),
+            Some(b't') => output.push(b'\t'),
+            Some(b'r') => output.push(b'\r'),
+            Some(b'0') => output.push(b'\0'),
+            Some(b'x') => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                let value = std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| parse_error("invalid \\x escape"))?;
+                output.push(value)
+            }
+            Some(other) if b"\\'\"".contains(&other) => output.push(other),
+            Some(other) => output.extend(&[b'\\', other]),
+            None => output.push(b'\\'),
+        }
+    }
+    String::from_utf8(output)
+        .map_err(|_| parse_error("invalid UTF-8 in string"))
+}
+
+/// Split a revset expression into tokens.
+///
+/// `lookup` tells whether a string is a known symbol. It is used to decide
+/// whether `foo-bar` is one symbol or a subtraction, and whether `x:y` is an
+/// old-style range of two symbols that contain operator characters.
+fn tokenize(
+    program: &str,
+    lookup: &dyn Fn(&str) -> bool,
+) -> Result<Vec<Token>, HgError> {
+    let mut tokens = Vec::new();
+    let symbol = |value: &str, position| Token {
+        kind: TokenKind::Symbol(value.to_owned()),
+        position,
+    };
+    let op = |value: &'static str, position| Token {
+        kind: TokenKind::Op(value),
+        position,
+    };
+
+    if !program.is_empty() {
+        // Attempt to parse old-style ranges first, to deal with things like
+        // tags which contain query metacharacters.
+        let mut parts = program.splitn(2, ':');
+        let first = parts.next().unwrap_or("");
+        let second = parts.next();
+        if std::iter::once(first)
+            .chain(second)
+            .filter(|part| !part.is_empty())
+            .all(lookup)
+        {
+            if !first.is_empty() {
+                tokens.push(symbol(first, 0))
+            }
+            if let Some(second) = second {
+                tokens.push(op(":", first.len()));
+                if !second.is_empty() {
+                    tokens.push(symbol(second, first.len() + 1))
+                }
+            }
+            tokens.push(Token {
+                kind: TokenKind::End,
+                position: program.len(),
+            });
+            return Ok(tokens);
+        }
+    }
+
+    let bytes = program.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let next_two = &bytes[pos..(pos + 2).min(bytes.len())];
+        if c.is_ascii_whitespace() {
+            // Skip inter-token whitespace
+        } else if next_two == b"::" || next_two == b".." || next_two == b"##" {
+            let value = match next_two {
+                b"::" => "::",
+                b".." => "..",
+                _ => "##",
+            };
+            tokens.push(op(value, pos));
+            pos += 1;
+        } else if let Some(value) =
+            SIMPLE_OPERATORS.iter().find(|op| op.as_bytes() == [c])
+        {
+            tokens.push(op(value, pos))
+        } else if c == b'"'
+            || c == b'\''
+            || next_two == b"r'"
+            || next_two == b"r\""
+        {
+            let raw = c == b'r';
+            if raw {
+                pos += 1;
+            }
+            let quote = bytes[pos];
+            pos += 1;
+            let start = pos;
+            loop {
+                match bytes.get(pos) {
+                    None => {
+                        return Err(syntax_error(
+                            program,
+                            "unterminated string",
+                            start,
+                        ))
+                    }
+                    // Skip over escaped characters
+                    Some(b'\\') => pos += 2,
+                    Some(&d) if d == quote => break,
+                    Some(_) => pos += 1,
+                }
+            }
+            let value = if raw {
+                program[start..pos].to_owned()
+            } else {
+                unescape(&bytes[start..pos])?
+            };
+            tokens.push(Token {
+                kind: TokenKind::String(value),
+                position: start,
+            })
+        } else if is_symbol_initial_letter(c) {
+            let start = pos;
+            pos += 1;
+            while pos < bytes.len() {
+                let d = bytes[pos];
+                if !is_symbol_letter(d) {
+                    break;
+                }
+                if d == b'.' && bytes[pos - 1] == b'.' {
+                    // Special case for `..`
+                    pos -= 1;
+                    break;
+                }
+                pos += 1;
+            }
+            let sym = &program[start..pos];
+            if let Some(keyword) = KEYWORDS.iter().find(|k| **k == sym) {
+                tokens.push(op(keyword, start))
+            } else if sym.contains('-') && !lookup(sym) {
+                // Looks like an expression such as `foo-bar`
+                let mut part_start = start;
+                let parts: Vec<&str> = sym.split('-').collect();
+                let (last, init) = parts.split_last().unwrap();
+                for part in init {
+                    // Possible consecutive `-`
+                    if !part.is_empty() {
+                        tokens.push(symbol(part, part_start))
+                    }
+                    part_start += part.len();
+                    tokens.push(op("-", part_start));
+                    part_start += 1;
+                }
+                // Possible trailing `-`
+                if !last.is_empty() {
+                    tokens.push(symbol(last, part_start))
+                }
+            } else {
+                tokens.push(symbol(sym, start))
+            }
+            pos -= 1;
+        } else {
+            return Err(syntax_error(
+                program,
+                format!("syntax error in revset '{}'", program),
+                pos,
+            ));
+        }
+        pos += 1;
+    }
+    tokens.push(Token {
+        kind: TokenKind::End,
+        position: pos,
+    });
+    Ok(tokens)
+}
+
+/// How an operator token can be used, and its binding strengths.
+///
+/// Mirrors the `elements` table of `mercurial/revsetlang.py`.
+fn binding_strength(kind: &TokenKind) -> u8 {
+    match kind {
+        TokenKind::Op(op) => match *op {
+            "(" | "[" | "#" => 21,
+            "##" => 20,
+            "~" | "^" => 18,
+            "::" | ".." => 17,
+            ":" => 15,
+            "not" | "!" => 10,
+            "-" | "and" | "&" | "%" => 5,
+            "or" | "|" | "+" => 4,
+            "=" => 3,
+            "," => 2,
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+/// Whether a token may start a new term: it has a primary or prefix rule.
+fn starts_term(kind: &TokenKind) -> bool {
+    match kind {
+        TokenKind::Symbol(_) | TokenKind::String(_) => true,
+        TokenKind::Op(op) => {
+            ["(", "-", "::", "..", ":", "not", "!"].contains(op)
+        }
+        TokenKind::End => false,
+    }
+}
+
+fn describe(kind: &TokenKind) -> &str {
+    match kind {
+        TokenKind::Op(op) => op,
+        TokenKind::Symbol(_) => "symbol",
+        TokenKind::String(_) => "string",
+        TokenKind::End => "end",
+    }
+}
+
+/// Intermediate parse tree, before `,` lists are only allowed as function
+/// arguments.
+enum Node {
+    Expr(Expr),
+    List(Vec<Expr>),
+}
+
+struct Parser<'a> {
+    /// The whole expression, for error messages
+    program: &'a str,
+    tokens: std::vec::IntoIter<Token>,
+    current: Token,
+}
+
+impl Parser<'_> {
+    fn advance(&mut self) -> Token {
+        let next = self.tokens.next().unwrap_or(Token {
+            kind: TokenKind::End,
+            position: self.current.position,
+        });
+        std::mem::replace(&mut self.current, next)
+    }
+
+    fn expect(&mut self, closing: &'static str) -> Result<(), HgError> {
+        if self.current.kind != TokenKind::Op(closing) {
+            return Err(syntax_error(
+                self.program,
+                format!("unexpected token: {}", describe(&self.current.kind)),
+                self.current.position,
+            ));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expr(&mut self, bind: u8) -> Result<Expr, HgError> {
+        match self.node(bind)? {
+            Node::Expr(expr) => Ok(expr),
+            Node::List(_) => Err(list_misuse()),
+        }
+    }
+
+    /// Parse `( … )` contents after the opening parenthesis
+    fn group(&mut self) -> Result<Option<Node>, HgError> {
+        let node = if self.current.kind == TokenKind::Op(")") {
+            None
+        } else {
+            Some(self.node(1)?)
+        };
+        self.expect(")")?;
+        Ok(node)
+    }
+
+    fn node(&mut self, bind: u8) -> Result<Node, HgError> {
+        let Token { kind, position } = self.advance();
+        let has_new_term = starts_term(&self.current.kind);
+        let mut node = match kind {
+            TokenKind::Symbol(s) => Node::Expr(Expr::Symbol(s)),
+            TokenKind::String(s) => Node::Expr(Expr::String(s)),
+            TokenKind::Op("(") => match self.group()? {
+                Some(Node::Expr(expr)) => Node::Expr(expr),
+                Some(Node::List(_)) => return Err(list_misuse()),
+                None => return Err(parse_error("missing argument")),
+            },
+            TokenKind::Op("::") | TokenKind::Op("..") => {
+                if has_new_term {
+                    let operand = self.expr(17)?;
+                    Node::Expr(Expr::DagRange(None, Some(Box::new(operand))))
+                } else {
+                    Node::Expr(Expr::DagRange(None, None))
+                }
+            }
+            TokenKind::Op(":") => {
+                if has_new_term {
+                    let operand = self.expr(15)?;
+                    Node::Expr(Expr::Range(None, Some(Box::new(operand))))
+                } else {
+                    Node::Expr(Expr::Range(None, None))
+                }
+            }
+            TokenKind::Op("-") => {
+                let operand = self.expr(19)?;
+                Node::Expr(negate(operand)?)
+            }
+            TokenKind::Op("not") | TokenKind::Op("!") => {
+                Node::Expr(Expr::Not(Box::new(self.expr(10)?)))
+            }
+            other => {
+                return Err(syntax_error(
+                    self.program,
+                    format!("not a prefix: {}", describe(&other)),
+                    position,
+                ))
+            }
+        };
+
+        while bind < binding_strength(&self.current.kind) {
+            let Token { kind, position } = self.advance();
+            let has_new_term = starts_term(&self.current.kind);
+            let left = match node {
+                Node::Expr(expr) => expr,
+                Node::List(mut list) if kind == TokenKind::Op(",") => {
+                    list.push(self.expr(2)?);
+                    node = Node::List(list);
+                    continue;
+                }
+                Node::List(_) => return Err(list_misuse()),
+            };
+            let boxed = Box::new(left);
+            node = match kind {
+                TokenKind::Op("(") => {
+                    let name = match *boxed {
+                        Expr::Symbol(name) => name,
+                        _ => return Err(parse_error("not a symbol")),
+                    };
+                    let arguments = match self.group()? {
+                        None => Vec::new(),
+                        Some(Node::Expr(expr)) => vec![expr],
+                        Some(Node::List(list)) => list,
+                    };
+                    Node::Expr(Expr::Func(name, arguments))
+                }
+                TokenKind::Op("~") => {
+                    Node::Expr(Expr::Ancestor(boxed, Box::new(self.expr(18)?)))
+                }
+                TokenKind::Op("^") => {
+                    let n = if has_new_term {
+                        Some(Box::new(self.expr(18)?))
+                    } else {
+                        None
+                    };
+                    Node::Expr(Expr::Parent(boxed, n))
+                }
+                TokenKind::Op("-") => {
+                    Node::Expr(Expr::Minus(boxed, Box::new(self.expr(5)?)))
+                }
+                TokenKind::Op("::") | TokenKind::Op("..") => {
+                    let right = if has_new_term {
+                        Some(Box::new(self.expr(17)?))
+                    } else {
+                        None
+                    };
+                    Node::Expr(Expr::DagRange(Some(boxed), right))
+                }
+                TokenKind::Op(":") => {
+                    let right = if has_new_term {
+                        Some(Box::new(self.expr(15)?))
+                    } else {
+                        None
+                    };
+                    Node::Expr(Expr::Range(Some(boxed), right))
+                }
+                TokenKind::Op("and") | TokenKind::Op("&") => {
+                    Node::Expr(Expr::And(boxed, Box::new(self.expr(5)?)))
+                }
+                TokenKind::Op("%") => {
+                    let right = if has_new_term {
+                        Some(Box::new(self.expr(5)?))
+                    } else {
+                        None
+                    };
+                    Node::Expr(Expr::Only(boxed, right))
+                }
+                TokenKind::Op("or")
+                | TokenKind::Op("|")
+                | TokenKind::Op("+") => {
+                    let right = self.expr(4)?;
+                    let mut operands = match *boxed {
+                        Expr::Or(operands) => operands,
+                        other => vec![other],
+                    };
+                    operands.push(right);
+                    Node::Expr(Expr::Or(operands))
+                }
+                TokenKind::Op(",") => {
+                    let right = self.expr(2)?;
+                    Node::List(vec![*boxed, right])
+                }
+                TokenKind::Op(op @ "[")
+                | TokenKind::Op(op @ "#")
+                | TokenKind::Op(op @ "##")
+                | TokenKind::Op(op @ "=") => {
+                    return Err(HgError::unsupported(format!(
+                        "revset operator '{}'",
+                        op
+                    )));
+                }
+                other => {
+                    return Err(syntax_error(
+                        self.program,
+                        format!("not an infix: {}", describe(&other)),
+                        position,
+                    ))
+                }
+            };
+        }
+        Ok(node)
+    }
+}
+
+pub(super) fn list_misuse() -> HgError {
+    parse_error(
+        "can't use a list in this context\n\
+         (see 'hg help \"revsets.x or y\"')",
+    )
+}
+
+/// `-x` is only valid for symbols, for example `-1` for the last revision.
+fn negate(operand: Expr) -> Result<Expr, HgError> {
+    match operand {
+        Expr::Symbol(s) | Expr::String(s) => {
+            Ok(Expr::String(format!("-{}", s)))
+        }
+        _ => Err(parse_error("can't negate that")),
+    }
+}
+
+/// Parse a revset expression.
+///
+/// See `tokenize` for the role of `lookup`.
+pub fn parse(
+    program: &str,
+    lookup: &dyn Fn(&str) -> bool,
+) -> Result<Expr, HgError> {
+    if program.is_empty() {
+        return Err(parse_error("empty query"));
+    }
+    let mut tokens = tokenize(program, lookup)?.into_iter();
+    let current = tokens.next().expect("tokenize always emits an end token");
+    let mut parser = Parser {
+        program,
+        tokens,
+        current,
+    };
+    let expr = parser.expr(0)?;
+    if parser.current.kind != TokenKind::End {
+        return Err(syntax_error(
+            program,
+            "invalid token",
+            parser.current.position,
+        ));
+    }
+    Ok(expr)
+}
+
+/// Whether a program is a range such as `1:3` or `:tip` at its top level,
+/// like `scmutil._pairspec` in Python. A range in parentheses is a group
+/// instead.
+///
+/// Like in Python, this only depends on the syntax: symbols are not looked
+/// up, so `foo-bar` is always a subtraction.
+pub fn is_range(program: &str) -> Result<bool, HgError> {
+    let lookup = |_: &str| false;
+    match parse(program, &lookup)? {
+        Expr::Range(_, _) => {}
+        _ => return Ok(false),
+    }
+    // The program parsed, so its parentheses are balanced. It is a group if
+    // the first one closes at the end.
+    let tokens = tokenize(program, &lookup)?;
+    if tokens[0].kind != TokenKind::Op("(") {
+        return Ok(true);
+    }
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Op("(") => depth += 1,
+            TokenKind::Op(")") => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Ok(tokens[index + 1].kind != TokenKind::End);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(s: &str) -> Box<Expr> {
+        Box::new(Expr::Symbol(s.to_owned()))
+    }
+
+    fn parse_with(
+        program: &str,
+        lookup: &dyn Fn(&str) -> bool,
+    ) -> Result<Expr, String> {
+        parse(program, lookup).map_err(|error| error.to_string())
+    }
+
+    fn parse_simple(program: &str) -> Result<Expr, String> {
+        parse_with(program, &|_| false)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            parse_simple("a or b and not c"),
+            Ok(Expr::Or(vec![
+                Expr::Symbol("a".to_owned()),
+                Expr::And(symbol("b"), Box::new(Expr::Not(symbol("c"))))
+            ]))
+        );
+        assert_eq!(
+            parse_simple("a::b - c"),
+            Ok(Expr::Minus(
+                Box::new(Expr::DagRange(Some(symbol("a")), Some(symbol("b")))),
+                symbol("c")
+            ))
+        );
+        assert_eq!(
+            parse_simple("::a^"),
+            Ok(Expr::DagRange(
+                None,
+                Some(Box::new(Expr::Parent(symbol("a"), None)))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_ranges_and_suffixes() {
+        assert_eq!(parse_simple(":"), Ok(Expr::Range(None, None)));
+        assert_eq!(parse_simple(".."), Ok(Expr::DagRange(None, None)));
+        assert_eq!(
+            parse_simple("a:"),
+            Ok(Expr::Range(Some(symbol("a")), None))
+        );
+        assert_eq!(
+            parse_simple("a.."),
+            Ok(Expr::DagRange(Some(symbol("a")), None))
+        );
+        assert_eq!(parse_simple("a%"), Ok(Expr::Only(symbol("a"), None)));
+        assert_eq!(
+            parse_simple("a~2"),
+            Ok(Expr::Ancestor(symbol("a"), symbol("2")))
+        );
+    }
+
+    #[test]
+    fn test_is_range() {
+        let is_range = |program| is_range(program).unwrap();
+        assert!(is_range("1:2"));
+        assert!(is_range(":"));
+        assert!(is_range("(1):(2)"));
+        assert!(!is_range("(1:2)"));
+        assert!(!is_range("((1:2))"));
+        assert!(!is_range("1::2"));
+        assert!(!is_range("1:2 + 3"));
+        // Symbols are not looked up, so undefined names make no difference
+        assert!(!is_range("(undefined:2)"));
+        assert!(is_range("(undefined):2"));
+        assert!(!is_range("foo-bar:baz"));
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(
+            parse_simple("limit(a or b, 2, 1)"),
+            Ok(Expr::Func(
+                "limit".to_owned(),
+                vec![
+                    Expr::Or(vec![
+                        Expr::Symbol("a".to_owned()),
+                        Expr::Symbol("b".to_owned())
+                    ]),
+                    Expr::Symbol("2".to_owned()),
+                    Expr::Symbol("1".to_owned())
+                ]
+            ))
+        );
+        assert_eq!(
+            parse_simple("all()"),
+            Ok(Expr::Func("all".to_owned(), vec![]))
+        );
+    }
+
+    #[test]
+    fn test_strings_and_negation() {
+        assert_eq!(
+            parse_simple(r#"'it\'s' + "a\x41" + r'\n'"#),
+            Ok(Expr::Or(vec![
+                Expr::String("it's".to_owned()),
+                Expr::String("aA".to_owned()),
+                Expr::String("\\n".to_owned())
+            ]))
+        );
+        assert_eq!(parse_simple("-1"), Ok(Expr::String("-1".to_owned())));
+    }
+
+    #[test]
+    fn test_symbols_with_dashes() {
+        assert_eq!(
+            parse_simple("foo-bar"),
+            Ok(Expr::Minus(symbol("foo"), symbol("bar")))
+        );
+        assert_eq!(
+            parse_with("foo-bar", &|s| s == "foo-bar"),
+            Ok(Expr::Symbol("foo-bar".to_owned()))
+        );
+        // Old-style range of symbols containing operator characters
+        assert_eq!(
+            parse_with("a(b):c", &|s| s == "a(b)" || s == "c"),
+            Ok(Expr::Range(Some(symbol("a(b)")), Some(symbol("c"))))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse_simple("1 +"),
+            Err("hg: parse error at 3: not a prefix: end\n(1 +\n    ^ here)"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_simple("first(2"),
+            Err("hg: parse error at 7: unexpected token: end\n\
+                 (first(2\n        ^ here)"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_simple("(1, 2)"),
+            Err("hg: parse error: can't use a list in this context\n\
+                 (see 'hg help \"revsets.x or y\"')"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_simple("'abc"),
+            Err(
+                "hg: parse error at 1: unterminated string\n('abc\n  ^ here)"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            parse_simple("1 $ 2"),
+            Err("hg: parse error at 2: syntax error in revset '1 $ 2'\n\
+                 (1 $ 2\n   ^ here)"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_simple("-ancestors(1)"),
+            Err("hg: parse error: can't negate that".to_owned())
+        );
+        assert_eq!(
+            parse_simple(""),
+            Err("hg: parse error: empty query".to_owned())
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! The files of the store of a repository, like `mercurial/store.py`
+//!
+//! The revlogs of tracked files and of the directories of tree manifests are
+//! `data/<path>.i` and `meta/<directory>/00manifest.i`, with their `.d` data
+//! files. Their names are encoded to be valid on all file systems, long ones
+//! being hashed, so the fncache lists them before encoding.
+
+use crate::errors::{HgError, HgResultExt, IoResultExt};
+use crate::repo::Repo;
+use crate::revlog::path_encode::path_encode;
+use crate::utils::files::{get_bytes_from_os_str, get_path_from_bytes};
+use crate::utils::find_slice_in_slice;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+
+/// The revlogs of tracked files and directories listed in the fncache of a
+/// store, like `fncache` in Python
+pub struct Fncache {
+    /// Sorted, before encoding
+    entries: BTreeSet<Vec<u8>>,
+    /// The entries with hashed encoded names, by encoded name, built when
+    /// first needed
+    hashed: RefCell<Option<HashMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl Fncache {
+    /// Read the fncache of a repository, empty if there is none
+    pub fn read(repo: &Repo) -> Result<Self, HgError> {
+        let bytes = repo
+            .store_vfs()
+            .read("fncache")
+            .io_not_found_as_none()?
+            .unwrap_or_default();
+        Self::parse(&bytes)
+    }
+
+    /// Parse the contents of a fncache file, which has one entry per line
+    pub fn parse(bytes: &[u8]) -> Result<Self, HgError> {
+        let lines = match bytes.split_last() {
+            None => &[][..],
+            Some((b'\n', lines)) => lines,
+            Some(_) => {
+                return Err(HgError::abort(
+                    "abort: fncache does not ends with a newline\n\
+                     (use 'hg debugrebuildfncache' to rebuild the fncache)",
+                ))
+            }
+        };
+        let mut entries = BTreeSet::new();
+        if !bytes.is_empty() {
+            for (index, line) in lines.split(|b| b == &b'\n').enumerate() {
+                if line.is_empty() {
+                    return Err(HgError::abort(format!(
+                        "abort: invalid entry in fncache, line {}",
+                        index + 1
+                    )));
+                }
+                entries.insert(decode_dir(line));
+            }
+        }
+        Ok(Self {
+            entries,
+            hashed: RefCell::new(None),
+        })
+    }
+
+    /// The entries in order, like `data/foo.i`
+    pub fn entries(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|entry| entry.as_slice())
+    }
+
+    pub fn contains(&self, path: &[u8]) -> bool {
+        self.entries.contains(path)
+    }
+
+    /// The path before encoding of a file of the store, or `None` if it
+    /// cannot be decoded.
+    ///
+    /// Hashed names, which start with `dh/`, can only be decoded if they are
+    /// in the fncache.
+    pub fn decode(&self, encoded: &[u8]) -> Option<Vec<u8>> {
+        if !encoded.starts_with(b"dh/") {
+            return decode_filename(encoded);
+        }
+        let mut hashed = self.hashed.borrow_mut();
+        hashed
+            .get_or_insert_with(|| {
+                self.entries
+                    .iter()
+                    .map(|entry| (path_encode(entry), entry))
+                    .filter(|(encoded, _)| encoded.starts_with(b"dh/"))
+                    .map(|(encoded, entry)| (encoded, entry.clone()))
+                    .collect()
+            })
+            .get(encoded)
+            .cloned()
+    }
+}
+
+/// What a file of the store is part of
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StoreFileKind {
+    Changelog,
+    Manifest,
+    /// The revlog of a tracked file, or of a directory of tree manifests
+    Filelog,
+    Other,
+}
+
+/// A revlog file of the store, like the tuples of `basicstore.walk` in
+/// Python
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreFile {
+    pub kind: StoreFileKind,
+    /// The path before encoding, like `data/foo.i`
+    pub path: Vec<u8>,
+    /// The path in the store directory
+    pub encoded_path: Vec<u8>,
+    /// The size of the file, `None` if a file of the fncache is missing
+    pub size: Option<u64>,
+}
+
+impl StoreFile {
+    pub fn is_missing(&self) -> bool {
+        self.size.is_none()
+    }
+}
+
+/// The revlog files of tracked files and directories, in the order of the
+/// fncache, like `fncachestore.datafiles` in Python.
+///
+/// Unlike in Python, the entries of the fncache that are missing from the
+/// store are included.
+pub fn data_files(
+    repo: &Repo,
+    fncache: &Fncache,
+) -> Result<Vec<StoreFile>, HgError> {
+    let store_vfs = repo.store_vfs();
+    let mut files = Vec::new();
+    for path in fncache.entries() {
+        let encoded_path = path_encode(path);
+        let file_path = store_vfs.join(get_path_from_bytes(&encoded_path));
+        let size = match std::fs::metadata(&file_path) {
+            Ok(metadata) => Some(metadata.len()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error).when_reading_file(&file_path),
+        };
+        files.push(StoreFile {
+            kind: StoreFileKind::Filelog,
+            path: path.to_owned(),
+            encoded_path,
+            size,
+        })
+    }
+    Ok(files)
+}
+
+/// The revlog files at the top of the store, in reverse order of type and
+/// path which puts the manifest before the changelog, like
+/// `basicstore.topfiles` in Python
+pub fn top_files(repo: &Repo) -> Result<Vec<StoreFile>, HgError> {
+    let store_path = repo.store_vfs().join("");
+    let mut files = Vec::new();
+    let entries =
+        std::fs::read_dir(&store_path).when_reading_file(&store_path)?;
+    for entry in entries {
+        let entry = entry.when_reading_file(&store_path)?;
+        let metadata = entry.metadata().when_reading_file(&entry.path())?;
+        let name = get_bytes_from_os_str(entry.file_name());
+        let revlog_type = match revlog_type(&name) {
+            Some(revlog_type) if metadata.is_file() => revlog_type,
+            _ => continue,
+        };
+        let kind = if name.starts_with(b"00changelog") {
+            StoreFileKind::Changelog
+        } else if name.starts_with(b"00manifest") {
+            StoreFileKind::Manifest
+        } else {
+            StoreFileKind::Other
+        };
+        let file = StoreFile {
+            kind,
+            path: decode_dir(&name),
+            encoded_path: name,
+            size: Some(metadata.len()),
+        };
+        files.push((revlog_type, file))
+    }
+    // Sorted by type then path like in Python, before reversing
+    files.sort_by(|(type1, file1), (type2, file2)| {
+        (type2, &file2.path).cmp(&(type1, &file1.path))
+    });
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+/// All the revlog files of the store, data files first, like
+/// `basicstore.walk` in Python
+pub fn walk(
+    repo: &Repo,
+    fncache: &Fncache,
+) -> Result<Vec<StoreFile>, HgError> {
+    let mut files = data_files(repo, fncache)?;
+    files.extend(top_files(repo)?);
+    Ok(files)
+}
+
+/// The index of a revlog, with the values of `FILEFLAGS_*` in Python
+const REVLOG_MAIN: u32 = 1 << 1;
+/// Another file of a revlog
+const REVLOG_OTHER: u32 = 1 << 11 | 1 << 0;
+/// A file that may change while streaming the store, like a nodemap
+const VOLATILE: u32 = 1 << 20;
+
+/// The type of a file that is part of a revlog, by which files are sorted,
+/// like `revlog_type` in Python
+fn revlog_type(name: &[u8]) -> Option<u32> {
+    if name.ends_with(b".i") || name.ends_with(b"i.tmpcensored") {
+        return Some(REVLOG_MAIN);
+    }
+    if name.ends_with(b".d") || name.ends_with(b"d.tmpcensored") {
+        return Some(REVLOG_OTHER);
+    }
+    let stem = if name.ends_with(b".n") {
+        &name[..name.len() - 2]
+    } else if name.ends_with(b".nd") {
+        &name[..name.len() - 3]
+    } else {
+        return None;
+    };
+    // The nodemaps of the backups of transactions, `undo.<name>.n`, are
+    // excluded
+    let is_backup =
+        stem.windows(b"undo.".len())
+            .enumerate()
+            .any(|(position, window)| {
+                let rest = &stem[position + window.len()..];
+                window == b"undo." && !rest.is_empty() && !rest.contains(&b'/')
+            });
+    if is_backup {
+        None
+    } else {
+        Some(REVLOG_OTHER | VOLATILE)
+    }
+}
+
+/// Undo the escaping of directories that would otherwise look like revlog
+/// files, like `decodedir` in Python
+pub fn decode_dir(path: &[u8]) -> Vec<u8> {
+    if find_slice_in_slice(path, b".hg/").is_none() {
+        return path.to_owned();
+    }
+    let path = replace_all(path, b".d.hg/", b".d/");
+    let path = replace_all(&path, b".i.hg/", b".i/");
+    replace_all(&path, b".hg.hg/", b".hg/")
+}
+
+/// Decode a path of the store that is not hashed, like `decodefilename` in
+/// Python: `_` escapes capitals and itself, `~` escapes other bytes in
+/// hexadecimal.
+pub fn decode_filename(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'_' => match bytes.next() {
+                Some(&b'_') => decoded.push(b'_'),
+                Some(&lower) if lower.is_ascii_lowercase() => {
+                    decoded.push(lower.to_ascii_uppercase())
+                }
+                _ => return None,
+            },
+            b'~' => {
+                let high = hex_digit(*bytes.next()?)?;
+                let low = hex_digit(*bytes.next()?)?;
+                decoded.push(high << 4 | low)
+            }
+            _ => decoded.push(byte),
+        }
+    }
+    Some(decode_dir(&decoded))
+}
+
+/// The value of a lowercase hexadecimal digit, as used by the encoding
+fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        _ => None,
+    }
+}
+
+fn replace_all(mut bytes: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(bytes.len());
+    while let Some(position) = find_slice_in_slice(bytes, from) {
+        replaced.extend(&bytes[..position]);
+        replaced.extend(to);
+        bytes = &bytes[position + from.len()..];
+    }
+    replaced.extend(bytes);
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::repo_with;
+
+    #[test]
+    fn test_parse() {
+        let fncache = Fncache::parse(b"data/b.i\ndata/a.d.hg/c.i\n").unwrap();
+        let entries: Vec<_> = fncache.entries().collect();
+        assert_eq!(entries, [&b"data/a.d/c.i"[..], b"data/b.i"]);
+        assert!(fncache.contains(b"data/b.i"));
+        assert_eq!(Fncache::parse(b"").unwrap().entries().count(), 0);
+
+        let error = Fncache::parse(b"data/a.i").err().unwrap();
+        assert!(error.to_string().contains("does not ends with a newline"));
+        let error = Fncache::parse(b"data/a.i\n\ndata/b.i\n").err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("invalid entry in fncache, line 2"));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode_dir(b"data/a.i.hg/b.hg.hg/c.i"),
+            b"data/a.i/b.hg/c.i"
+        );
+        assert_eq!(
+            decode_filename(b"data/_f_o_o~3a__.d.hg/~7e.i").unwrap(),
+            b"data/FOO:_.d/~.i"
+        );
+        assert_eq!(decode_filename(b"data/_1.i"), None);
+        assert_eq!(decode_filename(b"data/~4.i"), None);
+
+        let long = [&b"data/"[..], &[b'x'; 200], b".i"].concat();
+        let fncache = Fncache::parse(&[&long[..], b"\n"].concat()).unwrap();
+        let encoded = path_encode(&long);
+        assert!(encoded.starts_with(b"dh/"));
+        assert_eq!(fncache.decode(&encoded), Some(long));
+        assert_eq!(fncache.decode(b"dh/unknown.i"), None);
+        assert_eq!(fncache.decode(b"data/_a.i").unwrap(), b"data/A.i");
+    }
+
+    #[test]
+    fn test_walk() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let repo = repo_with(root, &["generaldelta"]);
+        let store = root.join(".hg/store");
+        std::fs::create_dir_all(store.join("data")).unwrap();
+        std::fs::write(store.join("fncache"), "data/A.i\ndata/b.i\n").unwrap();
+        std::fs::write(store.join("data/_a.i"), "index").unwrap();
+        let names = ["00changelog.i", "00changelog.n", "00manifest.i"];
+        for name in names.iter().chain(&["undo.backup.n", "lock"]) {
+            std::fs::write(store.join(name), "").unwrap();
+        }
+
+        let fncache = Fncache::read(&repo).unwrap();
+        let files = walk(&repo, &fncache).unwrap();
+        let summary: Vec<_> = files
+            .iter()
+            .map(|file| (file.kind, &file.path[..], file.size))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (StoreFileKind::Filelog, &b"data/A.i"[..], Some(5)),
+                (StoreFileKind::Filelog, b"data/b.i", None),
+                (StoreFileKind::Changelog, b"00changelog.n", Some(0)),
+                (StoreFileKind::Manifest, b"00manifest.i", Some(0)),
+                (StoreFileKind::Changelog, b"00changelog.i", Some(0)),
+            ]
+        );
+        assert_eq!(files[0].encoded_path, b"data/_a.i");
+        assert!(files[1].is_missing());
+    }
+
+    #[test]
+    fn test_top_files_order() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let repo = repo_with(root, &["generaldelta"]);
+        let store = root.join(".hg/store");
+        std::fs::create_dir_all(&store).unwrap();
+        let names = [
+            "00changelog.i",
+            "00changelog.d",
+            "00changelog.n",
+            "00changelog-1234.nd",
+            "00manifest.i",
+            "00manifest.d",
+        ];
+        for name in &names {
+            std::fs::write(store.join(name), "").unwrap();
+        }
+
+        // Nodemaps, then data files, then indexes
+        let paths: Vec<_> = top_files(&repo)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                &b"00changelog.n"[..],
+                b"00changelog-1234.nd",
+                b"00manifest.d",
+                b"00changelog.d",
+                b"00manifest.i",
+                b"00changelog.i",
+            ]
+        );
+    }
+}
@@ -0,0 +1,450 @@
+//! The template language of `-T/--template` options
+//!
+//! <https://www.mercurial-scm.org/repo/hg/help/templates>
+//!
+//! Templates only know about the syntax of the language and a few generic
+//! filters and functions. The data of each item being shown, such as a
+//! changeset or a file, comes from a `KeywordProvider` implemented by each
+//! command.
+
+mod evaluate;
+mod parser;
+
+use crate::errors::HgError;
+use crate::revlog::changelog::ChangesetDate;
+
+/// The value of a keyword or of a template expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Shown as nothing, like `None` in Python
+    None,
+    String(Vec<u8>),
+    Integer(i64),
+    Boolean(bool),
+    Date(ChangesetDate),
+    /// A list shown as its items separated by spaces.
+    ///
+    /// In `{list % template}`, each item is available to the template as
+    /// keyword `name`, such as `file` for the `files` keyword.
+    List {
+        name: &'static str,
+        items: Vec<Value>,
+    },
+    /// The result of `{list % template}`, shown concatenated
+    Mapped(Vec<Vec<u8>>),
+}
+
+impl Value {
+    pub fn list<T: Into<Value>>(
+        name: &'static str,
+        items: impl IntoIterator<Item = T>,
+    ) -> Self {
+        Value::List {
+            name,
+            items: items.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// The text of this value when it is part of the output
+    pub fn show(&self) -> Vec<u8> {
+        match self {
+            Value::None => Vec::new(),
+            Value::String(s) => s.clone(),
+            Value::Integer(i) => i.to_string().into_bytes(),
+            Value::Boolean(true) => b"True".to_vec(),
+            Value::Boolean(false) => b"False".to_vec(),
+            // Like `{date}` in Python, a float then the timezone offset
+            Value::Date(date) => {
+                format!("{}.0{}", date.timestamp, date.timezone_offset)
+                    .into_bytes()
+            }
+            Value::List { items, .. } => items
+                .iter()
+                .map(Value::show)
+                .collect::<Vec<_>>()
+                .join(&b' '),
+            Value::Mapped(parts) => parts.concat(),
+        }
+    }
+
+    /// Whether this value is considered true by `if()`
+    fn to_bool(&self) -> bool {
+        match self {
+            Value::None => false,
+            Value::String(s) => !s.is_empty(),
+            // Integers are evaluated as strings, so 0 is true
+            Value::Integer(_) | Value::Date(_) => true,
+            Value::Boolean(b) => *b,
+            Value::List { items, .. } => !items.is_empty(),
+            Value::Mapped(parts) => parts.iter().any(|part| !part.is_empty()),
+        }
+    }
+
+    /// Serialize to JSON like the `json` template filter.
+    ///
+    /// When `paranoid`, non-ASCII characters as well as `<` and `>` are
+    /// escaped. Strings that are not valid UTF-8 are not supported.
+    pub fn to_json(&self, paranoid: bool) -> Result<Vec<u8>, HgError> {
+        let list = |items: &mut dyn Iterator<Item = Result<Vec<u8>, _>>| {
+            let items = items.collect::<Result<Vec<_>, HgError>>()?;
+            let mut json = b"[".to_vec();
+            json.extend(items.join(&b", "[..]));
+            json.push(b']');
+            Ok(json)
+        };
+        match self {
+            Value::None => Ok(b"null".to_vec()),
+            Value::String(s) => json_string(s, paranoid),
+            Value::Integer(i) => Ok(i.to_string().into_bytes()),
+            Value::Boolean(b) => Ok(b.to_string().into_bytes()),
+            Value::Date(date) => {
+                Ok(format!("[{}, {}]", date.timestamp, date.timezone_offset)
+                    .into_bytes())
+            }
+            Value::List { items, .. } => {
+                list(&mut items.iter().map(|item| item.to_json(paranoid)))
+            }
+            Value::Mapped(parts) => {
+                list(&mut parts.iter().map(|part| json_string(part, paranoid)))
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(s: Vec<u8>) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(s: &[u8]) -> Self {
+        Value::String(s.to_vec())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.as_bytes().to_vec())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Integer(i)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Self {
+        Value::Integer(i.into())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<ChangesetDate> for Value {
+    fn from(date: ChangesetDate) -> Self {
+        Value::Date(date)
+    }
+}
+
+/// Quote and escape a string for JSON, like `encoding.jsonescape`
+fn json_string(s: &[u8], paranoid: bool) -> Result<Vec<u8>, HgError> {
+    let s = std::str::from_utf8(s).map_err(|_| {
+        HgError::unsupported("non-UTF-8 strings in JSON output")
+    })?;
+    let mut json = Vec::with_capacity(s.len() + 2);
+    json.push(b'"');
+    for c in s.chars() {
+        match c {
+            '"' => json.extend(b"\\\""),
+            '\\' => json.extend(b"\\\\"),
+            '\n' => json.extend(b"\\n"),
+            '\r' => json.extend(b"\\r"),
+            '\t' => json.extend(b"\\t"),
+            '\x08' => json.extend(b"\\b"),
+            '\x0c' => json.extend(b"\\f"),
+            '<' | '>' if paranoid => {
+                json.extend(format!("\\u{:04x}", u32::from(c)).bytes())
+            }
+            '\0'..='\x1f' | '\x7f' => {
+                json.extend(format!("\\u{:04x}", u32::from(c)).bytes())
+            }
+            c if paranoid && !c.is_ascii() => {
+                let mut utf16 = [0; 2];
+                for unit in c.encode_utf16(&mut utf16) {
+                    json.extend(format!("\\u{:04x}", unit).bytes())
+                }
+            }
+            c => {
+                let mut utf8 = [0; 4];
+                json.extend(c.encode_utf8(&mut utf8).bytes())
+            }
+        }
+    }
+    json.push(b'"');
+    Ok(json)
+}
+
+/// Provides the values of keywords for an item shown with a template
+pub trait KeywordProvider {
+    /// Return the value of the keyword `name`, or `None` if it is not known.
+    ///
+    /// Templates using unknown keywords are not supported, since Python
+    /// may know about them.
+    fn keyword(&self, name: &str) -> Result<Option<Value>, HgError>;
+}
+
+/// Keywords given as a list of names and values, such as the fields of a
+/// formatter item
+struct Fields<'a>(&'a [(&'a str, Value)]);
+
+impl KeywordProvider for Fields<'_> {
+    fn keyword(&self, name: &str) -> Result<Option<Value>, HgError> {
+        Ok(self
+            .0
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value.clone()))
+    }
+}
+
+/// A parsed template
+#[derive(Debug, Clone)]
+pub struct Template {
+    expr: parser::Expr,
+}
+
+impl Template {
+    /// Parse a template.
+    ///
+    /// Filters and functions that are not implemented in Rust yet return
+    /// `HgError::UnsupportedFeature`.
+    pub fn parse(template: &[u8]) -> Result<Self, HgError> {
+        Ok(Self {
+            expr: parser::parse(template)?,
+        })
+    }
+
+    /// Render the template for one item
+    pub fn render(
+        &self,
+        keywords: &dyn KeywordProvider,
+    ) -> Result<Vec<u8>, HgError> {
+        Ok(evaluate::evaluate(&self.expr, keywords)?.show())
+    }
+}
+
+/// Output of a list of items such as files, in the format selected with
+/// `-T/--template`, like `formatter.py` in Python
+pub enum Formatter {
+    /// `-T json`
+    Json {
+        first: bool,
+    },
+    Template(Template),
+}
+
+impl Formatter {
+    /// Select the output format from the value of `-T/--template`, which
+    /// must not be empty.
+    ///
+    /// Other built-in formats, style names, and references to templates
+    /// files or configuration are not supported.
+    pub fn new(spec: &[u8]) -> Result<Self, HgError> {
+        if spec.contains(&b'{') {
+            Ok(Formatter::Template(Template::parse(spec)?))
+        } else if spec == b"json" {
+            Ok(Formatter::Json { first: true })
+        } else {
+            Err(HgError::unsupported(format!(
+                "template style {:?}",
+                String::from_utf8_lossy(spec)
+            )))
+        }
+    }
+
+    /// The output before the first item
+    pub fn start(&self) -> &'static [u8] {
+        match self {
+            Formatter::Json { .. } => b"[",
+            Formatter::Template(_) => b"",
+        }
+    }
+
+    /// The output for one item made of the given fields, which templates
+    /// can use as keywords
+    pub fn item(
+        &mut self,
+        fields: &[(&str, Value)],
+    ) -> Result<Vec<u8>, HgError> {
+        match self {
+            Formatter::Json { first } => {
+                let mut output = Vec::new();
+                if !std::mem::replace(first, false) {
+                    output.push(b',')
+                }
+                output.extend(b"\n {\n");
+                let mut fields: Vec<_> = fields.iter().collect();
+                fields.sort_by_key(|(name, _)| *name);
+                let fields = fields
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let mut field =
+                            format!("  \"{}\": ", name).into_bytes();
+                        field.extend(value.to_json(false)?);
+                        Ok(field)
+                    })
+                    .collect::<Result<Vec<_>, HgError>>()?;
+                output.extend(fields.join(&b",\n"[..]));
+                output.extend(b"\n }");
+                Ok(output)
+            }
+            Formatter::Template(template) => template.render(&Fields(fields)),
+        }
+    }
+
+    /// The output after the last item
+    pub fn end(&self) -> &'static [u8] {
+        match self {
+            Formatter::Json { .. } => b"\n]\n",
+            Formatter::Template(_) => b"",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    struct Changeset;
+
+    impl KeywordProvider for Changeset {
+        fn keyword(&self, name: &str) -> Result<Option<Value>, HgError> {
+            Ok(Some(match name {
+                "rev" => 3.into(),
+                "node" => "0123456789abcdef0123456789abcdef01234567".into(),
+                "desc" => "Summary <b>\r\n\ncaf\u{e9} \u{1d11e}".into(),
+                "date" => ChangesetDate {
+                    timestamp: 1000000000,
+                    timezone_offset: -7200,
+                }
+                .into(),
+                "files" => Value::list("file", vec!["a", "dir/b"]),
+                "tags" => Value::list::<&str>("tag", vec![]),
+                _ => return Ok(None),
+            }))
+        }
+    }
+
+    fn render(template: &str) -> Result<String, String> {
+        Template::parse(template.as_bytes())
+            .and_then(|template| template.render(&Changeset))
+            .map(|output| String::from_utf8(output).unwrap())
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn test_keywords_and_filters() {
+        assert_eq!(
+            render("{rev}:{node|short} {date|isodate}\n"),
+            Ok("3:0123456789ab 2001-09-09 03:46 +0200\n".to_owned())
+        );
+        assert_eq!(render("{date}"), Ok("1000000000.0-7200".to_owned()));
+        assert_eq!(render("{files} {tags}"), Ok("a dir/b ".to_owned()));
+        assert_eq!(
+            render("{desc|firstline}|{firstline(desc)|json}"),
+            Ok("Summary <b>|\"Summary \\u003cb\\u003e\"".to_owned())
+        );
+        assert_eq!(
+            render("{desc|json}"),
+            Ok("\"Summary \\u003cb\\u003e\\r\\n\\ncaf\\u00e9 \\ud834\\udd1e\""
+                .to_owned())
+        );
+        assert_eq!(
+            render("{files|json} {date|json} {rev|json}"),
+            Ok("[\"a\", \"dir/b\"] [1000000000, -7200] 3".to_owned())
+        );
+        assert!(render("{unknown}").is_err());
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(
+            render("{if(tags, 'tagged', 'not tagged')} {if(files, rev)}"),
+            Ok("not tagged 3".to_owned())
+        );
+        assert_eq!(
+            render("{if(false, 'yes', 'no')}{if(True, '!')}"),
+            Ok("no!".to_owned())
+        );
+        assert_eq!(
+            render("{join(files, ', ')}|{join('ab', '-')}"),
+            Ok("a, dir/b|a-b".to_owned())
+        );
+        assert!(render("{join(rev, '-')}").is_err());
+        assert_eq!(
+            render("[{pad(rev, 3)}][{pad(rev, 3, '0', True)}]"),
+            Ok("[3  ][003]".to_owned())
+        );
+        assert_eq!(
+            render("{pad('abcdef', 4, truncate=True)}"),
+            Ok("abcd".to_owned())
+        );
+        assert_eq!(
+            render("{label(red, rev)} {label('log.{rev}', node|short)}"),
+            Ok("3 0123456789ab".to_owned())
+        );
+        assert_eq!(render("{rev * 2 - 10 / 3}"), Ok("3".to_owned()));
+    }
+
+    #[test]
+    fn test_map() {
+        assert_eq!(
+            render("{files % '{rev}:{file}\\n'}"),
+            Ok("3:a\n3:dir/b\n".to_owned())
+        );
+        assert_eq!(
+            render("{join(files % '<{file}>', ',')}"),
+            Ok("<a>,<dir/b>".to_owned())
+        );
+        assert_eq!(
+            render("{files % '{index}'|json}"),
+            Ok("[\"0\", \"1\"]".to_owned())
+        );
+        assert_eq!(render("{tags % 'x'}"), Ok("".to_owned()));
+    }
+
+    #[test]
+    fn test_json_formatter() {
+        let mut formatter = Formatter::new(b"json").unwrap();
+        let mut output = formatter.start().to_vec();
+        for path in &["b", "a\"\u{e9}"] {
+            output.extend(
+                formatter
+                    .item(&[("status", "M".into()), ("path", (*path).into())])
+                    .unwrap(),
+            );
+        }
+        output.extend(formatter.end());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[\n {\n  \"path\": \"b\",\n  \"status\": \"M\"\n },\
+             \n {\n  \"path\": \"a\\\"\u{e9}\",\n  \"status\": \"M\"\n }\n]\n"
+        );
+        let mut formatter = Formatter::new(b"{status} {path}\\n").unwrap();
+        assert_eq!(
+            formatter
+                .item(&[("status", "M".into()), ("path", "a".into())])
+                .unwrap(),
+            b"M a\n"
+        );
+        assert!(Formatter::new(b"compact").is_err());
+    }
+}
@@ -0,0 +1,295 @@
+// evaluate.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Evaluation of compiled templates, like `templateutil.py` together with
+//! the filters and functions of `templatefilters.py` and `templatefuncs.py`
+
+use super::parser::{parse_error, Expr, Filter};
+use super::{KeywordProvider, Value};
+use crate::errors::HgError;
+
+/// The keywords of one item of a `list % template` expression: the item
+/// itself and its `index`, on top of the keywords of the enclosing template
+struct MappedItem<'a> {
+    name: &'static str,
+    item: &'a Value,
+    index: usize,
+    parent: &'a dyn KeywordProvider,
+}
+
+impl KeywordProvider for MappedItem<'_> {
+    fn keyword(&self, name: &str) -> Result<Option<Value>, HgError> {
+        if name == self.name {
+            Ok(Some(self.item.clone()))
+        } else if name == "index" {
+            Ok(Some(Value::Integer(self.index as i64)))
+        } else {
+            self.parent.keyword(name)
+        }
+    }
+}
+
+fn lookup(
+    name: &str,
+    keywords: &dyn KeywordProvider,
+) -> Result<Value, HgError> {
+    keywords.keyword(name)?.ok_or_else(|| {
+        HgError::unsupported(format!("template keyword '{}'", name))
+    })
+}
+
+pub(super) fn evaluate(
+    expr: &Expr,
+    keywords: &dyn KeywordProvider,
+) -> Result<Value, HgError> {
+    let string = |expr: &Expr| -> Result<Vec<u8>, HgError> {
+        Ok(evaluate(expr, keywords)?.show())
+    };
+    Ok(match expr {
+        Expr::Integer(i) => Value::Integer(*i),
+        Expr::Symbol(name) => lookup(name, keywords)?,
+        Expr::String(s) => Value::String(s.clone()),
+        Expr::Template(parts) => {
+            let mut output = Vec::new();
+            for part in parts {
+                output.extend(string(part)?)
+            }
+            Value::String(output)
+        }
+        Expr::Filter(filter, arg) => {
+            apply_filter(*filter, evaluate(arg, keywords)?)?
+        }
+        Expr::Map(list, template) => {
+            let (name, items) = match evaluate(list, keywords)? {
+                Value::List { name, items } => (name, items),
+                _ => {
+                    return Err(HgError::unsupported(
+                        "map operation on a template value that is not a \
+                         list",
+                    ))
+                }
+            };
+            let mut outputs = Vec::with_capacity(items.len());
+            for (index, item) in items.iter().enumerate() {
+                let item_keywords = MappedItem {
+                    name,
+                    item,
+                    index,
+                    parent: keywords,
+                };
+                outputs.push(evaluate(template, &item_keywords)?.show())
+            }
+            Value::Mapped(outputs)
+        }
+        Expr::Negate(arg) => {
+            let i =
+                integer(arg, keywords, "negation needs an integer argument")?;
+            Value::Integer(i.checked_neg().ok_or_else(overflow)?)
+        }
+        Expr::Arithmetic(op, left, right) => {
+            let operand = |expr| {
+                integer(expr, keywords, "arithmetic only defined on integers")
+            };
+            let left = operand(left)?;
+            let right = operand(right)?;
+            let result = match op {
+                b'+' => left.checked_add(right),
+                b'-' => left.checked_sub(right),
+                b'*' => left.checked_mul(right),
+                _ => {
+                    if right == 0 {
+                        return Err(HgError::abort(
+                            "abort: division by zero is not defined",
+                        ));
+                    }
+                    floor_division(left, right)
+                }
+            };
+            Value::Integer(result.ok_or_else(overflow)?)
+        }
+        Expr::If(condition, then, otherwise) => {
+            if boolean(condition, keywords)? {
+                evaluate(then, keywords)?
+            } else if let Some(otherwise) = otherwise {
+                evaluate(otherwise, keywords)?
+            } else {
+                Value::None
+            }
+        }
+        Expr::Join(list, separator) => {
+            let separator = match separator {
+                Some(separator) => string(separator)?,
+                None => b" ".to_vec(),
+            };
+            let items = match evaluate(list, keywords)? {
+                Value::List { items, .. } => {
+                    items.iter().map(Value::show).collect()
+                }
+                Value::Mapped(parts) => parts,
+                Value::String(s) => s.iter().map(|&b| vec![b]).collect(),
+                Value::None => Vec::new(),
+                _ => {
+                    return Err(HgError::unsupported(
+                        "join() of a template value that is not a list",
+                    ))
+                }
+            };
+            Value::String(items.join(&separator[..]))
+        }
+        Expr::Pad {
+            text,
+            width,
+            fill_char,
+            left,
+            truncate,
+        } => {
+            let width =
+                integer(width, keywords, "pad() expects an integer width")?;
+            let text = string(text)?;
+            let fill_char = match fill_char {
+                Some(fill_char) => match &string(fill_char)?[..] {
+                    &[c] => c,
+                    _ => {
+                        return Err(parse_error(
+                            "pad() expects a single fill character",
+                        ))
+                    }
+                },
+                None => b' ',
+            };
+            let flag = |arg: &Option<Box<Expr>>| match arg {
+                Some(arg) => boolean(arg, keywords),
+                None => Ok(false),
+            };
+            let left = flag(left)?;
+            let truncate = flag(truncate)?;
+            Value::String(pad(text, width, fill_char, left, truncate)?)
+        }
+        Expr::Label(_label, content) => {
+            // Labels only matter for colors, which are not supported
+            Value::String(string(content)?)
+        }
+    })
+}
+
+fn overflow() -> HgError {
+    HgError::unsupported("integer overflow in template")
+}
+
+/// Python's `//`, rounding towards negative infinity
+fn floor_division(left: i64, right: i64) -> Option<i64> {
+    let quotient = left.checked_div(right)?;
+    if left % right != 0 && (left < 0) != (right < 0) {
+        quotient.checked_sub(1)
+    } else {
+        Some(quotient)
+    }
+}
+
+/// Evaluate as an integer like `int()` in Python, with the given error
+/// message for values that cannot be converted
+fn integer(
+    expr: &Expr,
+    keywords: &dyn KeywordProvider,
+    error: &str,
+) -> Result<i64, HgError> {
+    let integer = match evaluate(expr, keywords)? {
+        Value::Integer(i) => Some(i),
+        Value::Boolean(b) => Some(b.into()),
+        Value::String(s) => std::str::from_utf8(&s)
+            .ok()
+            .and_then(|s| s.trim().parse().ok()),
+        _ => None,
+    };
+    integer.ok_or_else(|| parse_error(error))
+}
+
+/// Evaluate as a boolean. Unlike elsewhere, a symbol that is not a keyword
+/// is taken as a literal such as `True` or `no`.
+fn boolean(
+    expr: &Expr,
+    keywords: &dyn KeywordProvider,
+) -> Result<bool, HgError> {
+    if let Expr::Symbol(name) = expr {
+        if let Some(value) = keywords.keyword(name)? {
+            return Ok(value.to_bool());
+        }
+        return parse_bool(name).ok_or_else(|| {
+            HgError::unsupported(format!("template keyword '{}'", name))
+        });
+    }
+    Ok(evaluate(expr, keywords)?.to_bool())
+}
+
+/// Like `stringutil.parsebool`
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "yes" | "true" | "on" | "always" => Some(true),
+        "0" | "no" | "false" | "off" | "never" => Some(false),
+        _ => None,
+    }
+}
+
+fn pad(
+    text: Vec<u8>,
+    width: i64,
+    fill_char: u8,
+    left: bool,
+    truncate: bool,
+) -> Result<Vec<u8>, HgError> {
+    if !text.is_ascii() {
+        // Would need the display width of each character
+        return Err(HgError::unsupported("pad() of non-ASCII text"));
+    }
+    let fill_width = width - text.len() as i64;
+    if fill_width < 0 && truncate {
+        let width = width.max(0) as usize;
+        return Ok(if left {
+            text[text.len() - width..].to_vec()
+        } else {
+            text[..width].to_vec()
+        });
+    }
+    if fill_width <= 0 {
+        return Ok(text);
+    }
+    let fill = vec![fill_char; fill_width as usize];
+    Ok(if left {
+        [fill, text].concat()
+    } else {
+        [text, fill].concat()
+    })
+}
+
+fn apply_filter(filter: Filter, value: Value) -> Result<Value, HgError> {
+    Ok(match filter {
+        Filter::FirstLine => {
+            let text = value.show();
+            let end = text
+                .iter()
+                .position(|&b| b == b'\n' || b == b'\r')
+                .unwrap_or(text.len());
+            Value::String(text[..end].to_vec())
+        }
+        Filter::IsoDate => match value {
+            Value::Date(date) => {
+                Value::String(date.format("%Y-%m-%d %H:%M %1%2").into_bytes())
+            }
+            _ => {
+                return Err(HgError::unsupported(
+                    "date filter of a template value that is not a date",
+                ))
+            }
+        },
+        Filter::Json => Value::String(value.to_json(true)?),
+        Filter::Short => {
+            let mut text = value.show();
+            text.truncate(12);
+            Value::String(text)
+        }
+    })
+}
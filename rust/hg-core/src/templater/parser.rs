@@ -0,0 +1,844 @@
+// parser.rs
+//
+// Copyright 2021 Mercurial contributors
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tokenizer and parser for the template language
+//!
+//! This follows `mercurial/templater.py`: literal text is scanned for `{…}`
+//! expressions, which are parsed with the same kind of Pratt parser as
+//! revsets. Quoted strings inside expressions are themselves templates,
+//! scanned recursively. The resulting tree is then checked and turned into
+//! an `Expr` like `compileexp` does in Python.
+
+use crate::errors::HgError;
+
+/// A template syntax error without a known position
+pub(super) fn parse_error(message: impl Into<String>) -> HgError {
+    HgError::abort(format!("hg: parse error: {}", message.into()))
+}
+
+/// Compiled template expression, ready to be evaluated
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Expr {
+    Integer(i64),
+    /// A keyword
+    Symbol(String),
+    String(Vec<u8>),
+    /// Parts to concatenate
+    Template(Vec<Expr>),
+    /// `x|name` or `name(x)`
+    Filter(Filter, Box<Expr>),
+    /// `list % template`
+    Map(Box<Expr>, Box<Expr>),
+    /// `-x`
+    Negate(Box<Expr>),
+    /// `x + y`, `x - y`, `x * y` or `x / y`
+    Arithmetic(u8, Box<Expr>, Box<Expr>),
+    /// `if(condition, then[, else])`
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    /// `join(list[, separator])`
+    Join(Box<Expr>, Option<Box<Expr>>),
+    /// `pad(text, width[, fillchar[, left[, truncate]]])`
+    Pad {
+        text: Box<Expr>,
+        width: Box<Expr>,
+        fill_char: Option<Box<Expr>>,
+        left: Option<Box<Expr>>,
+        truncate: Option<Box<Expr>>,
+    },
+    /// `label(label, expr)`
+    Label(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) enum Filter {
+    FirstLine,
+    IsoDate,
+    Json,
+    Short,
+}
+
+impl Filter {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "firstline" => Filter::FirstLine,
+            "isodate" => Filter::IsoDate,
+            "json" => Filter::Json,
+            "short" => Filter::Short,
+            _ => return None,
+        })
+    }
+}
+
+/// Parse tree before compilation, like the tuples produced by the generic
+/// parser in Python
+#[derive(Debug, Clone, PartialEq)]
+enum Tree {
+    Integer(String),
+    Symbol(String),
+    String(Vec<u8>),
+    Template(Vec<Tree>),
+    /// `( … )`, possibly empty
+    Group(Option<Box<Tree>>),
+    /// `name( … )`
+    Func(Box<Tree>, Option<Box<Tree>>),
+    /// `-x`
+    Negate(Box<Tree>),
+    /// Infix operators: `.`, `%`, `|`, `*`, `/`, `+`, `-`, `=` and `,`
+    Infix(u8, Box<Tree>, Box<Tree>),
+}
+
+impl Tree {
+    /// The node name used by Python in error messages
+    fn describe(&self) -> String {
+        match self {
+            Tree::Integer(_) => "integer".to_owned(),
+            Tree::Symbol(_) => "symbol".to_owned(),
+            Tree::String(_) => "string".to_owned(),
+            Tree::Template(_) => "template".to_owned(),
+            Tree::Group(_) => "group".to_owned(),
+            Tree::Func(..) => "func".to_owned(),
+            Tree::Negate(_) => "negate".to_owned(),
+            Tree::Infix(b'=', ..) => "keyvalue".to_owned(),
+            Tree::Infix(b',', ..) => "list".to_owned(),
+            Tree::Infix(op, ..) => char::from(*op).to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    /// One of `(=,).%|+-*/`
+    Op(u8),
+    Integer(String),
+    Symbol(String),
+    /// A raw string: `r'…'`
+    String(Vec<u8>),
+    /// A quoted template: `'…'` or `"…"`
+    Template(Vec<Tree>),
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+/// Mirrors the `elements` table of `mercurial/templater.py`
+fn binding_strength(kind: &TokenKind) -> u8 {
+    match kind {
+        TokenKind::Op(op) => match op {
+            b'(' => 20,
+            b'.' => 18,
+            b'%' | b'|' => 15,
+            b'*' | b'/' => 5,
+            b'+' | b'-' => 4,
+            b'=' => 3,
+            b',' => 2,
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+fn describe(kind: &TokenKind) -> String {
+    match kind {
+        TokenKind::Op(op) => char::from(*op).to_string(),
+        TokenKind::Integer(_) => "integer".to_owned(),
+        TokenKind::Symbol(_) => "symbol".to_owned(),
+        TokenKind::String(_) => "string".to_owned(),
+        TokenKind::Template(_) => "template".to_owned(),
+        TokenKind::End => "end".to_owned(),
+    }
+}
+
+/// Undo Python-style backslash escapes, like `codecs.escape_decode`
+fn unescape(input: &[u8]) -> Result<Vec<u8>, HgError> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut position = 0;
+    while position < input.len() {
+        let byte = input[position];
+        position += 1;
+        if byte != b'\\' {
+            output.push(byte);
+            continue;
+        }
+        let escaped = match input.get(position) {
+            Some(&escaped) => escaped,
+            None => return Err(parse_error("trailing \\ in string")),
+        };
+        position += 1;
+        match escaped {
+            b'\n' => {}
+            b'\\' | b'\'' | b'"' => output.push(escaped),
+            b'a' => output.push(0x07),
+            b'b' => output.push(0x08),
+            b'f' => output.push(0x0c),
+            b'n' => output.push(b'\n'),
+            b'r' => output.push(b'\r'),
+            b't' => output.push(b'\t'),
+            b'v' => output.push(0x0b),
+            b'0'..=b'7' => {
+                let mut value = u32::from(escaped - b'0');
+                for _ in 0..2 {
+                    match input.get(position) {
+                        Some(&digit @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(digit - b'0');
+                            position += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                output.push(value as u8)
+            }
+            b'x' => {
+                let value = input
+                    .get(position..position + 2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| {
+                        parse_error(format!(
+                            "invalid \\x escape at position {}",
+                            position - 2
+                        ))
+                    })?;
+                output.push(value);
+                position += 2;
+            }
+            other => output.extend(&[b'\\', other]),
+        }
+    }
+    Ok(output)
+}
+
+/// Splits the text of a template into literal strings and expressions.
+struct Scanner<'a> {
+    /// The whole template, for error messages
+    program: &'a [u8],
+}
+
+impl<'a> Scanner<'a> {
+    /// A syntax error at a given byte position, with a hint pointing at it
+    /// like `_addparseerrorhint` in Python
+    fn error(&self, message: impl Into<String>, position: usize) -> HgError {
+        let before = &self.program[..position.min(self.program.len())];
+        let offset = before.iter().filter(|&&b| b == b'\n').count();
+        let program =
+            String::from_utf8_lossy(self.program).replace('\n', "\\n");
+        HgError::abort(format!(
+            "hg: parse error at {}: {}\n({}\n{}^ here)",
+            position,
+            message.into(),
+            program,
+            " ".repeat(position + 1 + offset)
+        ))
+    }
+
+    /// Scan `program[start..stop]` until the end or the `quote` character,
+    /// returning the parsed parts and the position after the closing quote.
+    fn scan_template(
+        &self,
+        start: usize,
+        stop: usize,
+        quote: Option<u8>,
+    ) -> Result<(Vec<Tree>, usize), HgError> {
+        let program = self.program;
+        let mut parts = Vec::new();
+        let mut position = start;
+        while position < stop {
+            let found = program[position..stop]
+                .iter()
+                .position(|&b| b == b'{' || Some(b) == quote)
+                .map(|offset| position + offset);
+            let n = match found {
+                Some(n) => n,
+                None => {
+                    parts.push(Tree::String(unescape(
+                        &program[position..stop],
+                    )?));
+                    position = stop;
+                    break;
+                }
+            };
+            let c = program[n];
+            let backslashes = program[position..n]
+                .iter()
+                .rev()
+                .take_while(|&&b| b == b'\\')
+                .count();
+            if backslashes % 2 == 1 {
+                // Escaped, as in `\{` or `\\\{` but not `\\{`
+                let mut literal = unescape(&program[position..n - 1])?;
+                literal.push(c);
+                parts.push(Tree::String(literal));
+                position = n + 1;
+                continue;
+            }
+            if n > position {
+                parts.push(Tree::String(unescape(&program[position..n])?));
+            }
+            if Some(c) == quote {
+                return Ok((parts, n + 1));
+            }
+            let tokenizer = Tokenizer {
+                scanner: self,
+                start: n + 1,
+                position: n + 1,
+                stop,
+                done: false,
+            };
+            let (tree, end) = Parser::new(tokenizer)?.parse_all()?;
+            if program.get(end) != Some(&b'}') {
+                return Err(self.error("invalid token", end));
+            }
+            parts.push(tree);
+            position = end + 1;
+        }
+        if quote.is_some() {
+            return Err(self.error("unterminated string", start));
+        }
+        Ok((parts, position))
+    }
+}
+
+/// Lazily split a template expression into tokens, up to the closing `}`.
+///
+/// Tokens are only produced when the parser needs them, so that errors are
+/// reported in the same order as in Python.
+struct Tokenizer<'s, 'a> {
+    scanner: &'s Scanner<'a>,
+    /// Where the expression starts, after the opening `{`
+    start: usize,
+    position: usize,
+    stop: usize,
+    done: bool,
+}
+
+impl Tokenizer<'_, '_> {
+    fn next_token(&mut self) -> Result<Token, HgError> {
+        let program = self.scanner.program;
+        if self.done {
+            return Ok(Token {
+                kind: TokenKind::End,
+                position: self.position,
+            });
+        }
+        while self.position < self.stop {
+            let position = self.position;
+            let c = program[position];
+            let next_two = &program[position..(position + 2).min(self.stop)];
+            let next_three = &program[position..(position + 3).min(self.stop)];
+            if c.is_ascii_whitespace() || c == 0x0b {
+                self.position += 1;
+            } else if b"(=,).%|+-*/".contains(&c) {
+                self.position += 1;
+                return Ok(Token {
+                    kind: TokenKind::Op(c),
+                    position,
+                });
+            } else if c == b'"' || c == b'\'' {
+                let start = position + 1;
+                let (parts, end) =
+                    self.scanner.scan_template(start, self.stop, Some(c))?;
+                self.position = end;
+                return Ok(Token {
+                    kind: TokenKind::Template(parts),
+                    position: start,
+                });
+            } else if next_two == b"r'" || next_two == b"r\"" {
+                let quote = program[position + 1];
+                let start = position + 2;
+                let mut end = start;
+                loop {
+                    match program[..self.stop].get(end) {
+                        None => {
+                            return Err(self
+                                .scanner
+                                .error("unterminated string", start))
+                        }
+                        // Skip over escaped characters
+                        Some(b'\\') => end += 2,
+                        Some(&d) if d == quote => break,
+                        Some(_) => end += 1,
+                    }
+                }
+                self.position = end + 1;
+                return Ok(Token {
+                    kind: TokenKind::String(program[start..end].to_vec()),
+                    position: start,
+                });
+            } else if c.is_ascii_digit() {
+                let end = program[position..self.stop]
+                    .iter()
+                    .position(|b| !b.is_ascii_digit())
+                    .map_or(self.stop, |offset| position + offset);
+                self.position = end;
+                let digits = &program[position..end];
+                return Ok(Token {
+                    kind: TokenKind::Integer(
+                        String::from_utf8_lossy(digits).into_owned(),
+                    ),
+                    position,
+                });
+            } else if next_two == b"\\'"
+                || next_two == b"\\\""
+                || next_three == b"r\\'"
+                || next_three == b"r\\\""
+            {
+                return Err(HgError::unsupported(
+                    "escaped quoted strings in templates",
+                ));
+            } else if c.is_ascii_alphanumeric() || c == b'_' {
+                let end = program[position..self.stop]
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                    .map_or(self.stop, |offset| position + offset);
+                self.position = end;
+                let symbol = &program[position..end];
+                return Ok(Token {
+                    kind: TokenKind::Symbol(
+                        String::from_utf8_lossy(symbol).into_owned(),
+                    ),
+                    position,
+                });
+            } else if c == b'}' {
+                self.done = true;
+                return Ok(Token {
+                    kind: TokenKind::End,
+                    position,
+                });
+            } else {
+                return Err(self.scanner.error("syntax error", position));
+            }
+        }
+        Err(self
+            .scanner
+            .error("unterminated template expansion", self.start))
+    }
+}
+
+struct Parser<'s, 'a> {
+    tokens: Tokenizer<'s, 'a>,
+    current: Token,
+}
+
+impl<'s, 'a> Parser<'s, 'a> {
+    fn new(mut tokens: Tokenizer<'s, 'a>) -> Result<Self, HgError> {
+        let current = tokens.next_token()?;
+        Ok(Self { tokens, current })
+    }
+
+    fn error(&self, message: impl Into<String>, position: usize) -> HgError {
+        self.tokens.scanner.error(message, position)
+    }
+
+    /// Parse a whole expression, returning it with the position of the
+    /// token where parsing stopped
+    fn parse_all(mut self) -> Result<(Tree, usize), HgError> {
+        let tree = self.parse(0)?;
+        Ok((tree, self.current.position))
+    }
+
+    fn advance(&mut self) -> Result<Token, HgError> {
+        let next = self.tokens.next_token()?;
+        Ok(std::mem::replace(&mut self.current, next))
+    }
+
+    /// Parse the operand of `(`, up to the closing parenthesis
+    fn parenthesized(&mut self) -> Result<Option<Box<Tree>>, HgError> {
+        let tree = if self.current.kind == TokenKind::Op(b')') {
+            None
+        } else {
+            Some(Box::new(self.parse(1)?))
+        };
+        if self.current.kind != TokenKind::Op(b')') {
+            return Err(self.error(
+                format!("unexpected token: {}", describe(&self.current.kind)),
+                self.current.position,
+            ));
+        }
+        self.advance()?;
+        Ok(tree)
+    }
+
+    fn parse(&mut self, bind: u8) -> Result<Tree, HgError> {
+        let Token { kind, position } = self.advance()?;
+        let mut tree = match kind {
+            TokenKind::Integer(i) => Tree::Integer(i),
+            TokenKind::Symbol(s) => Tree::Symbol(s),
+            TokenKind::String(s) => Tree::String(s),
+            TokenKind::Template(parts) => Tree::Template(parts),
+            TokenKind::Op(b'(') => Tree::Group(self.parenthesized()?),
+            TokenKind::Op(b'-') => Tree::Negate(Box::new(self.parse(19)?)),
+            other => {
+                return Err(self.error(
+                    format!("not a prefix: {}", describe(&other)),
+                    position,
+                ))
+            }
+        };
+        while bind < binding_strength(&self.current.kind) {
+            let Token { kind, position } = self.advance()?;
+            tree = match kind {
+                TokenKind::Op(b'(') => {
+                    Tree::Func(Box::new(tree), self.parenthesized()?)
+                }
+                TokenKind::Op(op) if binding_strength(&kind) > 0 => {
+                    let right = self.parse(binding_strength(&kind))?;
+                    Tree::Infix(op, Box::new(tree), Box::new(right))
+                }
+                other => {
+                    return Err(self.error(
+                        format!("not an infix: {}", describe(&other)),
+                        position,
+                    ))
+                }
+            };
+        }
+        Ok(tree)
+    }
+}
+
+/// Replace lists of template parts by simpler nodes where possible, like
+/// `_unnesttemplatelist` in Python.
+fn unnest(tree: Tree) -> Tree {
+    let boxed = |tree: Box<Tree>| Box::new(unnest(*tree));
+    match tree {
+        Tree::Template(parts) => {
+            let mut parts: Vec<Tree> = parts.into_iter().map(unnest).collect();
+            match parts.len() {
+                0 => Tree::String(Vec::new()),
+                1 if matches_string(&parts[0]) => parts.remove(0),
+                _ => Tree::Template(parts),
+            }
+        }
+        Tree::Group(tree) => Tree::Group(tree.map(boxed)),
+        Tree::Func(name, arguments) => {
+            Tree::Func(boxed(name), arguments.map(boxed))
+        }
+        Tree::Negate(tree) => Tree::Negate(boxed(tree)),
+        Tree::Infix(op, left, right) => {
+            Tree::Infix(op, boxed(left), boxed(right))
+        }
+        other => other,
+    }
+}
+
+fn matches_string(tree: &Tree) -> bool {
+    if let Tree::String(_) = tree {
+        true
+    } else {
+        false
+    }
+}
+
+/// Flatten `a, b, c` function arguments, like `getlist` in Python
+fn arguments(tree: Option<Box<Tree>>) -> Vec<Tree> {
+    match tree.map(|tree| *tree) {
+        None => Vec::new(),
+        Some(Tree::Infix(b',', left, right)) => {
+            let mut list = arguments(Some(left));
+            list.push(*right);
+            list
+        }
+        Some(other) => vec![other],
+    }
+}
+
+fn symbol_name(tree: Tree) -> Result<String, HgError> {
+    match tree {
+        Tree::Symbol(name) => Ok(name),
+        other => Err(parse_error(format!(
+            "expected a symbol, got '{}'",
+            other.describe()
+        ))),
+    }
+}
+
+/// Turn a parse tree into an expression, like `compileexp` in Python.
+///
+/// At the top level of a template, integers are keywords: `{1}` is a
+/// variable. Elsewhere, such as in function arguments, they are numbers.
+fn compile(tree: Tree, top_level: bool) -> Result<Expr, HgError> {
+    let boxed = |tree: Tree, top_level| -> Result<Box<Expr>, HgError> {
+        Ok(Box::new(compile(tree, top_level)?))
+    };
+    Ok(match tree {
+        Tree::Integer(digits) if top_level => Expr::Symbol(digits),
+        Tree::Integer(digits) => {
+            Expr::Integer(digits.parse().map_err(|_| {
+                HgError::unsupported("large integers in templates")
+            })?)
+        }
+        Tree::Symbol(name) => Expr::Symbol(name),
+        Tree::String(s) => Expr::String(s),
+        Tree::Template(parts) => Expr::Template(
+            parts
+                .into_iter()
+                .map(|part| compile(part, true))
+                .collect::<Result<_, _>>()?,
+        ),
+        Tree::Group(None) => return Err(parse_error("missing argument")),
+        Tree::Group(Some(tree)) => compile(*tree, false)?,
+        Tree::Negate(tree) => Expr::Negate(boxed(*tree, false)?),
+        Tree::Func(name, args) => {
+            let name = symbol_name(*name)?;
+            compile_function(&name, arguments(args), false)?
+        }
+        Tree::Infix(b'|', left, right) => {
+            let name = symbol_name(*right)?;
+            match Filter::from_name(&name) {
+                Some(filter) => Expr::Filter(filter, boxed(*left, true)?),
+                // `x|f` is also `f(x)` for functions
+                None => compile_function(&name, vec![*left], true)?,
+            }
+        }
+        Tree::Infix(b'%', left, right) => {
+            let template = match *right {
+                tree @ Tree::Template(_) | tree @ Tree::String(_) => {
+                    compile(tree, true)?
+                }
+                Tree::Symbol(_) => {
+                    return Err(HgError::unsupported("named templates"))
+                }
+                _ => return Err(parse_error("expected template specifier")),
+            };
+            Expr::Map(boxed(*left, true)?, Box::new(template))
+        }
+        Tree::Infix(b'.', _, _) => {
+            return Err(HgError::unsupported("template member operator"))
+        }
+        Tree::Infix(b'=', _, _) => {
+            return Err(parse_error(
+                "can't use a key-value pair in this context",
+            ))
+        }
+        Tree::Infix(b',', _, _) => {
+            return Err(HgError::abort(
+                "hg: parse error: can't use a list in this context\n\
+                 (check place of comma and parens)",
+            ))
+        }
+        Tree::Infix(op, left, right) => {
+            Expr::Arithmetic(op, boxed(*left, false)?, boxed(*right, false)?)
+        }
+    })
+}
+
+/// Compile a call to the function or filter `name`. Arguments are compiled
+/// as top-level expressions for the `x|function` syntax.
+fn compile_function(
+    name: &str,
+    args: Vec<Tree>,
+    top_level: bool,
+) -> Result<Expr, HgError> {
+    if let Some(filter) = Filter::from_name(name) {
+        if args.len() != 1 {
+            return Err(parse_error(format!(
+                "filter {} expects one argument",
+                name
+            )));
+        }
+        let arg = args.into_iter().next().expect("one argument");
+        return Ok(Expr::Filter(filter, Box::new(compile(arg, top_level)?)));
+    }
+    if name == "pad" {
+        return compile_pad(args, top_level);
+    }
+    let arity_error = match name {
+        "if" if args.len() < 2 || args.len() > 3 => {
+            "if expects two or three arguments"
+        }
+        "join" if args.is_empty() || args.len() > 2 => {
+            "join expects one or two arguments"
+        }
+        "label" if args.len() != 2 => "label expects two arguments",
+        "if" | "join" | "label" => "",
+        _ => {
+            return Err(HgError::unsupported(format!(
+                "template function '{}'",
+                name
+            )))
+        }
+    };
+    if !arity_error.is_empty() {
+        return Err(parse_error(arity_error));
+    }
+    let mut args = args
+        .into_iter()
+        .map(|arg| Ok(Box::new(compile(arg, top_level)?)))
+        .collect::<Result<Vec<_>, HgError>>()?
+        .into_iter();
+    let mut next = || args.next().expect("arity was checked");
+    Ok(match name {
+        "if" => Expr::If(next(), next(), args.next()),
+        "join" => Expr::Join(next(), args.next()),
+        _ => Expr::Label(next(), next()),
+    })
+}
+
+/// `pad()` is the only supported function with named arguments
+fn compile_pad(args: Vec<Tree>, top_level: bool) -> Result<Expr, HgError> {
+    const NAMES: [&str; 5] = ["text", "width", "fillchar", "left", "truncate"];
+    let mut slots: [Option<Box<Expr>>; 5] = Default::default();
+    let mut keyword_seen = false;
+    for (index, arg) in args.into_iter().enumerate() {
+        let (slot, arg) = match arg {
+            Tree::Infix(b'=', key, value) => {
+                keyword_seen = true;
+                let key = symbol_name(*key)?;
+                let slot = NAMES.iter().position(|name| *name == key);
+                match slot {
+                    Some(slot) if slots[slot].is_none() => (slot, *value),
+                    _ => {
+                        return Err(HgError::unsupported(
+                            "invalid pad() arguments",
+                        ))
+                    }
+                }
+            }
+            _ if keyword_seen || index >= NAMES.len() => {
+                return Err(HgError::unsupported("invalid pad() arguments"))
+            }
+            arg => (index, arg),
+        };
+        slots[slot] = Some(Box::new(compile(arg, top_level)?));
+    }
+    let [text, width, fill_char, left, truncate] = slots;
+    match (text, width) {
+        (Some(text), Some(width)) => Ok(Expr::Pad {
+            text,
+            width,
+            fill_char,
+            left,
+            truncate,
+        }),
+        _ => Err(parse_error("pad() expects two to four arguments")),
+    }
+}
+
+/// Parse and compile a template
+pub(super) fn parse(template: &[u8]) -> Result<Expr, HgError> {
+    let scanner = Scanner { program: template };
+    let (parts, _) = scanner.scan_template(0, template.len(), None)?;
+    compile(unnest(Tree::Template(parts)), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(template: &str) -> Result<Expr, String> {
+        parse(template.as_bytes()).map_err(|error| error.to_string())
+    }
+
+    fn string(s: &str) -> Expr {
+        Expr::String(s.as_bytes().to_vec())
+    }
+
+    fn symbol(s: &str) -> Box<Expr> {
+        Box::new(Expr::Symbol(s.to_owned()))
+    }
+
+    #[test]
+    fn test_literal_text() {
+        assert_eq!(parse_str(""), Ok(string("")));
+        assert_eq!(parse_str(r"a\tb\n"), Ok(string("a\tb\n")));
+        assert_eq!(
+            parse_str(r"a\{b}\\{rev}"),
+            Ok(Expr::Template(vec![
+                string("a{"),
+                string("b}\\"),
+                Expr::Symbol("rev".to_owned())
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_eq!(
+            parse_str("{node|short}"),
+            Ok(Expr::Template(vec![Expr::Filter(
+                Filter::Short,
+                symbol("node")
+            )]))
+        );
+        assert_eq!(
+            parse_str("{files % '{file}\\n'}"),
+            Ok(Expr::Template(vec![Expr::Map(
+                symbol("files"),
+                Box::new(Expr::Template(vec![
+                    Expr::Symbol("file".to_owned()),
+                    string("\n")
+                ]))
+            )]))
+        );
+        assert_eq!(
+            parse_str("{if(tags, r'\\n', '')}"),
+            Ok(Expr::Template(vec![Expr::If(
+                symbol("tags"),
+                Box::new(string("\\n")),
+                Some(Box::new(string("")))
+            )]))
+        );
+        assert_eq!(
+            parse_str("{pad(rev, 1 + 2, left=True)}"),
+            Ok(Expr::Template(vec![Expr::Pad {
+                text: symbol("rev"),
+                width: Box::new(Expr::Arithmetic(
+                    b'+',
+                    Box::new(Expr::Integer(1)),
+                    Box::new(Expr::Integer(2))
+                )),
+                fill_char: None,
+                left: Some(symbol("True")),
+                truncate: None,
+            }]))
+        );
+        assert_eq!(
+            parse_str("{desc|firstline|json}"),
+            Ok(Expr::Template(vec![Expr::Filter(
+                Filter::Json,
+                Box::new(Expr::Filter(Filter::FirstLine, symbol("desc")))
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse_str("{node|}"),
+            Err("hg: parse error at 6: not a prefix: end\n\
+                 ({node|}\n       ^ here)"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_str("a\n{rev"),
+            Err("hg: parse error at 3: unterminated template expansion\n\
+                 (a\\n{rev\n     ^ here)"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_str("{rev rev}"),
+            Err("hg: parse error at 5: invalid token\n\
+                 ({rev rev}\n      ^ here)"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_str("{if(rev)}"),
+            Err("hg: parse error: if expects two or three arguments"
+                .to_owned())
+        );
+        assert_eq!(
+            parse_str("{rev|pad(3)}"),
+            Err("hg: parse error: expected a symbol, got 'func'".to_owned())
+        );
+        assert!(parse_str("{shortest(node)}")
+            .unwrap_err()
+            .contains("unsupported feature"));
+    }
+}
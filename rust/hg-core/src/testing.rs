@@ -70,3 +70,22 @@ impl Graph for VecGraph {
         Ok(self[rev as usize])
     }
 }
+
+/// Create an empty repository in `root` that has `requirements` besides
+/// `dotencode`, `fncache`, `revlogv1` and `store`
+#[cfg(test)]
+pub(crate) fn repo_with(
+    root: &std::path::Path,
+    requirements: &[&str],
+) -> crate::repo::Repo {
+    std::fs::create_dir_all(root.join(".hg/store")).unwrap();
+    let mut requires = "dotencode\nfncache\nrevlogv1\nstore\n".to_owned();
+    for requirement in requirements {
+        requires.push_str(requirement);
+        requires.push('\n');
+    }
+    std::fs::write(root.join(".hg/requires"), requires).unwrap();
+    let config =
+        crate::config::Config::load_from_explicit_sources(Vec::new()).unwrap();
+    crate::repo::Repo::find(&config, Some(root)).unwrap()
+}
@@ -0,0 +1,390 @@
+//! Journaling of changes to the files of the store, so that they can be
+//! undone when interrupted, like `mercurial/transaction.py`.
+//!
+//! The journal files have the same format as in Python, so that `hg recover`
+//! can roll back a transaction interrupted by a crash.
+
+use crate::errors::{HgError, HgResultExt, IoErrorContext, IoResultExt};
+#[cfg(test)]
+use crate::repo::Repo;
+use crate::repo::Vfs;
+use crate::utils::files::get_bytes_from_path;
+use format_bytes::format_bytes;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL: &str = "journal";
+const BACKUP_JOURNAL: &str = "journal.backupfiles";
+/// Version of the format of `journal.backupfiles`
+#[cfg(test)]
+const BACKUP_JOURNAL_VERSION: u32 = 2;
+
+/// A transaction on the store of a repository
+///
+/// Before a file is appended to, its size is recorded in `.hg/store/journal`
+/// with `add`. A file rewritten in place is first copied with `add_backup`.
+/// A transaction dropped without being closed is rolled back: files are
+/// truncated to their recorded size, or restored from their backup.
+///
+/// As in Python, writers are expected to hold the store lock for as long as
+/// the transaction is open. Since the store cannot be locked from Rust yet,
+/// only tests start transactions.
+pub struct Transaction<'repo> {
+    store_vfs: Vfs<'repo>,
+    journal: File,
+    backup_journal: File,
+    /// Files appended to, with their size before the transaction
+    entries: Vec<(PathBuf, u64)>,
+    /// Files rewritten, with their copy when they existed before
+    backups: Vec<(PathBuf, Option<PathBuf>)>,
+    /// Files that are obsolete once the transaction is closed
+    removals: Vec<PathBuf>,
+    done: bool,
+}
+
+impl<'repo> Transaction<'repo> {
+    /// Start a transaction, unless an interrupted one needs to be recovered
+    /// first
+    #[cfg(test)]
+    pub(crate) fn start(repo: &'repo Repo) -> Result<Self, HgError> {
+        let store_vfs = repo.store_vfs();
+        let journal_path = store_vfs.join(JOURNAL);
+        let journal = match OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&journal_path)
+        {
+            Ok(journal) => journal,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                return Err(HgError::abort(
+                    "abort: abandoned transaction found!\n\
+                     (run 'hg recover' to clean up transaction)",
+                ))
+            }
+            Err(error) => return Err(error).when_writing_file(&journal_path),
+        };
+        let backup_journal_path = store_vfs.join(BACKUP_JOURNAL);
+        let mut backup_journal = File::create(&backup_journal_path)
+            .when_writing_file(&backup_journal_path)?;
+        backup_journal
+            .write_all(&format_bytes!(b"{}\n", BACKUP_JOURNAL_VERSION))
+            .when_writing_file(&backup_journal_path)?;
+        Ok(Self {
+            store_vfs,
+            journal,
+            backup_journal,
+            entries: Vec::new(),
+            backups: Vec::new(),
+            removals: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// The store that this transaction writes to
+    pub fn store_vfs(&self) -> Vfs<'repo> {
+        self.store_vfs
+    }
+
+    /// Record the size of a file, relative to the store, before appending to
+    /// it
+    ///
+    /// Only the first size recorded for a file counts. Files with a backup
+    /// are restored from it instead.
+    pub fn add(
+        &mut self,
+        path: impl AsRef<Path>,
+        offset: u64,
+    ) -> Result<(), HgError> {
+        let path = path.as_ref();
+        if self.find_offset(path).is_some() || self.has_backup(path) {
+            return Ok(());
+        }
+        self.journal
+            .write_all(&journal_entry(path, offset))
+            .when_writing_file(&self.store_vfs.join(JOURNAL))?;
+        self.entries.push((path.to_owned(), offset));
+        Ok(())
+    }
+
+    /// The size recorded for a file, if any
+    pub fn find_offset(&self, path: impl AsRef<Path>) -> Option<u64> {
+        let path = path.as_ref();
+        self.entries
+            .iter()
+            .find(|(file, _)| file == path)
+            .map(|&(_, offset)| offset)
+    }
+
+    /// Change the size recorded for a file, after rewriting it
+    ///
+    /// Unlike in Python, the journal is rewritten rather than appended to,
+    /// so that playing it back never sees the former size.
+    pub fn replace(
+        &mut self,
+        path: impl AsRef<Path>,
+        offset: u64,
+    ) -> Result<(), HgError> {
+        let path = path.as_ref();
+        match self.entries.iter_mut().find(|(file, _)| file == path) {
+            Some(entry) => entry.1 = offset,
+            None => {
+                return Err(HgError::abort(format!(
+                    "abort: {} not found in the transaction",
+                    path.display()
+                )))
+            }
+        }
+        let content: Vec<u8> = self
+            .entries
+            .iter()
+            .flat_map(|(file, offset)| journal_entry(file, *offset))
+            .collect();
+        let temporary = format!("{}.tmp", JOURNAL);
+        let temporary_path = self.store_vfs.join(&temporary);
+        std::fs::write(&temporary_path, content)
+            .when_writing_file(&temporary_path)?;
+        self.store_vfs.rename(&temporary, JOURNAL)?;
+        let journal_path = self.store_vfs.join(JOURNAL);
+        self.journal = OpenOptions::new()
+            .append(true)
+            .open(&journal_path)
+            .when_writing_file(&journal_path)?;
+        Ok(())
+    }
+
+    /// Keep a copy of a file, relative to the store, as it was before the
+    /// transaction, to restore it instead of truncating it
+    pub fn add_backup(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), HgError> {
+        let path = path.as_ref();
+        if self.has_backup(path) {
+            return Ok(());
+        }
+        let size = match self.find_offset(path) {
+            // Created by this transaction, it is removed on rollback
+            Some(0) => return Ok(()),
+            size => size,
+        };
+        let backup = match self.store_vfs.read(path).io_not_found_as_none()? {
+            Some(mut content) => {
+                if let Some(size) = size {
+                    content.truncate(size as usize)
+                }
+                let name = path.file_name().expect("expected a file name");
+                let mut backup_name = format!("{}.backup.", JOURNAL);
+                backup_name.push_str(&name.to_string_lossy());
+                let backup = path.with_file_name(backup_name);
+                let backup_path = self.store_vfs.join(&backup);
+                std::fs::write(&backup_path, content)
+                    .when_writing_file(&backup_path)?;
+                Some(backup)
+            }
+            None => None,
+        };
+        let backup_bytes = match &backup {
+            Some(backup) => get_bytes_from_path(backup),
+            None => Vec::new(),
+        };
+        // Entries are `location\0file\0backup\0cache`, where the empty
+        // location is the store
+        self.backup_journal
+            .write_all(&format_bytes!(
+                b"\0{}\0{}\00\n",
+                get_bytes_from_path(path),
+                backup_bytes
+            ))
+            .when_writing_file(&self.store_vfs.join(BACKUP_JOURNAL))?;
+        self.backups.push((path.to_owned(), backup));
+        Ok(())
+    }
+
+    /// Remove a file, relative to the store, once the transaction is closed
+    /// successfully, like cleanup callbacks registered with `addpostclose`
+    /// in Python
+    pub fn remove_on_close(&mut self, path: impl AsRef<Path>) {
+        self.removals.push(path.as_ref().to_owned())
+    }
+
+    fn has_backup(&self, path: &Path) -> bool {
+        self.backups.iter().any(|(file, _)| file == path)
+    }
+
+    /// Make the changes of the transaction permanent
+    ///
+    /// Python renames the journal to the `undo` files of `hg rollback`,
+    /// along with the `.hg/undo.*` files it writes. This is not done here,
+    /// and rollback information left by Python is not touched.
+    pub fn close(mut self) -> Result<(), HgError> {
+        self.done = true;
+        for (_, backup) in &self.backups {
+            if let Some(backup) = backup {
+                remove_file(self.store_vfs, backup)?
+            }
+        }
+        remove_file(self.store_vfs, BACKUP_JOURNAL)?;
+        remove_file(self.store_vfs, JOURNAL)?;
+        for file in &self.removals {
+            remove_file(self.store_vfs, file)?
+        }
+        Ok(())
+    }
+
+    /// Undo the changes of the transaction
+    pub fn abort(mut self) -> Result<(), HgError> {
+        self.done = true;
+        self.rollback()
+    }
+
+    /// Like `_playback` in Python
+    fn rollback(&self) -> Result<(), HgError> {
+        for (file, offset) in &self.entries {
+            if *offset == 0 {
+                remove_file(self.store_vfs, file)?;
+                continue;
+            }
+            let path = self.store_vfs.join(file);
+            let handle = OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .when_writing_file(&path)?;
+            let size = handle.metadata().when_reading_file(&path)?.len();
+            if size < *offset {
+                return Err(HgError::abort(format!(
+                    "abort: attempted to truncate {} to {} bytes, but it was \
+                     already {} bytes",
+                    file.display(),
+                    offset,
+                    size
+                )));
+            }
+            handle.set_len(*offset).when_writing_file(&path)?;
+        }
+        for (file, backup) in &self.backups {
+            match backup {
+                Some(backup) => {
+                    let from = self.store_vfs.join(backup);
+                    let to = self.store_vfs.join(file);
+                    std::fs::copy(&from, &to).when_writing_file(&to)?;
+                }
+                None => remove_file(self.store_vfs, file)?,
+            }
+        }
+        remove_file(self.store_vfs, BACKUP_JOURNAL)?;
+        remove_file(self.store_vfs, JOURNAL)?;
+        for (_, backup) in &self.backups {
+            if let Some(backup) = backup {
+                remove_file(self.store_vfs, backup)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An interrupted transaction is rolled back, unless that fails: the journal
+/// is then left for `hg recover`.
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.rollback();
+        }
+    }
+}
+
+fn journal_entry(path: &Path, offset: u64) -> Vec<u8> {
+    format_bytes!(b"{}\0{}\n", get_bytes_from_path(path), offset)
+}
+
+/// Remove a file relative to the given `Vfs`, if it exists
+fn remove_file(vfs: Vfs, path: impl AsRef<Path>) -> Result<(), HgError> {
+    let path = vfs.join(path);
+    match std::fs::remove_file(&path) {
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        result => {
+            result.with_context(|| IoErrorContext::RemovingFile(path.clone()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::repo_with;
+
+    #[test]
+    fn test_rollback() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &[]);
+        let store = root.path().join(".hg/store");
+        std::fs::write(store.join("appended"), "before").unwrap();
+        std::fs::write(store.join("rewritten"), "original").unwrap();
+
+        let mut transaction = Transaction::start(&repo).unwrap();
+        assert!(Transaction::start(&repo).is_err());
+        transaction.add("appended", 6).unwrap();
+        transaction.add("appended", 12).unwrap();
+        transaction.add("created", 0).unwrap();
+        transaction.add_backup("rewritten").unwrap();
+        assert_eq!(transaction.find_offset("appended"), Some(6));
+        assert_eq!(
+            std::fs::read(store.join("journal")).unwrap(),
+            b"appended\x006\ncreated\x000\n"
+        );
+        assert_eq!(
+            std::fs::read(store.join("journal.backupfiles")).unwrap(),
+            b"2\n\0rewritten\0journal.backup.rewritten\x000\n"
+        );
+        std::fs::write(store.join("appended"), "before, after").unwrap();
+        std::fs::write(store.join("created"), "new").unwrap();
+        std::fs::write(store.join("rewritten"), "changed").unwrap();
+        drop(transaction);
+
+        let read = |name| std::fs::read(store.join(name)).unwrap();
+        assert_eq!(read("appended"), b"before");
+        assert_eq!(read("rewritten"), b"original");
+        assert!(!store.join("created").exists());
+        assert!(!store.join("journal").exists());
+        assert!(!store.join("journal.backup.rewritten").exists());
+    }
+
+    #[test]
+    fn test_close() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = repo_with(root.path(), &[]);
+        let store = root.path().join(".hg/store");
+        std::fs::write(store.join("rewritten"), "original").unwrap();
+        std::fs::write(store.join("obsolete"), "old").unwrap();
+        std::fs::write(store.join("undo"), "previous\x000\n").unwrap();
+
+        let mut transaction = Transaction::start(&repo).unwrap();
+        transaction.add("created", 0).unwrap();
+        transaction.add_backup("rewritten").unwrap();
+        transaction.replace("created", 3).unwrap();
+        transaction.remove_on_close("obsolete");
+        assert_eq!(
+            std::fs::read(store.join("journal")).unwrap(),
+            b"created\x003\n"
+        );
+        std::fs::write(store.join("created"), "new").unwrap();
+        std::fs::write(store.join("rewritten"), "changed").unwrap();
+        transaction.close().unwrap();
+
+        assert_eq!(std::fs::read(store.join("created")).unwrap(), b"new");
+        assert_eq!(
+            std::fs::read(store.join("rewritten")).unwrap(),
+            b"changed"
+        );
+        let leftovers: Vec<_> = std::fs::read_dir(&store)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with("journal"))
+            .collect();
+        assert!(leftovers.is_empty());
+        assert!(!store.join("obsolete").exists());
+        // Rollback information of Python is left alone
+        assert!(store.join("undo").exists());
+        assert!(Transaction::start(&repo).is_ok());
+    }
+}
@@ -198,6 +198,15 @@ pub(crate) fn strip_suffix<'a>(s: &'a str, suffix: &str) -> Option<&'a str> {
     }
 }
 
+// TODO: use the str method when we require Rust 1.45
+pub(crate) fn strip_prefix<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.starts_with(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
 #[cfg(unix)]
 pub fn shell_quote(value: &[u8]) -> Vec<u8> {
     // TODO: Use the `matches!` macro when we require Rust 1.42+
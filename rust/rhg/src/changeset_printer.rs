@@ -0,0 +1,278 @@
+//! Showing changesets like `hg log` does, for the commands that list
+//! changesets, like `logcmdutil.changesetdisplayer` in Python
+
+use crate::error::CommandError;
+use crate::ui::{formatter, StdoutBuffer, Ui};
+use format_bytes::format_bytes;
+use hg::config::Config;
+use hg::errors::HgError;
+use hg::namespaces::Namespaces;
+use hg::obsolete::ObsoleteSets;
+use hg::phases::{Phase, Phases};
+use hg::repo::Repo;
+use hg::revlog::changelog::{Changelog, ChangelogRevisionData};
+use hg::revlog::node::NULL_NODE;
+use hg::revlog::revlog::RevlogError;
+use hg::revlog::{Node, Revision, NULL_REVISION};
+use hg::templater::{Formatter, KeywordProvider, Value};
+use hg::utils::SliceExt;
+use hg::Graph;
+use std::io::Write;
+
+/// Writes changesets either in the default format of `hg log`, or with the
+/// formatter selected with `-T/--template`
+pub struct ChangesetPrinter {
+    formatter: Option<Formatter>,
+}
+
+impl ChangesetPrinter {
+    /// Fails as unsupported for configuration that changes the default
+    /// output in ways that are not implemented
+    pub fn new(
+        config: &Config,
+        template: Option<&str>,
+    ) -> Result<Self, CommandError> {
+        for &(section, item) in &[
+            (&b"ui"[..], &b"verbose"[..]),
+            (b"ui", b"debug"),
+            (b"ui", b"quiet"),
+        ] {
+            if config.get_bool(section, item)? {
+                return Err(CommandError::unsupported(format!(
+                    "non-default {}.{}",
+                    String::from_utf8_lossy(section),
+                    String::from_utf8_lossy(item)
+                )));
+            }
+        }
+        let formatter = formatter(config, template)?;
+        if formatter.is_none() {
+            for &(section, item) in &[
+                (&b"command-templates"[..], &b"log"[..]),
+                (b"ui", b"logtemplate"),
+                (b"ui", b"style"),
+            ] {
+                if config.get(section, item).is_some() {
+                    return Err(CommandError::unsupported(
+                        "configured log template",
+                    ));
+                }
+            }
+        }
+        Ok(Self { formatter })
+    }
+
+    /// Write the given changesets, in order
+    pub fn print(
+        &mut self,
+        ui: &Ui,
+        repo: &Repo,
+        changelog: &Changelog,
+        namespaces: &Namespaces,
+        revisions: impl IntoIterator<Item = Revision>,
+    ) -> Result<(), CommandError> {
+        let phases = repo.phases(changelog)?;
+        let mut stdout = ui.stdout_buffer();
+        if let Some(formatter) = &self.formatter {
+            stdout.write_all(formatter.start())?;
+        }
+        for rev in revisions {
+            let changeset =
+                Changeset::new(changelog, namespaces, &phases, rev)?;
+            match &mut self.formatter {
+                Some(Formatter::Template(template)) => {
+                    stdout.write_all(&template.render(&changeset)?)?
+                }
+                Some(formatter) => {
+                    stdout.write_all(&formatter.item(&changeset.fields()?)?)?
+                }
+                None => changeset.write_default(&mut stdout)?,
+            }
+        }
+        if let Some(formatter) = &self.formatter {
+            stdout.write_all(formatter.end())?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Hidden changesets, and what is shown about obsolete and unstable ones,
+/// are not supported: fail when there are some
+pub fn check_no_obsolete_changesets(repo: &Repo) -> Result<(), CommandError> {
+    let obsstore = repo.obsstore()?;
+    if obsstore.is_empty() {
+        return Ok(());
+    }
+    let changelog = Changelog::open(repo)?;
+    let phases = repo.phases(&changelog)?;
+    let sets = ObsoleteSets::compute(&obsstore, &changelog, &phases)?;
+    if sets == ObsoleteSets::default() {
+        Ok(())
+    } else {
+        Err(CommandError::unsupported("obsolete or unstable changesets"))
+    }
+}
+
+/// The data shown for one changeset
+struct Changeset {
+    rev: Revision,
+    node: Node,
+    data: ChangelogRevisionData,
+    branch: Vec<u8>,
+    bookmarks: Vec<Vec<u8>>,
+    tags: Vec<Vec<u8>>,
+    parents: Vec<(Revision, Node)>,
+    phase: Phase,
+}
+
+impl Changeset {
+    fn new(
+        changelog: &Changelog,
+        namespaces: &Namespaces,
+        phases: &Phases,
+        rev: Revision,
+    ) -> Result<Self, CommandError> {
+        let node_of = |rev| {
+            if rev == NULL_REVISION {
+                Ok(NULL_NODE)
+            } else {
+                changelog.node_from_rev(rev).copied().ok_or_else(|| {
+                    CommandError::from(RevlogError::InvalidRevision)
+                })
+            }
+        };
+        let node = node_of(rev)?;
+        let data = changelog.get_rev(rev)?;
+        let (branch, _closed) = data.branchinfo()?;
+        let to_vecs = |names: Vec<&[u8]>| {
+            names.into_iter().map(|name| name.to_vec()).collect()
+        };
+        let bookmarks = to_vecs(namespaces.bookmarks()?.names_for(&node));
+        let tags = to_vecs(namespaces.tags()?.names_for(&node));
+        let parent_revs = if rev == NULL_REVISION {
+            [NULL_REVISION, NULL_REVISION]
+        } else {
+            changelog.parents(rev).map_err(RevlogError::from)?
+        };
+        let mut parents = Vec::new();
+        for &parent in parent_revs.iter() {
+            parents.push((parent, node_of(parent)?))
+        }
+        Ok(Self {
+            rev,
+            node,
+            data,
+            branch,
+            bookmarks,
+            tags,
+            parents,
+            phase: phases.phase(rev)?,
+        })
+    }
+
+    /// The parents worth showing, like `scmutil.meaningfulparents`: none for
+    /// a single parent that is the previous revision.
+    fn meaningful_parents(&self) -> &[(Revision, Node)] {
+        if self.parents[1].0 != NULL_REVISION {
+            &self.parents
+        } else if self.parents[0].0 >= self.rev - 1 {
+            &[]
+        } else {
+            &self.parents[..1]
+        }
+    }
+
+    /// Write the output of `changesetprinter` in Python, without options
+    fn write_default(
+        &self,
+        out: &mut StdoutBuffer<impl Write>,
+    ) -> Result<(), CommandError> {
+        let short = |node: &Node| format!("{:x}", node.short()).into_bytes();
+        out.write_all(&format_bytes!(
+            b"changeset:   {}:{}\n",
+            self.rev,
+            short(&self.node)
+        ))?;
+        if self.branch != b"default" {
+            out.write_all(&format_bytes!(b"branch:      {}\n", self.branch))?;
+        }
+        for bookmark in &self.bookmarks {
+            out.write_all(&format_bytes!(b"bookmark:    {}\n", bookmark))?;
+        }
+        for tag in &self.tags {
+            out.write_all(&format_bytes!(b"tag:         {}\n", tag))?;
+        }
+        for (rev, node) in self.meaningful_parents() {
+            out.write_all(&format_bytes!(
+                b"parent:      {}:{}\n",
+                rev,
+                short(node)
+            ))?;
+        }
+        out.write_all(&format_bytes!(b"user:        {}\n", self.data.user()))?;
+        let date = self.data.date()?.format("%a %b %d %H:%M:%S %Y %1%2");
+        out.write_all(&format_bytes!(b"date:        {}\n", date.as_bytes()))?;
+        let description = self.data.description().trim();
+        if let Some(summary) = description
+            .split(|&b| b == b'\n' || b == b'\r')
+            .next()
+            .filter(|_| !description.is_empty())
+        {
+            out.write_all(&format_bytes!(b"summary:     {}\n", summary))?;
+        }
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// The fields of `-T json`, like `changesetformatter` in Python
+    fn fields(&self) -> Result<Vec<(&'static str, Value)>, CommandError> {
+        let parents = match self.parents[..] {
+            [p1, (NULL_REVISION, _)] => vec![p1],
+            _ => self.parents.clone(),
+        };
+        let hexes = parents.iter().map(|(_, node)| hex(node));
+        Ok(vec![
+            ("bookmarks", Value::list("bookmark", self.bookmarks.clone())),
+            ("branch", self.branch.clone().into()),
+            ("date", self.data.date()?.into()),
+            ("desc", self.data.description().into()),
+            ("node", hex(&self.node).into()),
+            ("parents", Value::list("node", hexes)),
+            ("phase", self.phase.name().into()),
+            ("rev", self.rev.into()),
+            ("tags", Value::list("tag", self.tags.clone())),
+            ("user", self.data.user().into()),
+        ])
+    }
+}
+
+/// The keywords available in `--template`
+impl KeywordProvider for Changeset {
+    fn keyword(&self, name: &str) -> Result<Option<Value>, HgError> {
+        Ok(Some(match name {
+            "author" | "user" => self.data.user().into(),
+            "bookmarks" => Value::list("bookmark", self.bookmarks.clone()),
+            "branch" => self.branch.clone().into(),
+            "date" => self.data.date()?.into(),
+            "desc" => self.data.description().trim().into(),
+            "files" => Value::list(
+                "file",
+                self.data.files().map(|file| file.as_bytes()),
+            ),
+            "node" => hex(&self.node).into(),
+            "p1node" => hex(&self.parents[0].1).into(),
+            "p1rev" => self.parents[0].0.into(),
+            "p2node" => hex(&self.parents[1].1).into(),
+            "p2rev" => self.parents[1].0.into(),
+            "phase" => self.phase.name().into(),
+            "rev" => self.rev.into(),
+            "tags" => Value::list("tag", self.tags.clone()),
+            _ => return Ok(None),
+        }))
+    }
+}
+
+fn hex(node: &Node) -> Vec<u8> {
+    format!("{:x}", node).into_bytes()
+}
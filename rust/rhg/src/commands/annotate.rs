@@ -0,0 +1,433 @@
+use crate::error::CommandError;
+use crate::patterns::{parse_patterns, pattern_matcher, relative_path};
+use clap::Arg;
+use format_bytes::format_bytes;
+use hg::matchers::{
+    AlwaysMatcher, DifferenceMatcher, IntersectionMatcher, Matcher,
+};
+use hg::operations::{AnnotateOptions, AnnotatedLine, Annotator};
+use hg::revlog::changelog::Changelog;
+use hg::revlog::manifest::Manifest;
+use hg::revlog::node::NULL_NODE;
+use hg::revlog::revlog::RevlogError;
+use hg::revlog::{Node, Revision, NULL_REVISION, WORKING_DIRECTORY_REVISION};
+use hg::PatternSyntax;
+use std::collections::{HashMap, HashSet};
+
+pub const HELP_TEXT: &str = "
+show changeset information by line for each file
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("annotate")
+        .alias("blame")
+        .arg(
+            Arg::with_name("rev")
+                .help("annotate the specified revision")
+                .short("-r")
+                .long("--rev")
+                .value_name("REV")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .help(
+                    "follow copies/renames and list the filename (DEPRECATED)",
+                )
+                .long("--follow"),
+        )
+        .arg(
+            Arg::with_name("no-follow")
+                .help("don't follow copies and renames")
+                .long("--no-follow"),
+        )
+        .arg(
+            Arg::with_name("text")
+                .help("treat all files as text")
+                .short("-a")
+                .long("--text"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .help("list the author (long with -v)")
+                .short("-u")
+                .long("--user"),
+        )
+        .arg(
+            Arg::with_name("file")
+                .help("list the filename")
+                .short("-f")
+                .long("--file"),
+        )
+        .arg(
+            Arg::with_name("date")
+                .help("list the date (short with -q)")
+                .short("-d")
+                .long("--date"),
+        )
+        .arg(
+            Arg::with_name("number")
+                .help("list the revision number (default)")
+                .short("-n")
+                .long("--number"),
+        )
+        .arg(
+            Arg::with_name("changeset")
+                .help("list the changeset")
+                .short("-c")
+                .long("--changeset"),
+        )
+        .arg(
+            Arg::with_name("line-number")
+                .help("show line number at the first appearance")
+                .short("-l")
+                .long("--line-number"),
+        )
+        .arg(
+            Arg::with_name("skip")
+                .help("revset to not display (EXPERIMENTAL)")
+                .long("--skip")
+                .value_name("REV")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("include")
+                .help("include names matching the given patterns")
+                .short("-I")
+                .long("--include")
+                .value_name("PATTERN")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .help("exclude names matching the given patterns")
+                .short("-X")
+                .long("--exclude")
+                .value_name("PATTERN")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("files")
+                .multiple(true)
+                .empty_values(false)
+                .value_name("FILE"),
+        )
+        .about(HELP_TEXT)
+}
+
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let args = invocation.subcommand_args;
+    let config = invocation.config;
+    for &(section, item) in &[
+        (&b"annotate"[..], &b"ignorews"[..]),
+        (b"annotate", b"ignorewsamount"),
+        (b"annotate", b"ignorewseol"),
+        (b"annotate", b"ignoreblanklines"),
+        (b"experimental", b"xdiff"),
+    ] {
+        if config.get_bool(section, item)? {
+            return Err(CommandError::unsupported(format!(
+                "non-default {}.{}",
+                String::from_utf8_lossy(section),
+                String::from_utf8_lossy(item)
+            )));
+        }
+    }
+    if config.get(b"ui", b"relative-paths") != Some(b"legacy")
+        && config.get_bool(b"ui", b"relative-paths")?
+    {
+        return Err(CommandError::unsupported("ui.relative-paths"));
+    }
+    let values = |name| -> Vec<&str> {
+        args.values_of(name)
+            .map(|values| values.collect())
+            .unwrap_or_default()
+    };
+    if values("files").is_empty() {
+        return Err(CommandError::abort(
+            "abort: at least one filename or pattern is required",
+        ));
+    }
+    let mut columns = Columns {
+        user: args.is_present("user"),
+        number: args.is_present("number"),
+        changeset: args.is_present("changeset"),
+        date: args.is_present("date"),
+        // `--follow` is a deprecated alias of `--file`
+        file: args.is_present("file") || args.is_present("follow"),
+        line_number: args.is_present("line-number"),
+    };
+    if !columns.user && !columns.changeset && !columns.date && !columns.file {
+        columns.number = true
+    }
+    if columns.line_number && !columns.changeset && !columns.number {
+        return Err(CommandError::abort(
+            "abort: at least one of -n/-c is required for -l",
+        ));
+    }
+    let verbose = config.get_bool(b"ui", b"verbose")?;
+    let quiet = config.get_bool(b"ui", b"quiet")?;
+    let full_hex = config.get_bool(b"ui", b"debug")?;
+
+    let repo = invocation.repo?;
+    let rev = args.value_of("rev").unwrap_or(".");
+    let rev = hg::revset::resolve_single(rev, repo).map_err(|e| (e, rev))?;
+    if rev == WORKING_DIRECTORY_REVISION {
+        return Err(CommandError::unsupported(
+            "annotate of the working directory",
+        ));
+    }
+    let mut skip = HashSet::new();
+    for revset in values("skip") {
+        let revs =
+            hg::revset::resolve(revset, repo).map_err(|e| (e, revset))?;
+        skip.extend(revs)
+    }
+    let options = AnnotateOptions {
+        follow: !args.is_present("no-follow"),
+        skip,
+    };
+
+    let root = hg::utils::current_dir()?.join(repo.working_directory_path());
+    let (file_patterns, explicit_files) =
+        parse_patterns(&root, &values("files"), PatternSyntax::RelPath)?;
+    let (include_patterns, _) =
+        parse_patterns(&root, &values("include"), PatternSyntax::Glob)?;
+    let (exclude_patterns, _) =
+        parse_patterns(&root, &values("exclude"), PatternSyntax::Glob)?;
+    let file_matcher = pattern_matcher(file_patterns, &root)?;
+    let include_matcher = pattern_matcher(include_patterns, &root)?;
+    let exclude_matcher = pattern_matcher(exclude_patterns, &root)?;
+    let intersection;
+    let matcher: &(dyn Matcher + Sync) =
+        match (&file_matcher, &include_matcher) {
+            (Some(files), Some(include)) => {
+                intersection = IntersectionMatcher::new(files, include);
+                &intersection
+            }
+            (Some(matcher), None) | (None, Some(matcher)) => matcher,
+            (None, None) => &AlwaysMatcher,
+        };
+    let difference;
+    let matcher: &(dyn Matcher + Sync) = match &exclude_matcher {
+        Some(exclude) => {
+            difference = DifferenceMatcher::new(matcher, exclude);
+            &difference
+        }
+        None => matcher,
+    };
+
+    let changelog = Changelog::open(repo)?;
+    let node = node_of(&changelog, rev)?;
+    let mut files = Vec::new();
+    let manifest_node = changelog.get_rev(rev)?.manifest_node()?;
+    if manifest_node != NULL_NODE {
+        let manifest = Manifest::open(repo)?.get_node(manifest_node.into())?;
+        for file in manifest.iter() {
+            let file = file?;
+            files.push((file.path.to_owned(), file.node_id()?))
+        }
+    }
+
+    let mut annotator = Annotator::new(repo, &changelog)?;
+    let mut changesets = HashMap::new();
+    let mut stdout = invocation.ui.stdout_buffer();
+    for (path, file_node) in &files {
+        if !matcher.matches(path) {
+            continue;
+        }
+        if !args.is_present("text") {
+            let data = annotator.data(path, *file_node)?;
+            if data.contains(&b'\0') {
+                stdout.write_all(&format_bytes!(
+                    b"{}: binary file\n",
+                    relative_path(repo, path)?
+                ))?;
+                continue;
+            }
+        }
+        let lines = annotator.annotate(path, *file_node, rev, &options)?;
+        for line in &lines {
+            if !changesets.contains_key(&line.revision) {
+                let changeset =
+                    Changeset::new(&changelog, line.revision, quiet)?;
+                changesets.insert(line.revision, changeset);
+            }
+        }
+        let column_values = |value: &dyn Fn(&AnnotatedLine) -> Vec<u8>| {
+            lines.iter().map(value).collect::<Vec<_>>()
+        };
+        let mut table = Vec::new();
+        if columns.user {
+            table.push((
+                &b" "[..],
+                column_values(&|line| {
+                    let user = &changesets[&line.revision].user;
+                    if verbose {
+                        user.clone()
+                    } else {
+                        short_user(user).to_vec()
+                    }
+                }),
+            ))
+        }
+        if columns.number {
+            table.push((
+                b" ",
+                column_values(&|line| line.revision.to_string().into_bytes()),
+            ))
+        }
+        if columns.changeset {
+            table.push((
+                b" ",
+                column_values(&|line| {
+                    let node = &changesets[&line.revision].node;
+                    if full_hex {
+                        format!("{:x}", node)
+                    } else {
+                        format!("{:x}", node.short())
+                    }
+                    .into_bytes()
+                }),
+            ))
+        }
+        if columns.date {
+            table.push((
+                b" ",
+                column_values(&|line| changesets[&line.revision].date.clone()),
+            ))
+        }
+        if columns.file {
+            table.push((
+                b" ",
+                column_values(&|line| line.path.as_bytes().to_vec()),
+            ))
+        }
+        if columns.line_number {
+            table.push((
+                b":",
+                column_values(&|line| {
+                    line.line_number.to_string().into_bytes()
+                }),
+            ))
+        }
+        // No separator in front of the first column
+        table[0].0 = b"";
+        let widths: Vec<_> = table
+            .iter()
+            .map(|(_, values)| values.iter().map(|v| width(v)).max())
+            .collect();
+        for (i, line) in lines.iter().enumerate() {
+            for ((separator, values), max_width) in table.iter().zip(&widths) {
+                let value = &values[i];
+                let padding = max_width.unwrap_or(0) - width(value);
+                stdout.write_all(separator)?;
+                stdout.write_all(&b" ".repeat(padding))?;
+                stdout.write_all(value)?;
+            }
+            stdout.write_all(if line.skipped { b"* " } else { b": " })?;
+            stdout.write_all(&line.line)?;
+        }
+        if let Some(last) = lines.last() {
+            if !last.line.ends_with(b"\n") {
+                stdout.write_all(b"\n")?;
+            }
+        }
+    }
+    stdout.flush()?;
+
+    for file in &explicit_files {
+        let dir = [file.as_bytes(), b"/"].concat();
+        let found = file.is_empty()
+            || files.iter().any(|(path, _)| {
+                path == file || path.as_bytes().starts_with(&dir)
+            });
+        if !found {
+            return Err(CommandError::abort(format!(
+                "abort: {}: no such file in rev {:x}",
+                file,
+                node.short()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The columns to show in front of each line
+struct Columns {
+    user: bool,
+    number: bool,
+    changeset: bool,
+    date: bool,
+    file: bool,
+    line_number: bool,
+}
+
+/// What is shown about the changesets that lines are attributed to
+struct Changeset {
+    node: Node,
+    user: Vec<u8>,
+    date: Vec<u8>,
+}
+
+impl Changeset {
+    fn new(
+        changelog: &Changelog,
+        rev: Revision,
+        quiet: bool,
+    ) -> Result<Self, CommandError> {
+        let entry = changelog.get_rev(rev)?;
+        let format = if quiet {
+            "%Y-%m-%d"
+        } else {
+            "%a %b %d %H:%M:%S %Y %1%2"
+        };
+        Ok(Self {
+            node: node_of(changelog, rev)?,
+            user: entry.user().to_vec(),
+            date: entry.date()?.format(format).into_bytes(),
+        })
+    }
+}
+
+fn node_of(
+    changelog: &Changelog,
+    rev: Revision,
+) -> Result<Node, CommandError> {
+    if rev == NULL_REVISION {
+        return Ok(NULL_NODE);
+    }
+    changelog
+        .node_from_rev(rev)
+        .copied()
+        .ok_or_else(|| CommandError::from(RevlogError::InvalidRevision))
+}
+
+/// The user name part of an author, like `stringutil.shortuser`
+fn short_user(user: &[u8]) -> &[u8] {
+    let mut user = user;
+    if let Some(at) = user.iter().position(|&b| b == b'@') {
+        user = &user[..at]
+    }
+    if let Some(bracket) = user.iter().position(|&b| b == b'<') {
+        user = &user[bracket + 1..]
+    }
+    if let Some(space) = user.iter().position(|&b| b == b' ') {
+        user = &user[..space]
+    }
+    if let Some(dot) = user.iter().position(|&b| b == b'.') {
+        user = &user[..dot]
+    }
+    user
+}
+
+/// The number of characters of a value, like `encoding.colwidth`
+fn width(value: &[u8]) -> usize {
+    String::from_utf8_lossy(value).chars().count()
+}
@@ -1,8 +1,11 @@
 use crate::error::CommandError;
 use clap::Arg;
 use format_bytes::format_bytes;
+use hg::errors::{IoErrorContext, IoResultExt};
 use hg::operations::cat;
-use hg::utils::hg_path::HgPathBuf;
+use hg::revlog::{Node, Revision};
+use hg::utils::files::get_path_from_bytes;
+use hg::utils::hg_path::{HgPath, HgPathBuf};
 use micro_timer::timed;
 use std::convert::TryFrom;
 
@@ -20,6 +23,19 @@ pub fn args() -> clap::App<'static, 'static> {
                 .value_name("REV")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("output")
+                .help("print output to file with formatted name")
+                .short("-o")
+                .long("--output")
+                .value_name("FORMAT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("decode")
+                .help("apply any matching decode filter")
+                .long("--decode"),
+        )
         .arg(
             clap::Arg::with_name("files")
                 .required(true)
@@ -33,8 +49,24 @@ pub fn args() -> clap::App<'static, 'static> {
 
 #[timed]
 pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
-    let rev = invocation.subcommand_args.value_of("rev");
-    let file_args = match invocation.subcommand_args.values_of("files") {
+    let args = invocation.subcommand_args;
+    let rev = args.value_of("rev");
+    if args.is_present("decode") {
+        return Err(CommandError::unsupported("`rhg cat --decode`"));
+    }
+    let output_pattern = match args.value_of("output") {
+        // Templates are not supported yet
+        Some(pattern) if pattern.contains('{') => {
+            return Err(CommandError::unsupported(
+                "`rhg cat --output` with a template",
+            ))
+        }
+        Some(pattern) if !pattern.is_empty() && pattern != "-" => {
+            Some(pattern.as_bytes())
+        }
+        _ => None,
+    };
+    let file_args = match args.values_of("files") {
         Some(files) => files.collect(),
         None => vec![],
     };
@@ -46,6 +78,18 @@ pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
 
     let mut files = vec![];
     for file in file_args.iter() {
+        let has_kind = file.find(':').map_or(false, |colon| {
+            let kind = &file[..=colon];
+            hg::parse_pattern_syntax(kind.as_bytes()).is_ok()
+                || kind == "set:"
+                || kind.starts_with("listfile")
+        });
+        if has_kind {
+            return Err(CommandError::unsupported(format!(
+                "`rhg cat` with file pattern {}",
+                file
+            )));
+        }
         // TODO: actually normalize `..` path segments etc?
         let normalized = cwd.join(&file);
         let stripped = normalized
@@ -57,28 +101,98 @@ pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
         files.push(hg_file);
     }
 
-    match rev {
-        Some(rev) => {
-            let output = cat(&repo, rev, &files).map_err(|e| (e, rev))?;
-            invocation.ui.write_stdout(&output.concatenated)?;
-            if !output.missing.is_empty() {
-                let short = format!("{:x}", output.node.short()).into_bytes();
-                for path in &output.missing {
-                    invocation.ui.write_stderr(&format_bytes!(
-                        b"{}: no such file in rev {}\n",
-                        path.as_bytes(),
-                        short
-                    ))?;
+    let rev = rev.unwrap_or(".");
+    let output = cat(&repo, rev, &files).map_err(|e| (e, rev))?;
+    match output_pattern {
+        Some(pattern) => {
+            let rev = output.rev;
+            for (path, data) in &output.results {
+                let file_name =
+                    make_file_name(pattern, path, &output.node, rev)?;
+                let file_name = get_path_from_bytes(&file_name);
+                if let Some(parent) = file_name.parent() {
+                    // Like Python, let opening the file report errors
+                    let _ = std::fs::create_dir_all(parent);
                 }
+                std::fs::write(file_name, data).with_context(|| {
+                    IoErrorContext::WritingFile(file_name.to_owned())
+                })?;
+            }
+        }
+        None => {
+            let mut stdout = invocation.ui.stdout_buffer();
+            for (_, data) in &output.results {
+                stdout.write_all(data)?;
+            }
+            stdout.flush()?;
+        }
+    }
+    if !output.missing.is_empty() {
+        let short = format!("{:x}", output.node.short()).into_bytes();
+        for path in &output.missing {
+            invocation.ui.write_stderr(&format_bytes!(
+                b"{}: no such file in rev {}\n",
+                path.as_bytes(),
+                short
+            ))?;
+        }
+    }
+    if output.found_any {
+        Ok(())
+    } else {
+        Err(CommandError::Unsuccessful)
+    }
+}
+
+/// Expand the `%` format specifications of `--output` for one file, like
+/// `cmdutil.makefilename`
+fn make_file_name(
+    pattern: &[u8],
+    path: &HgPath,
+    node: &Node,
+    rev: Revision,
+) -> Result<Vec<u8>, CommandError> {
+    let path = path.as_bytes();
+    let (dirname, basename) = match path.iter().rposition(|&b| b == b'/') {
+        Some(slash) => (&path[..slash], &path[slash + 1..]),
+        None => (&b"."[..], path),
+    };
+    let mut file_name = Vec::new();
+    let mut bytes = pattern.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'%' {
+            file_name.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'%') => file_name.push(b'%'),
+            Some(b's') => file_name.extend(basename),
+            Some(b'd') => file_name.extend(dirname),
+            Some(b'p') => file_name.extend(path),
+            Some(b'H') => file_name.extend(format!("{:x}", node).bytes()),
+            Some(b'h') => {
+                file_name.extend(format!("{:x}", node.short()).bytes())
+            }
+            Some(b'R') | Some(b'r') => {
+                file_name.extend(rev.to_string().bytes())
+            }
+            Some(b'm') | Some(b'b') => {
+                return Err(CommandError::unsupported(
+                    "`rhg cat --output` with %m or %b",
+                ))
+            }
+            Some(&spec) => {
+                return Err(CommandError::abort(format!(
+                    "abort: invalid format spec '%{}' in output filename",
+                    spec as char
+                )))
             }
-            if output.found_any {
-                Ok(())
-            } else {
-                Err(CommandError::Unsuccessful)
+            None => {
+                return Err(CommandError::abort(
+                    "abort: incomplete format spec in output filename",
+                ))
             }
         }
-        None => Err(CommandError::unsupported(
-            "`rhg cat` without `--rev` / `-r`",
-        )),
     }
+    Ok(file_name)
 }
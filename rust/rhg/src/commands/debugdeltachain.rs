@@ -0,0 +1,78 @@
+use crate::debug_revlog;
+use crate::error::CommandError;
+use hg::operations::debug_delta_chain;
+use hg::revlog::revlog::SparseReadOptions;
+use micro_timer::timed;
+
+pub const HELP_TEXT: &str = "
+dump information about delta chains in a revlog
+
+Columns are the revision, its delta chain (numbered by unique base), the
+length of that chain, the previous revision in the chain, how the delta was
+computed, the compressed and uncompressed sizes of the revision, the total
+compressed size of the chain and its ratio to the uncompressed size, the
+linear distance from the base of the chain to the end of the revision, the
+size of unrelated data in that distance and its ratio to the chain size.
+
+With sparse reads, which sparse revlogs or experimental.sparse-read = True
+enable, the total and largest sizes of the blocks of data read, the density
+of useful bytes in them and their number are added.
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    debug_revlog::args(clap::SubCommand::with_name("debugdeltachain"))
+        .about(HELP_TEXT)
+}
+
+#[timed]
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let (repo, revlog) = debug_revlog::open(invocation)?;
+    let sparse_read = SparseReadOptions::from_repo(repo)?;
+    let lines = debug_delta_chain(&revlog, sparse_read.as_ref())?;
+
+    let mut stdout = invocation.ui.stdout_buffer();
+    stdout.write_all(
+        b"    rev  chain# chainlen     prev   delta       size    rawsize  \
+          chainsize     ratio   lindist extradist extraratio",
+    )?;
+    if sparse_read.is_some() {
+        stdout.write_all(b"   readsize largestblk rddensity srchunks")?;
+    }
+    stdout.write_all(b"\n")?;
+    for line in lines {
+        stdout.write_all(
+            format!(
+                "{:7} {:7} {:8} {:8} {:>7} {:10} {:10} {:10} {:9.5} {:9} \
+                 {:9} {:10.5}",
+                line.rev,
+                line.chain_id,
+                line.chain_len,
+                line.prev_rev,
+                line.delta_type.as_str(),
+                line.compressed_size,
+                line.uncompressed_size,
+                line.chain_size,
+                line.chain_ratio(),
+                line.linear_distance,
+                line.extra_distance,
+                line.extra_ratio(),
+            )
+            .as_bytes(),
+        )?;
+        if let Some(sparse_read) = &line.sparse_read {
+            stdout.write_all(
+                format!(
+                    " {:10} {:10} {:9.5} {:8}",
+                    sparse_read.read_size,
+                    sparse_read.largest_block,
+                    sparse_read.density,
+                    sparse_read.chunks,
+                )
+                .as_bytes(),
+            )?;
+        }
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::debug_revlog;
+use crate::error::CommandError;
+use hg::operations::debug_index;
+use hg::revlog::Node;
+use micro_timer::timed;
+
+pub const HELP_TEXT: &str = "
+dump index data for a storage primitive
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    debug_revlog::args(clap::SubCommand::with_name("debugindex"))
+        .about(HELP_TEXT)
+}
+
+#[timed]
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let (_, revlog) = debug_revlog::open(invocation)?;
+    let lines = debug_index(&revlog)?;
+
+    // Like `--debug` in Python
+    let full_hashes = invocation.config.get_bool(b"ui", b"debug")?;
+    let hex = |node: &Node| {
+        if full_hashes {
+            format!("{:x}", node)
+        } else {
+            format!("{:x}", node.short())
+        }
+    };
+    let id_len = if full_hashes { 40 } else { 12 };
+
+    let mut stdout = invocation.ui.stdout_buffer();
+    stdout.write_all(
+        format!(
+            "   rev linkrev {:<width$} {:<width$} p2\n",
+            "nodeid",
+            "p1",
+            width = id_len
+        )
+        .as_bytes(),
+    )?;
+    for line in lines {
+        stdout.write_all(
+            format!(
+                "{:6} {:7} {} {} {}\n",
+                line.rev,
+                line.link_rev,
+                hex(&line.node),
+                hex(&line.p1),
+                hex(&line.p2)
+            )
+            .as_bytes(),
+        )?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
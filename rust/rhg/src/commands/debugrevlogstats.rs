@@ -0,0 +1,229 @@
+use crate::debug_revlog;
+use crate::error::CommandError;
+use hg::operations::{debug_revlog_stats, SizeStats};
+use hg::revlog::revlog::WriteOptions;
+use micro_timer::timed;
+
+pub const HELP_TEXT: &str = "
+show data and statistics about a revlog
+
+Like `hg debugrevlog`: how many revisions are stored as full or intermediate
+snapshots of each depth, or as deltas against which revision, which
+compression engines are used and how long the delta chains are.
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    debug_revlog::args(clap::SubCommand::with_name("debugrevlogstats"))
+        .about(HELP_TEXT)
+}
+
+#[timed]
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let (repo, revlog) = debug_revlog::open(invocation)?;
+    let sparse_revlog = WriteOptions::from_repo(repo)?.sparse_revlog;
+    let stats = debug_revlog_stats(&revlog, sparse_revlog)?;
+
+    let revisions = stats.revisions;
+    let total_size = stats.total_size();
+    let width = total_size.to_string().len();
+    let percent = |value: usize, total: usize, padding: usize| {
+        format_percent(value, total, width, padding)
+    };
+    let count = |value: usize| format!("{:width$}\n", value, width = width);
+    let mut out = String::new();
+
+    let mut flags = Vec::new();
+    if stats.inline {
+        flags.push("inline")
+    }
+    if stats.generaldelta {
+        flags.push("generaldelta")
+    }
+    if flags.is_empty() {
+        flags.push("(none)")
+    }
+    out += &format!("format : {}\n", stats.format);
+    out += &format!("flags  : {}\n", flags.join(", "));
+    out += "\n";
+
+    let merges = stats.merges;
+    out += &format!("revisions     : {}", count(revisions));
+    out += &format!("    merges    : {}", percent(merges, revisions, 0));
+    out += &format!(
+        "    normal    : {}",
+        percent(revisions - merges, revisions, 0)
+    );
+    let empty_texts = stats.empty_texts;
+    let empty = empty_texts + stats.empty_deltas;
+    let intermediate = stats.intermediate_snapshots();
+    let full = stats.snapshots.get(&0).cloned().unwrap_or_default();
+    out += &format!("revisions     : {}", count(revisions));
+    out += &format!("    empty     : {}", percent(empty, revisions, 0));
+    out += &format!(
+        "                   text  : {}",
+        percent(empty_texts, empty, 0)
+    );
+    out += &format!(
+        "                   delta : {}",
+        percent(stats.empty_deltas, empty, 0)
+    );
+    out += &format!(
+        "    snapshot  : {}",
+        percent(full.count + intermediate.count, revisions, 0)
+    );
+    for (depth, snapshots) in &stats.snapshots {
+        out += &format!(
+            "      lvl-{:<3} :       {}",
+            depth,
+            percent(snapshots.count, revisions, 0)
+        );
+    }
+    out += &format!(
+        "    deltas    : {}",
+        percent(stats.deltas.count, revisions, 0)
+    );
+    out += &format!("revision size : {}", count(total_size));
+    out += &format!(
+        "    snapshot  : {}",
+        percent(full.total + intermediate.total, total_size, 0)
+    );
+    for (depth, snapshots) in &stats.snapshots {
+        out += &format!(
+            "      lvl-{:<3} :       {}",
+            depth,
+            percent(snapshots.total, total_size, 0)
+        );
+    }
+    out += &format!(
+        "    deltas    : {}",
+        percent(stats.deltas.total, total_size, 0)
+    );
+
+    let chunk_type = |header: Option<u8>| match header {
+        None => "    empty     : ".to_owned(),
+        Some(byte) if byte.is_ascii_alphabetic() => {
+            format!("    0x{:02x} ({})  : ", byte, byte as char)
+        }
+        Some(byte) => format!("    0x{:02x}      : ", byte),
+    };
+    out += "\n";
+    out += &format!("chunks        : {}", count(revisions));
+    for (&header, &(chunks, _)) in &stats.chunk_types {
+        out += &chunk_type(header);
+        out += &percent(chunks, revisions, 0);
+    }
+    out += &format!("chunks size   : {}", count(total_size));
+    for (&header, &(_, size)) in &stats.chunk_types {
+        out += &chunk_type(header);
+        out += &percent(size, total_size, 0);
+    }
+
+    // Like Python, ratios are rounded down
+    let average_chain_length = stats.average_chain_length() as usize;
+    let compression_ratio = stats.compression_ratio() as usize;
+    let chain_width = average_chain_length
+        .max(stats.max_chain_length)
+        .max(stats.max_chain_reach)
+        .max(compression_ratio)
+        .to_string()
+        .len();
+    out += "\n";
+    for &(label, value) in &[
+        ("avg chain length  : ", average_chain_length),
+        ("max chain length  : ", stats.max_chain_length),
+        ("max chain reach   : ", stats.max_chain_reach),
+        ("compression ratio : ", compression_ratio),
+    ] {
+        out += &format!("{}{:width$}\n", label, value, width = chain_width);
+    }
+
+    let sizes = |stats: &SizeStats| {
+        format!("{} / {} / {}\n", stats.min, stats.max, stats.average())
+    };
+    out += "\n";
+    out += &format!(
+        "uncompressed data size (min/max/avg) : {}",
+        sizes(&stats.raw_sizes)
+    );
+    out += &format!("full revision size (min/max/avg)     : {}", sizes(&full));
+    out += &format!(
+        "inter-snapshot size (min/max/avg)    : {}",
+        sizes(&intermediate)
+    );
+    for (depth, snapshots) in stats.snapshots.range(1..) {
+        out += &format!(
+            "    level-{:<3} (min/max/avg)          : {}",
+            depth,
+            sizes(snapshots)
+        );
+    }
+    out += &format!(
+        "delta size (min/max/avg)             : {}",
+        sizes(&stats.deltas)
+    );
+
+    let deltas = stats.deltas.count;
+    if deltas > 0 {
+        let width = deltas.to_string().len();
+        let percent = |value: usize, total: usize, padding: usize| {
+            format_percent(value, total, width, padding)
+        };
+        let prev = stats.deltas_against_prev;
+        let prev_p1 = stats.deltas_against_prev_p1;
+        let prev_p2 = stats.deltas_against_prev_p2;
+        out += "\n";
+        out += &format!("deltas against prev  : {}", percent(prev, deltas, 0));
+        if prev > 0 {
+            out += &format!(
+                "    where prev = p1  : {}",
+                percent(prev_p1, prev, 4)
+            );
+            out += &format!(
+                "    where prev = p2  : {}",
+                percent(prev_p2, prev, 4)
+            );
+            out += &format!(
+                "    other            : {}",
+                percent(prev - prev_p1 - prev_p2, prev, 4)
+            );
+        }
+        if stats.generaldelta {
+            out += &format!(
+                "deltas against p1    : {}",
+                percent(stats.deltas_against_p1, deltas, 0)
+            );
+            out += &format!(
+                "deltas against p2    : {}",
+                percent(stats.deltas_against_p2, deltas, 0)
+            );
+            out += &format!(
+                "deltas against other : {}",
+                percent(stats.deltas_against_other, deltas, 0)
+            );
+        }
+    }
+    invocation.ui.write_stdout(out.as_bytes())?;
+    Ok(())
+}
+
+/// A count with its percentage of `total`, like `pcfmtstr` in Python
+fn format_percent(
+    value: usize,
+    total: usize,
+    width: usize,
+    padding: usize,
+) -> String {
+    let percentage = if total == 0 {
+        100.0
+    } else {
+        100.0 * value as f64 / total as f64
+    };
+    format!(
+        "{:width$} {:padding$}({:5.2}%)\n",
+        value,
+        "",
+        percentage,
+        width = width,
+        padding = padding
+    )
+}
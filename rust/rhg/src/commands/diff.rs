@@ -0,0 +1,976 @@
+use crate::error::CommandError;
+use crate::exitcode;
+use crate::patterns::{parse_patterns, pattern_matcher, relative_path};
+use crate::ui::StdoutBuffer;
+use clap::Arg;
+use format_bytes::format_bytes;
+use hg::diff::{git_binary_patch, git_blob_id, unified_hunks};
+use hg::errors::{HgResultExt, IoResultExt};
+use hg::matchers::{
+    AlwaysMatcher, DifferenceMatcher, IntersectionMatcher, Matcher,
+};
+use hg::operations::{
+    changed_files, file_pairs, Comparison, CopyKind, DiffFile, DiffSide,
+    FilePair,
+};
+use hg::repo::Repo;
+use hg::revlog::changelog::{Changelog, ChangesetDate};
+use hg::revlog::manifest::ManifestFlag;
+use hg::revlog::node::NULL_NODE;
+use hg::revlog::revlog::RevlogError;
+use hg::revlog::{Node, Revision, NULL_REVISION, WORKING_DIRECTORY_REVISION};
+use hg::utils::files::{get_bytes_from_path, get_path_from_bytes};
+use hg::utils::hg_path::{HgPath, HgPathBuf};
+use hg::{DirstateMap, Graph, PatternSyntax, StatusOptions};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+
+pub const HELP_TEXT: &str = "
+diff repository (or selected files)
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("diff")
+        .arg(
+            Arg::with_name("rev")
+                .help("revision")
+                .short("-r")
+                .long("--rev")
+                .value_name("REV")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("from")
+                .help("revision to diff from")
+                .long("--from")
+                .value_name("REV1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("to")
+                .help("revision to diff to")
+                .long("--to")
+                .value_name("REV2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("change")
+                .help("change made by revision")
+                .short("-c")
+                .long("--change")
+                .value_name("REV")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("text")
+                .help("treat all files as text")
+                .short("-a")
+                .long("--text"),
+        )
+        .arg(
+            Arg::with_name("git")
+                .help("use git extended diff format")
+                .short("-g")
+                .long("--git"),
+        )
+        .arg(
+            Arg::with_name("binary")
+                .help("generate binary diffs in git mode (default)")
+                .long("--binary"),
+        )
+        .arg(
+            Arg::with_name("nodates")
+                .help("omit dates from diff headers")
+                .long("--nodates"),
+        )
+        .arg(
+            Arg::with_name("noprefix")
+                .help("omit a/ and b/ prefixes from filenames")
+                .long("--noprefix"),
+        )
+        .arg(
+            Arg::with_name("unified")
+                .help("number of lines of context to show")
+                .short("-U")
+                .long("--unified")
+                .value_name("NUM")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stat")
+                .help("output diffstat-style summary of changes")
+                .long("--stat"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .help("include names matching the given patterns")
+                .short("-I")
+                .long("--include")
+                .value_name("PATTERN")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .help("exclude names matching the given patterns")
+                .short("-X")
+                .long("--exclude")
+                .value_name("PATTERN")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("files")
+                .multiple(true)
+                .empty_values(false)
+                .value_name("FILE"),
+        )
+        .about(HELP_TEXT)
+}
+
+/// The options of `patch.diffallopts` that `rhg diff` supports
+struct DiffOptions {
+    git: bool,
+    text: bool,
+    nodates: bool,
+    noprefix: bool,
+    nobinary: bool,
+    context: usize,
+}
+
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let args = invocation.subcommand_args;
+    let config = invocation.config;
+    for &(section, item) in &[
+        (&b"diff"[..], &b"showfunc"[..]),
+        (b"diff", b"ignorews"),
+        (b"diff", b"ignorewsamount"),
+        (b"diff", b"ignorewseol"),
+        (b"diff", b"ignoreblanklines"),
+        (b"diff", b"word-diff"),
+        (b"diff", b"upgrade"),
+        (b"diff", b"merge"),
+        (b"experimental", b"xdiff"),
+    ] {
+        if config.get_bool(section, item)? {
+            return Err(CommandError::unsupported(format!(
+                "non-default {}.{}",
+                String::from_utf8_lossy(section),
+                String::from_utf8_lossy(item)
+            )));
+        }
+    }
+    for &item in &[&b"extendedheader.index"[..], b"extendedheader.similarity"]
+    {
+        if config.get(b"experimental", item).is_some() {
+            return Err(CommandError::unsupported(format!(
+                "experimental.{}",
+                String::from_utf8_lossy(item)
+            )));
+        }
+    }
+    if config.get(b"ui", b"relative-paths") != Some(b"legacy")
+        && config.get_bool(b"ui", b"relative-paths")?
+    {
+        return Err(CommandError::unsupported("ui.relative-paths"));
+    }
+    let plain = std::env::var_os("HGPLAIN").is_some()
+        || std::env::var_os("HGPLAINEXCEPT").is_some();
+    // Options that change the format are disabled by HGPLAIN
+    let format_changing = |name, item| -> Result<bool, CommandError> {
+        if args.is_present(name) {
+            Ok(true)
+        } else if plain {
+            Ok(false)
+        } else {
+            Ok(config.get_bool(b"diff", item)?)
+        }
+    };
+    let context = match args.value_of("unified") {
+        Some(context) => Some(context.as_bytes()),
+        None => config.get(b"diff", b"unified"),
+    };
+    let context = match context {
+        Some(context) => {
+            match String::from_utf8_lossy(context).trim().parse::<i64>() {
+                Ok(context) if context >= 0 => context as usize,
+                // Python gives output that makes little sense
+                Ok(_) => {
+                    return Err(CommandError::unsupported(
+                        "negative diff context",
+                    ))
+                }
+                Err(_) => {
+                    return Err(CommandError::abort(format!(
+                        "abort: diff context lines count must be an \
+                         integer, not '{}'",
+                        String::from_utf8_lossy(context)
+                    )))
+                }
+            }
+        }
+        None => 3,
+    };
+    let mut options = DiffOptions {
+        git: args.is_present("git") || config.get_bool(b"diff", b"git")?,
+        text: args.is_present("text"),
+        nodates: args.is_present("nodates")
+            || config.get_bool(b"diff", b"nodates")?,
+        noprefix: format_changing("noprefix", &b"noprefix"[..])?,
+        nobinary: !args.is_present("binary")
+            && !plain
+            && config.get_bool(b"diff", b"nobinary")?,
+        context,
+    };
+    let stat = args.is_present("stat");
+    let full_hex = config.get_bool(b"ui", b"debug")?;
+
+    let repo = invocation.repo?;
+    let changelog = Changelog::open(repo)?;
+    let (rev1, rev2) = compared_revisions(repo, &changelog, args)?;
+    let rev1 = match rev1 {
+        Some(rev) => rev,
+        None => {
+            return Err(CommandError::unsupported(
+                "diff from the working directory",
+            ))
+        }
+    };
+
+    let root = hg::utils::current_dir()?.join(repo.working_directory_path());
+    let values = |name| -> Vec<&str> {
+        args.values_of(name)
+            .map(|values| values.collect())
+            .unwrap_or_default()
+    };
+    let (file_patterns, explicit_files) =
+        parse_patterns(&root, &values("files"), PatternSyntax::RelPath)?;
+    let (include_patterns, _) =
+        parse_patterns(&root, &values("include"), PatternSyntax::Glob)?;
+    let (exclude_patterns, _) =
+        parse_patterns(&root, &values("exclude"), PatternSyntax::Glob)?;
+    let file_matcher = pattern_matcher(file_patterns, &root)?;
+    let include_matcher = pattern_matcher(include_patterns, &root)?;
+    let exclude_matcher = pattern_matcher(exclude_patterns, &root)?;
+    let intersection;
+    let matcher: &(dyn Matcher + Sync) =
+        match (&file_matcher, &include_matcher) {
+            (Some(files), Some(include)) => {
+                intersection = IntersectionMatcher::new(files, include);
+                &intersection
+            }
+            (Some(matcher), None) | (None, Some(matcher)) => matcher,
+            (None, None) => &AlwaysMatcher,
+        };
+    let difference;
+    let matcher: &(dyn Matcher + Sync) = match &exclude_matcher {
+        Some(exclude) => {
+            difference = DifferenceMatcher::new(matcher, exclude);
+            &difference
+        }
+        None => matcher,
+    };
+
+    let mut comparison = Comparison::new(repo, &changelog)?;
+    let mut side1 = comparison.changeset(rev1, matcher)?;
+    let mut dirstate = None;
+    let side2 = match rev2 {
+        Some(rev2) => comparison.changeset(rev2, matcher)?,
+        None => {
+            // Like `rhg status`, which this relies on
+            if !config.get_option(b"rhg", b"status")?.unwrap_or(false) {
+                return Err(CommandError::unsupported(
+                    "diff of the working directory is experimental in rhg \
+                     (enable it with 'rhg.status = true' or enable fallback \
+                     with 'rhg.on-unsupported = fallback')",
+                ));
+            }
+            let mut dmap = DirstateMap::new();
+            // A new repository has no dirstate file
+            let dirstate_data =
+                repo.hg_vfs().mmap_open("dirstate").io_not_found_as_none()?;
+            if let Some(dirstate_data) = &dirstate_data {
+                dmap.read(dirstate_data)?;
+            }
+            let parents = repo.dirstate_parents()?;
+            if parents.p2 != NULL_NODE {
+                return Err(CommandError::unsupported(
+                    "diff of the working directory during a merge",
+                ));
+            }
+            let p1 =
+                hg::revset::resolve_single(".", repo).map_err(|e| (e, "."))?;
+            for file in &explicit_files {
+                if is_missing(repo, &mut dmap, &side1, file)? {
+                    invocation.ui.write_stderr(&format_bytes!(
+                        b"{}: No such file or directory\n",
+                        relative_path(repo, file)?
+                    ))?;
+                }
+            }
+            let p1_side = comparison.changeset(p1, matcher)?;
+            let side = working_directory(repo, &dmap, p1_side, matcher)?;
+            dirstate = Some(dmap);
+            side
+        }
+    };
+
+    let changed = match rev2 {
+        Some(rev2) => comparison.changed_files_between(rev1, rev2, matcher)?,
+        None => changed_files(&side1, &side2, matcher, |path, file1| {
+            let node = file1.node.expect("changeset files have a node ID");
+            let data1 = comparison.file_data(path, node)?;
+            Ok::<_, CommandError>(working_directory_data(repo, path)? != data1)
+        })?,
+    };
+    let copies = if options.git {
+        comparison.copies(&side1, &side2, dirstate.as_ref(), matcher)?
+    } else {
+        HashMap::new()
+    };
+    // Copy sources may be in directories that the matcher did not visit
+    for source in copies.values() {
+        if let Some(file) = comparison.find_file(&side1, source, matcher)? {
+            side1.files.entry(source.clone()).or_insert(file);
+        }
+    }
+    let pairs = file_pairs(&changed, &copies);
+
+    let node_id = |rev| -> Result<Vec<u8>, CommandError> {
+        let node = node_of(&changelog, rev)?;
+        Ok(if full_hex {
+            format!("{:x}", node)
+        } else {
+            format!("{:x}", node.short())
+        }
+        .into_bytes())
+    };
+    let mut revs = vec![node_id(rev1)?];
+    if let Some(rev2) = rev2 {
+        revs.push(node_id(rev2)?)
+    }
+    let date_of = |rev| -> Result<Vec<u8>, CommandError> {
+        let date = match rev {
+            Some(rev) => changelog.get_rev(rev)?.date()?,
+            None => now(),
+        };
+        Ok(format_date(&date))
+    };
+    let diff = Diff {
+        repo,
+        revs,
+        dates: [date_of(Some(rev1))?, date_of(rev2)?],
+        sides: [&side1, &side2],
+    };
+
+    let mut stdout = invocation.ui.stdout_buffer();
+    if stat {
+        options.context = 0;
+        options.noprefix = false;
+        let mut text = Vec::new();
+        for pair in &pairs {
+            diff.write_file(&mut text, &mut comparison, &options, pair)?;
+        }
+        let width = if plain { 80 } else { terminal_width() };
+        stdout.write_all(&diffstat(&text, width))?;
+    } else {
+        for pair in &pairs {
+            diff.write_file(&mut stdout, &mut comparison, &options, pair)?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The revisions to compare, `None` being the working directory, like
+/// `commands.diff` and `scmutil.revpair` in Python
+pub fn compared_revisions(
+    repo: &Repo,
+    changelog: &Changelog,
+    args: &clap::ArgMatches,
+) -> Result<(Option<Revision>, Option<Revision>), CommandError> {
+    let revsets: Vec<_> = args
+        .values_of("rev")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    let change = args.value_of("change");
+    let from = args.value_of("from");
+    let to = args.value_of("to");
+    let incompatible = [
+        ("rev", !revsets.is_empty(), "change", change.is_some()),
+        ("from", from.is_some(), "rev", !revsets.is_empty()),
+        ("from", from.is_some(), "change", change.is_some()),
+        ("to", to.is_some(), "rev", !revsets.is_empty()),
+        ("to", to.is_some(), "change", change.is_some()),
+    ];
+    for &(first, first_present, second, second_present) in &incompatible {
+        if first_present && second_present {
+            return Err(CommandError::abort_with_exit_code(
+                format!(
+                    "abort: cannot specify both --{} and --{}",
+                    first, second
+                ),
+                exitcode::INPUT_ERROR,
+            ));
+        }
+    }
+    let single = |revset: Option<&str>| match revset {
+        Some(revset) => {
+            let rev = hg::revset::resolve_single(revset, repo)
+                .map_err(|e| (e, revset))?;
+            Ok(working_directory_as_none(rev))
+        }
+        // Like `scmutil.revsingle` with an empty revset
+        None => Ok::<_, CommandError>(None),
+    };
+    if let Some(change) = change {
+        let rev = match single(Some(change))? {
+            Some(rev) => rev,
+            None => {
+                return Err(CommandError::unsupported(
+                    "diff of the working directory with --change",
+                ))
+            }
+        };
+        let parents = if rev == NULL_REVISION {
+            [NULL_REVISION, NULL_REVISION]
+        } else {
+            changelog.parents(rev).map_err(RevlogError::from)?
+        };
+        return Ok((Some(parents[0]), Some(rev)));
+    }
+    if from.is_some() || to.is_some() {
+        return Ok((single(from)?, single(to)?));
+    }
+    if revsets.is_empty() {
+        let p1 =
+            hg::revset::resolve_single(".", repo).map_err(|e| (e, "."))?;
+        return Ok((Some(p1), None));
+    }
+    let mut revs = Vec::new();
+    let mut any_empty = false;
+    for &revset in &revsets {
+        let resolved =
+            hg::revset::resolve(revset, repo).map_err(|e| (e, revset))?;
+        any_empty |= resolved.is_empty();
+        for rev in resolved {
+            if !revs.contains(&rev) {
+                revs.push(rev)
+            }
+        }
+    }
+    let (first, last) = match (revs.first(), revs.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return Err(CommandError::abort("abort: empty revision range")),
+    };
+    if first == last && revsets.len() >= 2 && any_empty {
+        return Err(CommandError::abort(
+            "abort: empty revision on one side of range",
+        ));
+    }
+    let first = working_directory_as_none(first);
+    if revs.len() == 1 && revsets.len() == 1 {
+        let revset = revsets[0];
+        let is_range =
+            hg::revset::is_range(revset).map_err(|e| (e, revset))?;
+        if !is_range {
+            return Ok((first, None));
+        }
+    }
+    Ok((first, working_directory_as_none(last)))
+}
+
+fn working_directory_as_none(rev: Revision) -> Option<Revision> {
+    if rev == WORKING_DIRECTORY_REVISION {
+        None
+    } else {
+        Some(rev)
+    }
+}
+
+fn node_of(
+    changelog: &Changelog,
+    rev: Revision,
+) -> Result<Node, CommandError> {
+    if rev == NULL_REVISION {
+        return Ok(NULL_NODE);
+    }
+    changelog
+        .node_from_rev(rev)
+        .copied()
+        .ok_or_else(|| CommandError::from(RevlogError::InvalidRevision))
+}
+
+/// The current date in the local timezone, used for the working directory
+fn now() -> ChangesetDate {
+    let now = chrono::Local::now();
+    ChangesetDate {
+        timestamp: now.timestamp(),
+        timezone_offset: -now.offset().local_minus_utc(),
+    }
+}
+
+fn format_date(date: &ChangesetDate) -> Vec<u8> {
+    date.format("%a %b %d %H:%M:%S %Y %1%2").into_bytes()
+}
+
+/// Whether a file argument names nothing in the working directory nor in
+/// the first side of the comparison, which Python warns about
+fn is_missing(
+    repo: &Repo,
+    dmap: &mut DirstateMap,
+    side1: &DiffSide,
+    path: &HgPath,
+) -> Result<bool, CommandError> {
+    if path.is_empty()
+        || dmap.get(path).is_some()
+        || dmap.has_dir(path)?
+        || side1.files.contains_key(path)
+    {
+        return Ok(false);
+    }
+    let dir = [path.as_bytes(), b"/"].concat();
+    if side1
+        .files
+        .keys()
+        .any(|file| file.as_bytes().starts_with(&dir))
+    {
+        return Ok(false);
+    }
+    let fs_path = repo
+        .working_directory_vfs()
+        .join(get_path_from_bytes(path.as_bytes()));
+    Ok(std::fs::symlink_metadata(fs_path).is_err())
+}
+
+/// The files of the working directory: those of its parent with the
+/// changes that `status` finds
+fn working_directory(
+    repo: &Repo,
+    dmap: &DirstateMap,
+    p1_side: DiffSide,
+    matcher: &(dyn Matcher + Sync),
+) -> Result<DiffSide, CommandError> {
+    let options = StatusOptions {
+        // Like in `rhg status`, assume the dirstate is not ambiguous and
+        // let lookups be compared to their contents
+        last_normal_time: 0,
+        check_exec: true,
+        list_clean: false,
+        list_unknown: false,
+        list_ignored: false,
+        collect_traversed_dirs: false,
+    };
+    let ((lookup, status), _) = hg::status(
+        dmap,
+        matcher,
+        repo.working_directory_path().to_owned(),
+        vec![],
+        options,
+    )?;
+    let mut files = p1_side.files;
+    for path in status.removed.iter().chain(&status.deleted) {
+        files.remove(&**path);
+    }
+    for path in status.modified.iter().chain(&status.added).chain(&lookup) {
+        let file = DiffFile {
+            node: None,
+            flag: working_directory_flag(repo, path)?,
+        };
+        files.insert(path.clone().into_owned(), file);
+    }
+    Ok(DiffSide { rev: None, files })
+}
+
+fn working_directory_flag(
+    repo: &Repo,
+    path: &HgPath,
+) -> Result<ManifestFlag, CommandError> {
+    let fs_path = repo
+        .working_directory_vfs()
+        .join(get_path_from_bytes(path.as_bytes()));
+    let metadata =
+        std::fs::symlink_metadata(&fs_path).when_reading_file(&fs_path)?;
+    Ok(if metadata.file_type().is_symlink() {
+        ManifestFlag::Symlink
+    } else if metadata.mode() & 0o100 != 0 {
+        ManifestFlag::Executable
+    } else {
+        ManifestFlag::Regular
+    })
+}
+
+/// The contents of a file of the working directory, or the target of a
+/// symbolic link
+fn working_directory_data(
+    repo: &Repo,
+    path: &HgPath,
+) -> Result<Vec<u8>, CommandError> {
+    let fs_path = repo
+        .working_directory_vfs()
+        .join(get_path_from_bytes(path.as_bytes()));
+    let metadata =
+        std::fs::symlink_metadata(&fs_path).when_reading_file(&fs_path)?;
+    if metadata.file_type().is_symlink() {
+        let target =
+            std::fs::read_link(&fs_path).when_reading_file(&fs_path)?;
+        Ok(get_bytes_from_path(target))
+    } else {
+        Ok(std::fs::read(&fs_path).when_reading_file(&fs_path)?)
+    }
+}
+
+fn git_mode(flag: ManifestFlag) -> &'static [u8] {
+    match flag {
+        ManifestFlag::Symlink => b"120000",
+        ManifestFlag::Executable => b"100755",
+        ManifestFlag::Regular => b"100644",
+    }
+}
+
+const NULL_HEX: &[u8] = b"0000000000000000000000000000000000000000";
+const EPOCH: &[u8] = b"Thu Jan 01 00:00:00 1970 +0000";
+
+/// What is needed to write the diff of each file
+struct Diff<'a> {
+    repo: &'a Repo,
+    /// The node IDs shown in non-git headers
+    revs: Vec<Vec<u8>>,
+    dates: [Vec<u8>; 2],
+    sides: [&'a DiffSide; 2],
+}
+
+impl Diff<'_> {
+    /// The contents and flag of a file on one side, if any
+    fn file(
+        &self,
+        comparison: &mut Comparison,
+        side: usize,
+        path: Option<&HgPathBuf>,
+    ) -> Result<Option<(Vec<u8>, ManifestFlag)>, CommandError> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let file = &self.sides[side].files[path];
+        let data = match file.node {
+            Some(node) => comparison.file_data(path, node)?,
+            None => working_directory_data(self.repo, path)?,
+        };
+        Ok(Some((data, file.flag)))
+    }
+
+    /// Write the diff of one file, like `patch.trydiff` and
+    /// `patch.diffcontent` in Python
+    fn write_file(
+        &self,
+        out: &mut impl Output,
+        comparison: &mut Comparison,
+        options: &DiffOptions,
+        pair: &FilePair,
+    ) -> Result<(), CommandError> {
+        let (a_prefix, b_prefix): (&[u8], &[u8]) = if options.noprefix {
+            (b"", b"")
+        } else {
+            (b"a/", b"b/")
+        };
+        let file1 = self.file(comparison, 0, pair.path1.as_ref())?;
+        let file2 = self.file(comparison, 1, pair.path2.as_ref())?;
+        let path1 = pair.path1.as_ref().or_else(|| pair.path2.as_ref());
+        let path1 = path1.expect("a file pair has a path").as_bytes();
+        let path2 = pair.path2.as_ref().or_else(|| pair.path1.as_ref());
+        let path2 = path2.expect("a file pair has a path").as_bytes();
+        let data1 = file1.as_ref().map(|(data, _)| &data[..]);
+        let data2 = file2.as_ref().map(|(data, _)| &data[..]);
+        let flag1 = file1.as_ref().map(|&(_, flag)| flag);
+        let flag2 = file2.as_ref().map(|&(_, flag)| flag);
+
+        let mut header = Vec::new();
+        if options.git {
+            header.push(format_bytes!(
+                b"diff --git {}{} {}{}",
+                a_prefix,
+                path1,
+                b_prefix,
+                path2
+            ));
+            match (flag1, flag2) {
+                (None, Some(flag2)) => header.push(format_bytes!(
+                    b"new file mode {}",
+                    git_mode(flag2)
+                )),
+                (Some(flag1), None) => header.push(format_bytes!(
+                    b"deleted file mode {}",
+                    git_mode(flag1)
+                )),
+                (Some(flag1), Some(flag2)) => {
+                    let (mode1, mode2) = (git_mode(flag1), git_mode(flag2));
+                    if mode1 != mode2 {
+                        header.push(format_bytes!(b"old mode {}", mode1));
+                        header.push(format_bytes!(b"new mode {}", mode2));
+                    }
+                    if let Some(kind) = pair.copy {
+                        let operation: &[u8] = match kind {
+                            CopyKind::Copy => b"copy",
+                            CopyKind::Rename => b"rename",
+                        };
+                        header.push(format_bytes!(
+                            b"{} from {}",
+                            operation,
+                            path1
+                        ));
+                        header.push(format_bytes!(
+                            b"{} to {}",
+                            operation,
+                            path2
+                        ));
+                    }
+                }
+                (None, None) => unreachable!("a file pair has a path"),
+            }
+        } else {
+            let revs: Vec<_> = self
+                .revs
+                .iter()
+                .map(|rev| format_bytes!(b"-r {}", rev))
+                .collect();
+            header.push(format_bytes!(b"diff {} {}", revs.join(&b' '), path1));
+        }
+
+        let is_binary =
+            |data: Option<&[u8]>| data.map_or(false, |data| data.contains(&0));
+        let binary = !options.text && (is_binary(data1) || is_binary(data2));
+        let text = if binary && options.git && !options.nobinary {
+            let text = git_binary_patch(
+                data1.unwrap_or_default(),
+                data2.unwrap_or_default(),
+            );
+            if !text.is_empty() {
+                header.push(format_bytes!(
+                    b"index {}..{}",
+                    data1.map_or(NULL_HEX.to_vec(), blob_id),
+                    data2.map_or(NULL_HEX.to_vec(), blob_id)
+                ))
+            }
+            text
+        } else {
+            let a = data1.unwrap_or_default();
+            let b = data2.unwrap_or_default();
+            if a.is_empty() && b.is_empty() {
+                Vec::new()
+            } else if binary {
+                if a == b {
+                    Vec::new()
+                } else {
+                    format_bytes!(b"Binary file {} has changed\n", path1)
+                }
+            } else {
+                let text = unified_hunks(a, b, options.context);
+                if !text.is_empty() {
+                    let date_tag = |date: &[u8], path: Option<&[u8]>| {
+                        if !options.git && !options.nodates {
+                            [b"\t", date].concat()
+                        } else if path.map_or(false, |p| p.contains(&b' ')) {
+                            b"\t".to_vec()
+                        } else {
+                            Vec::new()
+                        }
+                    };
+                    header.push(match data1 {
+                        None => format_bytes!(
+                            b"--- /dev/null{}",
+                            date_tag(EPOCH, None)
+                        ),
+                        Some(_) => format_bytes!(
+                            b"--- {}{}{}",
+                            a_prefix,
+                            path1,
+                            date_tag(&self.dates[0], Some(path1))
+                        ),
+                    });
+                    header.push(match data2 {
+                        None => format_bytes!(
+                            b"+++ /dev/null{}",
+                            date_tag(EPOCH, None)
+                        ),
+                        Some(_) => format_bytes!(
+                            b"+++ {}{}{}",
+                            b_prefix,
+                            path2,
+                            date_tag(&self.dates[1], Some(path2))
+                        ),
+                    });
+                }
+                text
+            }
+        };
+        if !text.is_empty() || header.len() > 1 {
+            out.write(&header.join(&b'\n'))?;
+            out.write(b"\n")?;
+        }
+        out.write(&text)
+    }
+}
+
+fn blob_id(data: &[u8]) -> Vec<u8> {
+    git_blob_id(data).into_bytes()
+}
+
+/// Where diffs are written: standard output, or a buffer for `--stat`
+trait Output {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CommandError>;
+}
+
+impl Output for Vec<u8> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CommandError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl<W: Write> Output for StdoutBuffer<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CommandError> {
+        Ok(self.write_all(bytes)?)
+    }
+}
+
+/// Summarize a diff, like `patch.diffstat` in Python
+fn diffstat(diff: &[u8], width: usize) -> Vec<u8> {
+    // Each file has its name, added and removed lines, and whether it is
+    // binary, like `patch.diffstatdata`
+    let mut stats: Vec<(Vec<u8>, usize, usize, bool)> = Vec::new();
+    let mut in_header = false;
+    for line in diff.split(|&byte| byte == b'\n') {
+        if line.starts_with(b"diff") {
+            in_header = true;
+            let name = if line.starts_with(b"diff --git a/") {
+                // Like the `diff --git a/(.*) b/(.*)` regex
+                let rest = &line[b"diff --git a/".len()..];
+                let separator = rest
+                    .windows(3)
+                    .rposition(|window| window == b" b/")
+                    .unwrap_or(0);
+                rest[separator + 3..].to_vec()
+            } else {
+                // Like the `^diff .*-r [a-z0-9]+\s(.*)$` regex
+                let position = line
+                    .windows(3)
+                    .rposition(|window| window == b"-r ")
+                    .unwrap_or(0);
+                let rest = &line[position + 3..];
+                let space = rest.iter().position(|&b| b == b' ').unwrap_or(0);
+                rest[space + 1..].to_vec()
+            };
+            stats.push((name, 0, 0, false));
+            continue;
+        }
+        let stat = match stats.last_mut() {
+            Some(stat) => stat,
+            None => continue,
+        };
+        if line.starts_with(b"@@") {
+            in_header = false
+        } else if line.starts_with(b"+") && !in_header {
+            stat.1 += 1
+        } else if line.starts_with(b"-") && !in_header {
+            stat.2 += 1
+        } else if line.starts_with(b"GIT binary patch")
+            || line.starts_with(b"Binary file")
+        {
+            stat.3 = true
+        } else if line.starts_with(b"rename from") {
+            stat.0 = line[12..].to_vec()
+        } else if line.starts_with(b"rename to") {
+            stat.0.extend_from_slice(b" => ");
+            stat.0.extend_from_slice(&line[10..])
+        }
+    }
+
+    let name_width =
+        |name: &[u8]| String::from_utf8_lossy(name).chars().count();
+    let max_name = stats.iter().map(|s| name_width(&s.0)).max().unwrap_or(0);
+    let max_total = stats.iter().map(|s| s.1 + s.2).max().unwrap_or(0);
+    let any_binary = stats.iter().any(|s| s.3);
+    let mut count_width = max_total.to_string().len();
+    if any_binary {
+        count_width = count_width.max(3)
+    }
+    let graph_width =
+        (width as i64 - count_width as i64 - max_name as i64 - 6).max(10);
+    let graph_width = graph_width as usize;
+    let scale = |count: usize| {
+        if max_total <= graph_width {
+            count
+        } else {
+            // Always show at least one `+` or `-` for some changes
+            (count * graph_width / max_total).max(if count > 0 {
+                1
+            } else {
+                0
+            })
+        }
+    };
+
+    let mut output = Vec::new();
+    for (name, adds, removes, binary) in &stats {
+        let count = if *binary {
+            "Bin".to_string()
+        } else {
+            (adds + removes).to_string()
+        };
+        output.extend_from_slice(&format_bytes!(
+            b" {}{} |  {}{} {}{}\n",
+            name,
+            vec![b' '; max_name - name_width(name)],
+            vec![b' '; count_width.saturating_sub(count.len())],
+            count.as_bytes(),
+            vec![b'+'; scale(*adds)],
+            vec![b'-'; scale(*removes)]
+        ));
+    }
+    if !stats.is_empty() {
+        let total_adds: usize = stats.iter().map(|s| s.1).sum();
+        let total_removes: usize = stats.iter().map(|s| s.2).sum();
+        output.extend_from_slice(
+            format!(
+                " {} files changed, {} insertions(+), {} deletions(-)\n",
+                stats.len(),
+                total_adds,
+                total_removes
+            )
+            .as_bytes(),
+        );
+    }
+    output
+}
+
+/// The width of the terminal, like `ui.termwidth` in Python
+fn terminal_width() -> usize {
+    if let Some(columns) = std::env::var_os("COLUMNS") {
+        if let Ok(columns) = columns.to_string_lossy().parse() {
+            return columns;
+        }
+    }
+    for &fd in &[libc::STDERR_FILENO, libc::STDOUT_FILENO, libc::STDIN_FILENO]
+    {
+        // Safety: `ioctl` only writes a `winsize` to the given pointer
+        unsafe {
+            if libc::isatty(fd) == 0 {
+                continue;
+            }
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) == 0
+                && size.ws_col > 0
+                && size.ws_row > 0
+            {
+                return size.ws_col.into();
+            }
+        }
+    }
+    80
+}
@@ -1,9 +1,10 @@
 use crate::error::CommandError;
-use crate::ui::Ui;
+use crate::ui::{formatter, Ui};
 use clap::Arg;
 use hg::operations::list_rev_tracked_files;
 use hg::operations::Dirstate;
 use hg::repo::Repo;
+use hg::templater::Formatter;
 use hg::utils::current_dir;
 use hg::utils::files::{get_bytes_from_path, relativize_path};
 use hg::utils::hg_path::{HgPath, HgPathBuf};
@@ -24,6 +25,14 @@ pub fn args() -> clap::App<'static, 'static> {
                 .value_name("REV")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("template")
+                .help("display with template")
+                .short("-T")
+                .long("--template")
+                .value_name("TEMPLATE")
+                .takes_value(true),
+        )
         .about(HELP_TEXT)
 }
 
@@ -35,25 +44,44 @@ pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
         ));
     }
 
-    let rev = invocation.subcommand_args.value_of("rev");
+    let args = invocation.subcommand_args;
+    let rev = args.value_of("rev");
+    let formatter = formatter(invocation.config, args.value_of("template"))?;
 
     let repo = invocation.repo?;
     if let Some(rev) = rev {
         let files = list_rev_tracked_files(repo, rev).map_err(|e| (e, rev))?;
-        display_files(invocation.ui, repo, files.iter())
+        let files = files.iter().collect::<Result<Vec<_>, _>>()?;
+        display_files(invocation.ui, repo, formatter, files)
     } else {
         let distate = Dirstate::new(repo)?;
         let files = distate.tracked_files()?;
-        display_files(invocation.ui, repo, files)
+        display_files(invocation.ui, repo, formatter, files)
     }
 }
 
 fn display_files<'a>(
     ui: &Ui,
     repo: &Repo,
+    mut formatter: Option<Formatter>,
     files: impl IntoIterator<Item = &'a HgPath>,
 ) -> Result<(), CommandError> {
     let mut stdout = ui.stdout_buffer();
+    if let Some(formatter) = &formatter {
+        stdout.write_all(formatter.start())?;
+    }
+    let mut write_path = |path: &[u8]| -> Result<(), CommandError> {
+        match &mut formatter {
+            Some(formatter) => {
+                stdout.write_all(&formatter.item(&[("path", path.into())])?)?
+            }
+            None => {
+                stdout.write_all(path)?;
+                stdout.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    };
 
     let cwd = current_dir()?;
     let working_directory = repo.working_directory_path();
@@ -66,8 +94,7 @@ fn display_files<'a>(
         let cwd = HgPathBuf::from(get_bytes_from_path(cwd_relative_to_repo));
         for file in files {
             any = true;
-            stdout.write_all(relativize_path(&file, &cwd).as_ref())?;
-            stdout.write_all(b"\n")?;
+            write_path(relativize_path(&file, &cwd).as_ref())?;
         }
     } else {
         let working_directory =
@@ -77,11 +104,13 @@ fn display_files<'a>(
             any = true;
             // Absolute path in the filesystem
             let file = working_directory.join(file);
-            stdout.write_all(relativize_path(&file, &cwd).as_ref())?;
-            stdout.write_all(b"\n")?;
+            write_path(relativize_path(&file, &cwd).as_ref())?;
         }
     }
 
+    if let Some(formatter) = &formatter {
+        stdout.write_all(formatter.end())?;
+    }
     stdout.flush()?;
     if any {
         Ok(())
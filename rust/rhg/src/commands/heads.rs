@@ -0,0 +1,177 @@
+use crate::changeset_printer::{
+    check_no_obsolete_changesets, ChangesetPrinter,
+};
+use crate::error::CommandError;
+use clap::Arg;
+use format_bytes::format_bytes;
+use hg::dagops;
+use hg::namespaces::Namespaces;
+use hg::revlog::changelog::Changelog;
+use hg::revlog::revlog::RevlogError;
+use hg::revlog::{Revision, NULL_REVISION};
+use std::collections::{BTreeSet, HashSet};
+
+pub const HELP_TEXT: &str = "
+Show branch heads
+
+With no arguments, show all open branch heads in the repository.
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("heads")
+        .arg(
+            Arg::with_name("rev")
+                .help("show only heads which are descendants of STARTREV")
+                .short("-r")
+                .long("--rev")
+                .value_name("STARTREV")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("topo")
+                .help("show topological heads only")
+                .short("-t")
+                .long("--topo"),
+        )
+        .arg(
+            Arg::with_name("active")
+                .help("show active branchheads only (DEPRECATED)")
+                .short("-a")
+                .long("--active"),
+        )
+        .arg(
+            Arg::with_name("closed")
+                .help("show normal and closed branch heads")
+                .short("-c")
+                .long("--closed"),
+        )
+        .arg(
+            Arg::with_name("template")
+                .help("display with template")
+                .short("-T")
+                .long("--template")
+                .value_name("TEMPLATE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("branches")
+                .multiple(true)
+                .empty_values(false)
+                .value_name("REV"),
+        )
+        .about(HELP_TEXT)
+}
+
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let args = invocation.subcommand_args;
+    let mut printer =
+        ChangesetPrinter::new(invocation.config, args.value_of("template"))?;
+
+    let repo = invocation.repo?;
+    check_no_obsolete_changesets(repo)?;
+    let start = match args.value_of("rev") {
+        Some(rev) => {
+            Some(hg::revset::resolve_single(rev, repo).map_err(|e| (e, rev))?)
+        }
+        None => None,
+    };
+    let changelog = Changelog::open(repo)?;
+    let namespaces = Namespaces::new(repo, &changelog);
+    let len = changelog.len() as Revision;
+    let descendants = match start {
+        Some(start) => Some(
+            dagops::descendants(&changelog, start, len)
+                .map_err(RevlogError::from)?,
+        ),
+        None => None,
+    };
+    // Like `localrepo.heads(start)` in Python
+    let topological_heads = || -> Result<HashSet<Revision>, CommandError> {
+        let all_revisions: HashSet<_>;
+        let revisions = match &descendants {
+            Some(descendants) => descendants,
+            None => {
+                all_revisions = (0..len).collect();
+                &all_revisions
+            }
+        };
+        let mut heads = dagops::heads(&changelog, revisions.iter())
+            .map_err(RevlogError::from)?;
+        // Only the null revision is a head of an empty repository
+        if heads.is_empty() {
+            heads.insert(NULL_REVISION);
+        }
+        Ok(heads)
+    };
+    let branch_of = |rev| -> Result<Vec<u8>, CommandError> {
+        let data = changelog.get_rev(rev)?;
+        Ok(data.branchinfo()?.0)
+    };
+
+    let mut heads: Vec<Revision> = if args.is_present("topo") {
+        topological_heads()?.into_iter().collect()
+    } else {
+        let closed = args.is_present("closed");
+        let mut heads = Vec::new();
+        for (_branch, branch_heads) in namespaces.branchmap()?.iter() {
+            heads.extend(
+                branch_heads
+                    .iter()
+                    .filter(|head| closed || !head.closed)
+                    .map(|head| head.rev)
+                    .filter(|rev| match &descendants {
+                        Some(descendants) => descendants.contains(rev),
+                        None => true,
+                    }),
+            )
+        }
+        heads
+    };
+
+    if let Some(revsets) = args.values_of("branches") {
+        let mut branches = BTreeSet::new();
+        for revset in revsets {
+            for rev in
+                hg::revset::resolve(revset, repo).map_err(|e| (e, revset))?
+            {
+                branches.insert(branch_of(rev)?);
+            }
+        }
+        let mut branch_heads = Vec::new();
+        for rev in heads {
+            let branch = branch_of(rev)?;
+            if branches.contains(&branch) {
+                branch_heads.push((rev, branch))
+            }
+        }
+        if args.is_present("active") {
+            let topological_heads = topological_heads()?;
+            branch_heads.retain(|(rev, _)| topological_heads.contains(rev));
+        }
+        let head_branches: HashSet<_> =
+            branch_heads.iter().map(|(_, branch)| branch).collect();
+        let headless: Vec<&[u8]> = branches
+            .iter()
+            .filter(|branch| !head_branches.contains(branch))
+            .map(|branch| &branch[..])
+            .collect();
+        if !headless.is_empty() {
+            let mut message = format_bytes!(
+                b"no open branch heads found on branches {}",
+                format_bytes::join(headless, b", ")
+            );
+            if let Some(rev) = args.value_of("rev") {
+                message
+                    .extend(format_bytes!(b" (started at {})", rev.as_bytes()))
+            }
+            message.push(b'\n');
+            invocation.ui.write_stderr(&message)?;
+        }
+        heads = branch_heads.into_iter().map(|(rev, _)| rev).collect();
+    }
+    if heads.is_empty() {
+        return Err(CommandError::Unsuccessful);
+    }
+    heads.sort_by(|a, b| b.cmp(a));
+    printer.print(invocation.ui, repo, &changelog, &namespaces, heads)
+}
@@ -0,0 +1,253 @@
+use crate::changeset_printer::check_no_obsolete_changesets;
+use crate::commands::status::{cat_file_is_modified, p1_manifest};
+use crate::error::CommandError;
+use clap::Arg;
+use format_bytes::format_bytes;
+use hg::errors::HgResultExt;
+use hg::matchers::AlwaysMatcher;
+use hg::namespaces::Namespaces;
+use hg::repo::Repo;
+use hg::revlog::changelog::Changelog;
+use hg::revlog::node::NULL_NODE;
+use hg::revlog::revlog::RevlogError;
+use hg::revlog::{Node, Revision, NULL_REVISION, WORKING_DIRECTORY_REVISION};
+use hg::{DirstateMap, StatusOptions};
+
+pub const HELP_TEXT: &str = "
+Identify the working directory or specified revision
+
+Print a summary identifying the repository state at REV using one or two
+parent hash identifiers, followed by a \"+\" if the working directory has
+uncommitted changes, the branch name (if not default), a list of tags, and
+a list of bookmarks.
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("identify")
+        .alias("id")
+        .arg(
+            Arg::with_name("rev")
+                .help("identify the specified revision")
+                .short("-r")
+                .long("--rev")
+                .value_name("REV")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("num")
+                .help("show local revision number")
+                .short("-n")
+                .long("--num"),
+        )
+        .arg(
+            Arg::with_name("id")
+                .help("show global revision id")
+                .short("-i")
+                .long("--id"),
+        )
+        .arg(
+            Arg::with_name("branch")
+                .help("show branch")
+                .short("-b")
+                .long("--branch"),
+        )
+        .arg(
+            Arg::with_name("tags")
+                .help("show tags")
+                .short("-t")
+                .long("--tags"),
+        )
+        .arg(
+            Arg::with_name("bookmarks")
+                .help("show bookmarks")
+                .short("-B")
+                .long("--bookmarks"),
+        )
+        .about(HELP_TEXT)
+}
+
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let args = invocation.subcommand_args;
+    let config = invocation.config;
+    let num = args.is_present("num");
+    let id = args.is_present("id");
+    let branch = args.is_present("branch");
+    let tags = args.is_present("tags");
+    let bookmarks = args.is_present("bookmarks");
+    let default = !(num || id || branch || tags || bookmarks);
+    let quiet = config.get_bool(b"ui", b"quiet")?;
+    let full_hex = config.get_bool(b"ui", b"debug")?;
+    let hex = |node: &Node| {
+        if full_hex {
+            format!("{:x}", node).into_bytes()
+        } else {
+            format!("{:x}", node.short()).into_bytes()
+        }
+    };
+
+    let repo = invocation.repo?;
+    check_no_obsolete_changesets(repo)?;
+    let rev = match args.value_of("rev") {
+        Some(rev) => {
+            hg::revset::resolve_single(rev, repo).map_err(|e| (e, rev))?
+        }
+        None => WORKING_DIRECTORY_REVISION,
+    };
+    let changelog = Changelog::open(repo)?;
+    let namespaces = Namespaces::new(repo, &changelog);
+    let tag_names = namespaces.tags()?;
+    let bookmark_names = namespaces.bookmarks()?;
+
+    let mut output = Vec::new();
+    let mut tag_list = Vec::new();
+    let mut bookmark_list = Vec::new();
+    let branch_name;
+    if rev == WORKING_DIRECTORY_REVISION {
+        let dirstate_parents = repo.dirstate_parents()?;
+        let mut parents = vec![dirstate_parents.p1];
+        if dirstate_parents.p2 != NULL_NODE {
+            parents.push(dirstate_parents.p2)
+        }
+        let mut hexes = Vec::new();
+        let mut revs = Vec::new();
+        for node in &parents {
+            hexes.push(hex(node));
+            revs.push(node_rev(&changelog, node)?.to_string().into_bytes());
+            tag_list.extend(tag_names.names_for(node));
+            bookmark_list.extend(bookmark_names.names_for(node));
+        }
+        let dirty: &[u8] = if (default || id || num)
+            && is_dirty(invocation, repo, &dirstate_parents.p1)?
+        {
+            b"+"
+        } else {
+            b""
+        };
+        if default || id {
+            output.push([&hexes.join(&b'+')[..], dirty].concat());
+        }
+        if num {
+            output.push([&revs.join(&b'+')[..], dirty].concat());
+        }
+        branch_name = repo.dirstate_branch()?;
+    } else {
+        let node = match rev {
+            NULL_REVISION => NULL_NODE,
+            rev => *changelog.node_from_rev(rev).ok_or_else(|| {
+                CommandError::from(RevlogError::InvalidRevision)
+            })?,
+        };
+        if default || id {
+            output.push(hex(&node));
+        }
+        if num {
+            output.push(rev.to_string().into_bytes());
+        }
+        tag_list = tag_names.names_for(&node);
+        bookmark_list = bookmark_names.names_for(&node);
+        let data = changelog.get_rev(rev)?;
+        branch_name = data.branchinfo()?.0;
+    }
+
+    if default && !quiet {
+        if branch_name != b"default" {
+            output.push(format_bytes!(b"({})", branch_name));
+        }
+        // Multiple tags or bookmarks are separated by `/`
+        if !tag_list.is_empty() {
+            output.push(tag_list.join(&b'/'));
+        }
+        if !bookmark_list.is_empty() {
+            output.push(bookmark_list.join(&b'/'));
+        }
+    } else {
+        if branch {
+            output.push(branch_name);
+        }
+        if tags {
+            output.extend(tag_list.into_iter().map(<[u8]>::to_vec));
+        }
+        if bookmarks {
+            output.extend(bookmark_list.into_iter().map(<[u8]>::to_vec));
+        }
+    }
+    invocation
+        .ui
+        .write_stdout(&format_bytes!(b"{}\n", output.join(&b' ')))?;
+    Ok(())
+}
+
+fn node_rev(
+    changelog: &Changelog,
+    node: &Node,
+) -> Result<Revision, CommandError> {
+    if *node == NULL_NODE {
+        return Ok(NULL_REVISION);
+    }
+    changelog
+        .rev_from_node((*node).into())
+        .map_err(CommandError::from)
+}
+
+/// Whether the working directory has uncommitted changes to tracked files,
+/// including missing ones, like `workingctx.dirty(missing=True)` in Python
+fn is_dirty(
+    invocation: &crate::CliInvocation,
+    repo: &Repo,
+    p1: &Node,
+) -> Result<bool, CommandError> {
+    // Like `rhg status`, which this relies on
+    if !invocation
+        .config
+        .get_option(b"rhg", b"status")?
+        .unwrap_or(false)
+    {
+        return Err(CommandError::unsupported(
+            "identify of the working directory is experimental in rhg \
+             (enable it with 'rhg.status = true' or enable fallback with \
+             'rhg.on-unsupported = fallback')",
+        ));
+    }
+    let mut dmap = DirstateMap::new();
+    // A new repository has no dirstate file
+    let dirstate_data =
+        repo.hg_vfs().mmap_open("dirstate").io_not_found_as_none()?;
+    if let Some(dirstate_data) = &dirstate_data {
+        dmap.read(dirstate_data)?;
+    }
+    let options = StatusOptions {
+        // Like in `rhg status`, assume the dirstate is not ambiguous and
+        // let lookups be compared to their contents
+        last_normal_time: 0,
+        check_exec: true,
+        list_clean: false,
+        list_unknown: false,
+        list_ignored: false,
+        collect_traversed_dirs: false,
+    };
+    let ((lookup, status), _) = hg::status(
+        &dmap,
+        &AlwaysMatcher,
+        repo.working_directory_path().to_owned(),
+        vec![],
+        options,
+    )?;
+    if !(status.modified.is_empty()
+        && status.added.is_empty()
+        && status.removed.is_empty()
+        && status.deleted.is_empty())
+    {
+        return Ok(true);
+    }
+    if lookup.is_empty() {
+        return Ok(false);
+    }
+    let p1_hex = format!("{:x}", p1);
+    let manifest = p1_manifest(repo, *p1).map_err(|e| (e, &*p1_hex))?;
+    for path in &lookup {
+        if cat_file_is_modified(repo, &manifest, path, &p1_hex)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,183 @@
+use crate::changeset_printer::{
+    check_no_obsolete_changesets, ChangesetPrinter,
+};
+use crate::error::CommandError;
+use crate::patterns::{relative_path, repo_path};
+use clap::Arg;
+use format_bytes::format_bytes;
+use hg::namespaces::Namespaces;
+use hg::operations::{log_revisions, LogOptions};
+use hg::revlog::changelog::Changelog;
+
+pub const HELP_TEXT: &str = "
+Show revision history of entire repository or files
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("log")
+        .arg(
+            Arg::with_name("follow")
+                .help(
+                    "follow changeset or file history, across copies and \
+                     renames",
+                )
+                .short("-f")
+                .long("--follow"),
+        )
+        .arg(
+            Arg::with_name("rev")
+                .help("show the specified revision or revset")
+                .short("-r")
+                .long("--rev")
+                .value_name("REV")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("keyword")
+                .help("do case-insensitive search for a given text")
+                .short("-k")
+                .long("--keyword")
+                .value_name("TEXT")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("user")
+                .help("revisions committed by user")
+                .short("-u")
+                .long("--user")
+                .value_name("USER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("branch")
+                .help("show changesets within the given named branch")
+                .short("-b")
+                .long("--branch")
+                .value_name("BRANCH")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .help("limit number of changes displayed")
+                .short("-l")
+                .long("--limit")
+                .value_name("NUM")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("template")
+                .help("display with template")
+                .short("-T")
+                .long("--template")
+                .value_name("TEMPLATE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("files")
+                .multiple(true)
+                .empty_values(false)
+                .value_name("FILE"),
+        )
+        .about(HELP_TEXT)
+}
+
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let args = invocation.subcommand_args;
+    let config = invocation.config;
+    let mut printer =
+        ChangesetPrinter::new(config, args.value_of("template"))?;
+    let limit = match args.value_of("limit") {
+        Some(limit) => match limit.trim().parse::<i64>() {
+            Ok(limit) if limit > 0 => Some(limit as usize),
+            Ok(_) => {
+                return Err(CommandError::abort(
+                    "abort: limit must be positive",
+                ))
+            }
+            Err(_) => {
+                return Err(CommandError::abort(
+                    "abort: limit must be a positive integer",
+                ))
+            }
+        },
+        None => None,
+    };
+    let values = |name| -> Vec<String> {
+        args.values_of(name)
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default()
+    };
+    let follow = args.is_present("follow");
+    let file_args = values("files");
+    if !file_args.is_empty() && !follow {
+        return Err(CommandError::unsupported(
+            "`rhg log` with files but without `--follow`",
+        ));
+    }
+
+    let repo = invocation.repo?;
+    check_no_obsolete_changesets(repo)?;
+    let root = hg::utils::current_dir()?.join(repo.working_directory_path());
+    let revisions = match args.values_of("rev") {
+        Some(revsets) => {
+            let mut revisions = Vec::new();
+            for revset in revsets {
+                let revs = hg::revset::resolve(revset, repo)
+                    .map_err(|e| (e, revset))?;
+                for rev in revs {
+                    if !revisions.contains(&rev) {
+                        revisions.push(rev)
+                    }
+                }
+            }
+            Some(revisions)
+        }
+        None => None,
+    };
+    let changelog = Changelog::open(repo)?;
+    let namespaces = Namespaces::new(repo, &changelog);
+    let mut branches = Vec::new();
+    for name in values("branch") {
+        branches.push(
+            namespaces
+                .lookup_branch(&name)
+                .map_err(|e| (e, name.as_str()))?,
+        )
+    }
+    let options = LogOptions {
+        revisions,
+        follow,
+        files: file_args
+            .iter()
+            .map(|file| repo_path(&root, file))
+            .collect::<Result<_, _>>()?,
+        keywords: values("keyword"),
+        users: values("user"),
+        branches,
+        limit,
+    };
+    let output = log_revisions(repo, &options)?;
+    for (file, node) in &output.missing {
+        invocation.ui.write_stderr(&format_bytes!(
+            b"{}: no such file in rev {}\n",
+            relative_path(repo, file)?,
+            format!("{:x}", node.short()).into_bytes()
+        ))?;
+    }
+
+    printer.print(
+        invocation.ui,
+        repo,
+        &changelog,
+        &namespaces,
+        output.revisions,
+    )
+}
@@ -0,0 +1,74 @@
+use crate::changeset_printer::{
+    check_no_obsolete_changesets, ChangesetPrinter,
+};
+use crate::error::CommandError;
+use clap::Arg;
+use hg::namespaces::Namespaces;
+use hg::revlog::changelog::Changelog;
+use hg::revlog::node::NULL_NODE;
+use hg::revlog::revlog::RevlogError;
+use hg::revlog::{NULL_REVISION, WORKING_DIRECTORY_REVISION};
+use hg::Graph;
+
+pub const HELP_TEXT: &str = "
+Show the parents of the working directory or revision
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("parents")
+        .arg(
+            Arg::with_name("rev")
+                .help("show parents of the specified revision")
+                .short("-r")
+                .long("--rev")
+                .value_name("REV")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("template")
+                .help("display with template")
+                .short("-T")
+                .long("--template")
+                .value_name("TEMPLATE")
+                .takes_value(true),
+        )
+        .about(HELP_TEXT)
+}
+
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let args = invocation.subcommand_args;
+    let mut printer =
+        ChangesetPrinter::new(invocation.config, args.value_of("template"))?;
+
+    let repo = invocation.repo?;
+    check_no_obsolete_changesets(repo)?;
+    let rev = match args.value_of("rev") {
+        Some(rev) => {
+            hg::revset::resolve_single(rev, repo).map_err(|e| (e, rev))?
+        }
+        None => WORKING_DIRECTORY_REVISION,
+    };
+    let changelog = Changelog::open(repo)?;
+    let namespaces = Namespaces::new(repo, &changelog);
+    let parents = match rev {
+        WORKING_DIRECTORY_REVISION => {
+            let parents = repo.dirstate_parents()?;
+            let mut revs = Vec::new();
+            for &node in &[parents.p1, parents.p2] {
+                if node != NULL_NODE {
+                    revs.push(changelog.rev_from_node(node.into())?)
+                }
+            }
+            revs
+        }
+        NULL_REVISION => Vec::new(),
+        rev => changelog
+            .parents(rev)
+            .map_err(RevlogError::from)?
+            .iter()
+            .copied()
+            .filter(|&parent| parent != NULL_REVISION)
+            .collect(),
+    };
+    printer.print(invocation.ui, repo, &changelog, &namespaces, parents)
+}
@@ -5,19 +5,27 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use crate::commands::diff::compared_revisions;
 use crate::error::CommandError;
-use crate::ui::Ui;
+use crate::ui::{formatter, Ui};
 use clap::{Arg, SubCommand};
 use hg;
 use hg::errors::IoResultExt;
 use hg::matchers::AlwaysMatcher;
-use hg::operations::cat;
 use hg::repo::Repo;
+use hg::revlog::changelog::Changelog;
+use hg::revlog::filelog::Filelog;
+use hg::revlog::manifest::{Manifest, ManifestChange, ManifestEntry};
 use hg::revlog::node::Node;
-use hg::utils::hg_path::{hg_path_to_os_string, HgPath};
+use hg::revlog::revlog::RevlogError;
+use hg::revlog::Revision;
+use hg::templater::Formatter;
+use hg::operations::Comparison;
+use hg::utils::hg_path::{hg_path_to_os_string, HgPath, HgPathBuf};
 use hg::{DirstateMap, StatusError};
 use hg::{HgPathCow, StatusOptions};
 use log::{info, warn};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs;
 use std::io::BufReader;
@@ -83,6 +91,23 @@ pub fn args() -> clap::App<'static, 'static> {
                 .short("-i")
                 .long("--ignored"),
         )
+        .arg(
+            Arg::with_name("rev")
+                .help("show difference from revision")
+                .long("--rev")
+                .value_name("REV")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("template")
+                .help("display with template")
+                .short("-T")
+                .long("--template")
+                .value_name("TEMPLATE")
+                .takes_value(true),
+        )
 }
 
 /// Pure data type allowing the caller to specify file states to display
@@ -162,7 +187,29 @@ pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
         }
     };
 
+    let mut formatter =
+        formatter(invocation.config, args.value_of("template"))?;
+    let formatter = &mut formatter;
+
     let repo = invocation.repo?;
+    if args.is_present("rev") {
+        let changelog = Changelog::open(repo)?;
+        return match compared_revisions(repo, &changelog, args)? {
+            (Some(rev1), Some(rev2)) => display_changesets_status(
+                ui,
+                formatter,
+                repo,
+                &changelog,
+                (rev1, rev2),
+                display_states,
+                args.is_present("all")
+                    || invocation.config.get_bool(b"ui", b"statuscopies")?,
+            ),
+            _ => Err(CommandError::unsupported(
+                "status of the working directory with --rev",
+            )),
+        };
+    }
     let mut dmap = DirstateMap::new();
     let dirstate_data = repo.hg_vfs().mmap_open("dirstate")?;
     let parents = dmap.read(&dirstate_data)?;
@@ -201,10 +248,13 @@ pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
             &lookup
         );
     }
+    if let Some(formatter) = formatter {
+        ui.write_stdout(formatter.start())?;
+    }
     // TODO check ordering to match `hg status` output.
     // (this is as in `hg help status`)
     if display_states.modified {
-        display_status_paths(ui, &(ds_status.modified), b"M")?;
+        display_status_paths(ui, formatter, &(ds_status.modified), b"M")?;
     }
     if !lookup.is_empty() {
         let p1: Node = parents
@@ -215,39 +265,116 @@ pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
             .p1
             .into();
         let p1_hex = format!("{:x}", p1);
+        let manifest = p1_manifest(repo, p1).map_err(|e| (e, &*p1_hex))?;
         let mut rechecked_modified: Vec<HgPathCow> = Vec::new();
         let mut rechecked_clean: Vec<HgPathCow> = Vec::new();
         for to_check in lookup {
-            if cat_file_is_modified(repo, &to_check, &p1_hex)? {
+            if cat_file_is_modified(repo, &manifest, &to_check, &p1_hex)? {
                 rechecked_modified.push(to_check);
             } else {
                 rechecked_clean.push(to_check);
             }
         }
         if display_states.modified {
-            display_status_paths(ui, &rechecked_modified, b"M")?;
+            display_status_paths(ui, formatter, &rechecked_modified, b"M")?;
         }
         if display_states.clean {
-            display_status_paths(ui, &rechecked_clean, b"C")?;
+            display_status_paths(ui, formatter, &rechecked_clean, b"C")?;
         }
     }
     if display_states.added {
-        display_status_paths(ui, &(ds_status.added), b"A")?;
+        display_status_paths(ui, formatter, &(ds_status.added), b"A")?;
     }
     if display_states.clean {
-        display_status_paths(ui, &(ds_status.clean), b"C")?;
+        display_status_paths(ui, formatter, &(ds_status.clean), b"C")?;
     }
     if display_states.removed {
-        display_status_paths(ui, &(ds_status.removed), b"R")?;
+        display_status_paths(ui, formatter, &(ds_status.removed), b"R")?;
     }
     if display_states.deleted {
-        display_status_paths(ui, &(ds_status.deleted), b"!")?;
+        display_status_paths(ui, formatter, &(ds_status.deleted), b"!")?;
     }
     if display_states.unknown {
-        display_status_paths(ui, &(ds_status.unknown), b"?")?;
+        display_status_paths(ui, formatter, &(ds_status.unknown), b"?")?;
     }
     if display_states.ignored {
-        display_status_paths(ui, &(ds_status.ignored), b"I")?;
+        display_status_paths(ui, formatter, &(ds_status.ignored), b"I")?;
+    }
+    if let Some(formatter) = formatter {
+        ui.write_stdout(formatter.end())?;
+    }
+    Ok(())
+}
+
+/// Display how files changed between two changesets, which are all
+/// tracked and present, and where they were copied from if `show_copies`
+fn display_changesets_status(
+    ui: &Ui,
+    formatter: &mut Option<Formatter>,
+    repo: &Repo,
+    changelog: &Changelog,
+    (rev1, rev2): (Revision, Revision),
+    display_states: DisplayStates,
+    show_copies: bool,
+) -> Result<(), CommandError> {
+    let manifest = Manifest::open(repo)?;
+    // Python does not list the clean files of directories that are the
+    // same on both sides
+    if display_states.clean && manifest.is_tree() {
+        return Err(CommandError::unsupported(
+            "clean files between revisions with tree manifests",
+        ));
+    }
+    // `rhg status` does not take file patterns yet
+    let matcher = AlwaysMatcher;
+    let manifest_of = |rev| -> Result<ManifestEntry, RevlogError> {
+        let manifest_node = changelog.get_rev(rev)?.manifest_node()?;
+        manifest.get_node_matching(manifest_node.into(), &matcher)
+    };
+    let manifest1 = manifest_of(rev1)?;
+    let manifest2 = manifest_of(rev2)?;
+    let mut modified = Vec::new();
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut clean = Vec::new();
+    let changes = manifest1.diff(&manifest2, &matcher, display_states.clean);
+    for change in changes {
+        let change = change?;
+        let paths = match change {
+            ManifestChange::Modified { .. }
+            | ManifestChange::FlagChanged { .. } => &mut modified,
+            ManifestChange::Added(_) => &mut added,
+            ManifestChange::Removed(_) => &mut removed,
+            ManifestChange::Clean(_) => &mut clean,
+        };
+        paths.push(HgPathCow::Borrowed(change.path()))
+    }
+    let copies = if show_copies {
+        let mut comparison = Comparison::new(repo, changelog)?;
+        let side1 = comparison.changeset(rev1, &matcher)?;
+        let side2 = comparison.changeset(rev2, &matcher)?;
+        comparison.copies(&side1, &side2, None, &matcher)?
+    } else {
+        HashMap::new()
+    };
+
+    if let Some(formatter) = formatter {
+        ui.write_stdout(formatter.start())?;
+    }
+    // In the order of `hg status`, without the states of the working
+    // directory
+    for &(display, paths, prefix) in &[
+        (display_states.modified, &modified, b"M"),
+        (display_states.added, &added, b"A"),
+        (display_states.removed, &removed, b"R"),
+        (display_states.clean, &clean, b"C"),
+    ] {
+        if display {
+            display_status_copies(ui, formatter, paths, prefix, &copies)?;
+        }
+    }
+    if let Some(formatter) = formatter {
+        ui.write_stdout(formatter.end())?;
     }
     Ok(())
 }
@@ -256,42 +383,87 @@ pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
 // harcode HgPathBuf, but probably not really useful at this point
 fn display_status_paths(
     ui: &Ui,
+    formatter: &mut Option<Formatter>,
     paths: &[HgPathCow],
     status_prefix: &[u8],
+) -> Result<(), CommandError> {
+    display_status_copies(ui, formatter, paths, status_prefix, &HashMap::new())
+}
+
+/// Like `display_status_paths`, followed by the sources of the copied files
+fn display_status_copies(
+    ui: &Ui,
+    formatter: &mut Option<Formatter>,
+    paths: &[HgPathCow],
+    status_prefix: &[u8],
+    copies: &HashMap<HgPathBuf, HgPathBuf>,
 ) -> Result<(), CommandError> {
     for path in paths {
         // Same TODO as in commands::root
         let bytes: &[u8] = path.as_bytes();
+        let source = copies.get(&**path);
+        if let Some(formatter) = formatter {
+            let mut fields = vec![
+                ("itemtype", "file".into()),
+                ("path", bytes.into()),
+                ("status", status_prefix.into()),
+            ];
+            if let Some(source) = source {
+                fields.push(("source", source.as_bytes().into()))
+            }
+            ui.write_stdout(&formatter.item(&fields)?)?;
+            continue;
+        }
         // TODO optim, probably lots of unneeded copies here, especially
         // if out stream is buffered
         ui.write_stdout(&[status_prefix, b" ", bytes, b"\n"].concat())?;
+        if let Some(source) = source {
+            ui.write_stdout(&[b"  ", source.as_bytes(), b"\n"].concat())?;
+        }
     }
     Ok(())
 }
 
+/// The manifest of the first parent of the working directory
+pub fn p1_manifest(
+    repo: &Repo,
+    p1: Node,
+) -> Result<ManifestEntry, RevlogError> {
+    let changelog = Changelog::open(repo)?;
+    let manifest_node = changelog.get_node(p1.into())?.manifest_node()?;
+    Manifest::open(repo)?.get_node(manifest_node.into())
+}
+
 /// Check if a file is modified by comparing actual repo store and file system.
 ///
 /// This meant to be used for those that the dirstate cannot resolve, due
 /// to time resolution limits.
 ///
 /// TODO: detect permission bits and similar metadata modifications
-fn cat_file_is_modified(
+pub fn cat_file_is_modified(
     repo: &Repo,
+    manifest: &ManifestEntry,
     hg_path: &HgPath,
     rev: &str,
 ) -> Result<bool, CommandError> {
-    // TODO CatRev expects &[HgPathBuf], something like
-    // &[impl Deref<HgPath>] would be nicer and should avoid the copy
-    let path_bufs = [hg_path.into()];
-    // TODO IIUC CatRev returns a simple Vec<u8> for all files
-    //      being able to tell them apart as (path, bytes) would be nicer
-    //      and OPTIM would allow manifest resolution just once.
-    let output = cat(repo, rev, &path_bufs).map_err(|e| (e, rev))?;
+    let file = match manifest.find_file(hg_path)? {
+        Some(file) => file,
+        None => return Ok(true),
+    };
+    let filelog = Filelog::open(repo, hg_path).map_err(|e| (e, rev))?;
+    let ignore_censored =
+        repo.config().get(b"censor", b"policy") == Some(b"ignore");
+    let hg_data = match filelog.get_node(file.node_id()?.into()) {
+        Ok(entry) => entry.into_data()?,
+        // Like `filectx.data` in Python
+        Err(RevlogError::Censored { .. }) if ignore_censored => Vec::new(),
+        Err(error) => return Err((error, rev).into()),
+    };
 
     let fs_path = repo
         .working_directory_vfs()
         .join(hg_path_to_os_string(hg_path).expect("HgPath conversion"));
-    let hg_data_len: u64 = match output.concatenated.len().try_into() {
+    let hg_data_len: u64 = match hg_data.len().try_into() {
         Ok(v) => v,
         Err(_) => {
             // conversion of data length to u64 failed,
@@ -304,9 +476,7 @@ fn cat_file_is_modified(
     {
         return Ok(true);
     }
-    for (fs_byte, hg_byte) in
-        BufReader::new(fobj).bytes().zip(output.concatenated)
-    {
+    for (fs_byte, hg_byte) in BufReader::new(fobj).bytes().zip(hg_data) {
         if fs_byte.map_err(|e| StatusError::from(e))? != hg_byte {
             return Ok(true);
         }
@@ -0,0 +1,90 @@
+use crate::error::CommandError;
+use clap::Arg;
+use format_bytes::format_bytes;
+use hg::operations::verify;
+use micro_timer::timed;
+
+pub const HELP_TEXT: &str = "
+verify the integrity of the repository
+
+Verify the integrity of the current repository.
+
+This will perform an extensive check of the repository's integrity,
+validating the hashes and checksums of each entry in the changelog,
+manifest, and tracked files, as well as the integrity of their crosslinks
+and indices.
+
+Returns 0 on success, 1 if errors are encountered.
+";
+
+pub fn args() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("verify")
+        .arg(
+            Arg::with_name("full")
+                .help("perform more checks (EXPERIMENTAL)")
+                .long("--full"),
+        )
+        .about(HELP_TEXT)
+}
+
+#[timed]
+pub fn run(invocation: &crate::CliInvocation) -> Result<(), CommandError> {
+    let args = invocation.subcommand_args;
+    let config = invocation.config;
+    // Verbose output has notes and extra warnings
+    if config.get_bool(b"ui", b"verbose")? {
+        return Err(CommandError::unsupported("`rhg verify --verbose`"));
+    }
+    let quiet = config.get_bool(b"ui", b"quiet")?;
+    let repo = invocation.repo?;
+    let ui = invocation.ui;
+
+    let output = verify(repo, args.is_present("full"))?;
+    if output.abandoned_transaction {
+        ui.write_stderr(b"abandoned transaction found - run hg recover\n")?;
+    }
+    for step in &output.steps {
+        if !quiet {
+            ui.write_stdout(format!("{}\n", step.description).as_bytes())?;
+        }
+        for problem in &step.problems {
+            ui.write_stderr(&problem.format())?;
+        }
+    }
+    if !quiet {
+        ui.write_stdout(&format_bytes!(
+            b"checked {} changesets with {} changes to {} files\n",
+            output.changesets,
+            output.file_revisions,
+            output.files
+        ))?;
+    }
+    let warnings = output.warnings();
+    if warnings > 0 {
+        ui.write_stderr(&format_bytes!(
+            b"{} warnings encountered!\n",
+            warnings
+        ))?;
+    }
+    if output.fncache_warned {
+        ui.write_stderr(
+            b"hint: run \"hg debugrebuildfncache\" to recover from \
+              corrupt fncache\n",
+        )?;
+    }
+    let errors = output.errors();
+    if errors > 0 {
+        ui.write_stderr(&format_bytes!(
+            b"{} integrity errors encountered!\n",
+            errors
+        ))?;
+        if let Some(rev) = output.first_damaged() {
+            ui.write_stderr(&format_bytes!(
+                b"(first damaged changeset appears to be {})\n",
+                rev
+            ))?;
+        }
+        return Err(CommandError::Unsuccessful);
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! The `-c|-m|FILE` arguments of commands inspecting a revlog, like
+//! `cmdutil.debugrevlogopts` in Python
+
+use crate::error::CommandError;
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use hg::operations::{debug_open_revlog, DebugRevlogKind};
+use hg::repo::Repo;
+use hg::revlog::revlog::Revlog;
+use hg::utils::hg_path::HgPath;
+
+pub fn args(app: App<'static, 'static>) -> App<'static, 'static> {
+    app.arg(
+        Arg::with_name("changelog")
+            .help("open changelog")
+            .short("-c")
+            .long("--changelog"),
+    )
+    .arg(
+        Arg::with_name("manifest")
+            .help("open manifest")
+            .short("-m")
+            .long("--manifest"),
+    )
+    .arg(Arg::with_name("file").value_name("FILE"))
+    .group(
+        ArgGroup::with_name("revlog")
+            .args(&["changelog", "manifest", "file"])
+            .required(true),
+    )
+}
+
+/// Open the revlog that the arguments designate
+pub fn open<'a>(
+    invocation: &crate::CliInvocation<'a>,
+) -> Result<(&'a Repo, Revlog), CommandError> {
+    let args = invocation.subcommand_args;
+    // Python can also open revlog files outside of a repository
+    let repo = invocation.repo.map_err(|_| {
+        CommandError::unsupported("inspecting a revlog without a repository")
+    })?;
+    let revlog = debug_open_revlog(repo, kind(args))?;
+    Ok((repo, revlog))
+}
+
+fn kind<'a>(args: &'a ArgMatches) -> DebugRevlogKind<'a> {
+    if args.is_present("changelog") {
+        DebugRevlogKind::Changelog
+    } else if args.is_present("manifest") {
+        DebugRevlogKind::Manifest
+    } else {
+        let file = args.value_of("file").expect("a revlog is required");
+        DebugRevlogKind::File(HgPath::new(file.as_bytes()))
+    }
+}
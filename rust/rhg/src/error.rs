@@ -69,6 +69,12 @@ impl From<HgError> for CommandError {
             HgError::UnsupportedFeature(message) => {
                 CommandError::unsupported(message)
             }
+            HgError::InputError(message) => {
+                CommandError::abort_with_exit_code(
+                    message,
+                    exitcode::INPUT_ERROR,
+                )
+            }
             _ => CommandError::abort(error.to_string()),
         }
     }
@@ -168,11 +174,31 @@ impl From<(RevlogError, &str)> for CommandError {
                 "abort: ambiguous revision identifier: {}",
                 rev
             )),
+            RevlogError::Censored { node, .. } => {
+                CommandError::abort(format!(
+                    "abort: censored node: {:x}\n\
+                     (set censor.policy to ignore errors)",
+                    node.short()
+                ))
+            }
             RevlogError::Other(error) => error.into(),
         }
     }
 }
 
+/// Errors about revisions that were already resolved, which can only come
+/// from corrupted or unsupported data
+impl From<RevlogError> for CommandError {
+    fn from(err: RevlogError) -> CommandError {
+        match err {
+            RevlogError::InvalidRevision | RevlogError::AmbiguousPrefix => {
+                HgError::corrupted("invalid revision in revlog").into()
+            }
+            err => (err, "").into(),
+        }
+    }
+}
+
 impl From<StatusError> for CommandError {
     fn from(error: StatusError) -> Self {
         CommandError::abort(format!("{}", error))
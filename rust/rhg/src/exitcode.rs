@@ -6,6 +6,9 @@ pub const OK: ExitCode = 0;
 /// Generic abort
 pub const ABORT: ExitCode = 255;
 
+/// Abort because of invalid user input, like `InputError` in Python
+pub const INPUT_ERROR: ExitCode = 10;
+
 // Abort when there is a config related error
 pub const CONFIG_ERROR_ABORT: ExitCode = 30;
 
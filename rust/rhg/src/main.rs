@@ -14,8 +14,11 @@ use std::path::PathBuf;
 use std::process::Command;
 
 mod blackbox;
+mod changeset_printer;
+mod debug_revlog;
 mod error;
 mod exitcode;
+mod patterns;
 mod ui;
 use error::CommandError;
 
@@ -352,13 +355,23 @@ macro_rules! subcommands {
 }
 
 subcommands! {
+    annotate
     cat
     debugdata
+    debugdeltachain
+    debugindex
     debugrequirements
+    debugrevlogstats
+    diff
     files
+    heads
+    identify
+    log
+    parents
     root
     config
     status
+    verify
 }
 
 pub struct CliInvocation<'a> {
@@ -478,7 +491,7 @@ impl OnUnsupported {
     }
 }
 
-const SUPPORTED_EXTENSIONS: &[&[u8]] = &[b"blackbox", b"share"];
+const SUPPORTED_EXTENSIONS: &[&[u8]] = &[b"blackbox", b"lfs", b"share"];
 
 fn check_extensions(config: &Config) -> Result<(), CommandError> {
     let enabled = config.get_section_keys(b"extensions");
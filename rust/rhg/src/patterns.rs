@@ -0,0 +1,140 @@
+//! Parsing file arguments and `-I`/`-X` patterns of commands, and showing
+//! paths relative to the current directory
+
+use crate::error::CommandError;
+use hg::matchers::IncludeMatcher;
+use hg::repo::Repo;
+use hg::utils::files::{canonical_path, get_bytes_from_path, relativize_path};
+use hg::utils::hg_path::{
+    path_to_hg_path_buf, HgPath, HgPathBuf, HgPathError,
+};
+use hg::{parse_pattern_syntax, IgnorePattern, PatternSyntax, StatusError};
+use std::path::{Component, Path, PathBuf};
+
+/// Parse file arguments or `-I`/`-X` patterns, like `matchmod._donormalize`
+/// in Python. Patterns relative to the current directory are made relative
+/// to the repository root.
+///
+/// Also returns the paths given by file arguments, for which Python warns
+/// when they do not exist.
+pub fn parse_patterns(
+    root: &Path,
+    args: &[&str],
+    default_syntax: PatternSyntax,
+) -> Result<(Vec<IgnorePattern>, Vec<HgPathBuf>), CommandError> {
+    let mut patterns = Vec::new();
+    let mut explicit_files = Vec::new();
+    for &arg in args {
+        let known_kind = arg.find(':').and_then(|colon| {
+            let syntax = parse_pattern_syntax(arg[..=colon].as_bytes());
+            syntax.ok().map(|syntax| (syntax, &arg[colon + 1..]))
+        });
+        let (syntax, pattern) = match known_kind {
+            Some(kind) => kind,
+            None if arg.starts_with("set:") || arg.starts_with("listfile") => {
+                return Err(CommandError::unsupported(format!(
+                    "file pattern {}",
+                    arg
+                )))
+            }
+            None => (default_syntax.clone(), arg),
+        };
+        let pattern = match syntax {
+            PatternSyntax::RelPath | PatternSyntax::Glob => {
+                repo_path(root, pattern)?.into_vec()
+            }
+            PatternSyntax::Path => {
+                let normalized = normalize(Path::new(pattern));
+                get_bytes_from_path(if normalized == Path::new("") {
+                    Path::new(".")
+                } else {
+                    &normalized
+                })
+            }
+            PatternSyntax::Regexp
+            | PatternSyntax::RelRegexp
+            | PatternSyntax::RelGlob
+            | PatternSyntax::RootGlob => pattern.as_bytes().to_vec(),
+            _ => {
+                return Err(CommandError::unsupported(format!(
+                    "file pattern {}",
+                    arg
+                )))
+            }
+        };
+        let syntax = match syntax {
+            PatternSyntax::RelPath => {
+                explicit_files.push(HgPathBuf::from_bytes(&pattern));
+                PatternSyntax::Path
+            }
+            PatternSyntax::Path => {
+                explicit_files.push(HgPathBuf::from_bytes(&pattern));
+                PatternSyntax::Path
+            }
+            syntax => syntax,
+        };
+        // Python matches the repository root as `.` or an empty path
+        let pattern = if pattern.is_empty() {
+            b".".to_vec()
+        } else {
+            pattern
+        };
+        patterns.push(IgnorePattern::new(syntax, &pattern, ""));
+    }
+    Ok((patterns, explicit_files))
+}
+
+pub fn pattern_matcher(
+    patterns: Vec<IgnorePattern>,
+    root: &Path,
+) -> Result<Option<IncludeMatcher<'static>>, CommandError> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let (matcher, _) = IncludeMatcher::new(patterns, root)
+        .map_err(|error| StatusError::Pattern(error))?;
+    Ok(Some(matcher))
+}
+
+/// Remove `..` components like `os.path.normpath`
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Convert a path relative to the current directory into a repository path,
+/// like `pathutil.canonpath`
+pub fn repo_path(root: &Path, file: &str) -> Result<HgPathBuf, CommandError> {
+    let cwd = hg::utils::current_dir()?;
+    let normalized = normalize(&cwd.join(file));
+    match canonical_path(root, &cwd, &normalized) {
+        Ok(path) => Ok(path_to_hg_path_buf(path)
+            .map_err(|e| CommandError::abort(e.to_string()))?),
+        Err(HgPathError::NotUnderRoot { .. }) => Err(CommandError::abort(
+            format!("abort: {} not under root '{}'", file, root.display()),
+        )),
+        Err(error) => Err(CommandError::abort(format!("abort: {}", error))),
+    }
+}
+
+/// Make a repository path relative to the current directory
+pub fn relative_path(
+    repo: &Repo,
+    path: &HgPath,
+) -> Result<Vec<u8>, CommandError> {
+    let cwd = hg::utils::current_dir()?;
+    let working_directory = cwd.join(repo.working_directory_path());
+    let path =
+        HgPathBuf::from(get_bytes_from_path(working_directory)).join(path);
+    let cwd = HgPathBuf::from(get_bytes_from_path(cwd));
+    Ok(relativize_path(&path, &cwd).into_owned())
+}
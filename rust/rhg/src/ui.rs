@@ -1,4 +1,7 @@
 use format_bytes::format_bytes;
+use hg::config::Config;
+use hg::errors::HgError;
+use hg::templater::Formatter;
 use std::borrow::Cow;
 use std::io;
 use std::io::{ErrorKind, Write};
@@ -110,3 +113,19 @@ pub fn utf8_to_local(s: &str) -> Cow<[u8]> {
     let bytes = s.as_bytes();
     Cow::Borrowed(bytes)
 }
+
+/// The formatter selected with `-T/--template`, or `None` for the default
+/// output of a command
+pub fn formatter(
+    config: &Config,
+    template: Option<&str>,
+) -> Result<Option<Formatter>, HgError> {
+    let template = match template {
+        Some(template) if !template.is_empty() => template,
+        _ => return Ok(None),
+    };
+    if !config.get_section_keys(b"templatealias").is_empty() {
+        return Err(HgError::unsupported("[templatealias] config"));
+    }
+    Ok(Some(Formatter::new(template.as_bytes())?))
+}
@@ -127,6 +127,193 @@ Specifying revisions by changeset ID
   $ $NO_FALLBACK rhg cat -r d file-2
   2
 
+Specifying revisions with revset expressions
+  $ $NO_FALLBACK rhg cat -r 'first(:, 2)' file-2
+  2
+  $ $NO_FALLBACK rhg files --revision=-5
+  file-1
+  file-2
+  $ $NO_FALLBACK rhg files -r 'ancestors(2) - 1::'
+  file-1
+  $ $NO_FALLBACK rhg files -r 'none()'
+  abort: empty revision set
+  [255]
+  $ $NO_FALLBACK rhg files -r '1 +'
+  hg: parse error at 3: not a prefix: end
+  (1 +
+      ^ here)
+  [10]
+  $ $NO_FALLBACK rhg files -r ''
+  hg: parse error: empty query
+  [10]
+  $ $NO_FALLBACK rhg files -r '::'
+  hg: parse error: can't use '::' in this context
+  [10]
+  $ $NO_FALLBACK rhg files -r 'draft()'
+  unsupported feature: revset function 'draft'
+  [252]
+
+Specifying revisions by name
+  $ hg bookmark -r 1 c
+  $ hg tag -r 2 some-tag
+  $ hg update -q 3
+  $ hg branch -q other-branch
+  $ hg commit -m "New branch"
+  $ $NO_FALLBACK rhg files -r c
+  file-1
+  file-2
+  $ $NO_FALLBACK rhg files -r c -T json
+  [
+   {
+    "path": "file-1"
+   },
+   {
+    "path": "file-2"
+   }
+  ]
+  $ $NO_FALLBACK rhg files -r c -T '{path|json}\n'
+  "file-1"
+  "file-2"
+  $ $NO_FALLBACK rhg files -r some-tag
+  file-1
+  file-2
+  file-3
+  $ $NO_FALLBACK rhg cat -r default .hgtags
+  cf8b83f14ead62b374b6e91a0e9303b85dfd9ed7 some-tag
+  $ $NO_FALLBACK rhg files -r 'other-branch^'
+  file-1
+  file-2
+  file-3
+  file-4
+  $ $NO_FALLBACK rhg files -r . | tail -n 1
+  file-4
+  $ $NO_FALLBACK rhg files -r tip | tail -n 1
+  file-4
+  $ rm -r .hg/cache
+  $ $NO_FALLBACK rhg files -r 'some-tag::default' | tail -n 1
+  file-6
+  $ $NO_FALLBACK rhg files -r unknown-name
+  abort: invalid revision identifier: unknown-name
+  [255]
+
+Log
+  $ $NO_FALLBACK rhg log -l 2
+  changeset:   7:7c97201bf59a
+  branch:      other-branch
+  tag:         tip
+  parent:      3:f646af7e9648
+  user:        test
+  date:        Thu Jan 01 00:00:00 1970 +0000
+  summary:     New branch
+  
+  changeset:   6:5bf95a8df1bd
+  user:        test
+  date:        Thu Jan 01 00:00:00 1970 +0000
+  summary:     Added tag some-tag for changeset cf8b83f14ead
+  
+  $ $NO_FALLBACK rhg log -r 'c + some-tag' -T '{rev}:{node} [{tags}] [{bookmarks}]\n'
+  1:91c6f6e73e39318534dc415ea4e8a09c99cd74d6 [] [c]
+  2:cf8b83f14ead62b374b6e91a0e9303b85dfd9ed7 [some-tag] []
+  $ $NO_FALLBACK rhg log -r 'null:1 + 0~2 + first(descendants(null), 3)' -T '{rev} '
+  -1 0 1  (no-eol)
+  $ $NO_FALLBACK rhg log -r 'ancestors(null) + 0^1 + 0^ + children(null)' -T '{rev} '
+  -1 0  (no-eol)
+  $ $NO_FALLBACK rhg log -r '::2' -T '{rev} '
+  0 1 2  (no-eol)
+  $ $NO_FALLBACK rhg log -r 'ancestors(1) + ::some-tag' -T '{rev} '
+  0 1 2  (no-eol)
+  $ $NO_FALLBACK rhg log -r 'p1() + p2() + parents()' -T '{rev} '
+  7  (no-eol)
+  $ $NO_FALLBACK rhg log -b 're:.*'
+  abort: unknown revision 're:.*'
+  [255]
+  $ $NO_FALLBACK rhg log -b other-branch -b 2 -u TEST -k BRANCH -k tags
+  changeset:   7:7c97201bf59a
+  branch:      other-branch
+  tag:         tip
+  parent:      3:f646af7e9648
+  user:        test
+  date:        Thu Jan 01 00:00:00 1970 +0000
+  summary:     New branch
+  
+  changeset:   6:5bf95a8df1bd
+  user:        test
+  date:        Thu Jan 01 00:00:00 1970 +0000
+  summary:     Added tag some-tag for changeset cf8b83f14ead
+  
+  $ $NO_FALLBACK rhg log -f file-2 -T '{rev}: {desc}\n'
+  1: Commit 2
+  $ $NO_FALLBACK rhg log -f ../outside
+  abort: ../outside not under root '$TESTTMP/repository'
+  [255]
+  $ $NO_FALLBACK rhg log -f -r 5 -l 3 -T '{rev} {p1rev} {p2rev}\n'
+  5 4 -1
+  4 3 -1
+  3 2 -1
+  $ $NO_FALLBACK rhg log -l 0
+  abort: limit must be positive
+  [255]
+  $ $NO_FALLBACK rhg log -r 1 -T '{node|short} {date|isodate} {desc|firstline|json} {files % "<{file}>"}\n'
+  91c6f6e73e39 1970-01-01 00:00 +0000 "Commit 2" <file-2>
+  $ $NO_FALLBACK rhg log -r 7 -T '{if(tags, join(tags, ","), "none")} {pad(rev, 3, "0", True)} {label("red", branch)}\n'
+  tip 007 other-branch
+  $ $NO_FALLBACK rhg log -r 0:1 -T json
+  [
+   {
+    "bookmarks": [],
+    "branch": "default",
+    "date": [0, 0],
+    "desc": "Commit 1",
+    "node": "6ae9681c6d30389694d8701faf24b583cf3ccafe",
+    "parents": ["0000000000000000000000000000000000000000"],
+    "phase": "draft",
+    "rev": 0,
+    "tags": [],
+    "user": "test"
+   },
+   {
+    "bookmarks": ["c"],
+    "branch": "default",
+    "date": [0, 0],
+    "desc": "Commit 2",
+    "node": "91c6f6e73e39318534dc415ea4e8a09c99cd74d6",
+    "parents": ["6ae9681c6d30389694d8701faf24b583cf3ccafe"],
+    "phase": "draft",
+    "rev": 1,
+    "tags": [],
+    "user": "test"
+   }
+  ]
+  $ $NO_FALLBACK rhg log -r 'none()' -T json
+  [
+  ]
+  $ $NO_FALLBACK rhg log -T '{rev'
+  hg: parse error at 1: unterminated template expansion
+  ({rev
+    ^ here)
+  [255]
+  $ $NO_FALLBACK rhg log -T '{unknown}'
+  unsupported feature: template keyword 'unknown'
+  [252]
+  $ $NO_FALLBACK rhg log -T status
+  unsupported feature: template style "status"
+  [252]
+  $ touch unknown-file
+  $ $NO_FALLBACK rhg status --config rhg.status=true -T json
+  [
+   {
+    "itemtype": "file",
+    "path": "unknown-file",
+    "status": "?"
+   }
+  ]
+  $ $NO_FALLBACK rhg status --config rhg.status=true -T '{status} {path}\n'
+  ? unknown-file
+  $ rm unknown-file
+  $ $NO_FALLBACK rhg log file-2
+  unsupported feature: `rhg log` with files but without `--follow`
+  [252]
+
 Cat files
   $ cd $TESTTMP
   $ rm -rf repository
@@ -142,33 +329,163 @@ Cat copied file should not display copy metadata
   $ hg commit -m "add copy of original"
   $ $NO_FALLBACK rhg cat -r 1 copy_of_original
   original content
+  $ $NO_FALLBACK rhg log -f copy_of_original -T '{rev}: {desc}\n'
+  1: add copy of original
+  0: add original
 
-Fallback to Python
+Cat defaults to the parent of the working directory
   $ $NO_FALLBACK rhg cat original
-  unsupported feature: `rhg cat` without `--rev` / `-r`
+  original content
+  $ $NO_FALLBACK rhg cat -o 'out/%R-%h/%d/%s' original copy_of_original
+  $ find out | sort
+  out
+  out/1-41263439dc17
+  out/1-41263439dc17/copy_of_original
+  out/1-41263439dc17/original
+  $ cat out/1-41263439dc17/original
+  original content
+  $ rm -r out
+  $ $NO_FALLBACK rhg cat -o '%p.%x' original
+  abort: invalid format spec '%x' in output filename
+  [255]
+
+Diff
+  $ $NO_FALLBACK rhg diff --git -c 1
+  diff --git a/original b/copy_of_original
+  copy from original
+  copy to copy_of_original
+  $ $NO_FALLBACK rhg diff -r 0 -r 1 --nodates
+  diff -r 1c9e69808da7 -r 41263439dc17 copy_of_original
+  --- /dev/null
+  +++ b/copy_of_original
+  @@ -0,0 +1,1 @@
+  +original content
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev 0 --rev 1 -A
+  A copy_of_original
+    original
+  C original
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev 0 --rev 1 -a
+  A copy_of_original
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev 0 --rev 1 \
+  >   --config ui.statuscopies=yes
+  A copy_of_original
+    original
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev 1 --rev null
+  R copy_of_original
+  R original
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev 0
+  unsupported feature: status of the working directory with --rev
+  [252]
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev 1:1
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev '(1:1)'
+  unsupported feature: status of the working directory with --rev
+  [252]
+  $ hg mv copy_of_original renamed
+  $ echo "more content" >> original
+  $ $NO_FALLBACK rhg diff --git
+  unsupported feature: diff of the working directory is experimental in rhg (enable it with 'rhg.status = true' or enable fallback with 'rhg.on-unsupported = fallback')
+  [252]
+  $ $NO_FALLBACK rhg diff --git --config rhg.status=true
+  diff --git a/original b/original
+  --- a/original
+  +++ b/original
+  @@ -1,1 +1,2 @@
+   original content
+  +more content
+  diff --git a/copy_of_original b/renamed
+  rename from copy_of_original
+  rename to renamed
+  $ $NO_FALLBACK rhg diff --stat -r 0 --config rhg.status=true
+   original |  1 +
+   renamed  |  1 +
+   2 files changed, 2 insertions(+), 0 deletions(-)
+  $ $NO_FALLBACK rhg diff --git -U 0 -X renamed --config rhg.status=true
+  diff --git a/copy_of_original b/copy_of_original
+  deleted file mode 100644
+  --- a/copy_of_original
+  +++ /dev/null
+  @@ -1,1 +0,0 @@
+  -original content
+  diff --git a/original b/original
+  --- a/original
+  +++ b/original
+  @@ -1,0 +2,1 @@
+  +more content
+  $ $NO_FALLBACK rhg diff -c 1 -r 0
+  abort: cannot specify both --rev and --change
+  [10]
+  $ hg revert -q --all
+  $ rm renamed original.orig
+
+Annotate follows copies
+  $ $NO_FALLBACK rhg annotate -nc copy_of_original
+  0 1c9e69808da7: original content
+  $ $NO_FALLBACK rhg blame -ufnl --no-follow copy_of_original
+  test 1 copy_of_original:1: original content
+  $ $NO_FALLBACK rhg annotate -ul original
+  abort: at least one of -n/-c is required for -l
+  [255]
+  $ $NO_FALLBACK rhg annotate -r 0 copy_of_original
+  abort: copy_of_original: no such file in rev 1c9e69808da7
+  [255]
+
+Heads, parents and identify
+  $ $NO_FALLBACK rhg heads -T '{rev}: {desc}\n'
+  1: add copy of original
+  $ $NO_FALLBACK rhg heads -r 1 -t
+  changeset:   1:41263439dc17
+  tag:         tip
+  user:        test
+  date:        Thu Jan 01 00:00:00 1970 +0000
+  summary:     add copy of original
+  
+  $ $NO_FALLBACK rhg heads unknown-branch 2>&1
+  abort: invalid revision identifier: unknown-branch
+  [255]
+  $ $NO_FALLBACK rhg parents -r 1 -T '{rev}: {desc}\n'
+  0: add original
+  $ $NO_FALLBACK rhg parents -T '{rev}: {desc}\n'
+  1: add copy of original
+  $ $NO_FALLBACK rhg identify -r 0 -n -i
+  1c9e69808da7 0
+  $ $NO_FALLBACK rhg id
+  unsupported feature: identify of the working directory is experimental in rhg (enable it with 'rhg.status = true' or enable fallback with 'rhg.on-unsupported = fallback')
+  [252]
+  $ $NO_FALLBACK rhg id --config rhg.status=true
+  41263439dc17 tip
+  $ echo "more content" >> original
+  $ $NO_FALLBACK rhg id -n -i --config rhg.status=true
+  41263439dc17+ 1+
+  $ $NO_FALLBACK rhg id -b -t
+  default tip
+  $ hg revert -q --no-backup original
+
+Fallback to Python
+  $ $NO_FALLBACK rhg cat original --decode
+  unsupported feature: `rhg cat --decode`
   [252]
-  $ rhg cat original
+  $ rhg cat original --decode
   original content
 
   $ FALLBACK_EXE="$RHG_FALLBACK_EXECUTABLE"
   $ unset RHG_FALLBACK_EXECUTABLE
-  $ rhg cat original
+  $ rhg cat original --decode
   abort: 'rhg.on-unsupported=fallback' without 'rhg.fallback-executable' set.
   [255]
   $ RHG_FALLBACK_EXECUTABLE="$FALLBACK_EXE"
   $ export RHG_FALLBACK_EXECUTABLE
 
-  $ rhg cat original --config rhg.fallback-executable=false
+  $ rhg cat original --decode --config rhg.fallback-executable=false
   [1]
 
-  $ rhg cat original --config rhg.fallback-executable=hg-non-existent
+  $ rhg cat original --decode --config rhg.fallback-executable=hg-non-existent
   tried to fall back to a 'hg-non-existent' sub-process but got error $ENOENT$
-  unsupported feature: `rhg cat` without `--rev` / `-r`
+  unsupported feature: `rhg cat --decode`
   [252]
 
-  $ rhg cat original --config rhg.fallback-executable=rhg
+  $ rhg cat original --decode --config rhg.fallback-executable=rhg
   Blocking recursive fallback. The 'rhg.fallback-executable = rhg' config points to `rhg` itself.
-  unsupported feature: `rhg cat` without `--rev` / `-r`
+  unsupported feature: `rhg cat --decode`
   [252]
 
 Requirements
@@ -237,6 +554,358 @@ Specifying revisions by changeset ID
   $ $NO_FALLBACK rhg cat -r c3ae8dec9fad of
   r5000
 
+Revlog v2 with sidedata
+  $ cd $TESTTMP
+  $ hg --config format.exp-use-side-data=yes init revlogv2
+  $ cd revlogv2
+  $ $NO_FALLBACK rhg debugrequirements | grep -e revlogv2 -e sidedata
+  exp-revlogv2.2
+  exp-sidedata-flag
+  $ echo a > a
+  $ hg commit -Aqm 0
+  $ hg cp a b
+  $ echo b >> b
+  $ hg commit -qm 1
+  $ $NO_FALLBACK rhg files -r 1
+  a
+  b
+  $ $NO_FALLBACK rhg cat -r 1 b
+  a
+  b
+  $ $NO_FALLBACK rhg heads -T '{rev}\n'
+  1
+
+Tree manifests
+  $ cd $TESTTMP
+  $ hg --config experimental.treemanifest=yes init treemanifest
+  $ cd treemanifest
+  $ $NO_FALLBACK rhg debugrequirements | grep treemanifest
+  treemanifest
+  $ mkdir -p dir/subdir other
+  $ echo a > dir/subdir/a
+  $ echo b > dir/b
+  $ echo c > other/c
+  $ echo d > dir.d
+  $ hg commit -Aqm 0
+  $ echo a2 > dir/subdir/a
+  $ hg rm -q other/c
+  $ chmod +x dir/b
+  $ hg commit -qm 1
+  $ $NO_FALLBACK rhg files -r 0
+  dir.d
+  dir/b
+  dir/subdir/a
+  other/c
+  $ $NO_FALLBACK rhg cat -r 1 dir/subdir/a
+  a2
+  $ $NO_FALLBACK rhg cat -r 1 dir/subdir
+  unsupported feature: `rhg cat` of a directory
+  [252]
+  $ $NO_FALLBACK rhg cat -r 1 'glob:dir/*'
+  unsupported feature: `rhg cat` with file pattern glob:dir/*
+  [252]
+  $ rhg cat -r 1 dir/subdir
+  a2
+  $ $NO_FALLBACK rhg status --config rhg.status=true --rev 0 --rev 1
+  M dir/b
+  M dir/subdir/a
+  R other/c
+  $ $NO_FALLBACK rhg diff --git -r 0 -r 1 -X dir/subdir
+  diff --git a/dir/b b/dir/b
+  old mode 100644
+  new mode 100755
+  diff --git a/other/c b/other/c
+  deleted file mode 100644
+  --- a/other/c
+  +++ /dev/null
+  @@ -1,1 +0,0 @@
+  -c
+
+The manifests of directories that patterns exclude are not read
+  $ mv .hg/store/meta/dir/subdir $TESTTMP/subdir-manifest
+  $ $NO_FALLBACK rhg diff --stat -r 0 -r 1 -X path:dir/subdir
+   other/c |  1 -
+   1 files changed, 0 insertions(+), 1 deletions(-)
+  $ mv $TESTTMP/subdir-manifest .hg/store/meta/dir/subdir
+  $ hg cp -q dir/b other/b
+  $ hg commit -qm 2
+  $ mv .hg/store/meta/dir/subdir $TESTTMP/subdir-manifest
+  $ $NO_FALLBACK rhg diff --git -r 1 -r 2 other
+  diff --git a/dir/b b/other/b
+  copy from dir/b
+  copy to other/b
+  $ mv $TESTTMP/subdir-manifest .hg/store/meta/dir/subdir
+  $ $NO_FALLBACK rhg verify
+  unsupported feature: verifying tree manifests
+  [252]
+
+Obsolescence markers
+  $ cd $TESTTMP
+  $ hg init obsolete
+  $ cd obsolete
+  $ echo a > a
+  $ hg commit -Aqm 0
+  $ echo b > b
+  $ hg commit -Aqm 1
+  $ cat >> .hg/hgrc << EOF
+  > [experimental]
+  > evolution.createmarkers = yes
+  > EOF
+  $ hg debugobsolete 1111111111111111111111111111111111111111 2222222222222222222222222222222222222222
+  1 new obsolescence markers
+  $ $NO_FALLBACK rhg log -T '{rev}\n'
+  1
+  0
+  $ hg up -q 0
+  $ hg debugobsolete `hg log -r 1 -T '{node}'`
+  1 new obsolescence markers
+  obsoleted 1 changesets
+  $ $NO_FALLBACK rhg log -T '{rev}\n'
+  unsupported feature: obsolete or unstable changesets
+  [252]
+  $ $NO_FALLBACK rhg id -r .
+  unsupported feature: obsolete or unstable changesets
+  [252]
+  $ $NO_FALLBACK rhg id -t
+  unsupported feature: obsolete or unstable changesets
+  [252]
+  $ rhg id -r .
+  * tip (glob)
+  $ $NO_FALLBACK rhg cat -r 1 b
+  unsupported feature: hidden changesets
+  [252]
+  $ $NO_FALLBACK rhg cat -r 0 a
+  a
+
+Large files
+  $ cd $TESTTMP
+  $ hg init lfs
+  $ cd lfs
+  $ cat >> .hg/hgrc << EOF
+  > [extensions]
+  > lfs =
+  > [lfs]
+  > track = all()
+  > usercache = $TESTTMP/lfs-usercache
+  > EOF
+  $ echo "large content" > large
+  $ hg commit -Aqm 0
+  $ grep lfs .hg/requires
+  lfs
+  $ $NO_FALLBACK rhg cat -r 0 large
+  large content
+  $ rm -r .hg/store/lfs/objects
+  $ $NO_FALLBACK rhg cat -r 0 large
+  large content
+  $ rm -r $TESTTMP/lfs-usercache
+  $ $NO_FALLBACK rhg cat -r 0 large
+  unsupported feature: lfs blob missing locally
+  [252]
+
+Censored revisions
+  $ cd $TESTTMP
+  $ hg init censored
+  $ cd censored
+  $ echo "some secret content" > secret
+  $ hg commit -Aqm 0
+  $ echo "public content" > secret
+  $ hg commit -qm 1
+  $ hg --config extensions.censor= censor -r 0 secret
+  $ $NO_FALLBACK rhg cat -r 0 secret
+  abort: censored node: * (glob)
+  (set censor.policy to ignore errors)
+  [255]
+  $ $NO_FALLBACK rhg cat -r 0 secret --config censor.policy=ignore
+  $ $NO_FALLBACK rhg cat -r 1 secret
+  public content
+  $ $NO_FALLBACK rhg diff -r 0 -r 1
+  abort: censored node: * (glob)
+  (set censor.policy to ignore errors)
+  [255]
+  $ $NO_FALLBACK rhg diff -r 0 -r 1 --nodates --config censor.policy=ignore
+  diff -r * -r * secret (glob)
+  --- a/secret
+  +++ b/secret
+  @@ -0,0 +1,1 @@
+  +public content
+  $ $NO_FALLBACK rhg annotate -r 0 secret
+  abort: censored node: * (glob)
+  (set censor.policy to ignore errors)
+  [255]
+  $ $NO_FALLBACK rhg annotate -r 1 secret --config censor.policy=ignore
+  abort: file censored data/secret.i:* (glob)
+  [255]
+
+Verify
+  $ $NO_FALLBACK rhg verify
+  checking changesets
+  checking manifests
+  crosschecking files in changesets and manifests
+  checking files
+   secret@0: censored file data
+  checked 2 changesets with 2 changes to 1 files
+  1 integrity errors encountered!
+  (first damaged changeset appears to be 0)
+  [1]
+  $ $NO_FALLBACK rhg verify --config censor.policy=ignore
+  checking changesets
+  checking manifests
+  crosschecking files in changesets and manifests
+  checking files
+   secret@0: checking rename of 1fb3819054a1: data/secret.i:1fb3819054a1
+  checked 2 changesets with 2 changes to 1 files
+  1 integrity errors encountered!
+  (first damaged changeset appears to be 0)
+  [1]
+  $ cd $TESTTMP
+  $ hg init verified
+  $ cd verified
+  $ echo a > a
+  $ hg commit -Aqm 0
+  $ hg cp a b
+  $ hg commit -qm 1
+  $ $NO_FALLBACK rhg verify
+  checking changesets
+  checking manifests
+  crosschecking files in changesets and manifests
+  checking files
+  checked 2 changesets with 2 changes to 2 files
+  $ rm .hg/store/data/a.i
+  $ grep -v data/b.i .hg/store/fncache > fncache
+  $ mv fncache .hg/store/fncache
+  $ $NO_FALLBACK rhg verify
+  checking changesets
+  checking manifests
+  crosschecking files in changesets and manifests
+  checking files
+   warning: revlog 'data/a.i' not in fncache!
+   0: empty or missing a
+   a@0: manifest refers to unknown revision b789fdd96dc2
+   warning: revlog 'data/b.i' not in fncache!
+   b@1: empty or missing copy source revlog a:b789fdd96dc2
+  checked 2 changesets with 1 changes to 2 files
+  2 warnings encountered!
+  hint: run "hg debugrebuildfncache" to recover from corrupt fncache
+  3 integrity errors encountered!
+  (first damaged changeset appears to be 0)
+  [1]
+
+Corrupted revlog entries are reported rather than read
+  $ cd $TESTTMP
+  $ hg init deltas
+  $ cd deltas
+  $ $TESTDIR/seq.py 1 100 > f
+  $ hg commit -Aqm 0
+  $ ($TESTDIR/seq.py 1 49; echo fifty; $TESTDIR/seq.py 51 100) > f
+  $ hg commit -qm 1
+  $ cat > $TESTTMP/corrupt.py <<EOF
+  > import struct, sys
+  > rev, field, value = int(sys.argv[1]), sys.argv[2], int(sys.argv[3])
+  > with open('.hg/store/data/f.i', 'rb') as fp:
+  >     data = bytearray(fp.read())
+  > pos = 0
+  > for r in range(rev):
+  >     pos += 64 + struct.unpack('>i', data[pos + 8:pos + 12])[0]
+  > if field == 'offset':
+  >     data[pos:pos + 6] = struct.pack('>Q', value)[2:]
+  > elif field == 'base':
+  >     data[pos + 16:pos + 20] = struct.pack('>i', value)
+  > elif field == 'length':
+  >     length = struct.unpack('>i', data[pos + 8:pos + 12])[0]
+  >     data[pos + 8:pos + 12] = struct.pack('>i', value)
+  >     del data[pos + 64 + value:pos + 64 + length]
+  > with open('.hg/store/data/f.i', 'wb') as fp:
+  >     fp.write(data)
+  > EOF
+  $ cp .hg/store/data/f.i $TESTTMP/f.i
+  $ "$PYTHON" $TESTTMP/corrupt.py 1 length 14
+  $ $NO_FALLBACK rhg verify 2>&1 | grep unpacking
+   f@1: unpacking dedf77728f67: patch cannot be decoded
+  $ cp $TESTTMP/f.i .hg/store/data/f.i
+  $ "$PYTHON" $TESTTMP/corrupt.py 1 base 5
+  $ $NO_FALLBACK rhg verify 2>&1 | grep unpacking
+   f@1: unpacking dedf77728f67: delta base 5 is not an earlier revision
+  $ cp $TESTTMP/f.i .hg/store/data/f.i
+  $ "$PYTHON" $TESTTMP/corrupt.py 0 base -1
+  $ $NO_FALLBACK rhg verify 2>&1 | grep unpacking
+   f@0: unpacking 5f215a9162b2: delta chain does not end at a snapshot
+   f@1: unpacking dedf77728f67: delta chain does not end at a snapshot
+  $ cp $TESTTMP/f.i .hg/store/data/f.i
+  $ "$PYTHON" $TESTTMP/corrupt.py 1 offset 5767168
+  $ $NO_FALLBACK rhg verify 2>&1 | grep unpacking
+   f@1: unpacking dedf77728f67: data offset is 5767168, 148 expected
+
+Inspecting revlogs
+
+  $ cd $TESTTMP
+  $ hg init inspected
+  $ cd inspected
+  $ $TESTDIR/seq.py 1 100 > a
+  $ hg commit -Aqm 0
+  $ $TESTDIR/seq.py 1 2 100 > a
+  $ hg commit -qm 1
+  $ hg update -q 0
+  $ $TESTDIR/seq.py 3 100 > a
+  $ hg commit -qm 2
+  $ $NO_FALLBACK rhg debugindex a
+     rev linkrev nodeid       p1           p2
+       0       0 5f215a9162b2 000000000000 000000000000
+       1       1 41e8ffd40e55 5f215a9162b2 000000000000
+       2       2 8eb774abfa3f 5f215a9162b2 000000000000
+  $ $NO_FALLBACK rhg debugindex -c --config ui.debug=1
+     rev linkrev nodeid                                   p1                                       p2
+       0       0 ef23ae13db58c0469da36b180f0241c0b08fd7f4 0000000000000000000000000000000000000000 0000000000000000000000000000000000000000
+       1       1 22e645f6aea0d0f43d92d6e804a3959f5e0e51f8 ef23ae13db58c0469da36b180f0241c0b08fd7f4 0000000000000000000000000000000000000000
+       2       2 988fefe5aabf2ed769fba56bfdc154382257fd0b ef23ae13db58c0469da36b180f0241c0b08fd7f4 0000000000000000000000000000000000000000
+  $ $NO_FALLBACK rhg debugdeltachain a
+      rev  chain# chainlen     prev   delta       size    rawsize  chainsize     ratio   lindist extradist extraratio   readsize largestblk rddensity srchunks
+        0       1        1       -1    base        148        292        148   0.50685       148         0    0.00000        148        148   1.00000        1
+        1       2        1       -1    base         83        145         83   0.57241        83         0    0.00000         83         83   1.00000        1
+        2       1        2        0      p1         12        288        160   0.55556       243        83    0.51875        243        243   0.65844        1
+  $ $NO_FALLBACK rhg debugrevlogstats a
+  format : 1
+  flags  : inline, generaldelta
+  
+  revisions     :   3
+      merges    :   0 ( 0.00%)
+      normal    :   3 (100.00%)
+  revisions     :   3
+      empty     :   0 ( 0.00%)
+                     text  :   0 (100.00%)
+                     delta :   0 (100.00%)
+      snapshot  :   2 (66.67%)
+        lvl-0   :         2 (66.67%)
+      deltas    :   1 (33.33%)
+  revision size : 243
+      snapshot  : 231 (95.06%)
+        lvl-0   :       231 (95.06%)
+      deltas    :  12 ( 4.94%)
+  
+  chunks        :   3
+      0x00      :   1 (33.33%)
+      0x78 (x)  :   2 (66.67%)
+  chunks size   : 243
+      0x00      :  12 ( 4.94%)
+      0x78 (x)  : 231 (95.06%)
+  
+  avg chain length  :   0
+  max chain length  :   1
+  max chain reach   : 243
+  compression ratio :   2
+  
+  uncompressed data size (min/max/avg) : 145 / 292 / 241
+  full revision size (min/max/avg)     : 83 / 148 / 115
+  inter-snapshot size (min/max/avg)    : 0 / 0 / 0
+  delta size (min/max/avg)             : 12 / 12 / 12
+  
+  deltas against prev  : 0 ( 0.00%)
+  deltas against p1    : 1 (100.00%)
+  deltas against p2    : 0 ( 0.00%)
+  deltas against other : 0 ( 0.00%)
+  $ $NO_FALLBACK rhg debugindex missing
+  unsupported feature: debug commands on revlogs outside of the store
+  [252]
+
 Crate a shared repository
 
   $ echo "[extensions]"      >> $HGRCPATH